@@ -9,23 +9,60 @@ extern crate simplelog;
 extern crate walkdir;
 
 use ieql::common::compilation::CompilableTo;
+use ieql::common::format;
 use ieql::common::retrieve::load_document;
 use ieql::common::validation::{Issue, Validatable};
 use ieql::input::document::{Document, DocumentBatch, DocumentReference,
-    DocumentReferenceBatch,
+    DocumentReferenceBatch, Provenance, TextExtraction,
 };
-use ieql::ScopeContent;
-use ieql::output::output::OutputBatch;
-use ieql::query::query::{Query, QueryGroup};
-use ieql::scan::scanner::{Scanner, AsyncScanInterface};
+use ieql::{ScopeContent, Threshold, Trigger};
+use ieql::output::compression::{decompress, Compression};
+use ieql::output::diff::OutputKey;
+use ieql::output::output::{DeduplicationKey, DeduplicationPolicy, Output, OutputBatch, OutputItem};
+#[cfg(feature = "sqlite")]
+use ieql::output::sink::sqlite::SqliteSink;
+use ieql::output::table::{TableColumn, TableFormat, TableWriter, DEFAULT_COLUMNS};
+use ieql::output::sink::OutputSink;
+#[cfg(feature = "kafka")]
+use ieql::output::sink::kafka::KafkaSink;
+#[cfg(feature = "s3")]
+use ieql::output::sink::s3::{S3Options, S3Sink};
+#[cfg(feature = "webhook")]
+use ieql::output::sink::webhook::{WebhookOptions, WebhookSink};
+#[cfg(feature = "signing")]
+use ieql::output::signing::{OutputSignature, OutputSigner, OutputVerifier};
+use ieql::output::store::OutputStore;
+use ieql::output::writer::NdjsonWriter;
+use ieql::query::query::{CompiledQueryGroup, Query, QueryGroup};
+use ieql::input::archive::{is_archive_path, ArchiveReader};
+use ieql::input::feed::{is_feed_path, parse_feed};
+use ieql::input::mail::{is_mbox_path, is_message_path, parse_mbox, parse_message};
+use ieql::input::rows::{split_rows, RowFormat, RowOptions};
+use ieql::input::stream::StreamingOptions;
+use ieql::input::warc::{is_warc_path, WarcReader};
+use ieql::scan::backfill::Backfill;
+use ieql::scan::cache::ScanCache;
+use ieql::scan::metrics::MetricsSnapshot;
+use ieql::scan::impact::{simulate_impact, MatchIdentity};
+use ieql::scan::scanner::{AsyncScanInterface, OutputLimits, ScanOptions, Scanner};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use std::thread;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use walkdir::WalkDir;
 
 use clap::{App, Arg, SubCommand};
 
+/// How often scan metrics are written during a scan (see `--metrics`).
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
 fn main() {
     simplelog::CombinedLogger::init(vec![simplelog::TermLogger::new(
         simplelog::LevelFilter::Info,
@@ -68,10 +105,133 @@ fn main() {
                 )
                 .arg_from_usage("-m, --multithreading 'Scan using multiple CPU threads'")
                 .arg_from_usage("-t, --threads=[# of threads] 'If multithreading, how many threads to use'")
+                .arg_from_usage("-l, --loader-threads=[# of threads] 'If multithreading, how many threads to use for loading files'")
+                .arg_from_usage("-M, --memory-budget=[megabytes] 'If multithreading, the maximum amount of document content to hold in memory at once'")
+                .arg_from_usage("-T, --time-budget=[milliseconds] 'If multithreading, the maximum time to spend scanning a single document'")
+                .arg_from_usage("--stream-above=[megabytes] 'If multithreading, scan files larger than this in overlapping windows of 8 MB rather than loading them whole'")
+                .arg_from_usage("--rows=[format] 'Scan each row of the input files (other than WARC files, feeds, archives, and email) as a document of its own: `csv`, `tsv`, or `lines`'")
+                .arg_from_usage("--url-column=[column] 'With `--rows=csv` or `--rows=tsv`, the column whose values are the URLs of the rows'")
+                .arg_from_usage("--cache=[path] 'If multithreading, skip documents that are unchanged since they were last scanned with the same queries, recording scanned documents in the given file'")
+                .arg_from_usage("--naive-text 'Extract the text of HTML documents by stripping their tags, which is faster than parsing them but keeps scripts and style sheets'")
                 .arg_from_usage("-h, --hide-outputs 'Do not show outputs'")
                 .arg_from_usage("-R, --recursive 'Enter directories recursively'")
                 .arg_from_usage("-o, --output=[dir] 'Directory to place outputs")
-                .args_from_usage("-p, --pretty 'Pretty-print output files'"),
+                .arg_from_usage("-p, --pretty 'Pretty-print output files'")
+                .arg_from_usage("--compress=[compression] 'Write each batch of outputs to a single compressed file in the output directory: `gzip` or `zstd`'")
+                .arg_from_usage("-E, --export=[path] 'Append outputs to the given file as they are produced, in the format given by `--format`'")
+                .arg_from_usage("-F, --format=[format] 'The format of exported outputs: `ndjson` (the default), `csv`, `tsv`, `sqlite`, or `store` (an append-only output store, which survives crashes)'")
+                .arg_from_usage("-K, --columns=[columns] 'The comma-separated columns of exported CSV or TSV outputs, such as `query_id,url,excerpt`'")
+                .arg_from_usage("-W, --webhook=[url] 'POST outputs as JSON to the given URL as they are produced, retrying failed deliveries'")
+                .arg_from_usage("--webhook-batch=[count] 'The maximum number of outputs to POST to the webhook at once'")
+                .arg_from_usage("--kafka-brokers=[brokers] 'Publish each output to Kafka, using the given comma-separated brokers, keyed by query ID'")
+                .arg_from_usage("--kafka-topic=[topic] 'The Kafka topic to which outputs are published (`ieql-outputs` by default)'")
+                .arg_from_usage("--s3-bucket=[bucket] 'Upload outputs to the given S3 bucket, using the credentials in `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`'")
+                .arg_from_usage("--s3-region=[region] 'The region of the S3 bucket (`AWS_REGION`, or `us-east-1`, by default)'")
+                .arg_from_usage("--s3-endpoint=[url] 'The endpoint of an S3-compatible service (AWS S3 in the bucket\'s region by default)'")
+                .arg_from_usage("--s3-prefix=[prefix] 'The prefix of the keys of uploaded objects'")
+                .arg_from_usage("--s3-partition=[components] 'The comma-separated components by which to partition uploaded objects: `date`, `query`, or both (`date` by default)'")
+                .arg_from_usage("-s, --seed=[seed] 'Seed for randomized behavior (such as file names), for reproducible runs'")
+                .arg_from_usage("-P, --partition=[i/N] 'Only run the i-th of N balanced partitions of the queries (e.g. `2/4`)'")
+                .arg_from_usage("-N, --thresholds=[path] 'A file of named thresholds that the queries may refer to'")
+                .arg_from_usage("-L, --library=[path] 'A file of shared triggers that the queries may refer to'")
+                .arg_from_usage("-w, --watch=[seconds] 'If multithreading, once the inputs are scanned, keep scanning new files in them every given number of seconds'")
+                .arg_from_usage("-C, --canonical=[path] 'Once the scan completes, write all outputs to the given file in canonical order, for comparison between runs'")
+                .arg_from_usage("-D, --max-outputs-per-document=[count] 'The maximum number of outputs per document; further outputs are dropped'")
+                .arg_from_usage("-Q, --max-outputs-per-query=[count] 'The maximum number of outputs per query over the whole scan; further outputs are dropped'")
+                .arg_from_usage("-S, --summary=[path] 'Once the scan completes, write a summary of the outputs (counts per query and per domain) to the given file'")
+                .arg_from_usage("--report=[path] 'Once the scan completes, write an HTML report of the outputs, with summary tables and highlighted excerpts, to the given file'")
+                .arg_from_usage("--metrics=[path] 'If multithreading, write scan metrics (documents scanned, outputs per query, and scan durations) to the given file in the Prometheus text format every few seconds'"),
+        )
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .about("Compare the matches of current and proposed IEQL queries")
+                .arg(
+                    Arg::with_name("current")
+                        .help("the path to the current query, or a directory which contains multiple queries")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("proposed")
+                        .help("the path to the proposed query, or a directory which contains multiple queries")
+                        .required(true)
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("inputs")
+                        .help("the path(s) to the input files")
+                        .required(true)
+                        .index(3)
+                        .min_values(1),
+                )
+                .arg_from_usage("-R, --recursive 'Enter directories recursively'")
+                .arg_from_usage("-f, --from-outputs 'Inputs are prior outputs; scan the documents they reference'")
+                .arg_from_usage("-N, --thresholds=[path] 'A file of named thresholds that the queries may refer to'")
+                .arg_from_usage("-L, --library=[path] 'A file of shared triggers that the queries may refer to'"),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merge output stores (see `scan --format=store`) into a single, compacted store")
+                .arg(
+                    Arg::with_name("destination")
+                        .help("the path of the store into which to merge; it is created if it does not exist")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("stores")
+                        .help("the path(s) of the stores to merge")
+                        .required(true)
+                        .index(2)
+                        .min_values(1),
+                )
+                .arg_from_usage("-d, --deduplicate 'Remove outputs of the same query about the same URL, merging their excerpts'"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare the outputs of two scans, such as before and after a query change")
+                .arg(
+                    Arg::with_name("before")
+                        .help("the path to the earlier outputs: an output file, or a directory of them")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("after")
+                        .help("the path to the later outputs: an output file, or a directory of them")
+                        .required(true)
+                        .index(2),
+                )
+                .arg_from_usage("-r, --report=[path] 'Write the full difference, including the outputs, to the given file'"),
+        )
+        .subcommand(
+            SubCommand::with_name("sign")
+                .about("Sign output files, writing a detached signature (`<file>.sig`) for each")
+                .arg(
+                    Arg::with_name("key")
+                        .help("the path of a file containing the hex-encoded 32-byte Ed25519 secret key with which to sign")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("outputs")
+                        .help("the path(s) of the output files to sign")
+                        .required(true)
+                        .index(2)
+                        .min_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Verify the detached signatures (`<file>.sig`) of output files")
+                .arg(
+                    Arg::with_name("outputs")
+                        .help("the path(s) of the output files to verify")
+                        .required(true)
+                        .index(1)
+                        .min_values(1),
+                )
+                .arg_from_usage("-k, --public-key=[key] 'The hex-encoded public key of the trusted signer; without it, signatures only show that the outputs were not altered, not who signed them'"),
         )
         .get_matches();
     run(matches);
@@ -81,6 +241,11 @@ fn run(matches: clap::ArgMatches) {
     match matches.subcommand() {
         ("validate", Some(m)) => run_validate(m),
         ("scan", Some(m)) => run_scan(m),
+        ("simulate", Some(m)) => run_simulate(m),
+        ("merge", Some(m)) => run_merge(m),
+        ("diff", Some(m)) => run_diff(m),
+        ("sign", Some(m)) => run_sign(m),
+        ("verify", Some(m)) => run_verify(m),
         _ => error!("no valid command specified; try running with `--help`."),
     }
 }
@@ -120,17 +285,247 @@ fn get_queries_from_file(file: String) -> QueryGroup {
             Ok(value) => value,
             Err(error) => {
                 error!("unable to load query `{}` (`{}`), skipping...", file, error);
-                return QueryGroup { queries: vec![], optimized_content: ScopeContent::Raw };
+                return QueryGroup {
+                    queries: vec![],
+                    optimized_content: ScopeContent::Raw,
+                    thresholds: BTreeMap::new(),
+                    triggers: vec![],
+                };
             }
         });
     }
-    QueryGroup { queries: queries, optimized_content: ScopeContent::Raw }
+    QueryGroup {
+        queries,
+        optimized_content: ScopeContent::Raw,
+        thresholds: BTreeMap::new(),
+        triggers: vec![],
+    }
+}
+
+fn write_canonical_outputs(path: &str, output_batch: &OutputBatch) -> bool {
+    let canonical = match output_batch.to_canonical_string() {
+        Ok(value) => value,
+        Err(error) => {
+            error!("unable to serialize outputs canonically: `{}`", error);
+            return false;
+        }
+    };
+    match fs::write(path, canonical) {
+        Ok(_) => {
+            info!("wrote {} output(s) in canonical order to `{}`", output_batch.outputs.len(), path);
+            true
+        }
+        Err(error) => {
+            error!("unable to write canonical outputs to `{}` (`{}`)", path, error);
+            false
+        }
+    }
+}
+
+fn write_output_summary(path: &str, output_batch: &OutputBatch) -> bool {
+    let summary = output_batch.summarize();
+    let serialized = match ron::ser::to_string_pretty(&summary, ron::ser::PrettyConfig::default()) {
+        Ok(value) => value,
+        Err(error) => {
+            error!("unable to serialize output summary: `{}`", error);
+            return false;
+        }
+    };
+    match fs::write(path, serialized) {
+        Ok(_) => {
+            info!(
+                "wrote summary of {} output(s) about {} document(s) to `{}`",
+                summary.outputs, summary.documents, path
+            );
+            true
+        }
+        Err(error) => {
+            error!("unable to write output summary to `{}` (`{}`)", path, error);
+            false
+        }
+    }
+}
+
+fn write_html_report(path: &str, output_batch: &OutputBatch) -> bool {
+    match fs::write(path, output_batch.to_html_report("IEQL Scan Report")) {
+        Ok(_) => {
+            info!("wrote report of {} output(s) to `{}`", output_batch.outputs.len(), path);
+            true
+        }
+        Err(error) => {
+            error!("unable to write report to `{}` (`{}`)", path, error);
+            false
+        }
+    }
+}
+
+/// Writes the given metrics to the file at the given path in the Prometheus
+/// text format. The file is replaced atomically, so that collectors (such
+/// as the node exporter's textfile collector) never read a partial file.
+fn write_metrics(path: &str, snapshot: &MetricsSnapshot) -> bool {
+    let temporary_path = format!("{}.tmp", path);
+    match fs::write(&temporary_path, snapshot.to_prometheus()).and_then(|_| fs::rename(&temporary_path, path)) {
+        Ok(_) => true,
+        Err(error) => {
+            error!("unable to write scan metrics to `{}` (`{}`)", path, error);
+            false
+        }
+    }
+}
+
+fn open_export_sink(
+    path: &str,
+    format: Option<&str>,
+    columns: Option<&str>,
+) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    let columns = match columns {
+        Some(list) => TableColumn::parse_list(list)?,
+        None => DEFAULT_COLUMNS.to_vec(),
+    };
+    match format.unwrap_or("ndjson") {
+        "ndjson" => Ok(Box::new(NdjsonWriter::append(path)?)),
+        "csv" => Ok(Box::new(TableWriter::append(path, TableFormat::Csv, &columns)?)),
+        "tsv" => Ok(Box::new(TableWriter::append(path, TableFormat::Tsv, &columns)?)),
+        "store" => {
+            let store = OutputStore::open(path)?;
+            if store.discarded() > 0 {
+                warn!(
+                    "discarded {} byte(s) of a partially written batch at the end of output store `{}`",
+                    store.discarded(),
+                    path
+                );
+            }
+            Ok(Box::new(store))
+        }
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(Box::new(SqliteSink::open(path)?)),
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" => Err(Issue::Error(String::from(
+            "the `sqlite` export format requires the `sqlite` feature",
+        ))),
+        other => Err(Issue::Error(format!(
+            "unknown export format `{}`; expected `ndjson`, `csv`, `tsv`, `sqlite`, or `store`",
+            other
+        ))),
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn open_webhook_sink(url: &str, batch_size: Option<&str>) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    let mut options = WebhookOptions::default();
+    if let Some(value) = batch_size {
+        options.batch_size = value.parse().map_err(|error| {
+            Issue::Error(format!("invalid webhook batch size `{}` (`{}`)", value, error))
+        })?;
+    }
+    Ok(Box::new(WebhookSink::new(url, options)))
+}
+
+#[cfg(not(feature = "webhook"))]
+fn open_webhook_sink(_url: &str, _batch_size: Option<&str>) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    Err(Issue::Error(String::from(
+        "delivering outputs to a webhook requires the `webhook` feature",
+    )))
+}
+
+#[cfg(feature = "s3")]
+fn open_s3_sink(bucket: &str, matches: &clap::ArgMatches) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    let credential = |name: &str| {
+        std::env::var(name).map_err(|_| {
+            Issue::Error(format!("uploading outputs to S3 requires `{}` to be set", name))
+        })
+    };
+    let region = match matches.value_of("s3-region") {
+        Some(value) => String::from(value),
+        None => std::env::var("AWS_REGION").unwrap_or_else(|_| String::from("us-east-1")),
+    };
+    let endpoint = match matches.value_of("s3-endpoint") {
+        Some(value) => String::from(value),
+        None => format!("https://s3.{}.amazonaws.com", region),
+    };
+    let mut options = S3Options::new(
+        &endpoint,
+        &region,
+        bucket,
+        &credential("AWS_ACCESS_KEY_ID")?,
+        &credential("AWS_SECRET_ACCESS_KEY")?,
+    );
+    options.prefix = String::from(matches.value_of("s3-prefix").unwrap_or_default());
+    if let Some(list) = matches.value_of("s3-partition") {
+        options.partition_by_date = false;
+        for component in list.split(',') {
+            match component.trim() {
+                "date" => options.partition_by_date = true,
+                "query" => options.partition_by_query = true,
+                "" => (),
+                other => {
+                    return Err(Issue::Error(format!(
+                        "unknown S3 partition `{}`; expected `date` or `query`",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+    Ok(Box::new(S3Sink::new(options)?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3_sink(_bucket: &str, _matches: &clap::ArgMatches) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    Err(Issue::Error(String::from(
+        "uploading outputs to S3 requires the `s3` feature",
+    )))
+}
+
+#[cfg(feature = "kafka")]
+fn open_kafka_sink(brokers: &str, topic: &str) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    Ok(Box::new(KafkaSink::connect(brokers, topic)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn open_kafka_sink(_brokers: &str, _topic: &str) -> Result<Box<dyn OutputSink + Send>, Issue> {
+    Err(Issue::Error(String::from(
+        "publishing outputs to Kafka requires the `kafka` feature",
+    )))
+}
+
+/// Handles the outputs of a multithreaded scan as they are produced:
+/// shows them, writes them to files, and keeps them for the canonical
+/// outputs and summary.
+struct CliSink {
+    hide_outputs: bool,
+    output_dir: Option<String>,
+    pretty: bool,
+    compression: Compression,
+    rng: StdRng,
+    outputs: Arc<Mutex<OutputBatch>>,
+}
+
+impl OutputSink for CliSink {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        if !self.hide_outputs {
+            for output in &batch.outputs {
+                info!("  - {}", output);
+            }
+        }
+        if let Some(output_dir) = &self.output_dir {
+            write_output_batch_to_file(output_dir, batch, self.pretty, self.compression, &mut self.rng);
+        }
+        self.outputs.lock().unwrap().merge_with(batch.clone());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        Ok(())
+    }
 }
 
 fn write_output_batch_to_file(
     parent_directory: &str,
     output_batch: &OutputBatch,
     pretty: bool,
+    compression: Compression,
+    rng: &mut StdRng,
 ) -> bool {
     let dir_path = Path::new(&parent_directory);
     if !dir_path.is_dir() {
@@ -140,6 +535,17 @@ fn write_output_batch_to_file(
         );
         return false;
     }
+    if compression != Compression::None {
+        let batch_filename = format!("outputs-{}.ieqlb{}", rng.gen::<u32>(), compression.extension());
+        let result = File::create(dir_path.join(&batch_filename))
+            .map_err(|error| Issue::Error(error.to_string()))
+            .and_then(|file| output_batch.write_compressed(file, compression));
+        if let Err(error) = result {
+            error!("unable to write outputs `{}` (`{}`)", batch_filename, error);
+            return false;
+        }
+        return true;
+    }
     for output in &output_batch.outputs {
         let query_id = match &output.query_id {
             Some(value) => value.clone(),
@@ -147,7 +553,7 @@ fn write_output_batch_to_file(
         };
         let output_filename = match &output.id {
             Some(value) => format!("output-{}-{}.ieqlo", value, query_id),
-            None => format!("output-{}-{}.ieqlo", rand::random::<u32>(), query_id),
+            None => format!("output-{}-{}.ieqlo", rng.gen::<u32>(), query_id),
         };
         let file_path = dir_path.join(output_filename.clone());
         let output_string = match match pretty {
@@ -169,7 +575,268 @@ fn write_output_batch_to_file(
             Err(error) => error!("unable to write output `{}` (`{}`)", output_filename, error),
         }
     }
-    return true;
+    true
+}
+
+/// Selects the partition described by the given specifier (of the form
+/// `i/N`, where `1 <= i <= N`) of the query group.
+fn get_partition(queries: QueryGroup, specifier: &str) -> Result<QueryGroup, Issue> {
+    let invalid = || {
+        Issue::Error(format!(
+            "invalid partition `{}`; expected `i/N`, where 1 <= i <= N",
+            specifier
+        ))
+    };
+    let mut parts = specifier.splitn(2, '/');
+    let index: usize = parts.next().and_then(|value| value.trim().parse().ok()).ok_or_else(invalid)?;
+    let count: usize = parts.next().and_then(|value| value.trim().parse().ok()).ok_or_else(invalid)?;
+    if index == 0 || index > count {
+        return Err(invalid());
+    }
+    Ok(queries.partition(count)?.swap_remove(index - 1))
+}
+
+/// Groups the given files into batches of unpopulated document references.
+/// Splits the files into batches of the given size, identified by the
+/// given prefix and their index (such as `archive-0`).
+fn batch_files(files: Vec<Box<Path>>, batch_size: usize, prefix: &str) -> Vec<DocumentReferenceBatch> {
+    let mut batches: Vec<DocumentReferenceBatch> = Vec::new();
+    let mut current_documents: Vec<DocumentReference> = Vec::new();
+    for file_path in files {
+        let document_reference = DocumentReference::Unpopulated(match file_path.to_str() {
+            Some(value) => String::from(value),
+            None => {
+                error!(
+                    "unable to handle file `{}`, skipping...",
+                    file_path.to_string_lossy()
+                );
+                continue;
+            }
+        }); // TODO: will the lossyness ever be an issue?
+        current_documents.push(document_reference);
+        if current_documents.len() >= batch_size {
+            let batch = std::mem::take(&mut current_documents);
+            let id = format!("{}-{}", prefix, batches.len());
+            batches.push(DocumentReferenceBatch::from(batch).with_id(&id));
+        }
+    }
+    if !current_documents.is_empty() {
+        let id = format!("{}-{}", prefix, batches.len());
+        batches.push(DocumentReferenceBatch::from(current_documents).with_id(&id));
+    }
+    batches
+}
+
+/// Whether the file at the given path holds documents that must be read
+/// from it: a WARC file (see `input::warc`), a feed (see `input::feed`), an
+/// archive (see `input::archive`), or email (see `input::mail`)—or, when
+/// files are split into rows (see `input::rows`), any file.
+fn is_container_file(path: &Path, rows: Option<&RowOptions>) -> bool {
+    rows.is_some()
+        || path.to_str().is_some_and(|path| {
+            is_warc_path(path) || is_feed_path(path) || is_archive_path(path) || is_mbox_path(path) || is_message_path(path)
+        })
+}
+
+/// Reads the documents of the given WARC file, feed, archive, or email, or
+/// the rows of any other file when files are split into rows, lazily in the
+/// case of WARC files and archives. Unreadable files are skipped with a
+/// warning.
+fn read_container_file(path: String, rows: Option<&RowOptions>) -> Box<dyn Iterator<Item = Document>> {
+    if is_feed_path(&path) {
+        return match load_document(&path).and_then(|feed| parse_feed(&feed)) {
+            Ok(documents) => Box::new(documents.into_iter()),
+            Err(error) => {
+                warn!("unable to read feed `{}` (`{}`), skipping...", path, error);
+                Box::new(std::iter::empty())
+            }
+        };
+    }
+    if is_mbox_path(&path) || is_message_path(&path) {
+        let documents = load_document(&path).and_then(|mail| match is_mbox_path(&path) {
+            true => parse_mbox(&mail),
+            false => parse_message(&mail).map(|message| vec![message]),
+        });
+        return match documents {
+            Ok(documents) => Box::new(documents.into_iter()),
+            Err(error) => {
+                warn!("unable to read email `{}` (`{}`), skipping...", path, error);
+                Box::new(std::iter::empty())
+            }
+        };
+    }
+    if is_archive_path(&path) {
+        return match ArchiveReader::open(&path) {
+            Ok(reader) => Box::new(reader.filter_map(move |result| match result {
+                Ok(document) => Some(document),
+                Err(Issue::Warning(message)) => {
+                    warn!("{}", message);
+                    None
+                }
+                Err(error) => {
+                    warn!("unable to read archive `{}` (`{}`), skipping the rest of it...", path, error);
+                    None
+                }
+            })),
+            Err(error) => {
+                warn!("{}, skipping...", error);
+                Box::new(std::iter::empty())
+            }
+        };
+    }
+    if let (false, Some(rows)) = (is_warc_path(&path), rows) {
+        return match load_document(&path).and_then(|file| split_rows(&file, rows)) {
+            Ok(documents) => Box::new(documents.into_iter()),
+            Err(error) => {
+                warn!("unable to split `{}` into rows (`{}`), skipping...", path, error);
+                Box::new(std::iter::empty())
+            }
+        };
+    }
+    match WarcReader::open(&path) {
+        Ok(reader) => Box::new(reader.filter_map(move |result| match result {
+            Ok(document) => Some(document),
+            Err(error) => {
+                warn!("unable to read WARC file `{}` (`{}`), skipping the rest of it...", path, error);
+                None
+            }
+        })),
+        Err(error) => {
+            warn!("{}, skipping...", error);
+            Box::new(std::iter::empty())
+        }
+    }
+}
+
+/// Reads the documents of the given WARC files, feeds, archives, and email
+/// (or rows; see `read_container_file()`), grouped into batches of
+/// populated document references of the given size, which are identified
+/// like those of `batch_files()`.
+fn batch_container_files(
+    files: Vec<Box<Path>>,
+    batch_size: usize,
+    prefix: &str,
+    rows: Option<RowOptions>,
+) -> impl Iterator<Item = DocumentReferenceBatch> {
+    let mut documents = files
+        .into_iter()
+        .flat_map(move |file| read_container_file(String::from(file.to_string_lossy()), rows.as_ref()));
+    let prefix = String::from(prefix);
+    let mut index = 0;
+    std::iter::from_fn(move || {
+        let batch: Vec<DocumentReference> = documents
+            .by_ref()
+            .take(batch_size)
+            .map(DocumentReference::Populated)
+            .collect();
+        if batch.is_empty() {
+            return None;
+        }
+        let id = format!("{}-{}", prefix, index);
+        index += 1;
+        Some(DocumentReferenceBatch::from(batch).with_id(&id))
+    })
+}
+
+/// Groups the given files into batches like `batch_files()`, except that
+/// the documents of WARC files, feeds, archives, and email are read from
+/// them (once the other files have been batched) rather than each file
+/// being a document.
+fn batch_inputs(
+    files: Vec<Box<Path>>,
+    batch_size: usize,
+    prefix: &str,
+    rows: Option<&RowOptions>,
+) -> Box<dyn Iterator<Item = DocumentReferenceBatch>> {
+    let (container_files, files): (Vec<Box<Path>>, Vec<Box<Path>>) =
+        files.into_iter().partition(|file| is_container_file(file, rows));
+    let container_prefix = format!("{}-container", prefix);
+    Box::new(
+        batch_files(files, batch_size, prefix)
+            .into_iter()
+            .chain(batch_container_files(container_files, batch_size, &container_prefix, rows.cloned())),
+    )
+}
+
+/// An endless source of the files in the given input paths, collected
+/// anew every `interval`. Files that were already scanned are skipped by
+/// the `Backfill` that consumes it, so only new files are scanned.
+struct DirectoryWatch {
+    file_paths: Vec<String>,
+    recursive: bool,
+    interval: Duration,
+    batch_size: usize,
+    rows: Option<RowOptions>,
+    enabled: bool,
+}
+
+impl IntoIterator for DirectoryWatch {
+    type Item = DocumentReferenceBatch;
+    type IntoIter = Box<dyn Iterator<Item = DocumentReferenceBatch>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        if !self.enabled {
+            return Box::new(std::iter::empty());
+        }
+        let mut round = 0;
+        // WARC files, feeds, archives, and email are only read once; other
+        // files are skipped by the `Backfill` once they have been scanned
+        let mut seen_container_files: HashSet<Box<Path>> = HashSet::new();
+        Box::new(
+            std::iter::repeat_with(move || {
+                thread::sleep(self.interval);
+                round += 1;
+                let file_paths: Vec<&str> = self.file_paths.iter().map(|path| path.as_str()).collect();
+                let files: Vec<Box<Path>> = collect_files(file_paths, self.recursive)
+                    .into_iter()
+                    .filter(|file| {
+                        !is_container_file(file, self.rows.as_ref()) || seen_container_files.insert(file.clone())
+                    })
+                    .collect();
+                let prefix = format!("watch{}", round);
+                batch_inputs(files, self.batch_size, &prefix, self.rows.as_ref())
+            })
+            .flatten(),
+        )
+    }
+}
+
+fn collect_files(file_paths: Vec<&str>, recursive: bool) -> Vec<Box<Path>> {
+    let mut files: Vec<Box<Path>> = Vec::new();
+    for file_path in file_paths {
+        let path = Path::new(file_path);
+        if !path.exists() {
+            warn!("unable to find file `{}`, skipping...", file_path);
+            continue;
+        }
+        if path.is_dir() {
+            if recursive {
+                for entry in WalkDir::new(path).follow_links(true).into_iter() {
+                    match entry {
+                        Ok(file) => {
+                            if file.path().is_dir() {
+                                continue;
+                            }
+                            files.push(Box::from(file.path()));
+                        }
+                        Err(error) => {
+                            warn!("unable to handle nested file `{}`, skipping...", error);
+                            continue;
+                        }
+                    }
+                }
+            } else {
+                warn!(
+                    "file `{}` is a directory, but recursion is not enabled; skipping...",
+                    file_path
+                );
+                continue;
+            }
+        } else {
+            files.push(Box::from(path));
+        }
+    }
+    files
 }
 
 fn run_validate(matches: &clap::ArgMatches) {
@@ -197,6 +864,7 @@ fn run_validate(matches: &clap::ArgMatches) {
         }
         None => info!("validation encountered no errors"),
     }
+    info!("threshold matches when {}", query.threshold.describe());
     match query.compile() {
         Ok(_value) => info!("query compiled successfully"),
         Err(error) => error!("unable to compile query: `{}`", error),
@@ -207,7 +875,39 @@ fn run_scan(matches: &clap::ArgMatches) {
     // Load queries
     let query_path = matches.value_of("query").unwrap();
     let file_paths: Vec<&str> = matches.values_of("inputs").unwrap().collect();
-    let queries = get_queries_from_file(String::from(query_path));
+    let mut queries = get_queries_from_file(String::from(query_path));
+    if let Some(path) = matches.value_of("thresholds") {
+        queries.thresholds = match get_thresholds_from_file(path) {
+            Ok(value) => value,
+            Err(error) => {
+                error!("unable to load named thresholds: `{}`", error);
+                return;
+            }
+        };
+    }
+    if let Some(path) = matches.value_of("library") {
+        queries.triggers = match get_triggers_from_file(path) {
+            Ok(value) => value,
+            Err(error) => {
+                error!("unable to load shared triggers: `{}`", error);
+                return;
+            }
+        };
+    }
+    if let Some(value) = matches.value_of("partition") {
+        queries = match get_partition(queries, value) {
+            Ok(partition) => partition,
+            Err(error) => {
+                error!("unable to partition queries: `{}`", error);
+                return;
+            }
+        };
+        if queries.queries.is_empty() {
+            warn!("partition `{}` contains no queries; nothing to scan", value);
+            return;
+        }
+        info!("using partition `{}` ({} queries)", value, queries.queries.len());
+    }
     let compiled_queries = match queries.compile() {
         Ok(value) => {
             debug!("queries compiled successfully");
@@ -226,45 +926,181 @@ fn run_scan(matches: &clap::ArgMatches) {
             8
         }
     };
+    let loader_threads: u8 = match matches.value_of("loader-threads").unwrap_or("2").parse() {
+        Ok(value) => value,
+        Err(error) => {
+            error!("invalid number of loader threads `{}` (`{}`), defaulting to 2...", matches.value_of("loader-threads").unwrap(), error);
+            2
+        }
+    };
+    let memory_budget: Option<usize> = match matches.value_of("memory-budget") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(megabytes) => Some(megabytes * 1024 * 1024),
+            Err(error) => {
+                error!("invalid memory budget `{}` (`{}`), ignoring...", value, error);
+                None
+            }
+        },
+        None => None,
+    };
+    let streaming: Option<StreamingOptions> = match matches.value_of("stream-above") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(megabytes) => Some(StreamingOptions {
+                threshold: megabytes * 1024 * 1024,
+                ..StreamingOptions::default()
+            }),
+            Err(error) => {
+                error!("invalid streaming threshold `{}` (`{}`), ignoring...", value, error);
+                None
+            }
+        },
+        None => None,
+    };
+    let rows: Option<RowOptions> = match matches.value_of("rows") {
+        Some(value) => match RowFormat::parse(value) {
+            Some(format) => Some(RowOptions {
+                format,
+                url_column: matches.value_of("url-column").map(String::from),
+            }),
+            None => {
+                error!("invalid row format `{}` (expected `csv`, `tsv`, or `lines`), ignoring...", value);
+                None
+            }
+        },
+        None => None,
+    };
+    let document_time_budget: Option<Duration> = match matches.value_of("time-budget") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(milliseconds) => Some(Duration::from_millis(milliseconds)),
+            Err(error) => {
+                error!("invalid time budget `{}` (`{}`), ignoring...", value, error);
+                None
+            }
+        },
+        None => None,
+    };
+    let output_cap = |argument: &str| -> Option<usize> {
+        let value = matches.value_of(argument)?;
+        match value.parse::<usize>() {
+            Ok(count) => Some(count),
+            Err(error) => {
+                error!("invalid output cap `{}` (`{}`), ignoring...", value, error);
+                None
+            }
+        }
+    };
+    let output_limits = OutputLimits {
+        per_document: output_cap("max-outputs-per-document"),
+        per_query: output_cap("max-outputs-per-query"),
+    };
+    let text_extraction = match matches.is_present("naive-text") {
+        true => TextExtraction::Naive,
+        false => TextExtraction::Dom,
+    };
     let hide_outputs = matches.is_present("hide-outputs");
     let recursive = matches.is_present("recursive");
     let should_output = matches.is_present("output");
     let output_dir = matches.value_of("output").unwrap_or("/tmp/"); // will not be used unless `should_output` is true
     let pretty_output = matches.is_present("pretty");
-    let mut files_to_scan: Vec<Box<Path>> = Vec::new();
-    for file_path in file_paths {
-        let path = Path::new(file_path);
-        if !path.exists() {
-            warn!("unable to find file `{}`, skipping...", file_path);
-            continue;
+    let compression = match matches.value_of("compress") {
+        Some(name) => match Compression::parse(name) {
+            Ok(value) => value,
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
+        },
+        None => Compression::None,
+    };
+    let canonical_path = matches.value_of("canonical");
+    let summary_path = matches.value_of("summary");
+    let report_path = matches.value_of("report");
+    // each sink is paired with a description of its destination
+    let mut sinks: Vec<(String, Box<dyn OutputSink + Send>)> = Vec::new();
+    if let Some(path) = matches.value_of("export") {
+        match open_export_sink(path, matches.value_of("format"), matches.value_of("columns")) {
+            Ok(sink) => sinks.push((format!("`{}`", path), sink)),
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
         }
-        if path.is_dir() {
-            if recursive {
-                for entry in WalkDir::new(path).follow_links(true).into_iter() {
-                    match entry {
-                        Ok(file) => {
-                            if file.path().is_dir() {
-                                continue;
-                            }
-                            files_to_scan.push(Box::from(file.path()));
-                        }
-                        Err(error) => {
-                            warn!("unable to handle nested file `{}`, skipping...", error);
-                            continue;
-                        }
-                    }
-                }
-            } else {
-                warn!(
-                    "file `{}` is a directory, but recursion is not enabled; skipping...",
-                    file_path
-                );
-                continue;
+    }
+    if let Some(url) = matches.value_of("webhook") {
+        match open_webhook_sink(url, matches.value_of("webhook-batch")) {
+            Ok(sink) => sinks.push((format!("webhook `{}`", url), sink)),
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
+        }
+    }
+    if let Some(brokers) = matches.value_of("kafka-brokers") {
+        let topic = matches.value_of("kafka-topic").unwrap_or("ieql-outputs");
+        match open_kafka_sink(brokers, topic) {
+            Ok(sink) => sinks.push((format!("Kafka topic `{}`", topic), sink)),
+            Err(error) => {
+                error!("{}", error);
+                return;
             }
-        } else {
-            files_to_scan.push(Box::from(path));
         }
     }
+    if let Some(bucket) = matches.value_of("s3-bucket") {
+        match open_s3_sink(bucket, matches) {
+            Ok(sink) => sinks.push((format!("S3 bucket `{}`", bucket), sink)),
+            Err(error) => {
+                error!("{}", error);
+                return;
+            }
+        }
+    }
+    let mut rng: StdRng = match matches.value_of("seed") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(seed) => StdRng::seed_from_u64(seed),
+            Err(error) => {
+                error!("invalid seed `{}` (`{}`), ignoring...", value, error);
+                StdRng::from_entropy()
+            }
+        },
+        None => StdRng::from_entropy(),
+    };
+    let watch_interval: Option<Duration> = match matches.value_of("watch") {
+        Some(value) => match value.parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(error) => {
+                error!("invalid watch interval `{}` (`{}`), ignoring...", value, error);
+                None
+            }
+        },
+        None => None,
+    };
+    if watch_interval.is_some() && !multithreaded {
+        error!("watching inputs requires `--multithreading`");
+        return;
+    }
+    let metrics_path = matches.value_of("metrics").map(String::from);
+    if metrics_path.is_some() && !multithreaded {
+        error!("writing scan metrics requires `--multithreading`");
+        return;
+    }
+    let cache: Option<Arc<ScanCache>> = match matches.value_of("cache") {
+        Some(path) => {
+            if !multithreaded {
+                error!("caching scanned documents requires `--multithreading`");
+                return;
+            }
+            match ScanCache::open(path) {
+                Ok(cache) => Some(Arc::new(cache)),
+                Err(issue) => {
+                    error!("{}", issue);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let file_paths_owned: Vec<String> = file_paths.iter().map(|path| String::from(*path)).collect();
+    let files_to_scan = collect_files(file_paths, recursive);
     info!(
         "scanning {} files with {} queries...",
         files_to_scan.len(),
@@ -274,70 +1110,104 @@ fn run_scan(matches: &clap::ArgMatches) {
     match multithreaded {
         true => {
             let batch_size = 64;
-            let mut async_interface: AsyncScanInterface = compiled_queries.scan_concurrently(threads);
-            info!("will perform scan using {} threads", threads);
-            let mut current_documents: Vec<DocumentReference> = Vec::new();
-            for file_path_box in files_to_scan {
-                let file_path = Box::leak(file_path_box);
-                let document_reference = DocumentReference::Unpopulated(match file_path.to_str() {
-                    Some(value) => String::from(value),
-                    None => {
-                        error!(
-                            "unable to handle file `{}`, skipping...",
-                            file_path.to_string_lossy()
-                        );
-                        continue;
-                    }
-                }); // TODO: will the lossyness ever be an issue?
-                current_documents.push(document_reference);
-                let num_documents = current_documents.len();
-                if num_documents >= batch_size {
-                    // time to push a batch
-                    let mut drain: Vec<DocumentReference> = Vec::new();
-                    drain.extend(current_documents.drain(0..batch_size));
-                    let len = drain.len();
-                    let batch = DocumentReferenceBatch::from(drain);
-                    match async_interface.process(batch) {
-                        Ok(_) => {
-                            debug!("sending new batch of {} documents", len);
-                        }
-                        Err(_) => {
-                            error!("unable to transmit batch to scan engine; shutting down...");
-                            break;
+            let options = ScanOptions {
+                threads,
+                loader_threads,
+                memory_budget,
+                document_time_budget,
+                output_limits,
+                output_capacity: None, // outputs are delivered to sinks
+                text_extraction,
+                streaming,
+                cache,
+            };
+            if let Some(issues) = options.validate() {
+                let mut has_error = false;
+                for issue in issues {
+                    match issue {
+                        Issue::Error(message) => {
+                            error!("invalid scan configuration: {}", message);
+                            has_error = true;
                         }
-                    };
+                        Issue::Warning(message) => warn!("{}", message),
+                    }
+                }
+                if has_error {
+                    return;
                 }
             }
-            if current_documents.len() != 0 {
-                // send all other documents
-                let batch = DocumentReferenceBatch::from(current_documents);
-                match async_interface.process(batch) {
-                    Ok(_) => {
-                        debug!("sending final batch");
-                    }
-                    Err(_) => {
-                        error!("unable to transmit batch to scan engine; shutting down...");
-                    }
-                };
+            // outputs are handled from the scanner threads, so that slow
+            // sinks (such as webhooks) slow the scan instead of accumulating
+            // outputs in memory
+            let outputs = Arc::new(Mutex::new(OutputBatch::new()));
+            let mut engine_sinks: Vec<Box<dyn OutputSink + Send>> = vec![Box::new(CliSink {
+                hide_outputs,
+                output_dir: match should_output {
+                    true => Some(String::from(output_dir)),
+                    false => None,
+                },
+                pretty: pretty_output,
+                compression,
+                rng,
+                outputs: outputs.clone(),
+            })];
+            for (destination, sink) in sinks {
+                info!("exporting outputs to {}", destination);
+                engine_sinks.push(sink);
             }
-            let mut output_batch = OutputBatch::new();
-            (&mut async_interface).shutdown();
-            loop {
-                match async_interface.lock_for_outputs() {
-                    Ok(value) => {
-                        if !hide_outputs {
-                            for output in &value.outputs {
-                                info!("  - {}", output);
-                            }
-                        }
-                        if should_output {
-                            write_output_batch_to_file(output_dir, &value, pretty_output);
-                        }
-                        output_batch.merge_with(value);
+            let mut async_interface: AsyncScanInterface =
+                compiled_queries.scan_concurrently_into(options, engine_sinks);
+            info!(
+                "will perform scan using {} threads ({} for loading)",
+                threads, loader_threads
+            );
+            // metrics are written periodically until the scan completes
+            let (stop_metrics, metrics_stopped) = mpsc::channel::<()>();
+            let metrics_writer = metrics_path.clone().map(|path| {
+                let metrics = async_interface.metrics();
+                thread::spawn(move || {
+                    while let Err(RecvTimeoutError::Timeout) = metrics_stopped.recv_timeout(METRICS_INTERVAL) {
+                        write_metrics(&path, &metrics.snapshot());
                     }
-                    Err(_) => break,
+                })
+            });
+            let archive = batch_inputs(files_to_scan, batch_size, "archive", rows.as_ref());
+            let live = DirectoryWatch {
+                file_paths: file_paths_owned,
+                recursive,
+                interval: watch_interval.unwrap_or_default(),
+                batch_size,
+                rows: rows.clone(),
+                enabled: watch_interval.is_some(),
+            };
+            if watch_interval.is_some() {
+                info!("will watch inputs for new files once existing files are scanned");
+            }
+            let mut backfill = Backfill::new();
+            // every output is delivered to the sinks, so none are received here
+            if let Err(error) = backfill.run(&async_interface, archive, live, |_| ()) {
+                error!("{}; shutting down...", error);
+            }
+            async_interface.shutdown();
+            // wait for the engine to finish (and close the sinks)
+            while async_interface.lock_for_outputs().is_ok() {}
+            let output_batch = std::mem::take(&mut *outputs.lock().unwrap());
+            drop(stop_metrics);
+            if let Some(writer) = metrics_writer {
+                let _ = writer.join();
+            }
+            if let Some(path) = &metrics_path {
+                if write_metrics(path, &async_interface.metrics().snapshot()) {
+                    info!("wrote scan metrics to `{}`", path);
                 }
             }
+            for issue in async_interface.issues() {
+                warn!("{}", issue);
+            }
+            let unchanged = async_interface.metrics().snapshot().documents_unchanged;
+            if unchanged > 0 {
+                info!("skipped {} document(s) that were unchanged since they were last scanned", unchanged);
+            }
             info!("{} currently processing", async_interface.batches_pending_processing());
             info!(
                 "finished scan and received {} output(s)",
@@ -346,12 +1216,30 @@ fn run_scan(matches: &clap::ArgMatches) {
             if should_output {
                 info!("wrote outputs to `{}`", output_dir);
             }
+            if let Some(path) = canonical_path {
+                write_canonical_outputs(path, &output_batch);
+            }
+            if let Some(path) = summary_path {
+                write_output_summary(path, &output_batch);
+            }
+            if let Some(path) = report_path {
+                write_html_report(path, &output_batch);
+            }
         }
         false => {
             info!("performing single-threaded scan...");
             warn!("single-threaded scans load all files into memory before performing the scan");
             warn!("for a more performant alternative, run with `--multithreading`");
+            let (container_files, files_to_scan): (Vec<Box<Path>>, Vec<Box<Path>>) =
+                files_to_scan.into_iter().partition(|file| is_container_file(file, rows.as_ref()));
             let mut documents: Vec<Document> = Vec::new();
+            for batch in batch_container_files(container_files, usize::MAX, "container", rows.clone()) {
+                for document_reference in batch.documents {
+                    if let DocumentReference::Populated(document) = document_reference {
+                        documents.push(document);
+                    }
+                }
+            }
             for file_path_box in files_to_scan {
                 let file_path = Box::leak(file_path_box);
                 let file_path_str = match file_path.to_str() {
@@ -375,15 +1263,27 @@ fn run_scan(matches: &clap::ArgMatches) {
                     }
                 }
             }
-            let document_batch = match DocumentBatch::from(documents).compile() {
+            let mut document_batch = match DocumentBatch::from(documents).compile_with(text_extraction) {
                 Ok(value) => value,
                 Err(error) => {
                     error!("unable to compile document batch: `{}`", error);
                     return;
                 }
             };
+            for document in &mut document_batch.documents {
+                for issue in document.issues.drain(..) {
+                    warn!("{}", issue);
+                }
+                document.provenance = Some(Provenance {
+                    batch: None,
+                    reference: document.url.clone(), // the path of the file
+                });
+            }
             debug!("performing scan...");
-            let output_batch = compiled_queries.scan_batch(&document_batch);
+            let (output_batch, issues) = compiled_queries.scan_batch_limited(&document_batch, output_limits);
+            for issue in issues {
+                warn!("{}", issue);
+            }
             info!("received {} output(s)", output_batch.outputs.len());
             if !hide_outputs {
                 for output in &output_batch.outputs {
@@ -391,11 +1291,367 @@ fn run_scan(matches: &clap::ArgMatches) {
                 }
             }
             if should_output {
-                write_output_batch_to_file(output_dir, &output_batch, pretty_output);
+                write_output_batch_to_file(output_dir, &output_batch, pretty_output, compression, &mut rng);
                 info!("wrote outputs to `{}`", output_dir);
             }
+            for (destination, sink) in sinks.iter_mut() {
+                match sink.send(&output_batch).and_then(|_| sink.close()) {
+                    Ok(_) => info!("exported outputs to {}", destination),
+                    Err(error) => error!("{}", error),
+                }
+            }
+            if let Some(path) = canonical_path {
+                write_canonical_outputs(path, &output_batch);
+            }
+            if let Some(path) = summary_path {
+                write_output_summary(path, &output_batch);
+            }
+            if let Some(path) = report_path {
+                write_html_report(path, &output_batch);
+            }
+        }
+    }
+}
+
+fn run_merge(matches: &clap::ArgMatches) {
+    let destination = matches.value_of("destination").unwrap();
+    let sources: Vec<&str> = matches.values_of("stores").unwrap().collect();
+    let store = match OutputStore::open(destination) {
+        Ok(value) => value,
+        Err(error) => {
+            error!("{}", error);
+            return;
+        }
+    };
+    if store.discarded() > 0 {
+        warn!(
+            "discarded {} byte(s) of a partially written batch at the end of output store `{}`",
+            store.discarded(),
+            destination
+        );
+    }
+    let deduplication = if matches.is_present("deduplicate") {
+        Some(DeduplicationPolicy {
+            key: DeduplicationKey::Url,
+            merge_excerpts: true,
+        })
+    } else {
+        None
+    };
+    let merged = match store.merge_from(&sources) {
+        Ok(value) => value,
+        Err(error) => {
+            error!("unable to merge output stores: {}", error);
+            return;
+        }
+    };
+    match store.compact(deduplication) {
+        Ok(remaining) => info!(
+            "merged {} output(s) from {} store(s) into `{}`, which now holds {} output(s)",
+            merged,
+            sources.len(),
+            destination,
+            remaining
+        ),
+        Err(error) => error!("unable to compact output store: {}", error),
+    }
+}
+
+fn run_simulate(matches: &clap::ArgMatches) {
+    let mut compiled_groups: Vec<CompiledQueryGroup> = Vec::new();
+    for argument in &["current", "proposed"] {
+        let mut queries = get_queries_from_file(String::from(matches.value_of(argument).unwrap()));
+        if let Some(path) = matches.value_of("thresholds") {
+            queries.thresholds = match get_thresholds_from_file(path) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("unable to load named thresholds: `{}`", error);
+                    return;
+                }
+            };
+        }
+        if let Some(path) = matches.value_of("library") {
+            queries.triggers = match get_triggers_from_file(path) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("unable to load shared triggers: `{}`", error);
+                    return;
+                }
+            };
+        }
+        match queries.compile() {
+            Ok(value) => compiled_groups.push(value),
+            Err(error) => {
+                error!("unable to compile {} queries: `{}`", argument, error);
+                return;
+            }
+        }
+    }
+    let file_paths: Vec<&str> = matches.values_of("inputs").unwrap().collect();
+    let mut files = collect_files(file_paths, matches.is_present("recursive"));
+    if matches.is_present("from-outputs") {
+        files = get_documents_from_outputs(files);
+    }
+    info!("simulating impact on {} files...", files.len());
+
+    let mut documents: Vec<Document> = Vec::new();
+    for file in files {
+        let file_path = file.to_string_lossy().into_owned();
+        match load_document(&file_path) {
+            Ok(document) => documents.push(document),
+            Err(error) => warn!("unable to process `{}` (`{}`), skipping...", file_path, error),
+        }
+    }
+    let document_batch = match DocumentBatch::from(documents).compile() {
+        Ok(value) => value,
+        Err(error) => {
+            error!("unable to compile document batch: `{}`", error);
+            return;
+        }
+    };
+
+    let report = simulate_impact(&compiled_groups[0], &compiled_groups[1], &document_batch);
+    let describe = |identity: &MatchIdentity| {
+        format!(
+            "`{}` on `{}`",
+            identity.query_id.as_deref().unwrap_or("unknown_query"),
+            identity.url.as_deref().unwrap_or("unknown_document")
+        )
+    };
+    for identity in &report.new_matches {
+        info!("  + {}", describe(identity));
+    }
+    for identity in &report.lost_matches {
+        info!("  - {}", describe(identity));
+    }
+    info!(
+        "{} new match(es), {} lost match(es), {} retained match(es)",
+        report.new_matches.len(),
+        report.lost_matches.len(),
+        report.retained_matches.len()
+    );
+}
+
+/// Reads every output of the output files at the given path (or of the
+/// directory at the given path, recursively), skipping signatures.
+fn read_output_files(path: &str) -> OutputBatch {
+    let mut batch = OutputBatch::new();
+    for file in collect_files(vec![path], true) {
+        if file.extension().is_some_and(|extension| extension == "sig") {
+            continue;
+        }
+        match fs::read(&file)
+            .map_err(|error| Issue::Error(error.to_string()))
+            .and_then(|data| read_outputs(&data))
+        {
+            Ok(outputs) => batch.outputs.extend(outputs),
+            Err(error) => warn!(
+                "unable to load output `{}` (`{}`), skipping...",
+                file.to_string_lossy(),
+                error
+            ),
+        }
+    }
+    batch
+}
+
+fn run_diff(matches: &clap::ArgMatches) {
+    let before = read_output_files(matches.value_of("before").unwrap());
+    let after = read_output_files(matches.value_of("after").unwrap());
+    let diff = before.diff(&after);
+    let describe = |key: &OutputKey| {
+        format!(
+            "`{}` on `{}`",
+            key.query_id.as_deref().unwrap_or("unknown_query"),
+            key.document.as_deref().unwrap_or("unknown_document")
+        )
+    };
+    for output in &diff.added {
+        info!("  + {}", describe(&output.diff_key()));
+    }
+    for output in &diff.removed {
+        info!("  - {}", describe(&output.diff_key()));
+    }
+    for change in &diff.changed {
+        info!("  ~ {} ({})", describe(&change.key), change.differences.join(", "));
+    }
+    info!(
+        "{} added, {} removed, {} changed, and {} unchanged output(s)",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged
+    );
+    if let Some(path) = matches.value_of("report") {
+        let result = ron::ser::to_string_pretty(&diff, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())
+            .and_then(|serialized| fs::write(path, serialized).map_err(|error| error.to_string()));
+        match result {
+            Ok(_) => info!("wrote difference to `{}`", path),
+            Err(error) => error!("unable to write difference to `{}` (`{}`)", path, error),
+        }
+    }
+}
+
+/// The path of the detached signature of the output file at the given path.
+#[cfg(feature = "signing")]
+fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+/// Reads the outputs of the output file at the given path as a batch, as
+/// they are signed (see `OutputSigner::sign_batch()`).
+#[cfg(feature = "signing")]
+fn read_signed_outputs(path: &str) -> Result<OutputBatch, Issue> {
+    let data = fs::read(path).map_err(|error| Issue::Error(format!("unable to read `{}` (`{}`)", path, error)))?;
+    Ok(OutputBatch::from(read_outputs(&data)?))
+}
+
+#[cfg(feature = "signing")]
+fn run_sign(matches: &clap::ArgMatches) {
+    let key_path = matches.value_of("key").unwrap();
+    let signer = match fs::read_to_string(key_path)
+        .map_err(|error| Issue::Error(format!("unable to read signing key `{}` (`{}`)", key_path, error)))
+        .and_then(|key| OutputSigner::from_hex(&key))
+    {
+        Ok(value) => value,
+        Err(error) => {
+            error!("{}", error);
+            return;
+        }
+    };
+    info!("signing outputs with public key `{}`", signer.public_key());
+    let mut signed = 0;
+    for path in matches.values_of("outputs").unwrap() {
+        let signature = match read_signed_outputs(path).and_then(|batch| signer.sign_batch(&batch)) {
+            Ok(value) => value,
+            Err(error) => {
+                error!("unable to sign `{}`: {}, skipping...", path, error);
+                continue;
+            }
+        };
+        let serialized = match ron::ser::to_string_pretty(&signature, ron::ser::PrettyConfig::default()) {
+            Ok(value) => value,
+            Err(error) => {
+                error!("unable to serialize signature of `{}` (`{}`), skipping...", path, error);
+                continue;
+            }
+        };
+        match fs::write(signature_path(path), serialized) {
+            Ok(_) => signed += 1,
+            Err(error) => error!("unable to write signature of `{}` (`{}`)", path, error),
         }
     }
+    info!("signed {} output file(s)", signed);
+}
+
+#[cfg(feature = "signing")]
+fn run_verify(matches: &clap::ArgMatches) {
+    let trusted = match matches.value_of("public-key").map(OutputVerifier::from_hex) {
+        Some(Ok(value)) => Some(value),
+        Some(Err(error)) => {
+            error!("{}", error);
+            return;
+        }
+        None => {
+            warn!("no `--public-key` given; verifying integrity only, not who signed the outputs");
+            None
+        }
+    };
+    let (mut verified, mut failed) = (0, 0);
+    for path in matches.values_of("outputs").unwrap() {
+        let result = fs::read_to_string(signature_path(path))
+            .map_err(|error| Issue::Error(format!("unable to read signature (`{}`)", error)))
+            .and_then(|contents| {
+                ron::de::from_str::<OutputSignature>(&contents)
+                    .map_err(|error| Issue::Error(format!("unable to deserialize signature (`{}`)", error)))
+            })
+            .and_then(|signature| {
+                let batch = read_signed_outputs(path)?;
+                match &trusted {
+                    Some(verifier) => verifier.verify_batch(&batch, &signature),
+                    None => OutputVerifier::from_signature(&signature)?.verify_batch(&batch, &signature),
+                }
+            });
+        match result {
+            Ok(_) => verified += 1,
+            Err(error) => {
+                error!("`{}` failed verification: {}", path, error);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => info!("verified {} output file(s)", verified),
+        _ => error!("verified {} output file(s); {} failed verification", verified, failed),
+    }
+}
+
+#[cfg(not(feature = "signing"))]
+fn run_sign(_matches: &clap::ArgMatches) {
+    error!("signing outputs requires the `signing` feature");
+}
+
+#[cfg(not(feature = "signing"))]
+fn run_verify(_matches: &clap::ArgMatches) {
+    error!("verifying outputs requires the `signing` feature");
+}
+
+/// Reads the outputs of an output file: either a single output, or a
+/// batch of outputs (see `--compress`), which may be compressed.
+fn read_outputs(data: &[u8]) -> Result<Vec<Output>, Issue> {
+    let decompressed = decompress(data)?;
+    let contents = String::from_utf8_lossy(&decompressed);
+    match ron::de::from_str::<Output>(&contents) {
+        Ok(output) => Ok(vec![output]),
+        Err(_) => match ron::de::from_str::<OutputBatch>(&contents) {
+            Ok(batch) => Ok(batch.outputs),
+            Err(error) => Err(Issue::Error(format!("unable to deserialize outputs (`{}`)", error))),
+        },
+    }
+}
+
+fn get_documents_from_outputs(output_files: Vec<Box<Path>>) -> Vec<Box<Path>> {
+    let mut documents: Vec<Box<Path>> = Vec::new();
+    for output_file in output_files {
+        let outputs: Vec<Output> = match fs::read(&output_file)
+            .map_err(|error| error.to_string())
+            .and_then(|data| read_outputs(&data).map_err(|error| error.to_string()))
+        {
+            Ok(value) => value,
+            Err(error) => {
+                warn!(
+                    "unable to load output `{}` (`{}`), skipping...",
+                    output_file.to_string_lossy(),
+                    error
+                );
+                continue;
+            }
+        };
+        for item in outputs.into_iter().flat_map(|output| output.items) {
+            if let OutputItem::Url(Some(url)) = item {
+                let path: Box<Path> = Box::from(Path::new(&url));
+                if !documents.contains(&path) {
+                    documents.push(path);
+                }
+            }
+        }
+    }
+    documents
+}
+
+/// Loads a map of named thresholds (RON, JSON, or YAML) from the given file.
+fn get_thresholds_from_file(path: &str) -> Result<BTreeMap<String, Threshold>, Issue> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| Issue::Error(format!("unable to read `{}`: `{}`", path, error)))?;
+    format::deserialize(&contents)
+}
+
+/// Loads a list of shared triggers (RON, JSON, or YAML) from the given file.
+fn get_triggers_from_file(path: &str) -> Result<Vec<Trigger>, Issue> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| Issue::Error(format!("unable to read `{}`: `{}`", path, error)))?;
+    format::deserialize(&contents)
 }
 
 fn get_query_from_file(path: String) -> Result<Query, Issue> {
@@ -428,7 +1684,7 @@ fn get_query_from_file(path: String) -> Result<Query, Issue> {
             )));
         }
     };
-    let query: Query = match ron::de::from_str(query_str) {
+    let query: Query = match query_str.parse() {
         Ok(value) => value,
         Err(error) => {
             return Err(Issue::Error(format!(
@@ -437,5 +1693,5 @@ fn get_query_from_file(path: String) -> Result<Query, Issue> {
             )));
         }
     };
-    return Ok(query);
+    Ok(query)
 }
@@ -0,0 +1,50 @@
+//! This file provides a stable checksum for deriving deterministic
+//! identifiers (such as those of `Output`s).
+
+/// `Checksum` computes a 64-bit FNV-1a hash. Unlike the hashers in the
+/// standard library, its output is stable across platforms, processes,
+/// and compiler versions, so it is suitable for identifiers that are
+/// compared between runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checksum(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Checksum {
+    /// Create a new, empty checksum.
+    pub fn new() -> Checksum {
+        Checksum(FNV_OFFSET_BASIS)
+    }
+
+    /// Add the given bytes to the checksum.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// Add the given optional string to the checksum, such that `None`,
+    /// `Some("")`, and adjacent strings are all distinguishable.
+    pub fn update_optional(&mut self, value: Option<&str>) {
+        match value {
+            Some(text) => {
+                self.update(&(text.len() as u64).to_le_bytes());
+                self.update(text.as_bytes());
+            }
+            None => self.update(&[0xff; 8]),
+        }
+    }
+
+    /// The current value of the checksum.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Checksum {
+        Checksum::new()
+    }
+}
@@ -0,0 +1,65 @@
+//! This file provides utilities for reading IEQL components (such as
+//! `Query`s) from their serialized forms.
+
+use common::validation::Issue;
+use ron;
+use serde::de::DeserializeOwned;
+use serde_json;
+use serde_yaml;
+
+/// Represents the serialization formats that IEQL components can be
+/// read from. RON is the canonical format of IEQL; JSON and YAML are
+/// supported for convenience when embedding queries in configuration.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SerializationFormat {
+    Ron,
+    Json,
+    Yaml,
+}
+
+impl SerializationFormat {
+    /// Detects the serialization format of the given string by looking
+    /// at its first significant character.
+    ///
+    /// * JSON documents begin with `{` or `[`.
+    /// * RON documents begin with `(` or with a struct name followed by
+    ///   `(`, as in `Query (`.
+    /// * Everything else is assumed to be YAML.
+    pub fn detect(serialized: &str) -> SerializationFormat {
+        let trimmed = serialized.trim_start();
+        if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            return SerializationFormat::Json;
+        }
+        let name_length = trimmed
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(trimmed.len());
+        if trimmed[name_length..].trim_start().starts_with('(') {
+            return SerializationFormat::Ron;
+        }
+        SerializationFormat::Yaml
+    }
+}
+
+/// Deserializes the given string, automatically detecting whether it
+/// is RON, JSON, or YAML. (See `SerializationFormat::detect()`.)
+///
+/// RON maps and lists also begin with `{` or `[`, so a string that is
+/// detected as JSON but fails to deserialize as JSON is tried as RON.
+pub fn deserialize<T: DeserializeOwned>(serialized: &str) -> Result<T, Issue> {
+    let format = SerializationFormat::detect(serialized);
+    let result = match format {
+        SerializationFormat::Ron => ron::de::from_str(serialized).map_err(|error| error.to_string()),
+        SerializationFormat::Json => serde_json::from_str(serialized).or_else(|error| {
+            ron::de::from_str(serialized).map_err(|_| error.to_string())
+        }),
+        SerializationFormat::Yaml => {
+            serde_yaml::from_str(serialized).map_err(|error| error.to_string())
+        }
+    };
+    result.map_err(|error| {
+        Issue::Error(format!(
+            "unable to deserialize as {:?}: `{}`",
+            format, error
+        ))
+    })
+}
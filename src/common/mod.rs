@@ -4,3 +4,6 @@ pub mod pattern;
 pub mod validation;
 pub mod retrieve;
 pub mod compilation;
+pub mod format;
+pub mod checksum;
+pub mod timestamp;
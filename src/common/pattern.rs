@@ -2,6 +2,17 @@
 use common::validation::{Validatable, Issue};
 use regex;
 use common::compilation::CompilableTo;
+use common::format;
+use query::scope::ScopeContent;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
+use scraper::{ElementRef, Html, Selector};
+use std::borrow::Cow;
+use std::fs;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
 /// The `Pattern` struct represents an uncompiled pattern. Patterns
 /// are essentially RegEx searches; given an expression, they _theoretically_
@@ -17,8 +28,70 @@ pub struct Pattern {
     
     /// Represents the type of pattern; i.e. RegEx or Raw.
     pub kind: PatternKind,
+
+    /// The optional Unicode normalization to apply to both the pattern
+    /// and the text being searched before matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<Normalization>,
+}
+
+/// `Normalization` describes how text is normalized before matching, so
+/// that (for example) composed and decomposed forms of the same character
+/// match one another.
+/// 
+/// Note that when a pattern is normalized, the excerpts of its
+/// `PatternMatch`es are taken from the _normalized_ text.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Normalization {
+    /// The Unicode normalization form to apply.
+    pub form: NormalizationForm,
+    /// Whether matching should ignore case.
+    #[serde(default)]
+    pub case_fold: bool,
+    /// Whether diacritics (combining marks, such as the accent of `é`)
+    /// should be removed; for example, `café` would match `cafe`.
+    #[serde(default)]
+    pub strip_diacritics: bool,
 }
 
+/// `NormalizationForm` denotes a Unicode normalization form.
+/// 
+/// * `Nfc` composes characters canonically (`e` + `◌́` becomes `é`)
+/// * `Nfkc` also replaces compatibility characters with their
+///   equivalents (`ﬁ` becomes `fi`, and `①` becomes `1`)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum NormalizationForm {
+    /// Canonical composition
+    Nfc,
+    /// Compatibility composition
+    Nfkc,
+}
+
+impl Normalization {
+    /// Normalizes the given text according to the form and, if enabled,
+    /// strips its diacritics. Case folding is performed by the RegEx
+    /// engine instead, as lowercasing a RegEx expression would change
+    /// its meaning (`\W` is not `\w`); dictionaries, whose terms are
+    /// literal, are folded separately. (See `fold_case()`.)
+    pub fn apply(&self, text: &str) -> String {
+        let text: Cow<str> = if self.strip_diacritics {
+            Cow::Owned(text.nfd().filter(|c| !is_combining_mark(*c)).collect())
+        } else {
+            Cow::Borrowed(text)
+        };
+        match self.form {
+            NormalizationForm::Nfc => text.nfc().collect(),
+            NormalizationForm::Nfkc => text.nfkc().collect(),
+        }
+    }
+}
+
+/// The maximum size, in bytes, of a compiled pattern before validation
+/// warns that it may stall scans. The RegEx engine always matches in linear
+/// time, but very large patterns are still slow to match against very large
+/// documents.
+pub const PATTERN_SIZE_BUDGET: usize = 1 << 20;
+
 /// `PatternMatch`es are what `CompiledPattern`s output when they encounter
 /// text that matches. A `PatternMatch` contains an excerpt of the match.
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -29,74 +102,528 @@ pub struct PatternMatch {
     /// A tuple of the index of the relevant portion of the `exerpt` that
     /// triggered the match in the form of (start-inclusive, end-exclusive).
     pub relevant: (usize, usize),
+    /// The text of each capture group that participated in the match,
+    /// keyed by the group's name (for named groups, such as `(?P<id>\d+)`)
+    /// or by its number (for unnamed groups, starting at `1`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub captures: BTreeMap<String, String>,
+    /// The byte offsets of the match within the content that was searched
+    /// (start-inclusive, end-exclusive), when they are known. They are not
+    /// known for normalized patterns, whose matches are found in the
+    /// normalized text, nor for `Selector` patterns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offsets: Option<(usize, usize)>,
+    /// The content of the document that was searched, such as `Text`,
+    /// when the match was produced by a scan. (The `offsets` refer to this
+    /// content, after the transforms of the query's scope and trigger, if
+    /// any, were applied.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<ScopeContent>,
+    /// The line and column at which the match starts within the content
+    /// that was searched, both counting from `1` (columns count
+    /// characters), when its `offsets` are known and the content is text.
+    /// (See `locate_matches()`.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<(usize, usize)>,
+}
+
+/// The number of bytes of context that excerpts include on each side of
+/// the match, at most.
+pub const EXCERPT_CONTEXT: usize = 150;
+
+/// The characters that end a sentence, when followed by whitespace.
+const SENTENCE_TERMINATORS: [char; 3] = ['.', '!', '?'];
+
+/// The characters that may follow a sentence terminator within the same
+/// sentence, such as the closing quote of `"Stop!"`.
+const SENTENCE_CLOSERS: [char; 7] = ['"', '\'', ')', ']', '\u{201d}', '\u{2019}', '\u{bb}'];
+
+impl PatternMatch {
+    /// Narrows the excerpt to the sentence (or sentences) that contain
+    /// the relevant portion, so that it reads well in alerts. Sentences
+    /// end at `.`, `!`, or `?` followed by whitespace, and at line
+    /// breaks. When the sentence extends beyond the excerpt's context on
+    /// either side, the excerpt is cut at a word boundary on that side
+    /// instead, so that no word is chopped.
+    pub fn to_sentences(&self) -> PatternMatch {
+        let excerpt = self.excerpt.as_str();
+        let (relevant_start, relevant_end) = self.relevant;
+        if relevant_start > relevant_end
+            || !excerpt.is_char_boundary(relevant_start)
+            || !excerpt.is_char_boundary(relevant_end)
+        {
+            return self.clone();
+        }
+        let before = &excerpt[..relevant_start];
+        let after = &excerpt[relevant_end..];
+
+        let start = match before
+            .char_indices()
+            .filter_map(|(index, _)| sentence_start(excerpt, index))
+            .next_back()
+        {
+            Some(start) => start,
+            None if relevant_start < EXCERPT_CONTEXT => 0, // the start of the content
+            None => before.find(char::is_whitespace).unwrap_or(0), // trimmed below
+        };
+        let end = match after.char_indices().find_map(|(index, _)| sentence_end(after, index)) {
+            Some(end) => end,
+            None if after.len() < EXCERPT_CONTEXT => after.len(), // the end of the content
+            None => after.rfind(char::is_whitespace).unwrap_or(after.len()),
+        };
+
+        let leading = &before[start.min(relevant_start)..];
+        let start = relevant_start - leading.trim_start().len();
+        let end = relevant_end + after[..end].trim_end().len();
+        PatternMatch {
+            excerpt: excerpt[start..end].to_string(),
+            relevant: (relevant_start - start, relevant_end - start),
+            ..self.clone()
+        }
+    }
+}
+
+/// If a sentence ends at the character at the given index of the text,
+/// the index at which it ends (exclusive, including closing quotes).
+fn sentence_end(text: &str, index: usize) -> Option<usize> {
+    let character = text[index..].chars().next()?;
+    if character == '\n' {
+        return Some(index);
+    }
+    if !SENTENCE_TERMINATORS.contains(&character) {
+        return None;
+    }
+    let rest = &text[index + character.len_utf8()..];
+    let closed = rest.trim_start_matches(&SENTENCE_CLOSERS[..]);
+    match closed.chars().next() {
+        None => Some(text.len() - closed.len()),
+        Some(next) if next.is_whitespace() => Some(text.len() - closed.len()),
+        Some(_) => None,
+    }
+}
+
+/// If a sentence ends at the character at the given index of the text,
+/// the index at which the next sentence starts (after any whitespace).
+fn sentence_start(text: &str, index: usize) -> Option<usize> {
+    let end = sentence_end(text, index)?;
+    let end = if end == index { index + 1 } else { end }; // a line break
+    let rest = &text[end..];
+    Some(text.len() - rest.trim_start().len())
 }
 
 /// A `CompiledPattern` is a `Pattern` whose RegEx has been compiled or,
 /// in the case that the `PatternType` is raw, whose expression has been
-/// RegEx escaped and _then_ compiled (as RegEx).
+/// RegEx escaped and _then_ compiled (as RegEx). `Dictionary` patterns
+/// are compiled into an automaton instead, and `Bytes` patterns into a
+/// byte-oriented RegEx.
 #[derive(Clone)]
 pub struct CompiledPattern {
-    /// The compiled RegEx of the pattern.
-    regex: regex::Regex
+    /// The compiled matcher of the pattern.
+    matcher: Matcher,
+    /// The normalization to apply to text before matching, if any.
+    normalization: Option<Normalization>,
+}
+
+/// A `Matcher` is the compiled form of a pattern's content.
+#[derive(Clone)]
+enum Matcher {
+    /// A compiled RegEx
+    RegEx(regex::Regex),
+    /// An automaton of literal dictionary terms, along with the terms
+    /// (which are loaded only once, when the pattern is compiled)
+    Dictionary(AhoCorasick, Arc<[String]>),
+    /// A compiled byte-oriented RegEx
+    Bytes(regex::bytes::Regex),
+    /// A compiled CSS selector, along with the RegEx (if any) that the
+    /// text of a selected element must match
+    Selector(Selector, Option<regex::Regex>),
 }
 
-/// `PatternKind` denotes the type of a pattern. Its two variants, `RegEx`
-/// and `Raw`, denote the type of compilation and matching to perform.
+/// The prefix of `Dictionary` pattern content that loads the terms from
+/// a file instead; for example, `file:/etc/ieql/brands.txt`.
+pub const DICTIONARY_FILE_PREFIX: &str = "file:";
+
+/// `PatternKind` denotes the type of a pattern. Its variants denote the
+/// type of compilation and matching to perform.
 /// 
 /// * `RegEx` patterns are compiled as RegEx
 /// * `Raw` patterns are RegEx escaped and then compiled as RegEx
+/// * `Word` patterns are RegEx escaped, wrapped in word boundaries, and
+///   then compiled as RegEx
+/// * `Glob` patterns are translated into anchored RegEx and then compiled
+///   as RegEx
+/// * `Dictionary` patterns are lists of plaintext terms, which are compiled
+///   into a single automaton
+/// * `Bytes` patterns are hexadecimal byte sequences, which are translated
+///   into byte-oriented RegEx and then compiled
+/// * `Selector` patterns are CSS selectors, which are matched against the
+///   elements of the text parsed as HTML
 #[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum PatternKind {
     /// A RegEx pattern
     RegEx,
     /// A plaintext pattern
-    Raw
+    Raw,
+    /// A plaintext pattern that only matches whole words; for example,
+    /// `art` matches "modern art" but not "start"
+    Word,
+    /// A glob pattern that must match the _entire_ text (or an entire line
+    /// of it), typically used for URLs in `Scope`s and `LinkTargets`.
+    /// `*` matches any sequence of characters except `/`, `**` matches
+    /// any sequence of characters, and `?` matches any single character
+    /// except `/`. For example, `https://*.example.com/news/**` matches
+    /// every news article on every subdomain of `example.com`.
+    Glob,
+    /// A list of plaintext terms, any of which may match. Terms are
+    /// separated by newlines; alternatively, content of the form
+    /// `file:<path>` loads the terms (one per line) from the given file
+    /// when the pattern is compiled. Blank lines are ignored.
+    Dictionary,
+    /// A sequence of bytes written in hexadecimal, where whitespace is
+    /// ignored and `??` matches any single byte; for example, `25 50 44 46`
+    /// matches the signature of a PDF file. Typically used with the `Bytes`
+    /// scope content, as the bytes need not be valid text.
+    Bytes,
+    /// A CSS selector, such as `meta[property="og:site_name"][content~="Example"]`,
+    /// that matches when any element of the text (parsed as HTML) is
+    /// selected. If the content has a second line, it is a RegEx that the
+    /// text of a selected element must also match; for example, `h1` followed
+    /// by `(?i)recall` matches headings that mention a recall. Typically used
+    /// with the `Raw` scope content, as the other contents are not HTML.
+    Selector,
 }
 
 impl Pattern {
+    /// Estimates the relative cost of matching the pattern, for balancing
+    /// work (see `QueryGroup::partition()`). This is a heuristic: plaintext
+    /// patterns are cheap, while RegEx patterns grow more expensive with the
+    /// length of their expression.
+    pub fn estimated_cost(&self) -> usize {
+        match self.kind {
+            PatternKind::Raw | PatternKind::Word | PatternKind::Glob | PatternKind::Bytes => 1,
+            PatternKind::Dictionary => 1 + self.content.lines().count() / 64,
+            PatternKind::RegEx => 4 + self.content.len() / 16,
+            PatternKind::Selector => 16, // the text must be parsed as HTML
+        }
+    }
+
+    /// Splits the content of a `Selector` pattern into its selector and
+    /// the RegEx (if any) that selected elements' text must match.
+    fn get_selector_parts(&self) -> (&str, Option<&str>) {
+        let mut lines = self.content.splitn(2, '\n');
+        let selector = lines.next().unwrap_or_default().trim();
+        let expression = lines
+            .next()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty());
+        (selector, expression)
+    }
+
+    /// Translates the content of a `Bytes` pattern into a byte-oriented
+    /// RegEx expression, validating its hexadecimal digits.
+    fn get_bytes_regex(&self) -> Result<String, Issue> {
+        let digits: Vec<char> = self.content.chars().filter(|c| !c.is_whitespace()).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(Issue::Error(format!(
+                "byte pattern `{}` must consist of pairs of hexadecimal digits",
+                self.content
+            )));
+        }
+        let mut expression = String::from("(?s-u)");
+        for pair in digits.chunks(2) {
+            let byte: String = pair.iter().collect();
+            if byte == "??" {
+                expression.push('.');
+                continue;
+            }
+            match u8::from_str_radix(&byte, 16) {
+                Ok(value) => expression.push_str(&format!("\\x{:02x}", value)),
+                Err(_) => {
+                    return Err(Issue::Error(format!(
+                        "byte pattern `{}` contains invalid byte `{}`",
+                        self.content, byte
+                    )))
+                }
+            }
+        }
+        Ok(expression)
+    }
+
+    /// Returns the terms of a `Dictionary` pattern, loading them from
+    /// their file if necessary. For other kinds of patterns, the content
+    /// is returned as the only term.
+    pub fn get_dictionary_terms(&self) -> Result<Vec<String>, Issue> {
+        if self.kind != PatternKind::Dictionary {
+            return Ok(vec![self.content.clone()]);
+        }
+        let content = if self.content.starts_with(DICTIONARY_FILE_PREFIX) && !self.content.contains('\n') {
+            let path = &self.content[DICTIONARY_FILE_PREFIX.len()..];
+            match fs::read_to_string(path) {
+                Ok(value) => Cow::Owned(value),
+                Err(error) => {
+                    return Err(Issue::Error(format!(
+                        "unable to load dictionary `{}` (`{}`)",
+                        path, error
+                    )))
+                }
+            }
+        } else {
+            Cow::Borrowed(self.content.as_str())
+        };
+        let terms: Vec<String> = content
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| match &self.normalization {
+                Some(normalization) if normalization.case_fold => {
+                    fold_case(&normalization.apply(line))
+                }
+                Some(normalization) => normalization.apply(line),
+                None => String::from(line),
+            })
+            .collect();
+        if terms.is_empty() {
+            return Err(Issue::Error(String::from("dictionary contains no terms")));
+        }
+        Ok(terms)
+    }
+
     /// Given any pattern, this function returns its expression
     /// as safe-to-compile RegEx. For `Raw` patterns, the expression
-    /// is escaped; for `RegEx` patterns, it is cloned. Note that this
+    /// is escaped; for `Word` patterns, it is escaped and wrapped in
+    /// word boundaries; for `Glob` patterns, it is translated into an
+    /// anchored expression; for `Dictionary` patterns, the terms are escaped
+    /// and joined as alternatives; for `Bytes` patterns, it is translated into
+    /// a byte-oriented expression (which only compiles as `regex::bytes`);
+    /// for `RegEx` patterns, it is cloned. Note that this
     /// function _does not_ validate whether the RegEx is valid; it simply
     /// prepares it for compilation.
     /// 
-    /// This is a utility function, and is currently not used during 
-    /// compilation.
-    pub fn get_as_safe_regex(&self) -> String {
-        match self.kind {
-            PatternKind::RegEx => self.content.clone(),
-            PatternKind::Raw => regex::escape(self.content.as_str())
+    /// When the pattern has a `normalization`, its content is normalized
+    /// first (and the expression is made case insensitive if necessary).
+    ///
+    /// Returns an error when the expression cannot be prepared—for example,
+    /// when the terms of a `Dictionary` pattern cannot be loaded—rather
+    /// than an empty expression, which would match everything.
+    pub fn get_as_safe_regex(&self) -> Result<String, Issue> {
+        let content = match &self.normalization {
+            Some(normalization) => normalization.apply(&self.content),
+            None => self.content.clone(),
+        };
+        let expression = self.get_as_safe_regex_of(&content)?;
+        Ok(match &self.normalization {
+            Some(normalization) if normalization.case_fold => format!("(?i){}", expression),
+            _ => expression,
+        })
+    }
+
+    fn get_as_safe_regex_of(&self, content: &str) -> Result<String, Issue> {
+        Ok(match self.kind {
+            PatternKind::RegEx => String::from(content),
+            PatternKind::Dictionary => {
+                let escaped: Vec<String> = self
+                    .get_dictionary_terms()?
+                    .iter()
+                    .map(|term| regex::escape(term))
+                    .collect();
+                escaped.join("|")
+            }
+            PatternKind::Bytes => self.get_bytes_regex()?,
+            PatternKind::Selector => String::from(self.get_selector_parts().1.unwrap_or_default()),
+            PatternKind::Raw => regex::escape(content),
+            PatternKind::Word => {
+                // Boundaries are only meaningful next to word characters;
+                // `\b` before a leading `+` (as in `+1`) would never match.
+                let is_word_char = |c: Option<char>| match c {
+                    Some(value) => value.is_alphanumeric() || value == '_',
+                    None => false,
+                };
+                let mut expression = regex::escape(content);
+                if is_word_char(content.chars().next()) {
+                    expression.insert_str(0, r"\b");
+                }
+                if is_word_char(content.chars().last()) {
+                    expression.push_str(r"\b");
+                }
+                expression
+            }
+            PatternKind::Glob => {
+                let mut expression = String::from("(?m)^");
+                let mut characters = content.chars().peekable();
+                while let Some(character) = characters.next() {
+                    match character {
+                        '*' => {
+                            if characters.peek() == Some(&'*') {
+                                characters.next();
+                                expression.push_str(".*");
+                            } else {
+                                expression.push_str("[^/]*");
+                            }
+                        }
+                        '?' => expression.push_str("[^/]"),
+                        other => expression.push_str(&regex::escape(&other.to_string())),
+                    }
+                }
+                expression.push('$');
+                expression
+            }
+        })
+    }
+
+    /// Compiles the `Pattern` (see `compile()`), failing if its RegEx
+    /// would compile to more than the given number of bytes.
+    fn compile_with_size_limit(&self, size_limit: Option<usize>) -> Result<CompiledPattern, Issue> {
+        let message = match self.kind {
+            PatternKind::Raw => "escaped regex literal could not compile",
+            PatternKind::RegEx => "regex could not compile",
+            PatternKind::Word => "escaped word literal could not compile",
+            PatternKind::Glob => "glob could not compile",
+            PatternKind::Dictionary => "dictionary could not compile",
+            PatternKind::Bytes => "byte pattern could not compile",
+            PatternKind::Selector => "selector could not compile",
+        };
+        if self.kind == PatternKind::Selector {
+            if self.normalization.is_some() {
+                return Err(Issue::Error(String::from(
+                    "selector patterns cannot be normalized",
+                )));
+            }
+            let (selector, expression) = self.get_selector_parts();
+            let selector = match Selector::parse(selector) {
+                Ok(result) => result,
+                Err(error) => return Err(Issue::Error(format!("{} (`{}`)", message, error))),
+            };
+            let regex_pattern = match expression.map(|expression| build_regex(expression, size_limit)) {
+                Some(Ok(result)) => Some(result),
+                Some(Err(error)) => return Err(Issue::Error(format!("{} (`{}`)", message, error))),
+                None => None,
+            };
+            return Ok(CompiledPattern {
+                matcher: Matcher::Selector(selector, regex_pattern),
+                normalization: None,
+            });
+        }
+        if self.kind == PatternKind::Bytes {
+            if self.normalization.is_some() {
+                return Err(Issue::Error(String::from(
+                    "byte patterns cannot be normalized",
+                )));
+            }
+            let regex_pattern = match build_bytes_regex(&self.get_bytes_regex()?, size_limit) {
+                Ok(result) => result,
+                Err(error) => return Err(Issue::Error(format!("{} (`{}`)", message, error))),
+            };
+            return Ok(CompiledPattern {
+                matcher: Matcher::Bytes(regex_pattern),
+                normalization: None,
+            });
+        }
+        if self.kind == PatternKind::Dictionary {
+            // case-folded terms are matched against case-folded text
+            // (see `prepare()`), so the automaton itself is case-sensitive
+            let terms = self.get_dictionary_terms()?;
+            let automaton = match AhoCorasickBuilder::new()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(&terms)
+            {
+                Ok(result) => result,
+                Err(error) => return Err(Issue::Error(format!("{} (`{}`)", message, error))),
+            };
+            return Ok(CompiledPattern {
+                matcher: Matcher::Dictionary(automaton, terms.into()),
+                normalization: self.normalization.clone(),
+            });
         }
+        let regex_pattern = match build_regex(self.get_as_safe_regex()?.as_str(), size_limit) {
+            Ok(result) => result,
+            Err(error) => return Err(Issue::Error(format!("{} (`{}`)", message, error))),
+        };
+        Ok(CompiledPattern {
+            matcher: Matcher::RegEx(regex_pattern),
+            normalization: self.normalization.clone(),
+        })
+    }
+}
+
+/// Builds the given RegEx, failing if it would compile to more than the
+/// given number of bytes (or the engine's default limit, if none is given).
+fn build_regex(expression: &str, size_limit: Option<usize>) -> Result<regex::Regex, regex::Error> {
+    let mut builder = regex::RegexBuilder::new(expression);
+    if let Some(limit) = size_limit {
+        builder.size_limit(limit);
     }
+    builder.build()
+}
+
+/// Builds the given byte RegEx, like `build_regex()`.
+fn build_bytes_regex(expression: &str, size_limit: Option<usize>) -> Result<regex::bytes::Regex, regex::Error> {
+    let mut builder = regex::bytes::RegexBuilder::new(expression);
+    if let Some(limit) = size_limit {
+        builder.size_limit(limit);
+    }
+    builder.build()
 }
 
 impl CompilableTo<CompiledPattern> for Pattern {
     /// This function compiles the `Pattern` into a `CompiledPattern` by
     /// escaping the RegEx expression as necessary and then compiling it.
     fn compile(&self) -> Result<CompiledPattern, Issue> {
-        let regex_pattern = match self.kind {
-            PatternKind::Raw => match regex::Regex::new(regex::escape(self.content.as_str()).as_str()) {
-                Ok(result) => result,
-                Err(_) => return Err(Issue::Error(String::from("escaped regex literal could not compile"))),
-            },
-            PatternKind::RegEx => match regex::Regex::new(&self.content.as_str()) {
-                Ok(result) => result,
-                Err(_) => return Err(Issue::Error(String::from("regex could not compile"))),
-            }
-        };
-        Ok(CompiledPattern {
-            regex: regex_pattern
-        })
+        self.compile_with_size_limit(None)
     }
 }
 
 impl CompiledPattern {
+    /// The terms of a compiled `Dictionary` pattern, as they were loaded
+    /// when it was compiled; `None` for other kinds of patterns.
+    pub fn dictionary_terms(&self) -> Option<&[String]> {
+        match &self.matcher {
+            Matcher::Dictionary(_, terms) => Some(terms),
+            _ => None,
+        }
+    }
+
     /// This function performs a 'quick check' for matching on the given string.
     /// It simply returns a boolean value representing whether the string matches
     /// the pattern or not. This function is more performant, but less featureful,
     /// than `full_check`.
-    pub fn quick_check(&self, other: &String) -> bool {
-        self.regex.is_match(&other)
+    pub fn quick_check(&self, other: &str) -> bool {
+        let prepared = self.prepare(other);
+        match &self.matcher {
+            Matcher::RegEx(regex) => regex.is_match(&prepared),
+            Matcher::Dictionary(automaton, _) => automaton.is_match(prepared.as_ref()),
+            Matcher::Bytes(regex) => regex.is_match(prepared.as_bytes()),
+            Matcher::Selector(selector, regex) => {
+                let html = Html::parse_document(other);
+                let mut elements = html.select(selector);
+                match regex {
+                    Some(regex) => elements.any(|element| regex.is_match(&element_text(&element))),
+                    None => elements.next().is_some(),
+                }
+            }
+        }
+    }
+
+    /// This function performs a 'quick check' on the given bytes. `Bytes`
+    /// patterns are matched against the bytes directly; other patterns are
+    /// matched against the bytes as (lossily converted) text.
+    pub fn quick_check_bytes(&self, other: &[u8]) -> bool {
+        match &self.matcher {
+            Matcher::Bytes(regex) => regex.is_match(other),
+            _ => self.quick_check(&String::from_utf8_lossy(other)),
+        }
+    }
+
+    /// This function performs a 'full check' on the given bytes for _every_
+    /// occurrence of the pattern, like `full_check_all()`. Excerpts are
+    /// lossily converted to text.
+    pub fn full_check_all_bytes(&self, other: &[u8]) -> Vec<PatternMatch> {
+        match &self.matcher {
+            Matcher::Bytes(regex) => regex
+                .find_iter(other)
+                .map(|finding| assemble_match(other, finding.start(), finding.end()))
+                .collect(),
+            _ => self.full_check_all(&String::from_utf8_lossy(other)),
+        }
     }
 
     /// This function performs a 'full check' on the given text; more specifically,
@@ -105,48 +632,581 @@ impl CompiledPattern {
     /// 
     /// Returns `Some(PatternMatch)` if there is a match. Otherwise, the function
     /// returns `None`.
-    pub fn full_check(&self, other: &String) -> Option<PatternMatch> {
-        match self.regex.find(&other) {
-            Some(finding) => {
-                let bounds: i64 = 150;
-                let mut start: i64 = finding.start() as i64;
-                let mut end: i64 = finding.end() as i64;
-                let mut relevant_start: i64 = 0;
-                let relevant_diff: i64 = (finding.end() - finding.start()) as i64;
-                start -= bounds;
-                end += bounds;
-                relevant_start += bounds;
-
-                if start < 0 {
-                    relevant_start -= start * -1;
-                    start = 0;
-                }
+    pub fn full_check(&self, other: &str) -> Option<PatternMatch> {
+        let prepared = self.prepare(other);
+        let found = self.find(&prepared);
+        match prepared {
+            Cow::Owned(_) => found.map(without_offsets),
+            Cow::Borrowed(_) => found,
+        }
+    }
 
-                if end > other.len() as i64 {
-                    end = other.len() as i64 - 1;
-                }
+    /// Finds the first match of the pattern in the given (prepared) text.
+    fn find(&self, other: &str) -> Option<PatternMatch> {
+        let regex = match &self.matcher {
+            Matcher::RegEx(regex) => regex,
+            Matcher::Dictionary(automaton, _) => {
+                return automaton
+                    .find(other)
+                    .map(|finding| assemble_match(other.as_bytes(), finding.start(), finding.end()))
+            }
+            Matcher::Bytes(regex) => {
+                return regex
+                    .find(other.as_bytes())
+                    .map(|finding| assemble_match(other.as_bytes(), finding.start(), finding.end()))
+            }
+            Matcher::Selector(..) => return self.full_check_all(other).into_iter().next(),
+        };
+        if has_capture_groups(regex) {
+            return regex
+                .captures(other)
+                .map(|captures| assemble_captured_match(regex, other, &captures));
+        }
+        regex
+            .find(other)
+            .map(|finding| assemble_match(other.as_bytes(), finding.start(), finding.end()))
+    }
+
+    /// This function performs a 'full check' on the given text for _every_
+    /// occurrence of the pattern, assembling a `PatternMatch` for each
+    /// (non-overlapping) match.
+    /// 
+    /// Returns an empty `Vec` if there is no match.
+    pub fn full_check_all(&self, other: &str) -> Vec<PatternMatch> {
+        let prepared = self.prepare(other);
+        let found = self.find_all(&prepared);
+        match prepared {
+            Cow::Owned(_) => found.into_iter().map(without_offsets).collect(),
+            Cow::Borrowed(_) => found,
+        }
+    }
+
+    /// Finds every match of the pattern in the given (prepared) text.
+    fn find_all(&self, other: &str) -> Vec<PatternMatch> {
+        let regex = match &self.matcher {
+            Matcher::RegEx(regex) => regex,
+            Matcher::Dictionary(automaton, _) => {
+                return automaton
+                    .find_iter(other)
+                    .map(|finding| assemble_match(other.as_bytes(), finding.start(), finding.end()))
+                    .collect()
+            }
+            Matcher::Bytes(_) => return self.full_check_all_bytes(other.as_bytes()),
+            Matcher::Selector(selector, regex) => {
+                let html = Html::parse_document(other);
+                return html
+                    .select(selector)
+                    .filter_map(|element| assemble_element_match(&element, regex.as_ref()))
+                    .collect();
+            }
+        };
+        if has_capture_groups(regex) {
+            return regex
+                .captures_iter(other)
+                .map(|captures| assemble_captured_match(regex, other, &captures))
+                .collect();
+        }
+        regex
+            .find_iter(other)
+            .map(|finding| assemble_match(other.as_bytes(), finding.start(), finding.end()))
+            .collect()
+    }
+
+    /// Normalizes the given text if the pattern requires it. The text
+    /// of case-folded dictionaries is also folded, like their terms.
+    fn prepare<'a>(&self, other: &'a str) -> Cow<'a, str> {
+        match (&self.normalization, &self.matcher) {
+            (Some(normalization), Matcher::Dictionary(..)) if normalization.case_fold => {
+                Cow::Owned(fold_case(&normalization.apply(other)))
+            }
+            (Some(normalization), _) => Cow::Owned(normalization.apply(other)),
+            (None, _) => Cow::Borrowed(other),
+        }
+    }
+}
+
+/// Folds the case of the given text, so that dictionary terms match
+/// regardless of case: the text is lowercased, and the characters whose
+/// case folding differs from their lowercase form are replaced (`ß`
+/// becomes `ss`, so that `Straße` matches `STRASSE`, and the final sigma
+/// `ς` becomes `σ`).
+fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for character in text.to_lowercase().chars() {
+        match character {
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            other => folded.push(other),
+        }
+    }
+    folded
+}
+
+/// The text of the given element, with its whitespace collapsed. Elements
+/// without text (such as `meta`) are represented by their HTML instead.
+fn element_text(element: &ElementRef) -> String {
+    let text = element.text().collect::<Vec<&str>>().join(" ");
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.is_empty() {
+        element.html()
+    } else {
+        text
+    }
+}
+
+/// Assembles a `PatternMatch` for the given selected element, whose
+/// excerpt is the element's text. When the pattern has a RegEx, the
+/// relevant part of the excerpt is the RegEx's match (and `None` is
+/// returned if there is no match); otherwise, it is the entire text.
+fn assemble_element_match(element: &ElementRef, regex: Option<&regex::Regex>) -> Option<PatternMatch> {
+    let text = element_text(element);
+    let relevant = match regex {
+        Some(regex) => {
+            let finding = regex.find(&text)?;
+            (finding.start(), finding.end())
+        }
+        None => (0, text.len()),
+    };
+    Some(PatternMatch {
+        excerpt: text,
+        relevant,
+        captures: BTreeMap::new(),
+        offsets: None,
+        content: None,
+        location: None,
+    })
+}
+
+/// Removes the offsets of the given match, whose offsets refer to
+/// normalized text rather than to the text that was searched.
+fn without_offsets(pattern_match: PatternMatch) -> PatternMatch {
+    PatternMatch {
+        offsets: None,
+        ..pattern_match
+    }
+}
+
+/// Records the `location` (line and column) of each of the given matches
+/// that has `offsets` within the given text, which must be the text that
+/// was searched. Locating many matches at once is efficient: the text is
+/// only read once, up to the last match.
+pub fn locate_matches(text: &str, matches: &mut [PatternMatch]) {
+    let mut order: Vec<usize> = (0..matches.len())
+        .filter(|index| match matches[*index].offsets {
+            Some((start, _)) => text.is_char_boundary(start),
+            None => false,
+        })
+        .collect();
+    order.sort_by_key(|index| matches[*index].offsets.map(|(start, _)| start));
+    let (mut line, mut line_start, mut position) = (1, 0, 0);
+    for index in order {
+        let start = matches[index].offsets.map_or(0, |(start, _)| start);
+        for (offset, byte) in text.as_bytes()[position..start].iter().enumerate() {
+            if *byte == b'\n' {
+                line += 1;
+                line_start = position + offset + 1;
+            }
+        }
+        position = start;
+        let column = text[line_start..start].chars().count() + 1;
+        matches[index].location = Some((line, column));
+    }
+}
 
-                let excerpt = String::from_utf8_lossy(&other.as_bytes()[start as usize..end as usize]).to_string();
+/// Whether the RegEx has any capture groups. (Extracting captures is
+/// slower than finding matches, so it is only done when necessary.)
+fn has_capture_groups(regex: &regex::Regex) -> bool {
+    regex.captures_len() > 1
+}
 
-                Some(PatternMatch {
-                    excerpt: excerpt, // TODO: only include a smaller excerpt, not the whole thing
-                    relevant: (relevant_start as usize, (relevant_start + relevant_diff) as usize)
-                })
-            },
-            None => None
+/// Assembles a `PatternMatch` for the given captures, including the
+/// text of each participating capture group.
+fn assemble_captured_match(regex: &regex::Regex, other: &str, captures: &regex::Captures) -> PatternMatch {
+    let whole = captures.get(0).unwrap(); // group 0 always participates
+    let mut pattern_match = assemble_match(other.as_bytes(), whole.start(), whole.end());
+    for (index, name) in regex.capture_names().enumerate().skip(1) {
+        if let Some(group) = captures.get(index) {
+            let key = match name {
+                Some(value) => String::from(value),
+                None => index.to_string(),
+            };
+            pattern_match
+                .captures
+                .insert(key, String::from(group.as_str()));
         }
     }
+    pattern_match
+}
+
+/// Assembles a `PatternMatch` for the match at the given byte offsets,
+/// including an excerpt of the surrounding text.
+fn assemble_match(other: &[u8], match_start: usize, match_end: usize) -> PatternMatch {
+    let bounds: i64 = EXCERPT_CONTEXT as i64;
+    let mut start: i64 = match_start as i64;
+    let mut end: i64 = match_end as i64;
+    let mut relevant_start: i64 = 0;
+    let relevant_diff: i64 = (match_end - match_start) as i64;
+    start -= bounds;
+    end += bounds;
+    relevant_start += bounds;
+
+    if start < 0 {
+        relevant_start -= -start;
+        start = 0;
+    }
+
+    if end > other.len() as i64 {
+        end = other.len() as i64;
+    }
+
+    let excerpt = String::from_utf8_lossy(&other[start as usize..end as usize]).to_string();
+
+    PatternMatch {
+        excerpt, // TODO: only include a smaller excerpt, not the whole thing
+        relevant: (relevant_start as usize, (relevant_start + relevant_diff) as usize),
+        captures: BTreeMap::new(),
+        offsets: Some((match_start, match_end)),
+        content: None,
+        location: None,
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Issue;
+
+    /// Deserializes a `Pattern` from RON, JSON, or YAML, detecting
+    /// the format automatically.
+    fn from_str(serialized: &str) -> Result<Pattern, Issue> {
+        format::deserialize(serialized)
+    }
 }
 
 impl Validatable for Pattern {
     /// This function determines whether the `Pattern` is valid.
     /// It performs a compilation check for itself and for its RegEx.
     /// 
+    /// It also warns when the compiled pattern exceeds `PATTERN_SIZE_BUDGET`.
+    /// 
     /// Returns `None` if there is no issue; otherwise, `Some(Vec<Issue>)`.
     fn validate(&self) -> Option<Vec<Issue>> {
-        match self.compile() {
-            Err(issue) => Some(vec![issue]),
-            Ok(_) => None
-        } // TODO: more expansive (and expensive) checking
+        // compiling within the budget also checks that the pattern compiles at
+        // all, so only patterns that exceed the budget are compiled twice
+        if self.compile_with_size_limit(Some(PATTERN_SIZE_BUDGET)).is_ok() {
+            return None; // TODO: more expansive (and expensive) checking
+        }
+        if let Err(issue) = self.compile() {
+            return Some(vec![issue]);
+        }
+        Some(vec![Issue::Warning(format!(
+            "pattern `{}` exceeds the complexity budget of {} bytes and may stall scans",
+            self.content, PATTERN_SIZE_BUDGET
+        ))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_word_pattern_boundaries() {
+        let pattern = Pattern {
+            content: String::from("art"),
+            kind: PatternKind::Word,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(pattern.quick_check("modern art, revisited"));
+        assert!(!pattern.quick_check("start"));
+
+        let symbolic = Pattern {
+            content: String::from("C++"),
+            kind: PatternKind::Word,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(symbolic.quick_check("written in C++."));
+        assert!(!symbolic.quick_check("ABC++"));
+    }
+
+    #[test]
+    fn test_full_check_all() {
+        let pattern = Pattern {
+            content: String::from("ab"),
+            kind: PatternKind::Raw,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        let matches = pattern.full_check_all("ab cd ab");
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].relevant, (0, 2));
+        assert_eq!(matches[1].relevant, (6, 8));
+        assert_eq!(&matches[1].excerpt[6..8], "ab");
+        assert!(pattern.full_check_all("cd").is_empty());
+
+        let text = "é ab\nab ab";
+        let mut matches = pattern.full_check_all(text);
+        let offsets: Vec<Option<(usize, usize)>> = matches.iter().map(|m| m.offsets).collect();
+        assert_eq!(offsets, vec![Some((3, 5)), Some((6, 8)), Some((9, 11))]);
+        matches.reverse(); // locating does not depend on the order of matches
+        locate_matches(text, &mut matches);
+        let locations: Vec<Option<(usize, usize)>> = matches.iter().map(|m| m.location).collect();
+        assert_eq!(locations, vec![Some((2, 4)), Some((2, 1)), Some((1, 3))]);
+    }
+
+    #[test]
+    fn test_sentence_excerpts() {
+        let pattern = Pattern {
+            content: String::from("storm"),
+            kind: PatternKind::Raw,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        let sentence = |text: &str| {
+            let sentence = pattern.full_check(text).unwrap().to_sentences();
+            assert_eq!(&sentence.excerpt[sentence.relevant.0..sentence.relevant.1], "storm");
+            sentence.excerpt
+        };
+        assert_eq!(sentence("It rained. A storm came (again). Then sun!"), "A storm came (again).");
+        assert_eq!(sentence("Winds rose.\nThe storm hit\nat noon"), "The storm hit");
+        assert_eq!(sentence("\"What a storm!\" she said. Yes."), "\"What a storm!\"");
+        assert_eq!(sentence("storm"), "storm");
+
+        // sentences longer than the excerpt are cut at word boundaries
+        let long = format!("{} storm {}", "wordy ".repeat(40), "wordy ".repeat(40));
+        let excerpt = sentence(&long);
+        assert!(excerpt.starts_with("wordy ") && excerpt.ends_with(" wordy"));
+        assert!(excerpt.len() <= 2 * EXCERPT_CONTEXT + 5);
+    }
+
+    #[test]
+    fn test_capture_extraction() {
+        let pattern = Pattern {
+            content: String::from(r"case (?P<id>\d+)-(\d+)"),
+            kind: PatternKind::RegEx,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        let pattern_match = pattern.full_check("see case 12-34 for details").unwrap();
+        assert_eq!(pattern_match.captures.get("id"), Some(&String::from("12")));
+        assert_eq!(pattern_match.captures.get("2"), Some(&String::from("34")));
+    }
+
+    #[test]
+    fn test_glob_pattern() {
+        let pattern = Pattern {
+            content: String::from("https://*.example.com/news/**"),
+            kind: PatternKind::Glob,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(pattern.quick_check("https://www.example.com/news/2019/article.html"));
+        assert!(!pattern.quick_check("https://www.example.com/sports/article.html"));
+        assert!(!pattern.quick_check("https://evil.com/.example.com/news/"));
+        assert!(!pattern.quick_check("see https://www.example.com/news/"));
+        assert!(pattern.quick_check("https://example.org/\nhttps://www.example.com/news/"));
+    }
+
+    #[test]
+    fn test_normalized_pattern() {
+        let mut pattern = Pattern {
+            content: String::from("Cafe\u{301}"), // decomposed
+            kind: PatternKind::Word,
+            normalization: Some(Normalization {
+                form: NormalizationForm::Nfc,
+                case_fold: false,
+                strip_diacritics: false,
+            }),
+        };
+        let compiled = pattern.compile().unwrap();
+        assert!(compiled.quick_check("the Caf\u{e9} is open")); // composed
+        assert!(!compiled.quick_check("the cafe is open"));
+
+        pattern.normalization = Some(Normalization {
+            form: NormalizationForm::Nfkc,
+            case_fold: true,
+            strip_diacritics: true,
+        });
+        let compiled = pattern.compile().unwrap();
+        assert!(compiled.quick_check("the cafe is open"));
+        assert!(compiled.quick_check("THE CAF\u{c9} IS OPEN"));
+        assert_eq!(compiled.full_check("le caf\u{e9}!").unwrap().excerpt, "le cafe!");
+        assert_eq!(compiled.full_check("le caf\u{e9}!").unwrap().offsets, None);
+    }
+
+    #[test]
+    fn test_dictionary_pattern() {
+        let pattern = Pattern {
+            content: String::from("acme\n\nAcme Corp\nwidget"),
+            kind: PatternKind::Dictionary,
+            normalization: None,
+        };
+        assert_eq!(pattern.get_dictionary_terms().unwrap().len(), 3);
+        let compiled = pattern.compile().unwrap();
+        assert!(compiled.quick_check("buy a widget"));
+        assert!(!compiled.quick_check("buy a gadget"));
+        let matches = compiled.full_check_all("Acme Corp sells acme widgets");
+        assert_eq!(matches.len(), 3);
+        assert_eq!(&matches[0].excerpt[matches[0].relevant.0..matches[0].relevant.1], "Acme Corp");
+
+        let empty = Pattern {
+            content: String::from("\n"),
+            kind: PatternKind::Dictionary,
+            normalization: None,
+        };
+        assert!(empty.compile().is_err());
+        let missing = Pattern {
+            content: String::from("file:/nonexistent/dictionary.txt"),
+            kind: PatternKind::Dictionary,
+            normalization: None,
+        };
+        assert!(missing.compile().is_err());
+        assert!(missing.get_as_safe_regex().is_err());
+        assert!(missing.validate().is_some());
+
+        let path = env::temp_dir().join(format!("ieql-dictionary-{}.txt", process::id()));
+        fs::write(&path, "gadget\nwidget").unwrap();
+        let from_file = Pattern {
+            content: format!("file:{}", path.display()),
+            kind: PatternKind::Dictionary,
+            normalization: None,
+        };
+        let compiled = from_file.compile().unwrap();
+        // the terms are kept, so the file is not needed after compilation
+        fs::remove_file(&path).unwrap();
+        assert_eq!(compiled.dictionary_terms().unwrap(), ["gadget", "widget"]);
+        assert!(compiled.quick_check("buy a gadget"));
+    }
+
+    #[test]
+    fn test_case_folded_dictionary_pattern() {
+        let pattern = Pattern {
+            content: String::from("CAF\u{c9}\nStra\u{df}e\n\u{39f}\u{394}\u{39f}\u{3a3}"),
+            kind: PatternKind::Dictionary,
+            normalization: Some(Normalization {
+                form: NormalizationForm::Nfc,
+                case_fold: true,
+                strip_diacritics: false,
+            }),
+        };
+        let compiled = pattern.compile().unwrap();
+        assert!(compiled.quick_check("meet at the caf\u{e9}"));
+        assert!(compiled.quick_check("WELCOME TO THE CAFE\u{301}")); // decomposed
+        assert!(compiled.quick_check("HAUPTSTRASSE 1"));
+        assert!(compiled.quick_check("\u{3b7} \u{3bf}\u{3b4}\u{3bf}\u{3c2}")); // final sigma
+        let found = compiled.full_check("Hauptstra\u{df}e 1").unwrap();
+        assert_eq!(&found.excerpt[found.relevant.0..found.relevant.1], "strasse");
+        assert!(!compiled.quick_check("a cafe"));
+    }
+
+    #[test]
+    fn test_pattern_complexity_budget() {
+        let budget = format!("the complexity budget of {} bytes", PATTERN_SIZE_BUDGET);
+        for (content, kind) in &[
+            (r"\w{50}", PatternKind::RegEx),
+            ("p\n\\w{50}", PatternKind::Selector),
+            ("?? ".repeat(50_000).as_str(), PatternKind::Bytes),
+        ] {
+            let pattern = Pattern {
+                content: String::from(*content),
+                kind: *kind,
+                normalization: None,
+            };
+            assert!(pattern.compile().is_ok(), "`{:?}` did not compile", kind);
+            match pattern.validate().as_deref() {
+                Some([Issue::Warning(message)]) => assert!(message.contains(&budget), "{}", message),
+                issues => panic!("`{:?}` was not warned about: {:?}", kind, issues),
+            }
+        }
+        for content in &[r"\w{20}", r"(?-u:\w){50}"] {
+            let pattern = Pattern {
+                content: String::from(*content),
+                kind: PatternKind::RegEx,
+                normalization: None,
+            };
+            assert!(pattern.compile().is_ok());
+            assert_eq!(pattern.validate(), None);
+        }
+    }
+
+    #[test]
+    fn test_bytes_pattern() {
+        let pattern = Pattern {
+            content: String::from("25 50 44 46 ?? ff"),
+            kind: PatternKind::Bytes,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(pattern.quick_check_bytes(b"\x00%PDF-\xff\x00"));
+        assert!(!pattern.quick_check_bytes(b"%PDF-1.4"));
+        let matches = pattern.full_check_all_bytes(b"..%PDF\n\xff..%PDF \xff");
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].relevant, (2, 8));
+
+        for content in &["25 5", "25 zz", ""] {
+            let invalid = Pattern {
+                content: String::from(*content),
+                kind: PatternKind::Bytes,
+                normalization: None,
+            };
+            assert!(invalid.compile().is_err());
+        }
+    }
+
+    #[test]
+    fn test_selector_pattern() {
+        let html = "<html><head><meta property=\"og:site_name\" content=\"Example News\"></head>\
+            <body><h1>Product  recall\nannounced</h1><h1>Weather</h1></body></html>";
+        let meta = Pattern {
+            content: String::from(r#"meta[property="og:site_name"][content~="Example"]"#),
+            kind: PatternKind::Selector,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(meta.quick_check(html));
+        assert!(!meta.quick_check("<meta property=\"og:site_name\" content=\"Other\">"));
+
+        let headings = Pattern {
+            content: String::from("h1"),
+            kind: PatternKind::Selector,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        let matches = headings.full_check_all(html);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].excerpt, "Product recall announced");
+        assert_eq!(matches[1].relevant, (0, 7));
+
+        let recalls = Pattern {
+            content: String::from("h1\n(?i)RECALL"),
+            kind: PatternKind::Selector,
+            normalization: None,
+        }
+        .compile()
+        .unwrap();
+        assert!(recalls.quick_check(html));
+        assert!(!recalls.quick_check("<h1>Weather</h1><p>recall</p>"));
+        let found = recalls.full_check(html).unwrap();
+        assert_eq!(&found.excerpt[found.relevant.0..found.relevant.1], "recall");
+
+        for content in &["h1[", "h1\n(unclosed"] {
+            let invalid = Pattern {
+                content: String::from(*content),
+                kind: PatternKind::Selector,
+                normalization: None,
+            };
+            assert!(invalid.compile().is_err());
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,20 +1,26 @@
 //! This file provides a utility class for loading files.
 
 use common::validation::Issue;
+use input::content_encoding::ContentEncoding;
 use input::document::Document;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 
 /// Loads the file at the given path and assembles a `Document`. This function
 /// is a utility. It currently only supports local files.
 ///
+/// Files compressed with gzip, zlib, or Brotli (as denoted by their `.gz`,
+/// `.zz`, or `.br` extension) are decompressed.
+///
 /// # Arguments
 /// * `path`: a `String` of the filepath to load
 pub fn load_document(path: &String) -> Result<Document, Issue> {
     // TODO: make this work for more than just local file paths
     let file_path = Path::new(&path);
-    let mut f: File = match File::open(&file_path) {
+    let mut f: File = match File::open(file_path) {
         Ok(value) => value,
         Err(error) => {
             return Err(Issue::Error(format!(
@@ -24,6 +30,13 @@ pub fn load_document(path: &String) -> Result<Document, Issue> {
             )));
         }
     };
+    // the modification time is the closest local equivalent of when a document was fetched
+    let timestamp = f
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64);
     let mut contents: Vec<u8> = Vec::new();
     match f.read_to_end(&mut contents) {
         Ok(_size) => {}
@@ -35,9 +48,27 @@ pub fn load_document(path: &String) -> Result<Document, Issue> {
             )));
         }
     }
+    let encoding = ContentEncoding::from_extension(path);
+    if encoding != ContentEncoding::Identity {
+        contents = match encoding.decode(&contents) {
+            Ok(value) => value,
+            Err(Issue::Error(message)) | Err(Issue::Warning(message)) => {
+                return Err(Issue::Error(format!(
+                    "{} in `{}`, skipping...",
+                    message,
+                    file_path.to_string_lossy()
+                )));
+            }
+        };
+    }
     Ok(Document {
         data: contents,
         mime: None,
+        base_url: None,
+        timestamp,
+        source: None,
+        content_encoding: None,
+        metadata: HashMap::new(),
         url: Some(String::from(file_path.to_string_lossy())),
     })
 }
@@ -0,0 +1,256 @@
+//! This file provides functionality related to timestamps, which are
+//! represented throughout IEQL as seconds since the Unix epoch (UTC).
+
+use common::validation::Issue;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time, in seconds since the Unix epoch. (Should the system
+/// clock be set before the epoch, the time is negative.)
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(error) => -(error.duration().as_secs() as i64),
+    }
+}
+
+/// Parses an RFC 3339 timestamp, such as `2019-05-01T12:30:00Z` or
+/// `2019-05-01T12:30:00+02:00`, or a date, such as `2019-05-01` (which
+/// denotes its midnight, UTC), into seconds since the Unix epoch.
+/// Fractional seconds are accepted but ignored.
+pub fn parse_timestamp(value: &str) -> Result<i64, Issue> {
+    let invalid = || {
+        Issue::Error(format!(
+            "invalid timestamp `{}`; expected a date (`YYYY-MM-DD`) or an RFC 3339 timestamp",
+            value
+        ))
+    };
+    let value = value.trim();
+    let (date, time) = match value.find(&['T', 't', ' '][..]) {
+        Some(index) => (&value[..index], Some(&value[index + 1..])),
+        None => (value, None),
+    };
+
+    let date_parts: Vec<&str> = date.split('-').collect();
+    if date_parts.len() != 3 {
+        return Err(invalid());
+    }
+    let year: i64 = parse_number(date_parts[0], 4).ok_or_else(invalid)?;
+    let month: i64 = parse_number(date_parts[1], 2).ok_or_else(invalid)?;
+    let day: i64 = parse_number(date_parts[2], 2).ok_or_else(invalid)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(&['Z', 'z', '+', '-'][..]) {
+            Some(index) => (&time[..index], &time[index..]),
+            None => return Err(invalid()), // RFC 3339 requires an offset
+        };
+        let clock = match clock.find('.') {
+            Some(index) => &clock[..index],
+            None => clock,
+        };
+        let clock_parts: Vec<&str> = clock.split(':').collect();
+        if clock_parts.len() != 3 {
+            return Err(invalid());
+        }
+        let hour: i64 = parse_number(clock_parts[0], 2).ok_or_else(invalid)?;
+        let minute: i64 = parse_number(clock_parts[1], 2).ok_or_else(invalid)?;
+        let second: i64 = parse_number(clock_parts[2], 2).ok_or_else(invalid)?;
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+        if offset != "Z" && offset != "z" {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let offset_parts: Vec<&str> = offset[1..].split(':').collect();
+            if offset_parts.len() != 2 {
+                return Err(invalid());
+            }
+            let offset_hour: i64 = parse_number(offset_parts[0], 2).ok_or_else(invalid)?;
+            let offset_minute: i64 = parse_number(offset_parts[1], 2).ok_or_else(invalid)?;
+            seconds -= sign * (offset_hour * 3600 + offset_minute * 60);
+        }
+    }
+    Ok(seconds)
+}
+
+/// Parses an RFC 2822 timestamp, as found in email headers and RSS feeds,
+/// such as `Wed, 01 May 2019 12:30:00 GMT` or `1 May 2019 14:30 +0200`,
+/// into seconds since the Unix epoch. The day of the week is optional (and
+/// ignored), as are the seconds; two-digit years and the obsolete zone
+/// names of RFC 822 (such as `EST`) are accepted.
+pub fn parse_rfc2822_timestamp(value: &str) -> Result<i64, Issue> {
+    let invalid = || {
+        Issue::Error(format!(
+            "invalid timestamp `{}`; expected an RFC 2822 timestamp",
+            value
+        ))
+    };
+    let value = value.trim();
+    let value = match value.find(',') {
+        Some(index) => &value[index + 1..],
+        None => value,
+    };
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 5 {
+        return Err(invalid());
+    }
+    let day: i64 = match parts[0].len() {
+        1 | 2 => parts[0].parse().map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    let month = MONTH_NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(parts[1]))
+        .ok_or_else(invalid)? as i64
+        + 1;
+    let year: i64 = match parts[2].len() {
+        2 => parse_number(parts[2], 2).map(|year| if year < 50 { year + 2000 } else { year + 1900 }),
+        _ => parse_number(parts[2], 4),
+    }
+    .ok_or_else(invalid)?;
+    if day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let clock_parts: Vec<&str> = parts[3].split(':').collect();
+    if clock_parts.len() != 2 && clock_parts.len() != 3 {
+        return Err(invalid());
+    }
+    let hour: i64 = parse_number(clock_parts[0], 2).ok_or_else(invalid)?;
+    let minute: i64 = parse_number(clock_parts[1], 2).ok_or_else(invalid)?;
+    let second: i64 = match clock_parts.get(2) {
+        Some(second) => parse_number(second, 2).ok_or_else(invalid)?,
+        None => 0,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    let offset: i64 = match parts[4].to_ascii_uppercase().as_str() {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        "EDT" => -4 * 3600,
+        "EST" | "CDT" => -5 * 3600,
+        "CST" | "MDT" => -6 * 3600,
+        "MST" | "PDT" => -7 * 3600,
+        "PST" => -8 * 3600,
+        zone => {
+            let sign = match zone.chars().next() {
+                Some('+') => 1,
+                Some('-') => -1,
+                _ => return Err(invalid()),
+            };
+            let offset = parse_number(&zone[1..], 4).ok_or_else(invalid)?;
+            sign * (offset / 100 * 3600 + offset % 100 * 60)
+        }
+    };
+    Ok(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Formats the given timestamp (in seconds since the Unix epoch) as an
+/// RFC 3339 timestamp in UTC, such as `2019-05-01T12:30:00Z`. This is
+/// the inverse of `parse_timestamp()`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86_400);
+    let seconds = timestamp.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// The abbreviated names of the months, as in RFC 2822 timestamps.
+const MONTH_NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Parses a number with exactly the given number of decimal digits.
+fn parse_number(value: &str, digits: usize) -> Option<i64> {
+    if value.len() != digits || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// The number of days in the given month of the given year.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The number of days between the Unix epoch and the given date of the
+/// proleptic Gregorian calendar. (This is Howard Hinnant's algorithm.)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12; // March is 0
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of the proleptic Gregorian calendar that is the given number
+/// of days after the Unix epoch; the inverse of `days_from_civil()`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = (if days >= 0 { days } else { days - 146_096 }) / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // March is 0
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_timestamp("2000-03-01").unwrap(), 951_868_800);
+        assert_eq!(parse_timestamp("2019-05-01T12:30:00Z").unwrap(), 1_556_713_800);
+        assert_eq!(parse_timestamp("2019-05-01T14:30:00.25+02:00").unwrap(), 1_556_713_800);
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z").unwrap(), -1);
+        assert!(parse_timestamp("2019-02-29").is_err());
+        assert!(parse_timestamp("2019-05-01T12:30:00").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_rfc2822_timestamp() {
+        assert_eq!(parse_rfc2822_timestamp("Wed, 01 May 2019 12:30:00 GMT").unwrap(), 1_556_713_800);
+        assert_eq!(parse_rfc2822_timestamp("1 May 2019 14:30 +0200").unwrap(), 1_556_713_800);
+        assert_eq!(parse_rfc2822_timestamp("Wed, 1 May 19 08:30:00 EDT").unwrap(), 1_556_713_800);
+        assert_eq!(parse_rfc2822_timestamp("Thu, 01 Jan 1970 00:00:00 -0000").unwrap(), 0);
+        assert!(parse_rfc2822_timestamp("Fri, 29 Feb 2019 12:30:00 GMT").is_err());
+        assert!(parse_rfc2822_timestamp("01 May 2019 12:30:00").is_err());
+        assert!(parse_rfc2822_timestamp("2019-05-01T12:30:00Z").is_err());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_868_800), "2000-03-01T00:00:00Z");
+        assert_eq!(format_timestamp(1_556_713_800), "2019-05-01T12:30:00Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+        for timestamp in &[-86_400 * 400, 68_169_599, 951_782_400, 4_107_542_399] {
+            assert_eq!(parse_timestamp(&format_timestamp(*timestamp)).unwrap(), *timestamp);
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! This module provides functionality for reading documents from ZIP and
+//! tar archives—such as bulk data dumps—without unpacking them to disk.
+//! Tar archives may be compressed as a whole (`.tar.gz` or `.tgz`, say;
+//! see `ContentEncoding`), and are read as a stream.
+//!
+//! Each file in an archive becomes a `Document`; directories, links, and
+//! other special entries are skipped. Files within an archive that are
+//! themselves compressed (such as `pages/index.html.gz`) are decompressed
+//! during compilation, like any other document.
+
+use common::timestamp::parse_timestamp;
+use common::validation::Issue;
+use input::content_encoding::ContentEncoding;
+use input::document::Document;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek};
+use zip::ZipArchive;
+
+/// The size of a block of a tar archive (and of each of its headers).
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// The default maximum size, in bytes, of a file that is read from an
+/// archive: 256 MiB. (See `ArchiveReader::with_max_member_size()`.)
+pub const DEFAULT_MAX_MEMBER_SIZE: u64 = 256 << 20;
+
+/// Whether the file at the given path is an archive, judging by its
+/// extension: `.zip`, or `.tar` (optionally compressed; see
+/// `ContentEncoding`), or `.tgz`.
+pub fn is_archive_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    let stripped = ContentEncoding::from_extension(&path).strip_extension(&path);
+    path.ends_with(".zip") || path.ends_with(".tgz") || stripped.ends_with(".tar")
+}
+
+/// The URL of the file at the given path within the archive with the given
+/// URL, such as `dump.zip!/pages/index.html`.
+fn member_url(archive_url: &str, member: &str) -> String {
+    format!("{}!/{}", archive_url, member.trim_start_matches("./").trim_start_matches('/'))
+}
+
+/// The `Document` of the file at the given path within the archive with
+/// the given URL.
+fn member_document(archive_url: &str, member: &str, data: Vec<u8>, timestamp: Option<i64>) -> Document {
+    let content_encoding = match ContentEncoding::from_extension(member) {
+        ContentEncoding::Identity => None,
+        encoding => Some(String::from(encoding.name())),
+    };
+    Document {
+        url: Some(member_url(archive_url, member)),
+        data,
+        mime: None,
+        base_url: None,
+        timestamp,
+        source: None,
+        content_encoding,
+        metadata: HashMap::new(),
+    }
+}
+
+/// A reader and a seeker, as a ZIP archive requires (to read its central
+/// directory, at its end).
+trait ReadSeek: Read + Seek + Send {}
+
+impl<R: Read + Seek + Send> ReadSeek for R {}
+
+/// The archives that an `ArchiveReader` reads, with its position in them.
+enum Members {
+    /// A ZIP archive, and the index of its next file.
+    Zip(ZipArchive<Box<dyn ReadSeek>>, usize),
+    /// A (decompressed) tar archive, which is read in order.
+    Tar(Box<dyn Read + Send>),
+}
+
+/// An `ArchiveReader` iterates over the documents of a ZIP or tar archive
+/// (see the module-level documentation), in the order in which they are
+/// stored. Each document's `url` is the path of the file within the
+/// archive, appended to the archive's URL with `!/` (as in
+/// `dump.tar.gz!/pages/index.html`), and its `timestamp` is when the file
+/// was modified.
+///
+/// Iteration ends at the end of the archive, or after the first error
+/// (such as a truncated archive), which is yielded. Files that are larger
+/// than the maximum member size are skipped, and a `Warning` is yielded in
+/// their place; iteration continues after it.
+pub struct ArchiveReader {
+    members: Members,
+    /// The URL of the archive.
+    url: String,
+    /// The maximum size, in bytes, of a file that is read.
+    max_member_size: u64,
+    /// The path of the next file of a tar archive, when it is given by
+    /// an extended header (as long paths are).
+    next_path: Option<String>,
+    /// Whether iteration has ended.
+    finished: bool,
+}
+
+impl ArchiveReader {
+    /// Opens the archive at the given path, whose format is detected from
+    /// its extension (see `is_archive_path()`).
+    pub fn open(path: &str) -> Result<ArchiveReader, Issue> {
+        let file = File::open(path).map_err(|error| Issue::Error(format!("unable to open `{}` (`{}`)", path, error)))?;
+        let file = BufReader::new(file);
+        match path.to_ascii_lowercase().ends_with(".zip") {
+            true => ArchiveReader::zip(file, path),
+            false => {
+                let encoding = match path.to_ascii_lowercase().ends_with(".tgz") {
+                    true => ContentEncoding::Gzip,
+                    false => ContentEncoding::from_extension(path),
+                };
+                Ok(ArchiveReader::tar(encoding.decoder(file), path))
+            }
+        }
+    }
+
+    /// Create a new `ArchiveReader` that reads the ZIP archive with the
+    /// given URL from the given reader. Returns an error when the archive's
+    /// central directory cannot be read.
+    pub fn zip<R: Read + Seek + Send + 'static>(reader: R, url: &str) -> Result<ArchiveReader, Issue> {
+        let reader: Box<dyn ReadSeek> = Box::new(reader);
+        let archive = ZipArchive::new(reader)
+            .map_err(|error| Issue::Error(format!("unable to read ZIP archive `{}` (`{}`)", url, error)))?;
+        Ok(ArchiveReader {
+            members: Members::Zip(archive, 0),
+            url: String::from(url),
+            max_member_size: DEFAULT_MAX_MEMBER_SIZE,
+            next_path: None,
+            finished: false,
+        })
+    }
+
+    /// Create a new `ArchiveReader` that reads the uncompressed tar archive
+    /// with the given URL from the given reader. (For compressed archives,
+    /// wrap the reader with `ContentEncoding::decoder()`, or use
+    /// `ArchiveReader::open()`.)
+    pub fn tar<R: Read + Send + 'static>(reader: R, url: &str) -> ArchiveReader {
+        ArchiveReader {
+            members: Members::Tar(Box::new(reader)),
+            url: String::from(url),
+            max_member_size: DEFAULT_MAX_MEMBER_SIZE,
+            next_path: None,
+            finished: false,
+        }
+    }
+
+    /// Sets the maximum size, in bytes, of a file that is read from the
+    /// archive (`DEFAULT_MAX_MEMBER_SIZE` by default). Sizes are checked as
+    /// files are read, rather than trusted from the archive's headers, so
+    /// that a maliciously crafted archive cannot exhaust memory.
+    pub fn with_max_member_size(mut self, bytes: u64) -> ArchiveReader {
+        self.max_member_size = bytes;
+        self
+    }
+
+    /// The `Warning` yielded in place of a file that is larger than the
+    /// maximum member size.
+    fn oversized(&self, member: &str) -> Issue {
+        Issue::Warning(format!(
+            "skipped `{}`: larger than the limit of {} bytes",
+            member_url(&self.url, member),
+            self.max_member_size
+        ))
+    }
+
+    /// Reads the next file of the ZIP archive, if there is one.
+    fn next_zip_document(&mut self) -> Result<Option<Document>, Issue> {
+        let url = &self.url;
+        let limit = self.max_member_size;
+        let (archive, index) = match &mut self.members {
+            Members::Zip(archive, index) => (archive, index),
+            Members::Tar(_) => return Ok(None),
+        };
+        while *index < archive.len() {
+            let mut file = archive
+                .by_index(*index)
+                .map_err(|error| Issue::Error(format!("unable to read ZIP archive `{}` (`{}`)", url, error)))?;
+            *index += 1;
+            if file.is_dir() {
+                continue;
+            }
+            // the size in the file's header may be a lie, so it is not
+            // used to allocate; reading stops just past the limit instead
+            let mut data: Vec<u8> = Vec::new();
+            (&mut file).take(limit.saturating_add(1)).read_to_end(&mut data).map_err(|error| {
+                Issue::Error(format!("unable to read `{}` (`{}`)", member_url(url, file.name()), error))
+            })?;
+            if data.len() as u64 > limit {
+                let name = String::from(file.name());
+                drop(file);
+                return Err(self.oversized(&name));
+            }
+            // ZIP archives record local times without a time zone, which
+            // are taken to be UTC
+            let timestamp = file
+                .last_modified()
+                .and_then(|modified| parse_timestamp(&format!("{}Z", modified)).ok());
+            return Ok(Some(member_document(url, file.name(), data, timestamp)));
+        }
+        Ok(None)
+    }
+
+    /// Reads exactly the given number of bytes of the tar archive, followed
+    /// by the padding to the end of their last block. When the bytes are
+    /// not kept, they are discarded as they are read (and an empty `Vec` is
+    /// returned).
+    fn read_tar_data(&mut self, size: u64, keep: bool) -> Result<Vec<u8>, Issue> {
+        let url = &self.url;
+        let reader = match &mut self.members {
+            Members::Tar(reader) => reader,
+            Members::Zip(..) => return Ok(Vec::new()),
+        };
+        let padded = size
+            .div_ceil(TAR_BLOCK_SIZE as u64)
+            .checked_mul(TAR_BLOCK_SIZE as u64)
+            .ok_or_else(|| Issue::Error(format!("tar archive `{}` has an invalid header", url)))?;
+        let failed = |error: io::Error| Issue::Error(format!("unable to read tar archive `{}` (`{}`)", url, error));
+        let mut data: Vec<u8> = Vec::new();
+        let read = match keep {
+            true => reader.take(padded).read_to_end(&mut data).map_err(failed)? as u64,
+            false => io::copy(&mut reader.take(padded), &mut io::sink()).map_err(failed)?,
+        };
+        if read < padded {
+            return Err(Issue::Error(format!("tar archive `{}` is truncated", url)));
+        }
+        data.truncate(size as usize);
+        Ok(data)
+    }
+
+    /// Reads the next file of the tar archive, if there is one.
+    fn next_tar_document(&mut self) -> Result<Option<Document>, Issue> {
+        loop {
+            let mut header = [0u8; TAR_BLOCK_SIZE];
+            let url = &self.url;
+            let reader = match &mut self.members {
+                Members::Tar(reader) => reader,
+                Members::Zip(..) => return Ok(None),
+            };
+            let mut read = 0;
+            while read < TAR_BLOCK_SIZE {
+                match reader.read(&mut header[read..]) {
+                    Ok(0) => break,
+                    Ok(count) => read += count,
+                    Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                    Err(error) => {
+                        return Err(Issue::Error(format!("unable to read tar archive `{}` (`{}`)", url, error)))
+                    }
+                }
+            }
+            // archives end with blocks of zeros (or, when they are written
+            // carelessly, simply end)
+            if read == 0 || header.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+            if read < TAR_BLOCK_SIZE {
+                return Err(Issue::Error(format!("tar archive `{}` is truncated", url)));
+            }
+            let header = TarHeader::parse(&header)
+                .ok_or_else(|| Issue::Error(format!("tar archive `{}` has an invalid header", self.url)))?;
+            let oversized = header.size > self.max_member_size;
+            match header.kind {
+                // GNU long paths and POSIX extended headers give the path
+                // of the next entry
+                b'L' | b'x' if oversized => {
+                    self.read_tar_data(header.size, false)?;
+                    self.next_path = None;
+                }
+                b'L' => {
+                    let data = self.read_tar_data(header.size, true)?;
+                    let path = String::from_utf8_lossy(&data);
+                    self.next_path = Some(String::from(path.trim_end_matches('\0')));
+                }
+                b'x' => {
+                    let data = self.read_tar_data(header.size, true)?;
+                    if let Some(path) = pax_path(&data) {
+                        self.next_path = Some(path);
+                    }
+                }
+                b'0' | b'\0' | b'7' => {
+                    let path = self.next_path.take().unwrap_or(header.path);
+                    if oversized {
+                        self.read_tar_data(header.size, false)?;
+                        return Err(self.oversized(&path));
+                    }
+                    let data = self.read_tar_data(header.size, true)?;
+                    return Ok(Some(member_document(&self.url, &path, data, header.timestamp)));
+                }
+                _ => {
+                    self.read_tar_data(header.size, false)?;
+                    self.next_path = None;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for ArchiveReader {
+    type Item = Result<Document, Issue>;
+
+    fn next(&mut self) -> Option<Result<Document, Issue>> {
+        if self.finished {
+            return None;
+        }
+        let result = match self.members {
+            Members::Zip(..) => self.next_zip_document(),
+            Members::Tar(_) => self.next_tar_document(),
+        };
+        match result {
+            Ok(Some(document)) => Some(Ok(document)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(issue) => {
+                // oversized files are skipped with a warning; errors are fatal
+                self.finished = matches!(issue, Issue::Error(_));
+                Some(Err(issue))
+            }
+        }
+    }
+}
+
+/// The parts of the header of an entry of a tar archive that are needed
+/// to read it.
+struct TarHeader {
+    /// The entry's path (including, for ustar archives, its prefix).
+    path: String,
+    /// The size of the entry's data, in bytes.
+    size: u64,
+    /// When the entry was modified.
+    timestamp: Option<i64>,
+    /// The entry's type flag (such as `0` for files, or `5` for
+    /// directories).
+    kind: u8,
+}
+
+impl TarHeader {
+    /// Parses the given header block, returning `None` when its checksum
+    /// does not match.
+    fn parse(block: &[u8; TAR_BLOCK_SIZE]) -> Option<TarHeader> {
+        // the checksum is the sum of the header's bytes, with those of the
+        // checksum itself taken to be spaces
+        let checksum = parse_tar_number(&block[148..156])?;
+        let sum: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| match index {
+                148..=155 => u64::from(b' '),
+                _ => u64::from(*byte),
+            })
+            .sum();
+        if sum != checksum {
+            return None;
+        }
+        let mut path = tar_string(&block[0..100]);
+        if &block[257..262] == b"ustar" {
+            let prefix = tar_string(&block[345..500]);
+            if !prefix.is_empty() {
+                path = format!("{}/{}", prefix, path);
+            }
+        }
+        Some(TarHeader {
+            path,
+            size: parse_tar_number(&block[124..136])?,
+            timestamp: parse_tar_number(&block[136..148]).map(|timestamp| timestamp as i64),
+            kind: block[156],
+        })
+    }
+}
+
+/// Reads the given NUL-terminated field of a tar header as text.
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Parses the given numeric field of a tar header, which is either octal
+/// text or, for large numbers (as GNU tar writes them), binary with its
+/// first bit set.
+fn parse_tar_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|byte| byte & 0x80 != 0) {
+        return Some(
+            field[1..]
+                .iter()
+                .fold(u64::from(field[0] & 0x7f), |number, byte| (number << 8) | u64::from(*byte)),
+        );
+    }
+    let text = tar_string(field);
+    let text = text.trim_matches(|character: char| character == ' ' || character == '\0');
+    match text.is_empty() {
+        true => Some(0),
+        false => u64::from_str_radix(text, 8).ok(),
+    }
+}
+
+/// The `path` of the given POSIX extended header, whose records are of
+/// the form `<length> <key>=<value>\n`.
+fn pax_path(data: &[u8]) -> Option<String> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|byte| *byte == b' ')?;
+        let length: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        if length <= space || length > rest.len() {
+            return None;
+        }
+        let record = &rest[space + 1..length];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(path) = record.strip_prefix(b"path=") {
+            return Some(String::from_utf8_lossy(path).into_owned());
+        }
+        rest = &rest[length..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// Writes the checksum of the given tar header.
+    fn write_checksum(header: &mut [u8]) {
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header[..TAR_BLOCK_SIZE].iter().map(|byte| u32::from(*byte)).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    }
+
+    /// Serializes a tar entry with the given path, type flag, and data.
+    fn tar_entry(path: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut header = [0u8; TAR_BLOCK_SIZE];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"13462310510"); // 2019-05-01T12:30:00Z
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        write_checksum(&mut header);
+        let mut entry = header.to_vec();
+        entry.extend_from_slice(data);
+        entry.resize(entry.len().div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE, 0);
+        entry
+    }
+
+    fn read_all(reader: ArchiveReader) -> Vec<Document> {
+        reader.collect::<Result<Vec<Document>, Issue>>().unwrap()
+    }
+
+    #[test]
+    fn test_tar_archive() {
+        let long_path = format!("pages/{}.html", "a".repeat(120));
+        // the length of a record includes that of the length itself
+        let pax = format!("{} path={}\n", 10 + long_path.len(), long_path);
+        let mut tar: Vec<u8> = Vec::new();
+        tar.extend(tar_entry("pages/", b'5', b""));
+        tar.extend(tar_entry("pages/index.html", b'0', b"<p>Hello, world!</p>"));
+        tar.extend(tar_entry("PaxHeaders/long", b'x', pax.as_bytes()));
+        tar.extend(tar_entry("pages/aaaa.html", b'0', b"long"));
+        tar.extend(tar_entry("./notes.txt", b'0', b""));
+        tar.extend([0u8; TAR_BLOCK_SIZE * 2].iter());
+
+        let documents = read_all(ArchiveReader::tar(Cursor::new(tar.clone()), "dump.tar"));
+        let urls: Vec<&str> = documents.iter().map(|document| document.url.as_deref().unwrap()).collect();
+        let long_url = format!("dump.tar!/{}", long_path);
+        assert_eq!(urls, vec!["dump.tar!/pages/index.html", long_url.as_str(), "dump.tar!/notes.txt"]);
+        assert_eq!(documents[0].data, b"<p>Hello, world!</p>");
+        assert_eq!(documents[0].timestamp, Some(1_556_713_800));
+        assert_eq!(documents[1].data, b"long");
+
+        // compressed archives are read through a decoder
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&tar).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+        let reader = ArchiveReader::tar(ContentEncoding::Gzip.decoder(Cursor::new(gzipped)), "dump.tar.gz");
+        assert_eq!(read_all(reader).len(), 3);
+
+        // corrupt and truncated archives end with an error
+        let mut corrupt = tar.clone();
+        corrupt[TAR_BLOCK_SIZE] ^= 1;
+        let results: Vec<Result<Document, Issue>> = ArchiveReader::tar(Cursor::new(corrupt), "dump.tar").collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        let truncated = tar[..TAR_BLOCK_SIZE * 2 + 10].to_vec();
+        let results: Vec<Result<Document, Issue>> = ArchiveReader::tar(Cursor::new(truncated), "dump.tar").collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+
+        // oversized files are skipped, without trusting the sizes in headers
+        let reader = ArchiveReader::tar(Cursor::new(tar.clone()), "dump.tar").with_max_member_size(5);
+        let results: Vec<Result<Document, Issue>> = reader.collect();
+        assert_eq!(results.len(), 3);
+        match &results[0] {
+            Err(Issue::Warning(message)) => assert!(message.contains("`dump.tar!/pages/index.html`")),
+            _ => panic!("`index.html` is larger than the limit"),
+        }
+        assert_eq!(results[2].as_ref().unwrap().url.as_deref(), Some("dump.tar!/notes.txt"));
+        let mut huge = tar_entry("huge.bin", b'0', b"");
+        huge[124] = 0x80;
+        huge[125..136].copy_from_slice(&[0xff; 11]);
+        write_checksum(&mut huge);
+        let results: Vec<Result<Document, Issue>> = ArchiveReader::tar(Cursor::new(huge), "dump.tar").collect();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0], Err(Issue::Error(_))));
+    }
+
+    #[test]
+    fn test_zip_archive() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        zip.add_directory("pages/", options).unwrap();
+        zip.start_file("pages/index.html", options).unwrap();
+        zip.write_all(b"<p>Hello, world!</p>").unwrap();
+        zip.start_file("pages/old.html.gz", options).unwrap();
+        zip.write_all(b"\x1f\x8b").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        let documents = read_all(ArchiveReader::zip(Cursor::new(zip.clone()), "dump.zip").unwrap());
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0].url.as_deref(), Some("dump.zip!/pages/index.html"));
+        assert_eq!(documents[0].data, b"<p>Hello, world!</p>");
+        assert!(documents[0].timestamp.is_some());
+        assert_eq!(documents[0].content_encoding, None);
+        assert_eq!(documents[1].content_encoding.as_deref(), Some("gzip"));
+        assert!(ArchiveReader::zip(Cursor::new(b"not a zip".to_vec()), "dump.zip").is_err());
+        let reader = ArchiveReader::zip(Cursor::new(zip), "dump.zip").unwrap().with_max_member_size(2);
+        let results: Vec<Result<Document, Issue>> = reader.collect();
+        assert!(matches!(results[0], Err(Issue::Warning(_))));
+        assert_eq!(results[1].as_ref().unwrap().data, b"\x1f\x8b");
+
+        assert!(is_archive_path("/dumps/2019.ZIP"));
+        assert!(is_archive_path("/dumps/2019.tar.gz"));
+        assert!(is_archive_path("/dumps/2019.tgz"));
+        assert!(!is_archive_path("/dumps/2019.html.gz"));
+    }
+}
@@ -0,0 +1,204 @@
+//! This module provides functionality for decompressing documents whose
+//! data is compressed, as web crawls and their archives often store them:
+//! with gzip, deflate, or Brotli (the `gzip`, `deflate`, and `br` values
+//! of an HTTP `Content-Encoding` header).
+
+use common::validation::Issue;
+use brotli_decompressor::Decompressor;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::Read;
+
+/// The magic number with which every gzip stream starts.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The size of the buffer of the Brotli decompressor.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// The default maximum size, in bytes, of decompressed data: 256 MiB.
+/// (See `ContentEncoding::decode_limited()`.)
+pub const DEFAULT_MAX_DECODED_SIZE: usize = 256 << 20;
+
+/// Denotes how the data of a document is compressed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    /// The data is not compressed.
+    Identity,
+    /// Gzip (RFC 1952).
+    Gzip,
+    /// Deflate, either wrapped in zlib (RFC 1950), as HTTP specifies, or
+    /// raw (RFC 1951), as some servers send it.
+    Deflate,
+    /// Brotli (RFC 7932).
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// Parses the given `Content-Encoding` (such as `gzip` or `br`),
+    /// returning `None` if it is not supported.
+    pub fn parse(label: &str) -> Option<ContentEncoding> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            "br" => Some(ContentEncoding::Brotli),
+            _ => None,
+        }
+    }
+
+    /// The `Content-Encoding` of this encoding (such as `br` for `Brotli`).
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// Detects the encoding of a file from its extension: `.gz`, `.zz`
+    /// (zlib), or `.br`. Other files are `Identity`.
+    pub fn from_extension(path: &str) -> ContentEncoding {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".gz") {
+            ContentEncoding::Gzip
+        } else if path.ends_with(".zz") {
+            ContentEncoding::Deflate
+        } else if path.ends_with(".br") {
+            ContentEncoding::Brotli
+        } else {
+            ContentEncoding::Identity
+        }
+    }
+
+    /// Detects the encoding of the given data from its magic number. Only
+    /// gzip can be detected reliably: Brotli has no magic number, and the
+    /// two bytes of a zlib header are too often the start of text.
+    pub fn from_magic(data: &[u8]) -> ContentEncoding {
+        if data.starts_with(&GZIP_MAGIC) {
+            ContentEncoding::Gzip
+        } else {
+            ContentEncoding::Identity
+        }
+    }
+
+    /// Removes the extension of this encoding (see `from_extension()`)
+    /// from the given path, so that `page.html.gz` becomes `page.html`.
+    pub fn strip_extension(self, path: &str) -> &str {
+        let extension = match self {
+            ContentEncoding::Identity => return path,
+            ContentEncoding::Gzip => ".gz",
+            ContentEncoding::Deflate => ".zz",
+            ContentEncoding::Brotli => ".br",
+        };
+        let split = path.len().saturating_sub(extension.len());
+        match path.get(split..) {
+            Some(suffix) if suffix.eq_ignore_ascii_case(extension) => &path[..split],
+            _ => path,
+        }
+    }
+
+    /// Wraps the given reader so that it decompresses the data that it
+    /// reads as it is read. Unlike `decode()`, deflate is only read as
+    /// zlib, as the data cannot be retried as raw deflate.
+    pub fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> Box<dyn Read + Send + 'a> {
+        match self {
+            ContentEncoding::Identity => Box::new(reader),
+            ContentEncoding::Gzip => Box::new(GzDecoder::new(reader)),
+            ContentEncoding::Deflate => Box::new(ZlibDecoder::new(reader)),
+            ContentEncoding::Brotli => Box::new(Decompressor::new(reader, BROTLI_BUFFER_SIZE)),
+        }
+    }
+
+    /// Decompresses the given data, which may decompress to at most
+    /// `DEFAULT_MAX_DECODED_SIZE` bytes. (See `decode_limited()`.)
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>, Issue> {
+        self.decode_limited(data, DEFAULT_MAX_DECODED_SIZE)
+    }
+
+    /// Decompresses the given data, which may decompress to at most the
+    /// given number of bytes, so that a small, maliciously compressed
+    /// document cannot exhaust memory. Returns a `Warning` when the data
+    /// decompresses to more than the limit, and an `Error` when it cannot
+    /// be decompressed.
+    pub fn decode_limited(self, data: &[u8], limit: usize) -> Result<Vec<u8>, Issue> {
+        // reading stops just past the limit, so that exceeding it is detected
+        let read = |reader: &mut dyn Read, decoded: &mut Vec<u8>| reader.take(limit as u64 + 1).read_to_end(decoded);
+        let mut decoded: Vec<u8> = Vec::new();
+        let result = match self {
+            ContentEncoding::Identity => return Ok(data.to_vec()),
+            ContentEncoding::Gzip => read(&mut GzDecoder::new(data), &mut decoded),
+            ContentEncoding::Deflate => match read(&mut ZlibDecoder::new(data), &mut decoded) {
+                Ok(size) => Ok(size),
+                Err(_) => {
+                    decoded.clear();
+                    read(&mut DeflateDecoder::new(data), &mut decoded)
+                }
+            },
+            ContentEncoding::Brotli => read(&mut Decompressor::new(data, BROTLI_BUFFER_SIZE), &mut decoded),
+        };
+        match result {
+            Ok(_) if decoded.len() > limit => Err(Issue::Warning(format!(
+                "`{}` data decompresses to more than the limit of {} bytes",
+                self.name(),
+                limit
+            ))),
+            Ok(_) => Ok(decoded),
+            Err(error) => Err(Issue::Error(format!(
+                "unable to decompress `{}` data (`{}`)",
+                self.name(),
+                error
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_content_encoding() {
+        let text = b"<p>Hello, world!</p>";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(text).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(text).unwrap();
+        let zlib = zlib.finish().unwrap();
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(text).unwrap();
+        let deflate = deflate.finish().unwrap();
+        // a Brotli stream of one uncompressed meta-block of the text,
+        // followed by an empty last meta-block
+        let mut brotli = vec![0x30, 0x01, 0x10];
+        brotli.extend_from_slice(text);
+        brotli.push(0x03);
+
+        assert_eq!(ContentEncoding::Gzip.decode(&gzip).unwrap(), text);
+        assert_eq!(ContentEncoding::Deflate.decode(&zlib).unwrap(), text);
+        assert_eq!(ContentEncoding::Deflate.decode(&deflate).unwrap(), text);
+        assert_eq!(ContentEncoding::Brotli.decode(&brotli).unwrap(), text);
+        assert!(ContentEncoding::Gzip.decode(text).is_err());
+        assert_eq!(ContentEncoding::Brotli.decode_limited(&brotli, text.len()).unwrap(), text);
+        assert!(matches!(ContentEncoding::Gzip.decode_limited(&gzip, text.len() - 1), Err(Issue::Warning(_))));
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::best());
+        bomb.write_all(&vec![0; 1 << 20]).unwrap();
+        let bomb = bomb.finish().unwrap();
+        assert!(bomb.len() < 2048);
+        assert!(matches!(ContentEncoding::Gzip.decode_limited(&bomb, 4096), Err(Issue::Warning(_))));
+        let mut streamed: Vec<u8> = Vec::new();
+        ContentEncoding::Gzip.decoder(&gzip[..]).read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, text);
+
+        assert_eq!(ContentEncoding::parse("GZIP"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::parse("compress"), None);
+        assert_eq!(ContentEncoding::from_magic(&gzip), ContentEncoding::Gzip);
+        assert_eq!(ContentEncoding::from_magic(&zlib), ContentEncoding::Identity);
+        assert_eq!(ContentEncoding::from_extension("/crawl/page.html.BR"), ContentEncoding::Brotli);
+        assert_eq!(ContentEncoding::Brotli.strip_extension("/crawl/page.html.BR"), "/crawl/page.html");
+        assert_eq!(ContentEncoding::Gzip.strip_extension("/crawl/page.html"), "/crawl/page.html");
+    }
+}
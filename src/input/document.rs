@@ -1,16 +1,107 @@
 //! This document provides functionality related to document handling.
 
+use common::checksum::Checksum;
 use common::compilation::CompilableTo;
 use common::validation::Issue;
-use query::scope::ScopeContent;
+use input::content_encoding::ContentEncoding;
+use input::readability::extract_main_content;
+use query::scope::{ContentTransform, ScopeContent};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
 use regex::Regex;
-use url::Url;
+use whatlang;
+use url::{ParseError, Url};
 use lazy_static::lazy_static;
 use htmlescape::decode_html;
+use sha2::{Digest, Sha256};
+use scraper::{ElementRef, Html, Node};
+use regex::bytes::Regex as BytesRegex;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use chardetng::EncodingDetector;
+use serde_json::Value;
+
+/// The magic number with which every PDF file starts.
+const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// The number of bytes at the start of a document within which a `<meta>`
+/// (or XML) declaration of its charset is sought (as in the HTML standard).
+const CHARSET_PRESCAN_LENGTH: usize = 1024;
+
+/// The elements whose contents are not human-readable text, and so are
+/// dropped by `TextExtraction::Dom`.
+const HIDDEN_ELEMENTS: [&str; 4] = ["script", "style", "noscript", "template"];
+
+/// The elements that begin and end a block of text, and so are separated
+/// from their surroundings by line breaks by `TextExtraction::Dom`.
+const BLOCK_ELEMENTS: [&str; 38] = [
+    "address", "article", "aside", "blockquote", "br", "caption", "dd", "details", "dialog", "div", "dl", "dt",
+    "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr",
+    "li", "main", "nav", "ol", "p", "pre", "section", "summary", "table", "td", "th", "title", "tr",
+];
+
+/// The domain given to documents that reside on the local filesystem.
+const LOCAL_DOMAIN: &str = "localhost";
+
+/// The number of characters of a document's text that are considered
+/// when detecting its language.
+const LANGUAGE_SAMPLE_LENGTH: usize = 4096;
+
+/// The ISO 639-1 codes of the languages that can be detected, alongside
+/// their ISO 639-3 codes (as used by the language detector).
+const LANGUAGE_CODES: [(&str, &str); 69] = [
+    ("af", "afr"), ("ak", "aka"), ("am", "amh"), ("ar", "ara"), ("az", "aze"),
+    ("be", "bel"), ("bg", "bul"), ("bn", "ben"), ("ca", "cat"), ("cs", "ces"),
+    ("da", "dan"), ("de", "deu"), ("el", "ell"), ("en", "eng"), ("eo", "epo"),
+    ("es", "spa"), ("et", "est"), ("fa", "pes"), ("fi", "fin"), ("fr", "fra"),
+    ("gu", "guj"), ("he", "heb"), ("hi", "hin"), ("hr", "hrv"), ("hu", "hun"),
+    ("hy", "hye"), ("id", "ind"), ("it", "ita"), ("ja", "jpn"), ("jv", "jav"),
+    ("ka", "kat"), ("km", "khm"), ("kn", "kan"), ("ko", "kor"), ("la", "lat"),
+    ("lt", "lit"), ("lv", "lav"), ("mk", "mkd"), ("ml", "mal"), ("mr", "mar"),
+    ("my", "mya"), ("nb", "nob"), ("ne", "nep"), ("nl", "nld"), ("or", "ori"),
+    ("pa", "pan"), ("pl", "pol"), ("pt", "por"), ("ro", "ron"), ("ru", "rus"),
+    ("si", "sin"), ("sk", "slk"), ("sl", "slv"), ("sn", "sna"), ("sr", "srp"),
+    ("sv", "swe"), ("ta", "tam"), ("te", "tel"), ("th", "tha"), ("tk", "tuk"),
+    ("tl", "tgl"), ("tr", "tur"), ("uk", "ukr"), ("ur", "urd"), ("uz", "uzb"),
+    ("vi", "vie"), ("yi", "yid"), ("zh", "cmn"), ("zu", "zul"),
+];
+
+/// Whether the given ISO 639-1 code is that of a language that can be
+/// detected in documents (see `CompiledDocument.language`).
+pub fn is_detectable_language(code: &str) -> bool {
+    LANGUAGE_CODES.iter().any(|(iso_639_1, _)| *iso_639_1 == code)
+}
+
+/// Detects the language of the given text, returning its ISO 639-1 code
+/// if it can be determined reliably.
+fn detect_language(text: &str) -> Option<String> {
+    let sample = match text.char_indices().nth(LANGUAGE_SAMPLE_LENGTH) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    let info = whatlang::detect(sample)?;
+    if !info.is_reliable() {
+        return None;
+    }
+    LANGUAGE_CODES
+        .iter()
+        .find(|(_, iso_639_3)| *iso_639_3 == info.lang().code())
+        .map(|(iso_639_1, _)| String::from(*iso_639_1))
+}
 
 lazy_static! {
+    static ref META_CHARSET_REGEX: BytesRegex =
+        BytesRegex::new(r#"(?i)(?:<meta\s[^>]*charset|<\?xml\s[^>]*encoding)\s*=\s*["']?\s*([a-z0-9_:.-]+)"#).unwrap();
     static ref HTML_REGEX: Regex = Regex::new(r"<(.*?)>").unwrap();
     static ref SPACE_REGEX: Regex = Regex::new(r"\s{2,}").unwrap();
+    static ref TITLE_REGEX: Regex = Regex::new(r"(?is)<title(?:\s[^>]*)?>(.*?)</title\s*>").unwrap();
+    static ref HEADING_REGEX: Regex =
+        Regex::new(r"(?is)<h[1-6](?:\s[^>]*)?>(.*?)</h[1-6]\s*>").unwrap();
+    static ref LINK_REGEX: Regex = Regex::new(r"(?is)<a(\s[^>]*)?>(.*?)</a\s*>").unwrap();
+    static ref BASE_REGEX: Regex = Regex::new(r"(?is)<base(\s[^>]*)>").unwrap();
+    static ref META_REGEX: Regex = Regex::new(r"(?is)<meta(\s[^>]*)>").unwrap();
+    static ref PARAGRAPH_REGEX: Regex = Regex::new(r"(?is)<p(?:\s[^>]*)?>(.*?)</p\s*>").unwrap();
+    static ref ATTRIBUTE_REGEX: Regex =
+        Regex::new(r#"(?s)([a-zA-Z_:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap();
 }
 
 /// The `Document` struct represents any kind of document, but typically
@@ -32,8 +123,36 @@ pub struct Document {
     /// This data is stored as a `Vec<u8>` primarily for first-class text
     /// document support (`utf8`).
     pub data: Vec<u8>,
-    /// `mime` represents a valid IETF `mime` type, as per RFC 2045.
+    /// `mime` represents a valid IETF `mime` type, as per RFC 2045. It may
+    /// include parameters (such as `text/html; charset=windows-1251`, as in
+    /// an HTTP `Content-Type` header); its `charset`, if any, declares the
+    /// encoding of `data`. (See `detect_encoding()`.)
     pub mime: Option<String>,
+    /// `base_url` is the URL against which a relative `url` is resolved
+    /// when extracting the document's domain, if present.
+    ///
+    /// For example, a document with the `url` `news/today.html` and the
+    /// `base_url` `https://example.com/` has the domain `example.com`.
+    pub base_url: Option<String>,
+    /// `timestamp` represents when the document was published or fetched,
+    /// in seconds since the Unix epoch (UTC), if it is known.
+    pub timestamp: Option<i64>,
+    /// `source` is a tag denoting the source or collection that the
+    /// document came from, if it is known—for example, `commoncrawl` or
+    /// `rss:nytimes`. It allows queries to be routed to specific feeds
+    /// in a mixed pipeline. (See `Scope.source`.)
+    pub source: Option<String>,
+    /// `content_encoding` is the compression of `data`, if it is known, in
+    /// the form of an HTTP `Content-Encoding` header: `gzip`, `deflate`, or
+    /// `br`. Compressed data is decompressed during compilation; when this
+    /// is `None`, gzipped data is still detected by its magic number. (See
+    /// `ContentEncoding`.)
+    pub content_encoding: Option<String>,
+    /// `metadata` is arbitrary information about the document that is
+    /// carried through scanning—for example, the ID of the crawl that
+    /// fetched it, or the headers with which it was served—so that it can
+    /// be included in outputs. (See `ResponseItem::Metadata`.)
+    pub metadata: HashMap<String, String>,
 }
 
 /// A `DocumentReference` is a reference to a document that is either
@@ -68,25 +187,171 @@ pub enum DocumentReference {
 pub struct DocumentReferenceBatch {
     /// Contains the DocumentReferences
     pub documents: Vec<DocumentReference>,
+    /// An optional identifier of the batch, such as `crawl-2019-05/17`,
+    /// which the scan engine records in the `Provenance` of every output
+    /// that the batch's documents produce.
+    pub id: Option<String>,
+}
+
+/// `Provenance` records where a document that is being scanned came
+/// from, so that each output can be traced back to its exact input.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Provenance {
+    /// The ID of the `DocumentReferenceBatch` that contained the
+    /// document, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+    /// The reference to the document: the path from which it was loaded
+    /// (for `Unpopulated` references) or its URL (for `Populated` ones).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
 }
 
 /// A `CompiledDocument` is a `Document` that has been processed and
 /// is ready to be scanned. During compilation, the IEQL document compiler
 /// extracts the following information from the `Document`:
 ///
-/// * **text** — the text of the document. Currently, only HTML parsing is supported.
+/// * **text** — the text of the document. HTML documents are parsed (see `TextExtraction`),
+///   and the text of PDF documents is extracted when the `pdf` feature is enabled.
 /// * **domain** — the domain name, if present, is also processed.
+/// * **language** — the language of the text, if it can be detected reliably.
+/// * **structure** — the title, headings, links, and metadata of HTML documents.
+/// * **robots** — the `noindex` and `noarchive` directives of HTML documents.
+/// * **hash** — the SHA-256 hash of the document's bytes, for deduplication.
 /// * **raw** — unlike `Documents`, whose contents are bytes, `CompiledDocuments` have text.
 ///
-/// In cases that the document is not HTML, `text` is identical to `raw`.
+/// In cases that the document is neither HTML nor PDF, `text` is identical to
+/// `raw`. The original bytes of the document remain available through `bytes()`.
 pub struct CompiledDocument {
     pub url: Option<String>,
     pub raw: String,
+    /// The MIME type of the document, without any parameters (such as
+    /// `charset`).
     pub mime: Option<String>,
     pub text: String,
     pub domain: Option<String>,
+    /// The ISO 639-1 code of the language of the document's text (such
+    /// as `en`), if it could be detected reliably.
+    pub language: Option<String>,
+    /// When the document was published or fetched, in seconds since the
+    /// Unix epoch (UTC), if it is known.
+    pub timestamp: Option<i64>,
+    /// The tag of the source or collection that the document came from,
+    /// if it is known.
+    pub source: Option<String>,
+    /// The metadata of the document. (See `Document.metadata`.)
+    pub metadata: HashMap<String, String>,
+    /// The structural elements of the document. (See `DocumentStructure`.)
+    pub structure: DocumentStructure,
+    /// Whether the document is HTML.
+    pub is_html: bool,
+    /// The robots directives of the document. (See `RobotsDirectives`.)
+    pub robots: RobotsDirectives,
+    /// The name of the character encoding from which the document's bytes
+    /// were decoded into `raw` (such as `UTF-8`, `windows-1252`, or
+    /// `Shift_JIS`), as detected by `Document::detect_encoding()`.
+    pub encoding: String,
+    /// The original bytes of the document, present only when they differ
+    /// from `raw` (such as when they are not UTF-8).
+    pub binary: Option<Vec<u8>>,
+    /// The SHA-256 hash of the document's original bytes, in lowercase
+    /// hexadecimal. Unlike `checksum()`, it does not depend on the URL,
+    /// so mirrors of the same document share it.
+    pub hash: String,
+    /// Where the document came from, when it was scanned by the scan
+    /// engine (or when the caller records it). (See `Provenance`.)
+    pub provenance: Option<Provenance>,
+    /// How the text of the document was extracted, if it is HTML.
+    pub text_extraction: TextExtraction,
+    /// The problems encountered while compiling the document, such as a
+    /// PDF whose text could not be extracted (in which case its `text` is
+    /// empty). They do not prevent the document from being scanned.
+    pub issues: Vec<Issue>,
+    /// The transformed contents of the document that have been computed
+    /// so far. (See `transformed_content()`.)
+    transformed: Mutex<HashMap<TransformKey, Arc<String>>>,
+    /// The main content of the document, once it has been computed. (See
+    /// `ScopeContent::MainContent`.)
+    main_content: OnceLock<String>,
+}
+
+/// A `DocumentStructure` contains the structural elements of an HTML
+/// document, each cleaned of markup, and the fields of a JSON document.
+/// Every element is on its own line. For other documents, all of them are
+/// empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DocumentStructure {
+    /// The document's `<title>`.
+    pub title: String,
+    /// The text of the document's headings (`<h1>` through `<h6>`).
+    pub headings: String,
+    /// The document's links, each of the form `href text` (for example,
+    /// `https://example.com/ Example`), where the text is the link's
+    /// anchor text.
+    pub links: String,
+    /// The distinct targets of the document's links as absolute URLs (for
+    /// example, `https://example.com/sports`). Relative links are resolved
+    /// against the document's `<base href>` or URL (see `resolved_url()`);
+    /// links that cannot be resolved, and `javascript:` links, are omitted.
+    pub link_targets: String,
+    /// The document's `<meta>` tags, each of the form `name: content`
+    /// (for example, `description: Today's news`), where the name is
+    /// the tag's `name`, `property`, or `http-equiv` attribute.
+    pub metadata: String,
+    /// The text of the document's paragraphs (`<p>`), separated by
+    /// blank lines.
+    pub paragraphs: String,
+    /// The string values of a JSON document, each of the form `key: value`,
+    /// where the key is the dotted path of the value (for example,
+    /// `items.0.title: Recall notice`).
+    pub fields: String,
+}
+
+/// `RobotsDirectives` represents the directives of an HTML document's
+/// `<meta name="robots">` tags that restrict how it may be used.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RobotsDirectives {
+    /// Whether the document asks not to be indexed (`noindex` or `none`).
+    pub noindex: bool,
+    /// Whether the document asks not to be archived (`noarchive`).
+    pub noarchive: bool,
+}
+
+impl RobotsDirectives {
+    /// Finds the robots directives among the given metadata, as found
+    /// in `DocumentStructure.metadata`.
+    fn from_metadata(metadata: &str) -> RobotsDirectives {
+        let mut directives = RobotsDirectives::default();
+        for line in metadata.lines() {
+            let (name, content) = match line.find(": ") {
+                Some(index) => (&line[..index], &line[index + 2..]),
+                None => continue,
+            };
+            if !name.trim().eq_ignore_ascii_case("robots") {
+                continue;
+            }
+            for directive in content.split(',').map(|value| value.trim().to_lowercase()) {
+                match directive.as_str() {
+                    "noindex" => directives.noindex = true,
+                    "noarchive" => directives.noarchive = true,
+                    "none" => directives.noindex = true,
+                    _ => (),
+                }
+            }
+        }
+        directives
+    }
+
+    /// Whether the document restricts its use in any way.
+    pub fn is_restricted(&self) -> bool {
+        self.noindex || self.noarchive
+    }
 }
 
+/// Identifies a transformed content of a `CompiledDocument`: the content,
+/// and the transforms applied to it.
+type TransformKey = (ScopeContent, Vec<ContentTransform>);
+
 /// Represents a batch (collection in the form of a `Vec`) of `Document`s.
 pub struct DocumentBatch {
     /// Contains the documents
@@ -99,39 +364,104 @@ pub struct CompiledDocumentBatch {
     pub documents: Vec<CompiledDocument>,
 }
 
+/// Denotes how the text of HTML documents is extracted. (See
+/// `CompiledDocument.text`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Default)]
+pub enum TextExtraction {
+    /// The HTML is parsed, and the text of its elements is extracted:
+    /// the contents of `<script>`, `<style>`, `<noscript>`, and
+    /// `<template>` elements are dropped, entities are decoded, and each
+    /// block (such as a paragraph or a list item) is on its own line,
+    /// with its whitespace collapsed. This is the default.
+    #[default]
+    Dom,
+    /// Anything between angle brackets is removed, and runs of whitespace
+    /// are collapsed. This is several times faster than `Dom`, but keeps
+    /// the contents of scripts and style sheets, and is confused by
+    /// attributes that contain `>`.
+    Naive,
+}
+
 /// This enum represents the various kinds of documents which support intelligent
 /// text extraction.
 enum DocumentKind {
     Html,
+    Json,
+    Pdf,
     Unknown,
 }
 
 impl Document {
-    /// This function detects the document's `DocumentKind` by looking at its path
-    /// and MIME information.
+    /// This function detects the document's `DocumentKind` by looking at its path,
+    /// MIME information, and (for PDFs) magic number.
     fn detect_document_kind(&self) -> DocumentKind {
+        // Detect PDF
+        let is_pdf = match &self.mime {
+            Some(value) => mime_essence(value) == "application/pdf",
+            None => false,
+        };
+        if is_pdf || self.data.starts_with(PDF_MAGIC) {
+            return DocumentKind::Pdf;
+        }
+
         // Detect HTML
         let mut is_html = match &self.mime {
-            Some(value) => value.eq("text/html"),
+            Some(value) => mime_essence(value) == "text/html",
             None => false,
         };
-        match &self.url {
-            Some(value) => {
-                if value.ends_with(".html") {
-                    is_html = true;
-                }
+        if let Some(value) = &self.url {
+            if ContentEncoding::from_extension(value).strip_extension(value).ends_with(".html") {
+                is_html = true;
             }
-            None => (),
         };
         if is_html {
             return DocumentKind::Html;
         }
 
+        // Detect JSON (including JSON Lines)
+        let is_json = match &self.mime {
+            Some(value) => {
+                let essence = mime_essence(value);
+                essence == "application/json" || essence == "application/x-ndjson" || essence.ends_with("+json")
+            }
+            None => false,
+        };
+        let has_json_extension = self.url.as_deref().is_some_and(|value| {
+            let path = ContentEncoding::from_extension(value).strip_extension(value);
+            path.ends_with(".json") || path.ends_with(".jsonl") || path.ends_with(".ndjson")
+        });
+        if is_json || has_json_extension {
+            return DocumentKind::Json;
+        }
+
         DocumentKind::Unknown
     }
 
+    /// This function returns the absolute URL of the document: its `url`,
+    /// resolved against `base_url` when it is relative. Returns `None` when
+    /// the document has no URL, or when it cannot be resolved.
+    pub fn resolved_url(&self) -> Option<Url> {
+        let own_url = self.url.as_ref()?;
+        match Url::parse(own_url.as_str()) {
+            Ok(url) => Some(url),
+            Err(ParseError::RelativeUrlWithoutBase) => Url::parse(self.base_url.as_ref()?.as_str())
+                .and_then(|base| base.join(own_url))
+                .ok(),
+            Err(_) => None,
+        }
+    }
+
     /// This function extracts the hostname (domain name) of a document. In cases where
     /// the host name isn't known, this function returns `None`.
+    ///
+    /// URLs are handled as follows:
+    ///
+    /// * **absolute URLs** — the host of the URL (e.g. `example.com`).
+    /// * **`file://` URLs** — the host of the URL, or `localhost` when no host is given.
+    /// * **`data:` URLs** — `None`, as inline data has no origin.
+    /// * **relative URLs** — resolved against `base_url` when present; otherwise, they
+    ///   are treated as local file paths (as the command line interface provides)
+    ///   and have the domain `localhost`.
     pub fn domain(&self) -> Option<String> {
         let own_url = match &self.url {
             Some(value) => value,
@@ -139,58 +469,506 @@ impl Document {
         };
         let parsed_url = match Url::parse(own_url.as_str()) {
             Ok(url) => url,
+            Err(ParseError::RelativeUrlWithoutBase) => match &self.base_url {
+                Some(base) => match Url::parse(base.as_str()).and_then(|base| base.join(own_url)) {
+                    Ok(url) => url,
+                    Err(_) => return None,
+                },
+                None => return Some(String::from(LOCAL_DOMAIN)),
+            },
             Err(_) => return None,
         };
-        match parsed_url.host_str() {
-            Some(value) => Some(String::from(value)),
-            None => None,
+        match parsed_url.scheme() {
+            "data" => None,
+            "file" => match parsed_url.host_str() {
+                Some(value) if !value.is_empty() => Some(String::from(value)),
+                _ => Some(String::from(LOCAL_DOMAIN)),
+            },
+            _ => parsed_url.host_str().map(String::from),
         }
     }
 
-    /// This function extracts text from the document's `data`. It assumes `utf8` encoding.
-    /// Note that this function is very different from `extract_document_text()`: this function
-    /// simply extracts text, while `extract_document_text()` also, in some cases, parses it.
-    fn raw(&self) -> String {
-        String::from_utf8_lossy(self.data.as_slice()).into_owned()
+    /// This function detects the character encoding of the document's `data`. In order of
+    /// precedence, the encoding is given by:
+    ///
+    /// * a byte order mark at the start of the data;
+    /// * the `charset` parameter of the document's `mime` type (typically taken from an HTTP
+    ///   `Content-Type` header);
+    /// * a `<meta charset>` (or `<meta http-equiv="Content-Type">`) declaration, or the
+    ///   `encoding` of an XML declaration, within the first 1024 bytes of the data;
+    /// * the data itself: UTF-8 when it is valid UTF-8, and otherwise a statistical guess,
+    ///   informed by the top-level domain of the document's URL.
+    ///
+    /// Unknown charset labels are ignored. PDFs, whose bytes are not text, are always UTF-8.
+    pub fn detect_encoding(&self) -> &'static Encoding {
+        if let DocumentKind::Pdf = self.detect_document_kind() {
+            return UTF_8;
+        }
+        if let Some((encoding, _)) = Encoding::for_bom(&self.data) {
+            return encoding;
+        }
+        if let Some(encoding) = self
+            .mime
+            .as_deref()
+            .and_then(mime_charset)
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+        {
+            return encoding;
+        }
+        let prescan = &self.data[..self.data.len().min(CHARSET_PRESCAN_LENGTH)];
+        if let Some(encoding) = META_CHARSET_REGEX
+            .captures(prescan)
+            .and_then(|captures| Encoding::for_label(&captures[1]))
+        {
+            // a document that can be read as ASCII is not UTF-16, whatever it says
+            if encoding == UTF_16LE || encoding == UTF_16BE {
+                return UTF_8;
+            }
+            return encoding;
+        }
+        if std::str::from_utf8(&self.data).is_ok() {
+            return UTF_8;
+        }
+        let mut detector = EncodingDetector::new();
+        detector.feed(&self.data, true);
+        let tld = self
+            .domain()
+            .and_then(|domain| domain.rsplit_once('.').map(|(_, tld)| tld.to_ascii_lowercase()));
+        detector.guess(tld.as_deref().map(str::as_bytes), false)
+    }
+
+    /// This function decompresses the document's `data` as given by its `content_encoding` or,
+    /// failing that, as detected from its magic number. Returns `None` when the data is not
+    /// compressed—including when it merely looks gzipped—and an error when it cannot be
+    /// decompressed as given, or when it decompresses to more than `DEFAULT_MAX_DECODED_SIZE`
+    /// bytes.
+    pub(crate) fn decompress(&self) -> Result<Option<Document>, Issue> {
+        let encoding = match &self.content_encoding {
+            Some(label) => ContentEncoding::parse(label)
+                .ok_or_else(|| Issue::Error(format!("unsupported content encoding `{}`", label)))?,
+            None => ContentEncoding::from_magic(&self.data),
+        };
+        if encoding == ContentEncoding::Identity {
+            return Ok(None);
+        }
+        let data = match encoding.decode(&self.data) {
+            Ok(value) => value,
+            Err(Issue::Error(_)) if self.content_encoding.is_none() => return Ok(None),
+            Err(issue) => return Err(issue),
+        };
+        Ok(Some(Document {
+            url: self.url.clone(),
+            data,
+            mime: self.mime.clone(),
+            base_url: self.base_url.clone(),
+            timestamp: self.timestamp,
+            source: self.source.clone(),
+            content_encoding: None,
+            metadata: self.metadata.clone(),
+        }))
+    }
+
+    /// This function decodes the document's `data` into text in the given encoding, replacing
+    /// malformed sequences with `U+FFFD` and removing any byte order mark. Note that this
+    /// function is very different from `extract_document_text()`: this function simply decodes
+    /// text, while `extract_document_text()` also, in some cases, parses it.
+    pub(crate) fn decode(&self, encoding: &'static Encoding) -> String {
+        encoding.decode(&self.data).0.into_owned()
     }
 
     /// This function intelligently extracts text from the document—which is to say that it is
-    /// able to parse HTML documents and extract the human-readable text. Additional document types,
-    /// such as PDFs, will be supported in the future.
-    fn extract_document_text(&self) -> String {
+    /// able to parse HTML documents and extract the human-readable text (see `TextExtraction`),
+    /// to extract the string values of JSON documents (one per line, without their keys), and
+    /// (when the `pdf` feature is enabled) to extract the text of PDF documents. When the text
+    /// of a PDF cannot be extracted, it is empty; when a JSON document cannot be parsed, its
+    /// text is `raw`. In either case, a warning is added to the given issues.
+    fn extract_document_text(&self, raw: &str, extraction: TextExtraction, issues: &mut Vec<Issue>) -> String {
+        let url = self.url.as_deref().unwrap_or("unknown_document");
         match &self.detect_document_kind() {
-            DocumentKind::Html => {
-                let extracted = String::from(SPACE_REGEX.replace_all(&HTML_REGEX.replace_all(&self.raw(), " "), " "));
-                match decode_html(extracted.as_str()) {
-                    Ok(value) => value,
-                    Err(_) => extracted
+            DocumentKind::Html => extraction.extract(raw),
+            DocumentKind::Json => match flatten_json(raw) {
+                Some(fields) => {
+                    let values: Vec<String> = fields.into_iter().map(|(_, value)| value).collect();
+                    values.join("\n")
+                }
+                None => {
+                    issues.push(Issue::Warning(format!(
+                        "unable to parse JSON document `{}`; its text is its raw content",
+                        url
+                    )));
+                    String::from(raw)
+                }
+            },
+            DocumentKind::Pdf => match extract_pdf_text(&self.data) {
+                Ok(value) => value,
+                Err(error) => {
+                    issues.push(Issue::Warning(format!(
+                        "unable to extract the text of PDF `{}` (`{}`); its text is empty",
+                        url, error
+                    )));
+                    String::new()
                 }
             },
-            DocumentKind::Unknown => self.raw(),
+            DocumentKind::Unknown => String::from(raw),
+        }
+    }
+
+    /// This function extracts the structural elements of the document from its decoded text.
+    /// Only HTML documents (and, for their `fields`, JSON documents) have structure; for other
+    /// documents, the returned structure is empty.
+    fn extract_document_structure(&self, raw: &str) -> DocumentStructure {
+        match &self.detect_document_kind() {
+            DocumentKind::Html => {
+                let title = match TITLE_REGEX.captures(raw) {
+                    Some(captures) => clean_fragment(&captures[1]),
+                    None => String::new(),
+                };
+                let headings: Vec<String> = HEADING_REGEX
+                    .captures_iter(raw)
+                    .map(|captures| clean_fragment(&captures[1]))
+                    .filter(|heading| !heading.is_empty())
+                    .collect();
+                let links: Vec<String> = LINK_REGEX
+                    .captures_iter(raw)
+                    .filter_map(|captures| {
+                        let attributes = captures.get(1).map_or("", |value| value.as_str());
+                        let href = get_attribute(attributes, "href")?;
+                        Some(format!("{} {}", href, clean_fragment(&captures[2])).trim().to_string())
+                    })
+                    .collect();
+                let link_targets = self.extract_link_targets(raw);
+                let metadata: Vec<String> = META_REGEX
+                    .captures_iter(raw)
+                    .filter_map(|captures| {
+                        let attributes = &captures[1];
+                        let name = get_attribute(attributes, "name")
+                            .or_else(|| get_attribute(attributes, "property"))
+                            .or_else(|| get_attribute(attributes, "http-equiv"))?;
+                        let content = get_attribute(attributes, "content")?;
+                        Some(format!("{}: {}", name, content))
+                    })
+                    .collect();
+                let paragraphs: Vec<String> = PARAGRAPH_REGEX
+                    .captures_iter(raw)
+                    .map(|captures| clean_fragment(&captures[1]))
+                    .filter(|paragraph| !paragraph.is_empty())
+                    .collect();
+                DocumentStructure {
+                    title,
+                    headings: headings.join("\n"),
+                    links: links.join("\n"),
+                    link_targets: link_targets.join("\n"),
+                    metadata: metadata.join("\n"),
+                    paragraphs: paragraphs.join("\n\n"),
+                    fields: String::new(),
+                }
+            }
+            DocumentKind::Json => {
+                let fields: Vec<String> = flatten_json(raw)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(key, value)| match key.is_empty() {
+                        true => value,
+                        false => format!("{}: {}", key, value),
+                    })
+                    .collect();
+                DocumentStructure {
+                    fields: fields.join("\n"),
+                    ..DocumentStructure::default()
+                }
+            }
+            DocumentKind::Pdf | DocumentKind::Unknown => DocumentStructure::default(),
+        }
+    }
+
+    /// This function resolves the targets of the links of the given HTML
+    /// (see `DocumentStructure::link_targets`), in order of appearance.
+    fn extract_link_targets(&self, raw: &str) -> Vec<String> {
+        let document_url = self.resolved_url();
+        let base_url = BASE_REGEX
+            .captures(raw)
+            .and_then(|captures| get_attribute(&captures[1], "href"))
+            .and_then(|href| match &document_url {
+                Some(url) => url.join(&href).ok(),
+                None => Url::parse(&href).ok(),
+            })
+            .or(document_url);
+        let mut seen: HashSet<String> = HashSet::new();
+        LINK_REGEX
+            .captures_iter(raw)
+            .filter_map(|captures| {
+                let attributes = captures.get(1).map_or("", |value| value.as_str());
+                let href = get_attribute(attributes, "href")?;
+                let target = match &base_url {
+                    Some(url) => url.join(href.trim()).ok()?,
+                    None => Url::parse(href.trim()).ok()?,
+                };
+                if target.scheme() == "javascript" {
+                    return None;
+                }
+                Some(String::from(target.as_str()))
+            })
+            .filter(|target| seen.insert(target.clone()))
+            .collect()
+    }
+}
+
+/// Computes the SHA-256 hash of the given bytes, in lowercase hexadecimal.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl TextExtraction {
+    /// Extracts the human-readable text from the given HTML.
+    pub fn extract(self, html: &str) -> String {
+        match self {
+            TextExtraction::Dom => extract_dom_text(html),
+            TextExtraction::Naive => extract_naive_text(html),
+        }
+    }
+}
+
+/// A step of the traversal of an HTML document in `extract_dom_text()`.
+enum DomVisit<'a> {
+    Element(ElementRef<'a>),
+    Text(&'a str),
+    BlockEnd,
+}
+
+/// Extracts the human-readable text from the given HTML by parsing it.
+/// (See `TextExtraction::Dom`.)
+fn extract_dom_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    element_text(document.root_element(), |_| false)
+}
+
+/// Extracts the human-readable text of the given element as described in
+/// `TextExtraction::Dom`, also skipping the elements (and their contents)
+/// for which `skip` returns `true`.
+pub(crate) fn element_text<F: Fn(ElementRef) -> bool>(element: ElementRef, skip: F) -> String {
+    let mut extracted = String::new();
+    // the element is traversed with a stack, as deeply nested markup
+    // would otherwise overflow the call stack
+    let mut stack: Vec<DomVisit> = vec![DomVisit::Element(element)];
+    while let Some(visit) = stack.pop() {
+        let element = match visit {
+            DomVisit::Element(element) => element,
+            DomVisit::Text(text) => {
+                extracted.push_str(text);
+                continue;
+            }
+            DomVisit::BlockEnd => {
+                extracted.push('\n');
+                continue;
+            }
+        };
+        let name = element.value().name();
+        if HIDDEN_ELEMENTS.contains(&name) || skip(element) {
+            continue;
+        }
+        if BLOCK_ELEMENTS.contains(&name) {
+            extracted.push('\n');
+            stack.push(DomVisit::BlockEnd);
+        }
+        for child in element.children().rev() {
+            match child.value() {
+                Node::Text(text) => stack.push(DomVisit::Text(text)),
+                Node::Element(_) => stack.extend(ElementRef::wrap(child).map(DomVisit::Element)),
+                _ => (), // comments and the like are not text
+            }
+        }
+    }
+    let lines: Vec<String> = extracted
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+/// Extracts the human-readable text from the given HTML by removing its
+/// tags. (See `TextExtraction::Naive`.)
+fn extract_naive_text(html: &str) -> String {
+    let extracted = String::from(SPACE_REGEX.replace_all(&HTML_REGEX.replace_all(html, " "), " "));
+    match decode_html(extracted.as_str()) {
+        Ok(value) => value,
+        Err(_) => extracted
+    }
+}
+
+/// The MIME type of the given content type, without its parameters (such
+/// as `charset`), lowercased.
+fn mime_essence(mime: &str) -> String {
+    mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// The value of the `charset` parameter of the given content type, if any.
+fn mime_charset(mime: &str) -> Option<&str> {
+    mime.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches('"'))
+        } else {
+            None
         }
+    })
+}
+
+/// Extracts the text of the given PDF.
+#[cfg(feature = "pdf")]
+fn extract_pdf_text(data: &[u8]) -> Result<String, String> {
+    // the PDF library panics on some malformed documents
+    match std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data)) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(error)) => Err(error.to_string()),
+        Err(_) => Err(String::from("malformed PDF")),
     }
 }
 
+/// Extracts the text of the given PDF, which requires the `pdf` feature.
+#[cfg(not(feature = "pdf"))]
+fn extract_pdf_text(_data: &[u8]) -> Result<String, String> {
+    Err(String::from("PDF support requires the `pdf` feature"))
+}
+
+/// Removes the markup from the given fragment of HTML, decodes its
+/// entities, and collapses its whitespace.
+fn clean_fragment(fragment: &str) -> String {
+    let stripped = HTML_REGEX.replace_all(fragment, " ");
+    let collapsed = SPACE_REGEX.replace_all(&stripped, " ");
+    let decoded = match decode_html(&collapsed) {
+        Ok(value) => value,
+        Err(_) => collapsed.into_owned(),
+    };
+    String::from(decoded.trim())
+}
+
+/// Flattens the given JSON—or sequence of JSON values, as in JSON Lines—into
+/// its string values, in order, each paired with its dotted key path (such
+/// as `items.0.title`; the path of a top-level string is empty). Returns
+/// `None` if it is not valid JSON.
+fn flatten_json(raw: &str) -> Option<Vec<(String, String)>> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for value in serde_json::Deserializer::from_str(raw).into_iter::<Value>() {
+        flatten_json_value(&value.ok()?, "", &mut fields);
+    }
+    Some(fields)
+}
+
+/// Adds the string values within the given JSON value, whose key path is
+/// given, to the given fields. (See `flatten_json()`.)
+fn flatten_json_value(value: &Value, path: &str, fields: &mut Vec<(String, String)>) {
+    let child_path = |key: &str| match path.is_empty() {
+        true => String::from(key),
+        false => format!("{}.{}", path, key),
+    };
+    match value {
+        Value::String(text) => fields.push((String::from(path), text.clone())),
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten_json_value(item, &child_path(&index.to_string()), fields);
+            }
+        }
+        Value::Object(entries) => {
+            for (key, item) in entries {
+                flatten_json_value(item, &child_path(key), fields);
+            }
+        }
+        _ => (), // numbers, booleans, and nulls are not text
+    }
+}
+
+/// Finds the value of the attribute with the given (case-insensitive) name
+/// among the given HTML tag attributes, decoding its entities.
+fn get_attribute(attributes: &str, name: &str) -> Option<String> {
+    ATTRIBUTE_REGEX
+        .captures_iter(attributes)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .and_then(|captures| captures.get(2).or_else(|| captures.get(3)).or_else(|| captures.get(4)))
+        .map(|value| match decode_html(value.as_str()) {
+            Ok(decoded) => decoded,
+            Err(_) => String::from(value.as_str()),
+        })
+}
+
 impl CompilableTo<CompiledDocument> for Document {
     fn compile(&self) -> Result<CompiledDocument, Issue> {
-        let text = self.extract_document_text();
+        self.compile_with(TextExtraction::default())
+    }
+}
+
+impl Document {
+    /// Compiles the document like `compile()`, but extracts the text of
+    /// HTML documents as given. (See `TextExtraction`.)
+    pub fn compile_with(&self, text_extraction: TextExtraction) -> Result<CompiledDocument, Issue> {
+        // data is decompressed at most once, so that nested (or
+        // self-reproducing) compressed data cannot recurse indefinitely
+        match self.decompress()? {
+            Some(decompressed) => decompressed.compile_decompressed(text_extraction),
+            None => self.compile_decompressed(text_extraction),
+        }
+    }
+
+    /// Compiles the document, whose data has already been decompressed
+    /// (if necessary), like `compile_with()`.
+    fn compile_decompressed(&self, text_extraction: TextExtraction) -> Result<CompiledDocument, Issue> {
+        let encoding = self.detect_encoding();
+        let raw = self.decode(encoding);
+        let mut issues: Vec<Issue> = Vec::new();
+        let text = self.extract_document_text(&raw, text_extraction, &mut issues);
         let domain = self.domain();
-        let raw = self.raw();
+        let language = detect_language(&text);
+        let structure = self.extract_document_structure(&raw);
+        let robots = RobotsDirectives::from_metadata(&structure.metadata);
+        let binary = if raw.as_bytes() == self.data.as_slice() {
+            None // the bytes are already available as `raw`
+        } else {
+            Some(self.data.clone())
+        };
         Ok(CompiledDocument {
             url: self.url.clone(),
-            raw: raw,
-            mime: self.mime.clone(),
-            text: text,
-            domain: domain,
+            raw,
+            mime: self.mime.as_deref().map(mime_essence),
+            text,
+            domain,
+            language,
+            timestamp: self.timestamp,
+            source: self.source.clone(),
+            metadata: self.metadata.clone(),
+            structure,
+            is_html: match self.detect_document_kind() {
+                DocumentKind::Html => true,
+                DocumentKind::Json | DocumentKind::Pdf | DocumentKind::Unknown => false,
+            },
+            robots,
+            encoding: String::from(encoding.name()),
+            binary,
+            hash: hash_bytes(&self.data),
+            provenance: None,
+            text_extraction,
+            issues,
+            transformed: Mutex::new(HashMap::new()),
+            main_content: OnceLock::new(),
         })
     }
 }
 
 impl CompilableTo<CompiledDocumentBatch> for DocumentBatch {
     fn compile(&self) -> Result<CompiledDocumentBatch, Issue> {
+        self.compile_with(TextExtraction::default())
+    }
+}
+
+impl DocumentBatch {
+    /// Compiles the batch like `compile()`, but extracts the text of HTML
+    /// documents as given. (See `TextExtraction`.)
+    pub fn compile_with(&self, text_extraction: TextExtraction) -> Result<CompiledDocumentBatch, Issue> {
         let mut compiled_documents: Vec<CompiledDocument> = Vec::new();
         for document in &self.documents {
-            let compiled_document = match document.compile() {
+            let compiled_document = match document.compile_with(text_extraction) {
                 Ok(value) => value,
                 Err(_error) => continue, // silent failure
             };
@@ -207,12 +985,89 @@ impl CompiledDocument {
     /// given `ScopeContent`. For example, if the `ScopeContent`
     /// is `Raw`, this function will return the document's `Raw` data.
     /// If it is `Text`, this function will return the document's parsed
-    /// text.
+    /// text. If it is `Bytes`, this function will return the `Raw` data,
+    /// as the document's bytes may not be valid text (see `bytes()`).
+    /// Structural content, such as `Title`, is taken from the document's
+    /// `structure`.
     pub fn content(&self, content: ScopeContent) -> &String {
         match content {
-            ScopeContent::Raw => &self.raw,
+            ScopeContent::Raw | ScopeContent::Bytes => &self.raw,
             ScopeContent::Text => &self.text,
+            ScopeContent::Title => &self.structure.title,
+            ScopeContent::Headings => &self.structure.headings,
+            ScopeContent::Links => &self.structure.links,
+            ScopeContent::LinkTargets => &self.structure.link_targets,
+            ScopeContent::Metadata => &self.structure.metadata,
+            ScopeContent::Paragraphs => &self.structure.paragraphs,
+            ScopeContent::Fields => &self.structure.fields,
+            ScopeContent::MainContent => self.main_content(),
+        }
+    }
+
+    /// This function returns the main content of the document (see
+    /// `ScopeContent::MainContent`), extracting it the first time it is
+    /// needed. Documents that are not HTML are all main content.
+    pub fn main_content(&self) -> &String {
+        self.main_content.get_or_init(|| match self.is_html {
+            true => extract_main_content(&self.raw),
+            false => self.text.clone(),
+        })
+    }
+
+    /// This function returns the document content relative to the given
+    /// `ScopeContent`, with the given transforms applied in order. (See
+    /// `Scope.transforms`.) Each distinct combination of content and
+    /// transforms is only computed once; later calls share the result.
+    pub fn transformed_content(
+        &self,
+        content: ScopeContent,
+        transforms: &[ContentTransform],
+    ) -> Arc<String> {
+        let key = (content, transforms.to_vec());
+        if let Some(value) = self.transformed.lock().unwrap().get(&key) {
+            return value.clone();
+        }
+        // computed without holding the lock; a concurrent computation is merely redundant
+        let strips_boilerplate = self.is_html && transforms.contains(&ContentTransform::StripBoilerplate);
+        let mut text = match content {
+            // boilerplate can only be identified in markup, so the text is extracted anew
+            ScopeContent::Text if strips_boilerplate => {
+                self.text_extraction
+                    .extract(&ContentTransform::StripBoilerplate.apply(&self.raw))
+            }
+            _ => self.content(content).clone(),
+        };
+        for transform in transforms {
+            text = match transform {
+                ContentTransform::StripBoilerplate => match content {
+                    ScopeContent::Raw if self.is_html => transform.apply(&text),
+                    _ => text, // already applied, or not applicable
+                },
+                _ => transform.apply(&text),
+            };
         }
+        let text = Arc::new(text);
+        self.transformed.lock().unwrap().insert(key, text.clone());
+        text
+    }
+
+    /// This function returns the original bytes of the document, without
+    /// any lossy conversion to text.
+    pub fn bytes(&self) -> &[u8] {
+        match &self.binary {
+            Some(value) => value.as_slice(),
+            None => self.raw.as_bytes(),
+        }
+    }
+
+    /// This function returns a stable checksum of the document's URL
+    /// and raw content. Identical documents at the same URL always have
+    /// the same checksum, even across runs.
+    pub fn checksum(&self) -> u64 {
+        let mut checksum = Checksum::new();
+        checksum.update_optional(self.url.as_deref());
+        checksum.update_optional(Some(&self.raw));
+        checksum.value()
     }
 }
 
@@ -222,8 +1077,398 @@ impl From<Vec<Document>> for DocumentBatch {
     }
 }
 
+impl DocumentReferenceBatch {
+    /// Identifies the batch by the given ID. (See `id`.)
+    pub fn with_id(self, id: &str) -> DocumentReferenceBatch {
+        DocumentReferenceBatch {
+            id: Some(String::from(id)),
+            ..self
+        }
+    }
+}
+
+impl DocumentReference {
+    /// The reference to the document, as recorded in its `Provenance`:
+    /// its path or, for documents already in memory, its URL.
+    pub fn reference(&self) -> Option<&str> {
+        match self {
+            DocumentReference::Populated(document) => document.url.as_deref(),
+            DocumentReference::Unpopulated(path) => Some(path.as_str()),
+        }
+    }
+}
+
 impl From<Vec<DocumentReference>> for DocumentReferenceBatch {
     fn from(docs: Vec<DocumentReference>) -> DocumentReferenceBatch {
-        DocumentReferenceBatch { documents: docs }
+        DocumentReferenceBatch {
+            documents: docs,
+            id: None,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Document {
+    /// A document with the given URL and text, and no other information;
+    /// shared by the tests throughout the crate.
+    pub(crate) fn from_text(url: &str, text: &str) -> Document {
+        Document {
+            url: Some(String::from(url)),
+            data: text.as_bytes().to_vec(),
+            mime: None,
+            base_url: None,
+            timestamp: None,
+            source: None,
+            content_encoding: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// A document with the given URL and HTML, whose MIME type is
+    /// `text/html`; shared by the tests throughout the crate.
+    pub(crate) fn from_html(url: &str, html: &str) -> Document {
+        Document {
+            mime: Some(String::from("text/html")),
+            ..Document::from_text(url, html)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_document(url: &str, base_url: Option<&str>) -> Document {
+        Document {
+            base_url: base_url.map(String::from),
+            ..Document::from_text(url, "")
+        }
+    }
+
+    #[test]
+    fn test_domain_extraction() {
+        let domain = |url, base| get_document(url, base).domain();
+        assert_eq!(domain("https://example.com/a", None), Some(String::from("example.com")));
+        assert_eq!(domain("file:///tmp/a.html", None), Some(String::from("localhost")));
+        assert_eq!(domain("file://server/a.html", None), Some(String::from("server")));
+        assert_eq!(domain("data:text/plain,hello", None), None);
+        assert_eq!(domain("/tmp/a.html", None), Some(String::from("localhost")));
+        assert_eq!(
+            domain("news/today.html", Some("https://example.com/")),
+            Some(String::from("example.com"))
+        );
+    }
+
+    #[test]
+    fn test_language_detection() {
+        let language = |text: &str| {
+            let mut document = get_document("https://example.com/a.html", None);
+            document.data = text.as_bytes().to_vec();
+            document.compile().unwrap().language
+        };
+        assert_eq!(
+            language("<p>Hello everyone, and welcome to the evening news.</p>"),
+            Some(String::from("en"))
+        );
+        assert_eq!(
+            language("<p>Bonjour à tous, et bienvenue au journal du soir.</p>"),
+            Some(String::from("fr"))
+        );
+        assert_eq!(language("<p>42</p>"), None);
+        assert!(is_detectable_language("zh"));
+        assert!(!is_detectable_language("xx"));
+    }
+
+    #[test]
+    fn test_document_hash() {
+        let hash = |url: &str, data: &[u8]| {
+            let mut document = get_document(url, None);
+            document.data = data.to_vec();
+            document.compile().unwrap().hash
+        };
+        assert_eq!(
+            hash("https://example.com/a", b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hash("https://example.com/a", b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash("https://mirror.example/a", b"abc"), hash("https://example.com/a", b"abc"));
+        assert_ne!(hash("https://example.com/a", b"\xff"), hash("https://example.com/a", b"\xfe"));
+    }
+
+    #[test]
+    fn test_structure_extraction() {
+        let mut document = get_document("https://example.com/a.html", None);
+        document.data = br#"<html><head><title>Today &amp; tomorrow</title>
+            <meta charset="utf-8"><meta name="description" content="The news">
+            <meta content='Example' property=og:site_name></head>
+            <body><h1 class="big">Breaking <em>news</em></h1><p>Body text</p>
+            <h2>Weather</h2><a href="/sports">Sports</a><a name="anchor">Skipped</a>
+            </body></html>"#
+            .to_vec();
+        let compiled = document.compile().unwrap();
+        assert_eq!(compiled.content(ScopeContent::Title), "Today & tomorrow");
+        assert_eq!(compiled.content(ScopeContent::Headings), "Breaking news\nWeather");
+        assert_eq!(compiled.content(ScopeContent::Links), "/sports Sports");
+        assert_eq!(compiled.content(ScopeContent::LinkTargets), "https://example.com/sports");
+        assert_eq!(
+            compiled.content(ScopeContent::Metadata),
+            "description: The news\nog:site_name: Example"
+        );
+        assert_eq!(compiled.content(ScopeContent::Paragraphs), "Body text");
+
+        let plain = get_document("https://example.com/a.txt", None).compile().unwrap();
+        assert_eq!(plain.structure, DocumentStructure::default());
+    }
+
+    #[test]
+    fn test_link_target_extraction() {
+        let link_targets = |url: &str, base_url: Option<&str>, html: &str| {
+            let mut document = get_document(url, base_url);
+            document.data = html.as_bytes().to_vec();
+            document.mime = Some(String::from("text/html"));
+            document.compile().unwrap().structure.link_targets
+        };
+        let html = r#"<a href="../b.html">B</a> <a href='https://bad.example/x?y=1'>X</a>
+            <a href="javascript:void(0)">Menu</a> <a href="../b.html">B again</a>"#;
+        assert_eq!(
+            link_targets("https://example.com/news/a.html", None, html),
+            "https://example.com/b.html\nhttps://bad.example/x?y=1"
+        );
+        assert_eq!(
+            link_targets("news/a.html", Some("https://example.com/"), html),
+            "https://example.com/b.html\nhttps://bad.example/x?y=1"
+        );
+        assert_eq!(link_targets("/tmp/a.html", None, html), "https://bad.example/x?y=1");
+        assert_eq!(
+            link_targets(
+                "https://example.com/a.html",
+                None,
+                r#"<base href="https://cdn.example.net/docs/"><a href="c.html">C</a>"#
+            ),
+            "https://cdn.example.net/docs/c.html"
+        );
+    }
+
+    #[test]
+    fn test_transformed_content() {
+        let mut document = get_document("https://example.com/a.html", None);
+        document.data = b"<nav>Home | About</nav><p>Hello,   WORLD!</p><script>var x;</script>".to_vec();
+        let compiled = document.compile().unwrap();
+        let transforms = [
+            ContentTransform::Lowercase,
+            ContentTransform::StripPunctuation,
+            ContentTransform::CollapseWhitespace,
+            ContentTransform::StripBoilerplate,
+        ];
+        let text = compiled.transformed_content(ScopeContent::Text, &transforms);
+        assert_eq!(text.as_str(), "hello world");
+        // each pipeline is only computed once
+        assert!(Arc::ptr_eq(&text, &compiled.transformed_content(ScopeContent::Text, &transforms)));
+        assert_eq!(
+            compiled
+                .transformed_content(ScopeContent::Raw, &[ContentTransform::StripBoilerplate])
+                .as_str(),
+            " <p>Hello,   WORLD!</p> "
+        );
+    }
+
+    #[test]
+    fn test_text_extraction() {
+        let html = "<html><head><title>News</title><style>p { color: red; }</style></head><body>\
+            <div title=\"1 > 0\">Hello &amp; <b>welcome</b></div><script>var x = '<p>';</script>\
+            <ul><li>One</li><li>Two</li></ul><!-- hidden --></body></html>";
+        assert_eq!(TextExtraction::Dom.extract(html), "News\nHello & welcome\nOne\nTwo");
+        let naive = TextExtraction::Naive.extract(html);
+        assert!(naive.contains("color: red;"));
+        assert!(naive.contains("0\">Hello & welcome"));
+
+        // compilation and the scan engine default to parsing
+        let mut document = get_document("https://example.com/a.html", None);
+        document.data = html.as_bytes().to_vec();
+        assert_eq!(document.compile().unwrap().text, TextExtraction::Dom.extract(html));
+        let compiled = document.compile_with(TextExtraction::Naive).unwrap();
+        assert_eq!(compiled.text, naive);
+        assert_eq!(compiled.text_extraction, TextExtraction::Naive);
+    }
+
+    #[test]
+    fn test_encoding_detection() {
+        let compile = |url: &str, mime: Option<&str>, data: &[u8]| {
+            let mut document = get_document(url, None);
+            document.mime = mime.map(String::from);
+            document.data = data.to_vec();
+            document.compile().unwrap()
+        };
+        // from the `Content-Type` header
+        let compiled = compile(
+            "https://example.ru/a",
+            Some("text/html; charset=windows-1251"),
+            b"<p>\xcf\xf0\xe8\xe2\xe5\xf2</p>",
+        );
+        assert_eq!(compiled.encoding, "windows-1251");
+        assert_eq!(compiled.text, "Привет");
+        assert_eq!(compiled.mime.as_deref(), Some("text/html"));
+        assert!(compiled.binary.is_some());
+        // from a `<meta>` tag
+        let compiled = compile("a.html", None, b"<meta charset=\"iso-8859-1\"><p>Caf\xe9</p>");
+        assert_eq!(compiled.encoding, "windows-1252");
+        assert_eq!(compiled.text, "Café");
+        let compiled = compile(
+            "a.html",
+            None,
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\"><p>\x93\xfa\x96\x7b</p>",
+        );
+        assert_eq!(compiled.encoding, "Shift_JIS");
+        assert_eq!(compiled.text, "日本");
+        // from a byte order mark, which overrides declarations
+        let compiled = compile("a.html", Some("text/html; charset=windows-1252"), b"\xef\xbb\xbf<p>\xc3\xa9</p>");
+        assert_eq!(compiled.encoding, "UTF-8");
+        assert_eq!(compiled.text, "é");
+        // from the bytes themselves
+        let compiled = compile("https://example.com/a", None, "naïve".as_bytes());
+        assert_eq!(compiled.encoding, "UTF-8");
+        assert!(compiled.binary.is_none());
+        let compiled = compile(
+            "https://example.de/a",
+            None,
+            b"Gr\xfc\xdfe aus M\xfcnchen, wo die Stra\xdfen sch\xf6n und die B\xe4ume gr\xfcn sind.",
+        );
+        assert_eq!(compiled.encoding, "windows-1252");
+        assert!(compiled.raw.contains("Grüße aus München"));
+    }
+
+    #[test]
+    fn test_compressed_documents() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let html = b"<title>Recall</title><p>Hello &amp; welcome</p>";
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(html).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        // gzipped data is detected by its magic number
+        let mut document = get_document("https://example.com/page.html.gz", None);
+        document.data = gzipped.clone();
+        let compiled = document.compile().unwrap();
+        assert!(compiled.is_html);
+        assert_eq!(compiled.text, "Recall\nHello & welcome");
+        assert_eq!(compiled.hash, hash_bytes(html));
+
+        // other encodings must be given
+        let mut brotli = vec![0x30, 0x01, 0x10];
+        brotli.extend_from_slice(&b"<p>Hello, world!</p>"[..]);
+        brotli.push(0x03);
+        let mut document = get_document("https://example.com/a", None);
+        document.mime = Some(String::from("text/html"));
+        document.data = brotli;
+        document.content_encoding = Some(String::from("br"));
+        assert_eq!(document.compile().unwrap().text, "Hello, world!");
+        document.content_encoding = Some(String::from("gzip"));
+        assert!(document.compile().is_err());
+        document.content_encoding = Some(String::from("compress"));
+        assert!(document.compile().is_err());
+
+        // nested compression is only decompressed once
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&gzipped).unwrap();
+        let mut document = get_document("https://example.com/page.html.gz.gz", None);
+        document.data = encoder.finish().unwrap();
+        assert_eq!(document.compile().unwrap().bytes(), gzipped.as_slice());
+
+        // data that merely looks gzipped is left as it is
+        let mut document = get_document("https://example.com/a", None);
+        document.data = gzipped[..4].to_vec();
+        assert_eq!(document.compile().unwrap().bytes(), &gzipped[..4]);
+    }
+
+    #[test]
+    fn test_json_documents() {
+        let compile = |url: &str, mime: Option<&str>, data: &str| {
+            let mut document = get_document(url, None);
+            document.mime = mime.map(String::from);
+            document.data = data.as_bytes().to_vec();
+            document.compile().unwrap()
+        };
+        let json = r#"{"title": "Recall: \"Acme\" blenders", "count": 2, "items": [{"name": "A\u00e9"}, {"name": "B", "ok": true}]}"#;
+        let compiled = compile("https://api.example.com/recalls", Some("application/json; charset=utf-8"), json);
+        assert_eq!(compiled.text, "Recall: \"Acme\" blenders\nAé\nB");
+        assert_eq!(
+            compiled.content(ScopeContent::Fields),
+            "title: Recall: \"Acme\" blenders\nitems.0.name: Aé\nitems.1.name: B"
+        );
+        assert!(!compiled.is_html);
+        assert!(compiled.issues.is_empty());
+
+        // JSON Lines, detected by extension
+        let compiled = compile("/feeds/today.jsonl", None, "{\"a\": \"x\"}\n\"y\"\n");
+        assert_eq!(compiled.content(ScopeContent::Fields), "a: x\ny");
+
+        // invalid JSON is scanned as it is
+        let compiled = compile("https://example.com/a.json", None, "{\"a\": ");
+        assert_eq!(compiled.text, "{\"a\": ");
+        assert_eq!(compiled.content(ScopeContent::Fields), "");
+        assert_eq!(compiled.issues.len(), 1);
+    }
+
+    /// Builds a minimal PDF with a single page that shows the given text.
+    fn get_pdf(text: &str) -> Vec<u8> {
+        let stream = format!("BT /F1 12 Tf 72 712 Td ({}) Tj ET", text);
+        let objects = [
+            String::from("<< /Type /Catalog /Pages 2 0 R >>"),
+            String::from("<< /Type /Pages /Kids [3 0 R] /Count 1 >>"),
+            String::from(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>",
+            ),
+            format!("<< /Length {} >>\nstream\n{}\nendstream", stream.len(), stream),
+            String::from("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>"),
+        ];
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets: Vec<usize> = Vec::new();
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", index + 1, object));
+        }
+        let xref = pdf.len();
+        pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            pdf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        ));
+        pdf.into_bytes()
+    }
+
+    #[test]
+    fn test_pdf_text_extraction() {
+        let compile = |url: &str, mime: Option<&str>, data: Vec<u8>| {
+            let mut document = get_document(url, None);
+            document.mime = mime.map(String::from);
+            document.data = data;
+            document.compile().unwrap()
+        };
+        // PDFs are detected by their magic number, regardless of their URL
+        let compiled = compile("https://example.com/report", None, get_pdf("Quarterly recall notice"));
+        assert!(!compiled.is_html);
+        if cfg!(feature = "pdf") {
+            assert!(compiled.text.contains("Quarterly recall notice"));
+            assert!(compiled.issues.is_empty());
+        } else {
+            assert_eq!(compiled.text, "");
+            assert_eq!(compiled.issues.len(), 1);
+        }
+        assert!(compiled.raw.starts_with("%PDF-"));
+
+        // ...or by their MIME type; when extraction fails, the text is empty
+        let compiled = compile("https://example.com/a", Some("application/pdf"), b"not a PDF".to_vec());
+        assert_eq!(compiled.text, "");
+        assert!(matches!(compiled.issues.as_slice(), [Issue::Warning(_)]));
+        assert!(compile("https://example.com/a", None, b"not a PDF".to_vec()).issues.is_empty());
     }
 }
@@ -0,0 +1,338 @@
+//! This module provides functionality for expanding RSS and Atom feeds
+//! into their entries, so that each entry of a feed is scanned—and
+//! matched—as a document of its own, rather than the feed being scanned
+//! as one blob of XML in which the entries run together.
+//!
+//! Each entry becomes an HTML `Document` whose title is the entry's title
+//! and whose body is the entry's content (or, failing that, its summary or
+//! description), so that structural contents such as `Title` and
+//! `Paragraphs` apply to entries as they do to web pages. RSS 0.9x, 1.0
+//! (RDF), and 2.0 feeds are supported, as are Atom feeds.
+
+use common::timestamp::{parse_rfc2822_timestamp, parse_timestamp};
+use common::validation::Issue;
+use htmlescape::encode_minimal;
+use input::content_encoding::ContentEncoding;
+use input::document::{Document, TextExtraction};
+use lazy_static::lazy_static;
+use regex::bytes::Regex as BytesRegex;
+use roxmltree::{Node, ParsingOptions};
+
+/// The MIME types of feeds.
+const FEED_MIMES: [&str; 3] = ["application/rss+xml", "application/atom+xml", "application/rdf+xml"];
+
+/// The number of bytes at the start of a document within which the root
+/// element of a feed is sought.
+const FEED_PRESCAN_LENGTH: usize = 1024;
+
+lazy_static! {
+    /// Matches the start of an XML document whose root element is that of
+    /// a feed (`rss`, `feed`, or `rdf:RDF`), after any byte order mark,
+    /// XML declaration, processing instructions, and comments.
+    static ref FEED_ROOT_REGEX: BytesRegex = BytesRegex::new(
+        r"(?s)^(?:\xEF\xBB\xBF)?\s*(?:<\?.*?\?>\s*|<!--.*?-->\s*)*<(?:rss|feed|rdf:RDF)[\s>]"
+    )
+    .unwrap();
+}
+
+/// Whether the file at the given path is a feed, judging by its extension
+/// (`.rss` or `.atom`, optionally compressed; see `ContentEncoding`).
+pub fn is_feed_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    let path = ContentEncoding::from_extension(&path).strip_extension(&path);
+    path.ends_with(".rss") || path.ends_with(".atom")
+}
+
+/// Whether the given document is a feed, judging by its MIME type or, for
+/// uncompressed documents, by its root element.
+pub fn is_feed(document: &Document) -> bool {
+    let has_feed_mime = document.mime.as_deref().is_some_and(|mime| {
+        let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        FEED_MIMES.contains(&essence.as_str())
+    });
+    let prescan = &document.data[..document.data.len().min(FEED_PRESCAN_LENGTH)];
+    has_feed_mime || FEED_ROOT_REGEX.is_match(prescan)
+}
+
+/// The first child element of the given node with the given local name
+/// (that is, regardless of its namespace, so that `content:encoded`
+/// is `encoded`).
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// The text of the given node, trimmed.
+fn node_text(node: Node) -> String {
+    let text: String = node
+        .descendants()
+        .filter(|descendant| descendant.is_text())
+        .filter_map(|descendant| descendant.text())
+        .collect();
+    String::from(text.trim())
+}
+
+/// The text of the first child element of the given node with the given
+/// local name, if it is present and not empty.
+fn child_text(node: Node, name: &str) -> Option<String> {
+    let text = node_text(child(node, name)?);
+    match text.is_empty() {
+        true => None,
+        false => Some(text),
+    }
+}
+
+/// The contents of the given Atom text construct (such as `title` or
+/// `content`) as HTML, given its `type`: `html` is escaped HTML, `xhtml`
+/// is inline XHTML, and anything else is plain text. Returns `None` for
+/// out-of-line content (which has a `src`).
+fn atom_html(node: Node, xml: &str) -> Option<String> {
+    if node.has_attribute("src") {
+        return None;
+    }
+    match node.attribute("type").unwrap_or("text") {
+        "html" => Some(node_text(node)),
+        "xhtml" => match (node.first_child(), node.last_child()) {
+            (Some(first), Some(last)) => Some(String::from(xml[first.range().start..last.range().end].trim())),
+            _ => Some(String::new()),
+        },
+        _ => Some(encode_minimal(&node_text(node))),
+    }
+}
+
+/// The parts of an entry of a feed from which its `Document` is assembled.
+struct FeedEntry {
+    /// The entry's link, as given.
+    link: Option<String>,
+    /// The entry's title, as text.
+    title: Option<String>,
+    /// The entry's content, as HTML.
+    content: Option<String>,
+    /// When the entry was published (or, failing that, updated).
+    timestamp: Option<i64>,
+}
+
+impl FeedEntry {
+    /// Reads an RSS `item`.
+    fn from_rss(item: Node) -> FeedEntry {
+        let permalink = child(item, "guid")
+            .filter(|guid| guid.attribute("isPermaLink") != Some("false"))
+            .map(node_text)
+            .filter(|guid| guid.starts_with("http://") || guid.starts_with("https://"));
+        let pub_date = child_text(item, "pubDate").and_then(|date| parse_rfc2822_timestamp(&date).ok());
+        let dc_date = child_text(item, "date").and_then(|date| parse_timestamp(&date).ok());
+        FeedEntry {
+            link: child_text(item, "link").or(permalink),
+            title: child_text(item, "title"),
+            content: child_text(item, "encoded").or_else(|| child_text(item, "description")),
+            timestamp: pub_date.or(dc_date),
+        }
+    }
+
+    /// Reads an Atom `entry`.
+    fn from_atom(entry: Node, xml: &str) -> FeedEntry {
+        let link = entry
+            .children()
+            .filter(|child| child.is_element() && child.tag_name().name() == "link")
+            .find(|link| link.attribute("rel").unwrap_or("alternate") == "alternate")
+            .and_then(|link| link.attribute("href"))
+            .map(String::from);
+        let title = child(entry, "title")
+            .and_then(|title| atom_html(title, xml))
+            .map(|title| TextExtraction::Dom.extract(&title).replace('\n', " "))
+            .filter(|title| !title.is_empty());
+        let content = child(entry, "content")
+            .and_then(|content| atom_html(content, xml))
+            .or_else(|| child(entry, "summary").and_then(|summary| atom_html(summary, xml)));
+        let timestamp = child_text(entry, "published")
+            .or_else(|| child_text(entry, "updated"))
+            .and_then(|date| parse_timestamp(&date).ok());
+        FeedEntry {
+            link,
+            title,
+            content,
+            timestamp,
+        }
+    }
+
+    /// Assembles the entry's `Document`. The entry's link is resolved
+    /// against the feed's URL; entries without a link are identified by
+    /// their index within the feed (as in `feed.rss#item-3`).
+    fn into_document(self, feed: &Document, index: usize) -> Document {
+        let link = self.link.map(|link| {
+            match feed.resolved_url().and_then(|base| base.join(&link).ok()) {
+                Some(url) => String::from(url.as_str()),
+                None => link,
+            }
+        });
+        let url = link.or_else(|| feed.url.as_ref().map(|url| format!("{}#item-{}", url, index)));
+        let html = format!(
+            "<!DOCTYPE html>\n<html><head><title>{}</title></head><body>{}</body></html>",
+            encode_minimal(self.title.as_deref().unwrap_or("")),
+            self.content.as_deref().unwrap_or("")
+        );
+        Document {
+            url,
+            data: html.into_bytes(),
+            mime: Some(String::from("text/html; charset=utf-8")),
+            base_url: feed.base_url.clone(),
+            timestamp: self.timestamp.or(feed.timestamp),
+            source: feed.source.clone(),
+            content_encoding: None,
+            metadata: feed.metadata.clone(),
+        }
+    }
+}
+
+/// Expands the given RSS or Atom feed into one `Document` per entry, in
+/// order (see the module-level documentation). Each document's `url` is
+/// the entry's link, its `timestamp` is when the entry was published (or,
+/// failing that, the feed's `timestamp`), and its `source` is the feed's.
+///
+/// The feed is decompressed and decoded like any other document (see
+/// `Document::detect_encoding()`). Returns an error when the feed is not
+/// well-formed XML, or when it is not an RSS or Atom feed.
+pub fn parse_feed(feed: &Document) -> Result<Vec<Document>, Issue> {
+    let decompressed = feed.decompress()?;
+    let decompressed = decompressed.as_ref().unwrap_or(feed);
+    let xml = decompressed.decode(decompressed.detect_encoding());
+    let name = feed.url.as_deref().unwrap_or("unknown_document");
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let parsed = roxmltree::Document::parse_with_options(&xml, options)
+        .map_err(|error| Issue::Error(format!("unable to parse feed `{}` (`{}`)", name, error)))?;
+    let root = parsed.root_element();
+    let entries: Vec<FeedEntry> = match root.tag_name().name() {
+        // RSS 2.0 items are within the channel; RSS 1.0 items are not
+        "rss" | "RDF" => root
+            .descendants()
+            .filter(|node| node.is_element() && node.tag_name().name() == "item")
+            .map(FeedEntry::from_rss)
+            .collect(),
+        "feed" => root
+            .children()
+            .filter(|node| node.is_element() && node.tag_name().name() == "entry")
+            .map(|entry| FeedEntry::from_atom(entry, &xml))
+            .collect(),
+        other => {
+            return Err(Issue::Error(format!(
+                "`{}` is not an RSS or Atom feed (its root element is `{}`)",
+                name, other
+            )))
+        }
+    };
+    Ok(entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| entry.into_document(feed, index))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compilation::CompilableTo;
+
+    #[test]
+    fn test_parse_rss_feed() {
+        let rss = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+            <channel>
+                <title>Example News</title>
+                <link>https://example.com/</link>
+                <item>
+                    <title>Acme recalls blenders</title>
+                    <link>/news/blenders</link>
+                    <description>Acme recalled two million blenders.</description>
+                    <content:encoded><![CDATA[<p>Acme recalled <b>two million</b> blenders on Tuesday.</p>]]></content:encoded>
+                    <pubDate>Wed, 01 May 2019 12:30:00 GMT</pubDate>
+                </item>
+                <item>
+                    <title>Markets &amp; more</title>
+                    <guid>https://example.com/news/markets</guid>
+                    <description>&lt;p&gt;Stocks rose.&lt;/p&gt;</description>
+                </item>
+                <item><description>An item without a title or a link.</description></item>
+            </channel>
+            </rss>"#;
+        let feed = Document {
+            timestamp: Some(0),
+            source: Some(String::from("rss:example")),
+            ..Document::from_text("https://example.com/feed.rss", rss)
+        };
+        assert!(is_feed(&feed));
+        let entries = parse_feed(&feed).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].url.as_deref(), Some("https://example.com/news/blenders"));
+        assert_eq!(entries[0].timestamp, Some(1_556_713_800));
+        assert_eq!(entries[0].source.as_deref(), Some("rss:example"));
+        let compiled = entries[0].compile().unwrap();
+        assert_eq!(compiled.structure.title, "Acme recalls blenders");
+        assert!(compiled.text.contains("Acme recalled two million blenders on Tuesday."));
+
+        assert_eq!(entries[1].url.as_deref(), Some("https://example.com/news/markets"));
+        assert_eq!(entries[1].timestamp, Some(0));
+        let compiled = entries[1].compile().unwrap();
+        assert_eq!(compiled.structure.title, "Markets & more");
+        assert_eq!(compiled.structure.paragraphs, "Stocks rose.");
+
+        assert_eq!(entries[2].url.as_deref(), Some("https://example.com/feed.rss#item-2"));
+    }
+
+    #[test]
+    fn test_parse_atom_feed() {
+        let atom = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <title>Example Blog</title>
+                <entry>
+                    <title type="html">Caf&#233; &lt;em&gt;reviews&lt;/em&gt;</title>
+                    <link rel="edit" href="https://example.com/edit/1"/>
+                    <link href="https://example.com/posts/1"/>
+                    <updated>2019-05-02T00:00:00Z</updated>
+                    <published>2019-05-01T12:30:00Z</published>
+                    <summary>A summary.</summary>
+                    <content type="xhtml"><div xmlns="http://www.w3.org/1999/xhtml"><p>The full post.</p></div></content>
+                </entry>
+                <entry>
+                    <title>Plain &lt;text&gt;</title>
+                    <summary>Only a summary &amp; nothing else.</summary>
+                </entry>
+            </feed>"#;
+        let feed = Document::from_text("/feeds/blog.atom", atom);
+        assert!(is_feed(&feed));
+        let entries = parse_feed(&feed).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].url.as_deref(), Some("https://example.com/posts/1"));
+        assert_eq!(entries[0].timestamp, Some(1_556_713_800));
+        let compiled = entries[0].compile().unwrap();
+        assert_eq!(compiled.structure.title, "Café reviews");
+        assert_eq!(compiled.structure.paragraphs, "The full post.");
+        assert!(!compiled.text.contains("A summary."));
+
+        assert_eq!(entries[1].url.as_deref(), Some("/feeds/blog.atom#item-1"));
+        let compiled = entries[1].compile().unwrap();
+        assert_eq!(compiled.structure.title, "Plain <text>");
+        assert!(compiled.text.contains("Only a summary & nothing else."));
+    }
+
+    #[test]
+    fn test_invalid_feeds() {
+        let html = Document::from_text("https://example.com/", "<!DOCTYPE html><html><body>Hi</body></html>");
+        assert!(!is_feed(&html));
+        assert!(parse_feed(&html).is_err());
+        let unknown = Document::from_text("https://example.com/a.xml", "<?xml version=\"1.0\"?><urlset></urlset>");
+        assert!(!is_feed(&unknown));
+        match parse_feed(&unknown) {
+            Err(Issue::Error(message)) => assert!(message.contains("`urlset`")),
+            _ => panic!("a sitemap is not a feed"),
+        }
+
+        assert!(is_feed_path("/feeds/news.RSS"));
+        assert!(is_feed_path("/feeds/blog.atom.gz"));
+        assert!(!is_feed_path("/feeds/sitemap.xml"));
+    }
+}
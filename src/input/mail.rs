@@ -0,0 +1,458 @@
+//! This module provides functionality for reading email: mbox files, which
+//! hold many messages—as mail archives do—and single RFC 822 messages
+//! (such as `.eml` files). Each message becomes a `Document` of its own.
+//!
+//! A message's document contains its body: the first HTML part of the
+//! message or, failing that, its first plain text part, decoded from
+//! base64 or quoted-printable as needed; attachments are skipped. The
+//! message's headers are the document's `metadata`, keyed by their
+//! lowercase names (such as `subject` and `from`), with any encoded words
+//! (RFC 2047) decoded.
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use common::timestamp::parse_rfc2822_timestamp;
+use common::validation::Issue;
+use encoding_rs::Encoding;
+use input::content_encoding::ContentEncoding;
+use input::document::Document;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// The depth to which multipart messages are searched for their body.
+const MAX_MULTIPART_DEPTH: usize = 8;
+
+/// Decodes base64 leniently, as mail clients write it: with or without
+/// padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+lazy_static! {
+    /// Matches an encoded word (RFC 2047), such as `=?utf-8?Q?caf=C3=A9?=`.
+    static ref ENCODED_WORD_REGEX: Regex = Regex::new(r"=\?([^?\s]+)\?([BbQq])\?([^?\s]*)\?=").unwrap();
+    /// Matches the whitespace between adjacent encoded words, which is not
+    /// part of the text.
+    static ref ENCODED_WORD_GAP_REGEX: Regex = Regex::new(r"\?=\s+=\?").unwrap();
+}
+
+/// Whether the file at the given path is an mbox file, judging by its
+/// extension (`.mbox`, optionally compressed; see `ContentEncoding`).
+pub fn is_mbox_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    ContentEncoding::from_extension(&path).strip_extension(&path).ends_with(".mbox")
+}
+
+/// Whether the file at the given path is a single message, judging by its
+/// extension (`.eml`, optionally compressed; see `ContentEncoding`).
+pub fn is_message_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    ContentEncoding::from_extension(&path).strip_extension(&path).ends_with(".eml")
+}
+
+/// The headers of a message or of a part of one: pairs of their lowercase
+/// names and their (unfolded) values, in order.
+type Headers = Vec<(String, String)>;
+
+/// The value of the first header with the given (lowercase) name, if any.
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The value of the parameter with the given name of the given header
+/// value (such as the `boundary` of a `Content-Type`), unquoted.
+fn header_parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        match key.trim().eq_ignore_ascii_case(name) {
+            true => Some(String::from(value.trim().trim_matches('"'))),
+            false => None,
+        }
+    })
+}
+
+/// Removes the line ending (`\n` or `\r\n`) from the end of the given line,
+/// if it has one.
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Splits the given message (or part) into its headers and its body, which
+/// are separated by the first blank line.
+fn split_message(message: &[u8]) -> (Headers, &[u8]) {
+    let mut headers: Headers = Vec::new();
+    let mut offset = 0;
+    for line in message.split_inclusive(|byte| *byte == b'\n') {
+        offset += line.len();
+        let text = String::from_utf8_lossy(trim_line_ending(line));
+        if text.is_empty() {
+            return (headers, &message[offset..]);
+        }
+        // folded headers continue on lines that start with whitespace
+        if text.starts_with(' ') || text.starts_with('\t') {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(text.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = text.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), String::from(value.trim())));
+        }
+    }
+    (headers, &[])
+}
+
+/// Decodes the given quoted-printable data. In headers (`header` is true),
+/// underscores are spaces, as in the `Q` encoding of RFC 2047.
+fn decode_quoted_printable(data: &[u8], header: bool) -> Vec<u8> {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded: Vec<u8> = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        match data[index] {
+            b'=' => {
+                let rest = &data[index + 1..];
+                if rest.starts_with(b"\r\n") {
+                    index += 3; // a soft line break
+                    continue;
+                } else if rest.starts_with(b"\n") {
+                    index += 2;
+                    continue;
+                }
+                match (rest.first().and_then(|byte| hex(*byte)), rest.get(1).and_then(|byte| hex(*byte))) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        index += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'='),
+                }
+            }
+            b'_' if header => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    decoded
+}
+
+/// Decodes the encoded words (RFC 2047) of the given header value. Encoded
+/// words that cannot be decoded are left as they are.
+fn decode_header(value: &str) -> String {
+    if !value.contains("=?") {
+        return String::from(value);
+    }
+    let value = ENCODED_WORD_GAP_REGEX.replace_all(value, "?==?");
+    let decoded = ENCODED_WORD_REGEX.replace_all(&value, |captures: &regex::Captures| {
+        // the charset may be followed by a language, as in `utf-8*en`
+        let label = captures[1].split('*').next().unwrap_or("");
+        let data = match &captures[2] {
+            "B" | "b" => BASE64.decode(&captures[3]).ok(),
+            _ => Some(decode_quoted_printable(captures[3].as_bytes(), true)),
+        };
+        match (Encoding::for_label(label.as_bytes()), data) {
+            (Some(encoding), Some(data)) => encoding.decode(&data).0.into_owned(),
+            _ => String::from(&captures[0]),
+        }
+    });
+    decoded.into_owned()
+}
+
+/// Decodes the given body as given by its `Content-Transfer-Encoding`.
+/// Bodies that are not valid base64 are left as they are.
+fn decode_body(headers: &Headers, body: &[u8]) -> Vec<u8> {
+    let transfer_encoding = header(headers, "content-transfer-encoding").map(str::to_ascii_lowercase);
+    match transfer_encoding.as_deref() {
+        Some("base64") => {
+            let data: Vec<u8> = body.iter().filter(|byte| !byte.is_ascii_whitespace()).copied().collect();
+            BASE64.decode(&data).unwrap_or_else(|_| body.to_vec())
+        }
+        Some("quoted-printable") => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    }
+}
+
+/// Splits the given multipart body into its parts, which are delimited by
+/// lines of `--` and the given boundary (and end with a line of `--`, the
+/// boundary, and `--`).
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts: Vec<&[u8]> = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|byte| *byte == b'\n') {
+        let text = trim_line_ending(line);
+        if let Some(rest) = text.strip_prefix(delimiter.as_bytes()) {
+            let closing = rest.starts_with(b"--");
+            let rest = if closing { &rest[2..] } else { rest };
+            if rest.iter().all(|byte| byte.is_ascii_whitespace()) {
+                // the line ending before a delimiter belongs to the delimiter
+                if let Some(start) = start {
+                    parts.push(trim_line_ending(&body[start..offset]));
+                }
+                if closing {
+                    return parts;
+                }
+                start = Some(offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// A text part of a message.
+struct TextPart {
+    /// The part's MIME type, with its charset, if any (such as
+    /// `text/html; charset=iso-8859-1`).
+    mime: String,
+    /// The part's data, decoded from its transfer encoding.
+    data: Vec<u8>,
+}
+
+/// Finds the text parts of the given message (or part), in order, searching
+/// multipart messages to the given depth. Attachments are skipped.
+fn find_text_parts(headers: &Headers, body: &[u8], depth: usize, parts: &mut Vec<TextPart>) {
+    let is_attachment = header(headers, "content-disposition")
+        .is_some_and(|disposition| disposition.trim().to_ascii_lowercase().starts_with("attachment"));
+    if is_attachment {
+        return;
+    }
+    let content_type = header(headers, "content-type").unwrap_or("text/plain");
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if essence.starts_with("multipart/") {
+        let boundary = match header_parameter(content_type, "boundary") {
+            Some(boundary) if depth > 0 => boundary,
+            _ => return,
+        };
+        for part in split_multipart(body, &boundary) {
+            let (part_headers, part_body) = split_message(part);
+            find_text_parts(&part_headers, part_body, depth - 1, parts);
+        }
+    } else if essence == "text/plain" || essence == "text/html" {
+        let mime = match header_parameter(content_type, "charset") {
+            Some(charset) => format!("{}; charset={}", essence, charset),
+            None => essence,
+        };
+        parts.push(TextPart {
+            mime,
+            data: decode_body(headers, body),
+        });
+    }
+}
+
+/// Converts the given message into a `Document` with the given URL (see the
+/// module-level documentation), whose other properties are those of the
+/// given document (the mbox file or message that it was read from).
+fn message_document(message: &[u8], url: Option<String>, origin: &Document) -> Document {
+    let (headers, body) = split_message(message);
+    let mut parts: Vec<TextPart> = Vec::new();
+    find_text_parts(&headers, body, MAX_MULTIPART_DEPTH, &mut parts);
+    let body = parts
+        .iter()
+        .position(|part| part.mime.starts_with("text/html"))
+        .or_else(|| (!parts.is_empty()).then_some(0));
+    let (mime, data) = match body {
+        Some(index) => {
+            let part = parts.swap_remove(index);
+            (part.mime, part.data)
+        }
+        None => (String::from("text/plain"), Vec::new()),
+    };
+    let timestamp = header(&headers, "date").and_then(|date| parse_rfc2822_timestamp(date).ok());
+    let mut metadata = origin.metadata.clone();
+    for (name, value) in headers.iter() {
+        let value = decode_header(value);
+        // repeated headers (such as `received`) are on separate lines
+        metadata
+            .entry(name.clone())
+            .and_modify(|existing: &mut String| {
+                existing.push('\n');
+                existing.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    Document {
+        url,
+        data,
+        mime: Some(mime),
+        base_url: origin.base_url.clone(),
+        timestamp: timestamp.or(origin.timestamp),
+        source: origin.source.clone(),
+        content_encoding: None,
+        metadata,
+    }
+}
+
+/// Parses the given RFC 822 message (such as an `.eml` file) into a
+/// `Document` of its body, whose `metadata` is its headers (see the
+/// module-level documentation). Its `timestamp` is the message's `Date`,
+/// if it can be parsed, and its other properties are those of the given
+/// document. Returns an error when the message cannot be decompressed.
+pub fn parse_message(message: &Document) -> Result<Document, Issue> {
+    let decompressed = message.decompress()?;
+    let decompressed = decompressed.as_ref().unwrap_or(message);
+    Ok(message_document(&decompressed.data, message.url.clone(), message))
+}
+
+/// Expands the given mbox file into one `Document` per message, in order,
+/// as `parse_message()` parses them. Each document's `url` is that of the
+/// mbox file with the index of the message as its fragment (as in
+/// `archive.mbox#message-3`).
+///
+/// Messages are separated by lines that start with `From ` (at the start
+/// of the file or after a blank line); lines of the messages that start
+/// with `>From ` (or `>>From `, and so on) are unescaped by removing one
+/// `>`. Returns an error when the file does not start with `From `, or
+/// when it cannot be decompressed.
+pub fn parse_mbox(mbox: &Document) -> Result<Vec<Document>, Issue> {
+    let decompressed = mbox.decompress()?;
+    let decompressed = decompressed.as_ref().unwrap_or(mbox);
+    let name = mbox.url.as_deref().unwrap_or("unknown_document");
+    let data = decompressed.data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&decompressed.data);
+    if !data.starts_with(b"From ") {
+        return Err(Issue::Error(format!("`{}` is not an mbox file", name)));
+    }
+
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    let mut previous_blank = true;
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        let blank = trim_line_ending(line).is_empty();
+        if previous_blank && line.starts_with(b"From ") {
+            messages.push(Vec::new());
+            previous_blank = blank;
+            continue;
+        }
+        previous_blank = blank;
+        let message = match messages.last_mut() {
+            Some(message) => message,
+            None => continue,
+        };
+        let quotes = line.iter().take_while(|byte| **byte == b'>').count();
+        match quotes > 0 && line[quotes..].starts_with(b"From ") {
+            true => message.extend_from_slice(&line[1..]),
+            false => message.extend_from_slice(line),
+        }
+    }
+    Ok(messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| {
+            // the blank line before the next `From ` line is not part of
+            // the message
+            let message = trim_line_ending(message);
+            let url = mbox.url.as_ref().map(|url| format!("{}#message-{}", url, index));
+            message_document(message, url, mbox)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compilation::CompilableTo;
+
+    #[test]
+    fn test_parse_mbox() {
+        let mbox = "From alice@example.com Wed May  1 12:30:00 2019\r
+From: Alice <alice@example.com>\r
+To: Bob <bob@example.com>\r
+Subject: =?utf-8?Q?Caf=C3=A9?=\r
+ =?utf-8?B?IG1lbnU=?=\r
+Date: Wed, 01 May 2019 12:30:00 +0000\r
+Received: from a.example.com\r
+Received: from b.example.com\r
+Content-Type: text/plain; charset=utf-8\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+The caf=C3=A9 is open=\r
+ late.\r
+>From the kitchen: soup.\r
+\r
+From bob@example.com Thu May  2 08:00:00 2019
+From: Bob <bob@example.com>
+Subject: Re: menu
+Content-Type: multipart/mixed; boundary=\"outer\"
+
+This is a multi-part message in MIME format.
+--outer
+Content-Type: multipart/alternative; boundary=inner
+
+--inner
+Content-Type: text/plain
+
+Plain soup.
+--inner
+Content-Type: text/html; charset=iso-8859-1
+Content-Transfer-Encoding: base64
+
+PHA+U291cGUgZHUgam91ciDgIGxhIGNy6G1lLjwvcD4=
+--inner--
+
+--outer
+Content-Type: text/plain
+Content-Disposition: attachment; filename=\"recipe.txt\"
+
+Secret recipe.
+--outer--
+";
+        let document = Document {
+            timestamp: Some(0),
+            source: Some(String::from("mail:leaks")),
+            ..Document::from_text("/mail/archive.mbox", mbox)
+        };
+        let messages = parse_mbox(&document).unwrap();
+        assert_eq!(messages.len(), 2);
+
+        assert_eq!(messages[0].url.as_deref(), Some("/mail/archive.mbox#message-0"));
+        assert_eq!(messages[0].timestamp, Some(1_556_713_800));
+        assert_eq!(messages[0].source.as_deref(), Some("mail:leaks"));
+        assert_eq!(messages[0].metadata["subject"], "Café menu");
+        assert_eq!(messages[0].metadata["from"], "Alice <alice@example.com>");
+        assert_eq!(messages[0].metadata["received"], "from a.example.com\nfrom b.example.com");
+        assert_eq!(messages[0].mime.as_deref(), Some("text/plain; charset=utf-8"));
+        let compiled = messages[0].compile().unwrap();
+        assert_eq!(compiled.raw, "The café is open late.\r\nFrom the kitchen: soup.\r\n");
+
+        assert_eq!(messages[1].url.as_deref(), Some("/mail/archive.mbox#message-1"));
+        assert_eq!(messages[1].timestamp, Some(0));
+        assert_eq!(messages[1].mime.as_deref(), Some("text/html; charset=iso-8859-1"));
+        let compiled = messages[1].compile().unwrap();
+        assert_eq!(compiled.text, "Soupe du jour à la crème.");
+        assert!(!compiled.raw.contains("Secret recipe"));
+
+        match parse_mbox(&Document::from_text("/mail/notes.mbox", "Subject: hi\n\nhello")) {
+            Err(Issue::Error(message)) => assert!(message.contains("not an mbox file")),
+            _ => panic!("a message is not an mbox file"),
+        }
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let eml = "Subject: Quarterly numbers\nX-Mailer: Example\n\nRevenue is up.\n";
+        let message = parse_message(&Document::from_text("/mail/numbers.eml", eml)).unwrap();
+        assert_eq!(message.url.as_deref(), Some("/mail/numbers.eml"));
+        assert_eq!(message.data, b"Revenue is up.\n");
+        assert_eq!(message.metadata["x-mailer"], "Example");
+
+        // messages without a text part have an empty body
+        let image = "Content-Type: image/png\nContent-Transfer-Encoding: base64\n\niVBORw0KGgo=\n";
+        assert!(parse_message(&Document::from_text("/mail/image.eml", image)).unwrap().data.is_empty());
+
+        assert!(is_mbox_path("/mail/archive.MBOX.gz"));
+        assert!(is_message_path("/mail/numbers.eml"));
+        assert!(!is_message_path("/mail/archive.mbox"));
+    }
+}
@@ -1,4 +1,12 @@
 //! This module provides functionality for inputs—namely, loading
 //! and handling `Document`s.
 
-pub mod document;
\ No newline at end of file
+pub mod archive;
+pub mod content_encoding;
+pub mod document;
+pub mod feed;
+pub mod mail;
+pub mod readability;
+pub mod rows;
+pub mod stream;
+pub mod warc;
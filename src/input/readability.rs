@@ -0,0 +1,209 @@
+//! This module provides functionality for extracting the main content of
+//! HTML pages—the body of an article, say, without its navigation,
+//! advertisements, comments, and footers—in the manner of Readability.
+//! (See `ScopeContent::MainContent`.)
+//!
+//! The blocks of text of a page (its paragraphs, say) are scored by their
+//! length and their number of commas, and each block's score is credited
+//! to its parent and, by half, to its grandparent. The element with the
+//! highest score—discounted by the share of its text that is links—is the
+//! main content, along with those of its siblings that score well enough.
+//! Elements that are unlikely to be content, judging by their tag or by
+//! their `class` and `id`, are ignored.
+
+use input::document::element_text;
+use regex::Regex;
+use lazy_static::lazy_static;
+use scraper::{ElementRef, Html};
+use std::collections::HashMap;
+
+/// The elements whose text is scored as a block of text.
+const SCORED_ELEMENTS: [&str; 5] = ["p", "pre", "td", "blockquote", "li"];
+
+/// The elements that are never part of the main content.
+const BOILERPLATE_ELEMENTS: [&str; 8] = ["nav", "header", "footer", "aside", "form", "iframe", "button", "menu"];
+
+/// The number of characters that a block of text must have to be scored.
+const MIN_BLOCK_LENGTH: usize = 25;
+
+/// The minimum score of a sibling of the main content that is included in
+/// it, and the share of the main content's score that it must also have.
+const SIBLING_SCORE_THRESHOLD: (f64, f64) = (10.0, 0.2);
+
+lazy_static! {
+    /// Matches the `class` and `id` of elements that are unlikely to be
+    /// content.
+    static ref UNLIKELY_REGEX: Regex = Regex::new(
+        r"(?i)\b(ad|ads|advert|banner|breadcrumbs?|combx|comments?|community|cookies?|disqus|footer|header|menu|modal|nav|newsletter|popup|promo|related|remark|rss|share|sharing|shoutbox|sidebar|social|sponsor|subscribe|tags|widget)\b|-(ad|nav|menu|share)\b"
+    )
+    .unwrap();
+    /// Matches the `class` and `id` of elements that are likely to be
+    /// content.
+    static ref LIKELY_REGEX: Regex =
+        Regex::new(r"(?i)\b(article|body|content|entry|main|page|post|story|text)\b").unwrap();
+}
+
+/// The `class` and `id` of the given element, separated by a space.
+fn class_and_id(element: ElementRef) -> String {
+    format!(
+        "{} {}",
+        element.value().attr("class").unwrap_or(""),
+        element.value().attr("id").unwrap_or("")
+    )
+}
+
+/// Whether the given element is unlikely to be (or contain) content.
+fn is_unlikely(element: ElementRef) -> bool {
+    if BOILERPLATE_ELEMENTS.contains(&element.value().name()) {
+        return true;
+    }
+    let class_and_id = class_and_id(element);
+    UNLIKELY_REGEX.is_match(&class_and_id) && !LIKELY_REGEX.is_match(&class_and_id)
+}
+
+/// The score of the given element before any text is credited to it,
+/// judging by its tag and by its `class` and `id`.
+fn initial_score(element: ElementRef) -> f64 {
+    let tag_score = match element.value().name() {
+        "article" | "main" => 10.0,
+        "div" | "section" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "ol" | "ul" | "dl" | "dd" | "dt" | "li" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    let class_and_id = class_and_id(element);
+    let mut class_score = 0.0;
+    if LIKELY_REGEX.is_match(&class_and_id) {
+        class_score += 25.0;
+    }
+    if UNLIKELY_REGEX.is_match(&class_and_id) {
+        class_score -= 25.0;
+    }
+    tag_score + class_score
+}
+
+/// The share of the given element's text that is the text of links.
+fn link_density(element: ElementRef) -> f64 {
+    let length = element.text().map(|text| text.trim().chars().count()).sum::<usize>();
+    if length == 0 {
+        return 0.0;
+    }
+    let link_length: usize = element
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter(|descendant| descendant.value().name() == "a")
+        .flat_map(|link| link.text())
+        .map(|text| text.trim().chars().count())
+        .sum();
+    link_length as f64 / length as f64
+}
+
+/// Extracts the main content of the given HTML as text, one block per line
+/// (see the module-level documentation). When no main content can be
+/// identified—because the page has no substantial blocks of text—the text
+/// of the whole page is returned, without the elements that are unlikely
+/// to be content.
+pub fn extract_main_content(html: &str) -> String {
+    let document = Html::parse_document(html);
+    // candidates are indexed by their node, and kept in document order
+    let mut indices = HashMap::new();
+    let mut candidates: Vec<(ElementRef, f64)> = Vec::new();
+    for element in document.root_element().descendants().filter_map(ElementRef::wrap) {
+        if !SCORED_ELEMENTS.contains(&element.value().name()) {
+            continue;
+        }
+        let mut lineage = element.ancestors().filter_map(ElementRef::wrap);
+        if is_unlikely(element) || lineage.any(is_unlikely) {
+            continue;
+        }
+        let text = element_text(element, |_| false);
+        let length = text.chars().count();
+        if length < MIN_BLOCK_LENGTH {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (length / 100).min(3) as f64;
+        let ancestors = element.ancestors().filter_map(ElementRef::wrap).take(2);
+        for (generation, ancestor) in ancestors.enumerate() {
+            let index = *indices.entry(ancestor.id()).or_insert_with(|| {
+                candidates.push((ancestor, initial_score(ancestor)));
+                candidates.len() - 1
+            });
+            candidates[index].1 += score / (generation + 1) as f64;
+        }
+    }
+    for candidate in candidates.iter_mut() {
+        candidate.1 *= 1.0 - link_density(candidate.0);
+    }
+    let top = candidates
+        .iter()
+        .fold(None, |best: Option<(ElementRef, f64)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(*candidate),
+        });
+    let (top, top_score) = match top {
+        Some(value) => value,
+        None => return element_text(document.root_element(), is_unlikely),
+    };
+
+    // siblings of the main content that score well, or that are substantial
+    // paragraphs, are part of it (such as when an article is split up by
+    // a figure)
+    let threshold = SIBLING_SCORE_THRESHOLD.0.max(top_score * SIBLING_SCORE_THRESHOLD.1);
+    let siblings: Vec<ElementRef> = match top.parent().and_then(ElementRef::wrap) {
+        Some(parent) => parent.children().filter_map(ElementRef::wrap).collect(),
+        None => vec![top],
+    };
+    let mut blocks: Vec<String> = Vec::new();
+    for sibling in siblings {
+        let included = sibling == top
+            || candidates.iter().any(|(candidate, score)| *candidate == sibling && *score >= threshold)
+            || (sibling.value().name() == "p"
+                && !is_unlikely(sibling)
+                && element_text(sibling, |_| false).chars().count() > 80
+                && link_density(sibling) < 0.25);
+        if included {
+            let text = element_text(sibling, is_unlikely);
+            if !text.is_empty() {
+                blocks.push(text);
+            }
+        }
+    }
+    blocks.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_main_content() {
+        let html = r#"<html><head><title>Acme recalls blenders</title></head><body>
+            <nav><a href="/">Home</a> | <a href="/news">News</a> | <a href="/sports">Sports, weather, and more</a></nav>
+            <div class="top-ad">Buy our newsletter, now with more news, fewer ads, and a free tote bag!</div>
+            <div id="main">
+                <article class="story">
+                    <h1>Acme recalls blenders</h1>
+                    <p>Acme, the appliance maker, recalled two million blenders on Tuesday, citing a risk of fire.</p>
+                    <figure><img src="blender.jpg"><figcaption>A recalled blender.</figcaption></figure>
+                    <p>Owners should stop using the blenders immediately, and contact Acme for a refund, the company said.</p>
+                    <div class="share-buttons"><a href="/share">Share this story on social media, by email, or by fax</a></div>
+                </article>
+                <div class="related"><ul><li><a href="/a">Acme stock falls, again, after a week of bad news</a></li></ul></div>
+            </div>
+            <footer><p>Copyright 2020, Example News, a subsidiary of Example Holdings, Inc.</p></footer>
+        </body></html>"#;
+        let content = extract_main_content(html);
+        assert!(content.contains("Acme recalls blenders\nAcme, the appliance maker, recalled two million blenders"));
+        assert!(content.contains("contact Acme for a refund"));
+        for boilerplate in ["Home", "newsletter", "Share this story", "stock falls", "Copyright"].iter() {
+            assert!(!content.contains(boilerplate), "`{}` is boilerplate", boilerplate);
+        }
+
+        // pages without substantial text fall back to all of their text
+        assert_eq!(
+            extract_main_content("<nav>Menu</nav><div>Short note.</div><footer>Footer</footer>"),
+            "Short note."
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! This module provides functionality for splitting row-oriented files—
+//! such as CSV exports of datasets, or logs with one record per line—into
+//! one `Document` per row, so that each row is scanned (and matched) on its
+//! own rather than the file being scanned as one blob in which the rows run
+//! together.
+
+use common::validation::Issue;
+use input::document::Document;
+
+/// Denotes how a file is split into rows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowFormat {
+    /// Comma-separated values (RFC 4180), whose first row names the columns.
+    Csv,
+    /// Tab-separated values, which are otherwise read like `Csv`.
+    Tsv,
+    /// Each line that is not blank is a row.
+    Lines,
+}
+
+impl RowFormat {
+    /// Parses the given format: `csv`, `tsv`, or `lines`.
+    pub fn parse(label: &str) -> Option<RowFormat> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "csv" => Some(RowFormat::Csv),
+            "tsv" => Some(RowFormat::Tsv),
+            "lines" => Some(RowFormat::Lines),
+            _ => None,
+        }
+    }
+}
+
+/// Configures how files are split into rows. (See `split_rows()`.)
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowOptions {
+    /// The format of the files.
+    pub format: RowFormat,
+    /// The name of the column whose value is the URL of each row, if any.
+    /// Only CSV and TSV files have columns.
+    pub url_column: Option<String>,
+}
+
+/// Parses the given CSV text (RFC 4180) with the given delimiter into its
+/// records: fields may be quoted, in which case they may contain the
+/// delimiter, line breaks, and doubled quotes (`""`).
+fn parse_delimited(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut characters = text.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' if quoted => match characters.peek() {
+                Some('"') => {
+                    field.push('"');
+                    characters.next();
+                }
+                _ => quoted = false,
+            },
+            '"' if field.is_empty() => quoted = true,
+            '\r' if !quoted && characters.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            character if character == delimiter && !quoted => record.push(std::mem::take(&mut field)),
+            character => field.push(character),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// A row's `Document`, whose other properties are those of the given file.
+fn row_document(file: &Document, url: Option<String>, text: String) -> Document {
+    Document {
+        url,
+        data: text.into_bytes(),
+        mime: Some(String::from("text/plain; charset=utf-8")),
+        base_url: file.base_url.clone(),
+        timestamp: file.timestamp,
+        source: file.source.clone(),
+        content_encoding: None,
+        metadata: file.metadata.clone(),
+    }
+}
+
+/// Splits the given file into one `Document` per row, in order (see the
+/// module-level documentation). The file is decompressed and decoded like
+/// any other document (see `Document::detect_encoding()`).
+///
+/// Each row of a CSV or TSV file becomes a document whose text is its
+/// fields, one per line, each of the form `column: value` (for example,
+/// `name: Acme`); empty rows are skipped. Each line of other files becomes
+/// a document whose text is the line. A document's `url` is the value of
+/// its `url_column`, if it is given and not empty, and otherwise the
+/// file's URL with the number of the row (counting from 1, after the row
+/// of column names) or line as its fragment, as in `export.csv#row-3` or
+/// `app.log#line-3`. Returns an error when the `url_column` is not one of
+/// the file's columns.
+pub fn split_rows(file: &Document, options: &RowOptions) -> Result<Vec<Document>, Issue> {
+    let decompressed = file.decompress()?;
+    let decompressed = decompressed.as_ref().unwrap_or(file);
+    let text = decompressed.decode(decompressed.detect_encoding());
+    let name = file.url.as_deref().unwrap_or("unknown_document");
+    let fragment_url = |kind: &str, number: usize| file.url.as_ref().map(|url| format!("{}#{}-{}", url, kind, number));
+
+    let delimiter = match options.format {
+        RowFormat::Csv => ',',
+        RowFormat::Tsv => '\t',
+        RowFormat::Lines => {
+            return Ok(text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| row_document(file, fragment_url("line", index + 1), String::from(line)))
+                .collect())
+        }
+    };
+    let mut records = parse_delimited(&text, delimiter).into_iter();
+    let columns: Vec<String> = records
+        .next()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|column| String::from(column.trim()))
+        .collect();
+    let url_index = match &options.url_column {
+        Some(url_column) => Some(
+            columns
+                .iter()
+                .position(|column| column == url_column)
+                .ok_or_else(|| Issue::Error(format!("`{}` has no column `{}`", name, url_column)))?,
+        ),
+        None => None,
+    };
+    Ok(records
+        .enumerate()
+        .filter(|(_, record)| record.iter().any(|field| !field.trim().is_empty()))
+        .map(|(index, record)| {
+            let url = url_index
+                .and_then(|url_index| record.get(url_index))
+                .map(|url| String::from(url.trim()))
+                .filter(|url| !url.is_empty())
+                .or_else(|| fragment_url("row", index + 1));
+            // fields beyond the named columns are named by their position
+            let fields: Vec<String> = record
+                .iter()
+                .enumerate()
+                .filter(|(field_index, _)| Some(*field_index) != url_index)
+                .map(|(field_index, field)| match columns.get(field_index) {
+                    Some(column) => format!("{}: {}", column, field),
+                    None => format!("{}: {}", field_index, field),
+                })
+                .collect();
+            row_document(file, url, fields.join("\n"))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(documents: &[Document]) -> Vec<&str> {
+        documents
+            .iter()
+            .map(|document| std::str::from_utf8(&document.data).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_rows() {
+        let csv = "link,name,notes\r\nhttps://example.com/acme,Acme,\"Recalled \"\"two million\"\"\r\nblenders, in May\"\r\n,,\r\n,Globex,,extra\r\n";
+        let file = Document {
+            source: Some(String::from("exports")),
+            ..Document::from_text("/exports/companies.csv", csv)
+        };
+        let options = RowOptions {
+            format: RowFormat::Csv,
+            url_column: Some(String::from("link")),
+        };
+        let rows = split_rows(&file, &options).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].url.as_deref(), Some("https://example.com/acme"));
+        assert_eq!(rows[1].url.as_deref(), Some("/exports/companies.csv#row-3"));
+        assert_eq!(rows[0].source.as_deref(), Some("exports"));
+        assert_eq!(
+            texts(&rows),
+            vec![
+                "name: Acme\nnotes: Recalled \"two million\"\r\nblenders, in May",
+                "name: Globex\nnotes: \n3: extra"
+            ]
+        );
+
+        let options = RowOptions {
+            format: RowFormat::Csv,
+            url_column: Some(String::from("url")),
+        };
+        match split_rows(&file, &options) {
+            Err(Issue::Error(message)) => assert!(message.contains("no column `url`")),
+            _ => panic!("the file has no `url` column"),
+        }
+
+        let tsv = Document::from_text("/exports/companies.tsv", "name\tcity\nAcme\tSpringfield\n");
+        let options = RowOptions {
+            format: RowFormat::Tsv,
+            url_column: None,
+        };
+        let rows = split_rows(&tsv, &options).unwrap();
+        assert_eq!(rows[0].url.as_deref(), Some("/exports/companies.tsv#row-1"));
+        assert_eq!(texts(&rows), vec!["name: Acme\ncity: Springfield"]);
+
+        let log = Document::from_text("/logs/app.log", "started\n\n  \nfailed to connect\r\n");
+        let options = RowOptions {
+            format: RowFormat::Lines,
+            url_column: None,
+        };
+        let rows = split_rows(&log, &options).unwrap();
+        let urls: Vec<&str> = rows.iter().map(|row| row.url.as_deref().unwrap()).collect();
+        assert_eq!(urls, vec!["/logs/app.log#line-1", "/logs/app.log#line-4"]);
+        assert_eq!(texts(&rows), vec!["started", "failed to connect"]);
+
+        assert_eq!(RowFormat::parse("TSV"), Some(RowFormat::Tsv));
+        assert_eq!(RowFormat::parse("xlsx"), None);
+    }
+}
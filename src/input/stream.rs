@@ -0,0 +1,275 @@
+//! This module provides functionality for streaming documents that are
+//! too large to hold in memory—such as multi-gigabyte logs—by splitting
+//! them into fixed-size, overlapping _windows_, each of which is scanned
+//! as a document of its own.
+//!
+//! Because consecutive windows overlap, a match that spans the boundary
+//! between two windows is still found (in at least one of them) as long
+//! as it is no longer than the overlap; a match in the overlap may be
+//! found in both. Triggers only see one window at a time, however, so a
+//! query only matches when its threshold is met within a single window.
+//! Windows are scanned as plain text, whatever the kind of the document.
+
+use common::validation::{Issue, Validatable};
+use input::content_encoding::ContentEncoding;
+use input::document::Document;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::time::UNIX_EPOCH;
+
+/// Configures which documents are streamed, and how they are split into
+/// windows (see the module-level documentation).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamingOptions {
+    /// The size, in bytes, above which files are streamed rather than
+    /// loaded whole. (For compressed files, this is their compressed size.)
+    pub threshold: usize,
+    /// The size of each window, in bytes.
+    pub window: usize,
+    /// The number of bytes that consecutive windows share. Windows must
+    /// be at least `MIN_WINDOW_MARGIN` bytes larger than their overlap.
+    pub overlap: usize,
+}
+
+/// The number of bytes by which windows must exceed their overlap. A
+/// window may end before an incomplete character, and its overlap may
+/// begin before the character that contains it, each of which takes up
+/// to a character (four bytes) from the window; the margin ensures that
+/// each window still holds the overlap and at least one character more.
+pub const MIN_WINDOW_MARGIN: usize = 8;
+
+impl Default for StreamingOptions {
+    /// Streams files above 64 MiB in windows of 8 MiB that overlap by
+    /// 64 KiB.
+    fn default() -> StreamingOptions {
+        StreamingOptions {
+            threshold: 64 << 20,
+            window: 8 << 20,
+            overlap: 64 << 10,
+        }
+    }
+}
+
+impl Validatable for StreamingOptions {
+    /// Ensures that consecutive windows overlap by (at least) `overlap`
+    /// bytes, which requires that windows are at least
+    /// `MIN_WINDOW_MARGIN` bytes larger than their overlap.
+    fn validate(&self) -> Option<Vec<Issue>> {
+        if self.window < self.overlap.saturating_add(MIN_WINDOW_MARGIN) {
+            return Some(vec![Issue::Error(format!(
+                "streaming windows must be at least {} bytes larger than their overlap",
+                MIN_WINDOW_MARGIN
+            ))]);
+        }
+        None
+    }
+}
+
+/// Moves the given index back to the start of the UTF-8 character that
+/// contains it, so that characters are not split between windows.
+fn character_boundary(data: &[u8], mut index: usize) -> usize {
+    while index > 0 && index < data.len() && data[index] & 0xc0 == 0x80 {
+        index -= 1;
+    }
+    index
+}
+
+/// The length of the given data without the UTF-8 character at its end,
+/// if that character is incomplete. (Data that is not UTF-8 is left
+/// whole.)
+fn complete_length(data: &[u8]) -> usize {
+    let lead = (data.len().saturating_sub(4)..data.len())
+        .rev()
+        .find(|index| data[*index] & 0xc0 != 0x80);
+    match lead {
+        Some(index) => {
+            let width = match data[index] {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            match index + width > data.len() {
+                true => index,
+                false => data.len(),
+            }
+        }
+        None => data.len(),
+    }
+}
+
+/// `DocumentWindows` iterates over the windows of a document that is read
+/// from a stream (see the module-level documentation). Each window is a
+/// `Document` whose `url` is that of the streamed document with the range
+/// of bytes of the window as its fragment, such as `app.log#bytes=0-8388607`
+/// (where, as in an HTTP `Range` header, the range is inclusive).
+///
+/// Iteration ends at the end of the stream, or after the first error
+/// (such as a corrupt compressed stream), which is yielded.
+pub struct DocumentWindows<R: Read> {
+    reader: R,
+    /// The URL of the streamed document.
+    url: String,
+    /// When the streamed document was modified, if known.
+    timestamp: Option<i64>,
+    options: StreamingOptions,
+    /// The bytes of the previous window that the next window begins with.
+    carried: Vec<u8>,
+    /// The offset of the first of the carried bytes in the stream.
+    offset: u64,
+    /// Whether iteration has ended.
+    finished: bool,
+}
+
+impl DocumentWindows<Box<dyn Read + Send>> {
+    /// Opens the file at the given path for streaming. Files compressed
+    /// with gzip, zlib, or Brotli (as denoted by their extension; see
+    /// `ContentEncoding::from_extension()`) are decompressed as they are
+    /// read, so that offsets are those of the decompressed data. Returns an
+    /// `Error` if the options are invalid (see `StreamingOptions::validate()`).
+    pub fn open(path: &str, options: StreamingOptions) -> Result<DocumentWindows<Box<dyn Read + Send>>, Issue> {
+        if let Some(mut issues) = options.validate() {
+            return Err(issues.remove(0));
+        }
+        let file = File::open(path).map_err(|error| Issue::Error(format!("unable to open `{}` (`{}`)", path, error)))?;
+        let timestamp = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64);
+        let reader = ContentEncoding::from_extension(path).decoder(BufReader::new(file));
+        let mut windows = DocumentWindows::new(reader, path, options);
+        windows.timestamp = timestamp;
+        Ok(windows)
+    }
+}
+
+impl<R: Read> DocumentWindows<R> {
+    /// Create a new `DocumentWindows` that streams the document with the
+    /// given URL from the given reader. If the options are invalid (see
+    /// `StreamingOptions::validate()`), iteration yields an error.
+    pub fn new(reader: R, url: &str, options: StreamingOptions) -> DocumentWindows<R> {
+        DocumentWindows {
+            reader,
+            url: String::from(url),
+            timestamp: None,
+            options,
+            carried: Vec::new(),
+            offset: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for DocumentWindows<R> {
+    type Item = Result<Document, Issue>;
+
+    fn next(&mut self) -> Option<Result<Document, Issue>> {
+        if self.finished {
+            return None;
+        }
+        if let Some(mut issues) = self.options.validate() {
+            self.finished = true;
+            return Some(Err(issues.remove(0)));
+        }
+        let window = self.options.window;
+        let mut data = std::mem::take(&mut self.carried);
+        let carried = data.len();
+        let wanted = window.saturating_sub(carried) as u64;
+        if let Err(error) = (&mut self.reader).take(wanted).read_to_end(&mut data) {
+            self.finished = true;
+            return Some(Err(Issue::Error(format!("unable to read `{}` (`{}`)", self.url, error))));
+        }
+        let start = self.offset;
+        let at_end = data.len() < window;
+        if at_end {
+            self.finished = true;
+            if data.len() == carried && start > 0 {
+                return None; // the carried bytes were already scanned
+            }
+        }
+
+        let end = match at_end {
+            true => data.len(),
+            false => complete_length(&data),
+        };
+        if !at_end {
+            // the margin ensures that the next window starts after this one
+            let next_start = character_boundary(&data, end - self.options.overlap);
+            self.carried = data.split_off(next_start);
+            self.offset = start + next_start as u64;
+            data.extend_from_slice(&self.carried[..end - next_start]);
+        }
+        let url = format!(
+            "{}#bytes={}-{}",
+            self.url,
+            start,
+            (start + data.len() as u64).saturating_sub(1)
+        );
+        Some(Ok(Document {
+            url: Some(url),
+            data,
+            mime: None,
+            base_url: None,
+            timestamp: self.timestamp,
+            source: None,
+            content_encoding: None,
+            metadata: HashMap::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn windows(data: &[u8], window: usize, overlap: usize) -> Vec<Document> {
+        let options = StreamingOptions {
+            threshold: 0,
+            window,
+            overlap,
+        };
+        DocumentWindows::new(data, "app.log", options)
+            .collect::<Result<Vec<Document>, Issue>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_document_windows() {
+        let documents = windows(b"0123456789abcdefghij", 10, 2);
+        let urls: Vec<&str> = documents.iter().map(|document| document.url.as_deref().unwrap()).collect();
+        assert_eq!(urls, vec!["app.log#bytes=0-9", "app.log#bytes=8-17", "app.log#bytes=16-19"]);
+        let data: Vec<&[u8]> = documents.iter().map(|document| document.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"0123456789"[..], b"89abcdefgh", b"ghij"]);
+
+        // windows do not split characters, overlap by at least `overlap`
+        // bytes, and end with the stream
+        let documents = windows("aé€bcdefghé€ijklmno".as_bytes(), 10, 2);
+        let data: Vec<String> = documents
+            .iter()
+            .map(|document| String::from_utf8(document.data.clone()).unwrap())
+            .collect();
+        assert_eq!(data, vec!["aé€bcde", "defghé€", "€ijklmno"]);
+        assert_eq!(windows(b"", 10, 2).len(), 1);
+        assert_eq!(windows(b"0123456789", 10, 2).len(), 1);
+        assert_eq!(windows(b"0123456789a", 10, 2).len(), 2);
+    }
+
+    #[test]
+    fn test_document_windows_margin() {
+        for (window, overlap) in &[(4, 1), (9, 2), (64, 64)] {
+            let options = StreamingOptions {
+                threshold: 0,
+                window: *window,
+                overlap: *overlap,
+            };
+            assert!(options.validate().is_some());
+            let mut documents = DocumentWindows::new(&b"0123456789"[..], "app.log", options);
+            assert!(matches!(documents.next(), Some(Err(Issue::Error(_)))));
+            assert!(documents.next().is_none());
+        }
+        assert!(StreamingOptions::default().validate().is_none());
+    }
+}
@@ -0,0 +1,383 @@
+//! This module provides functionality for reading documents from WARC
+//! (Web ARChive) files, such as those published by Common Crawl, either
+//! uncompressed (`.warc`) or compressed record by record (`.warc.gz`).
+//!
+//! Only records that contain documents become `Document`s: `response`
+//! records (whose HTTP headers are removed, and whose HTTP body is
+//! decoded when it is chunked or gzipped), `resource` records, and
+//! `conversion` records (such as the extracted text of Common Crawl's WET
+//! files). Other records, such as `request` and `metadata` records, are
+//! skipped.
+
+use common::timestamp::parse_timestamp;
+use common::validation::Issue;
+use flate2::read::MultiGzDecoder;
+use input::content_encoding::ContentEncoding;
+use input::document::Document;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+/// Whether the file at the given path is a WARC file, judging by its
+/// extension (`.warc` or `.warc.gz`).
+pub fn is_warc_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".warc") || path.ends_with(".warc.gz")
+}
+
+/// A `WarcReader` iterates over the documents of a WARC file (see the
+/// module-level documentation). Each document's `url` is the record's
+/// `WARC-Target-URI`, its `timestamp` is the record's `WARC-Date`, and its
+/// `mime` is the `Content-Type` of the HTTP response (or, for other
+/// records, of the record), without parameters other than the charset
+/// (see `Document::detect_encoding()`).
+///
+/// Iteration ends at the end of the file, or after the first error (such
+/// as a truncated record), which is yielded.
+pub struct WarcReader<R: BufRead> {
+    reader: R,
+    /// Whether iteration has ended.
+    finished: bool,
+}
+
+/// The headers of a WARC record or of an HTTP response: pairs of their
+/// lowercase names and their values, in order.
+type Headers = Vec<(String, String)>;
+
+/// A single record of a WARC file.
+struct WarcRecord {
+    /// The record's headers.
+    headers: Headers,
+    /// The record's content block.
+    block: Vec<u8>,
+}
+
+impl WarcRecord {
+    /// The value of the header with the given (lowercase) name, if any.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl WarcReader<BufReader<Box<dyn Read + Send>>> {
+    /// Opens the WARC file at the given path, which may be gzipped (as
+    /// detected from its first bytes, regardless of its extension).
+    pub fn open(path: &str) -> Result<WarcReader<BufReader<Box<dyn Read + Send>>>, Issue> {
+        let failed = |error: std::io::Error| Issue::Error(format!("unable to open `{}` (`{}`)", path, error));
+        let mut file = BufReader::new(File::open(path).map_err(failed)?);
+        let reader: Box<dyn Read + Send> = match ContentEncoding::from_magic(file.fill_buf().map_err(failed)?) {
+            ContentEncoding::Gzip => Box::new(MultiGzDecoder::new(file)),
+            _ => Box::new(file),
+        };
+        Ok(WarcReader::new(BufReader::new(reader)))
+    }
+}
+
+impl<R: BufRead> WarcReader<R> {
+    /// Create a new `WarcReader` that reads an uncompressed WARC file
+    /// from the given reader. (For `.warc.gz` files, wrap the reader in a
+    /// `flate2::read::MultiGzDecoder`, or use `WarcReader::open()`.)
+    pub fn new(reader: R) -> WarcReader<R> {
+        WarcReader {
+            reader,
+            finished: false,
+        }
+    }
+
+    /// Reads a line, without its line ending. Returns `None` at the end
+    /// of the file.
+    fn read_line(&mut self) -> Result<Option<String>, Issue> {
+        let mut line: Vec<u8> = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+            }
+            Err(error) => Err(Issue::Error(format!("unable to read WARC record (`{}`)", error))),
+        }
+    }
+
+    /// Reads the next record, if there is one.
+    fn read_record(&mut self) -> Result<Option<WarcRecord>, Issue> {
+        // records are separated by blank lines
+        let version = loop {
+            match self.read_line()? {
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+                None => return Ok(None),
+            }
+        };
+        if !version.starts_with("WARC/") {
+            return Err(Issue::Error(format!(
+                "invalid WARC record; expected a version line, found `{}`",
+                version
+            )));
+        }
+        let mut headers: Headers = Vec::new();
+        loop {
+            let line = match self.read_line()? {
+                Some(line) => line,
+                None => return Err(Issue::Error(String::from("truncated WARC record headers"))),
+            };
+            if line.is_empty() {
+                break;
+            }
+            if let Some(index) = line.find(':') {
+                headers.push((
+                    line[..index].trim().to_ascii_lowercase(),
+                    String::from(line[index + 1..].trim()),
+                ));
+            }
+        }
+        let length: u64 = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse().ok())
+            .ok_or_else(|| Issue::Error(String::from("WARC record without a valid `Content-Length`")))?;
+        let mut block: Vec<u8> = Vec::new();
+        match (&mut self.reader).take(length).read_to_end(&mut block) {
+            Ok(read) if read as u64 == length => Ok(Some(WarcRecord { headers, block })),
+            Ok(_) => Err(Issue::Error(String::from("truncated WARC record"))),
+            Err(error) => Err(Issue::Error(format!("unable to read WARC record (`{}`)", error))),
+        }
+    }
+}
+
+/// Removes the parameters (such as the charset) from the given content
+/// type, and lowercases it.
+fn essence(content_type: &str) -> Option<String> {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    match essence.is_empty() {
+        true => None,
+        false => Some(essence.to_ascii_lowercase()),
+    }
+}
+
+/// Removes the parameters other than the charset from the given content
+/// type, and lowercases it (but not the charset). For example,
+/// `Text/HTML; Charset=UTF-8; q=1` becomes `text/html; charset=UTF-8`.
+fn normalize_content_type(content_type: &str) -> Option<String> {
+    let essence = essence(content_type)?;
+    let charset = content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim())
+        } else {
+            None
+        }
+    });
+    match charset {
+        Some(charset) => Some(format!("{}; charset={}", essence, charset)),
+        None => Some(essence),
+    }
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`. Returns `None`
+/// if the body is not validly chunked.
+fn decode_chunked(body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&rest[..line_end]).ok()?;
+        let size_field = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_field, 16).ok()?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(rest.get(..size)?);
+        rest = rest.get(size..)?;
+        rest = rest.strip_prefix(b"\r\n").unwrap_or(rest);
+    }
+}
+
+/// Splits the given HTTP response into its (lowercase) headers and its
+/// body, decoding the body when it is chunked or compressed. Returns `None`
+/// if the response has no header terminator.
+fn parse_http_response(response: &[u8]) -> Option<(Headers, Vec<u8>)> {
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let headers: Headers = head
+        .split("\r\n")
+        .skip(1) // the status line
+        .filter_map(|line| {
+            let index = line.find(':')?;
+            Some((
+                line[..index].trim().to_ascii_lowercase(),
+                String::from(line[index + 1..].trim()),
+            ))
+        })
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.to_ascii_lowercase())
+    };
+    let mut body = response[header_end + 4..].to_vec();
+    if header("transfer-encoding").is_some_and(|value| value.contains("chunked")) {
+        if let Some(decoded) = decode_chunked(&body) {
+            body = decoded;
+        }
+    }
+    if let Some(encoding) = header("content-encoding").and_then(|value| ContentEncoding::parse(&value)) {
+        if let Ok(decoded) = encoding.decode(&body) {
+            body = decoded;
+        }
+    }
+    Some((headers, body))
+}
+
+impl WarcRecord {
+    /// Converts the record into a `Document`, if it contains one.
+    fn into_document(self) -> Option<Document> {
+        let record_type = self.header("warc-type")?.to_ascii_lowercase();
+        let url = self
+            .header("warc-target-uri")
+            .map(|uri| String::from(uri.trim_start_matches('<').trim_end_matches('>')));
+        let timestamp = self.header("warc-date").and_then(|date| parse_timestamp(date).ok());
+        let record_content_type = self.header("content-type");
+        let (mime, data) = match record_type.as_str() {
+            "response" if record_content_type.and_then(essence).as_deref() == Some("application/http") => {
+                let (headers, body) = parse_http_response(&self.block)?;
+                let mime = headers
+                    .iter()
+                    .find(|(name, _)| name == "content-type")
+                    .and_then(|(_, value)| normalize_content_type(value));
+                (mime, body)
+            }
+            "response" | "resource" | "conversion" => {
+                (record_content_type.and_then(normalize_content_type), self.block)
+            }
+            _ => return None,
+        };
+        Some(Document {
+            url,
+            data,
+            mime,
+            base_url: None,
+            timestamp,
+            source: None,
+            content_encoding: None,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for WarcReader<R> {
+    type Item = Result<Document, Issue>;
+
+    fn next(&mut self) -> Option<Result<Document, Issue>> {
+        while !self.finished {
+            match self.read_record() {
+                Ok(Some(record)) => {
+                    if let Some(document) = record.into_document() {
+                        return Some(Ok(document));
+                    }
+                }
+                Ok(None) => self.finished = true,
+                Err(issue) => {
+                    self.finished = true;
+                    return Some(Err(issue));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    /// Serializes a WARC record with the given type, headers, and block.
+    fn record(record_type: &str, headers: &[(&str, &str)], block: &[u8]) -> Vec<u8> {
+        let mut record = format!("WARC/1.0\r\nWARC-Type: {}\r\n", record_type).into_bytes();
+        for (name, value) in headers {
+            record.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        record.extend_from_slice(format!("Content-Length: {}\r\n\r\n", block.len()).as_bytes());
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+        record
+    }
+
+    #[test]
+    fn test_warc_reader() {
+        let mut gzipped_body = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped_body.write_all(b"<p>compressed</p>").unwrap();
+        let gzipped_body = gzipped_body.finish().unwrap();
+        let mut compressed_response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        compressed_response.extend_from_slice(&gzipped_body);
+
+        let records: Vec<Vec<u8>> = vec![
+            record("warcinfo", &[("Content-Type", "application/warc-fields")], b"software: test\r\n"),
+            record(
+                "request",
+                &[("WARC-Target-URI", "https://example.com/"), ("Content-Type", "application/http; msgtype=request")],
+                b"GET / HTTP/1.1\r\n\r\n",
+            ),
+            record(
+                "response",
+                &[
+                    ("WARC-Target-URI", "<https://example.com/>"),
+                    ("WARC-Date", "2019-05-01T12:30:00Z"),
+                    ("Content-Type", "application/http; msgtype=response"),
+                ],
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/HTML; charset=utf-8\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+            ),
+            record(
+                "response",
+                &[("WARC-Target-URI", "https://example.com/gz"), ("Content-Type", "application/http; msgtype=response")],
+                &compressed_response,
+            ),
+            record(
+                "conversion",
+                &[("WARC-Target-URI", "https://example.com/text"), ("Content-Type", "text/plain")],
+                b"extracted text",
+            ),
+        ];
+
+        let documents: Vec<Document> = WarcReader::new(&records.concat()[..])
+            .collect::<Result<Vec<Document>, Issue>>()
+            .unwrap();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[0].url.as_deref(), Some("https://example.com/"));
+        assert_eq!(documents[0].data, b"hello, world".to_vec());
+        assert_eq!(documents[0].mime.as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(documents[0].timestamp, Some(1_556_713_800));
+        assert_eq!(documents[1].data, b"<p>compressed</p>".to_vec());
+        assert_eq!(documents[2].data, b"extracted text".to_vec());
+        assert_eq!(documents[2].mime.as_deref(), Some("text/plain"));
+
+        // .warc.gz files compress each record separately
+        let mut compressed: Vec<u8> = Vec::new();
+        for record in &records {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(record).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+        let reader = WarcReader::new(BufReader::new(MultiGzDecoder::new(&compressed[..])));
+        assert_eq!(reader.filter_map(Result::ok).count(), 3);
+
+        // a truncated record is an error, after which iteration ends
+        let truncated = &records.concat()[..records[0].len() + 20];
+        let results: Vec<Result<Document, Issue>> = WarcReader::new(truncated).collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+
+        assert!(is_warc_path("CC-MAIN-20190501.warc.gz"));
+        assert!(!is_warc_path("page.html"));
+    }
+}
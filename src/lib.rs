@@ -10,12 +10,44 @@
 extern crate serde_derive;
 extern crate serde;
 extern crate regex;
+extern crate regex_syntax;
 extern crate ron;
 extern crate url;
+extern crate whatlang;
 extern crate log;
 extern crate simplelog;
 extern crate lazy_static;
 extern crate htmlescape;
+extern crate aho_corasick;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate unicode_normalization;
+extern crate scraper;
+extern crate sha2;
+extern crate rand;
+extern crate flate2;
+extern crate encoding_rs;
+extern crate chardetng;
+extern crate brotli_decompressor;
+extern crate roxmltree;
+extern crate base64;
+extern crate zip;
+#[cfg(feature = "hyperscan")]
+extern crate hyperscan;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(any(feature = "webhook", feature = "s3"))]
+extern crate ureq;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
+#[cfg(feature = "s3")]
+extern crate hmac;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "signing")]
+extern crate ed25519_dalek;
+#[cfg(feature = "pdf")]
+extern crate pdf_extract;
 
 pub mod common;
 pub mod query;
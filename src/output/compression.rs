@@ -0,0 +1,171 @@
+//! This file provides functionality related to compressing outputs for
+//! storage. Outputs that include `FullContent` are large, but compress
+//! well.
+
+use common::validation::Issue;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use input::content_encoding::ContentEncoding;
+use output::output::OutputBatch;
+use ron;
+use std::io::{Read, Write};
+
+/// Denotes how serialized outputs are compressed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Compression {
+    /// No compression.
+    None,
+    /// Gzip (RFC 1952), which every platform can decompress.
+    Gzip,
+    /// Zstandard, which compresses better and faster than gzip, but is
+    /// only available when the `zstd` feature is enabled.
+    Zstd,
+}
+
+/// The magic number with which every Zstandard frame starts.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    /// Parses the name of a compression: `none`, `gzip`, or `zstd`.
+    pub fn parse(name: &str) -> Result<Compression, Issue> {
+        match name.trim() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            other => Err(Issue::Error(format!(
+                "unknown compression `{}`; expected `none`, `gzip`, or `zstd`",
+                other
+            ))),
+        }
+    }
+
+    /// The conventional file extension of the compression, including its
+    /// leading period (such as `.gz`), or an empty string for `None`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Determines the compression of the given data from its first bytes.
+    /// Data that is neither gzip nor Zstandard is assumed to be
+    /// uncompressed.
+    pub fn detect(data: &[u8]) -> Compression {
+        if ContentEncoding::from_magic(data) == ContentEncoding::Gzip {
+            Compression::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Compresses the given data.
+pub fn compress(data: &[u8], compression: Compression) -> Result<Vec<u8>, Issue> {
+    let failed = |error: std::io::Error| Issue::Error(format!("unable to compress outputs (`{}`)", error));
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).map_err(failed)?;
+            encoder.finish().map_err(failed)
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::encode_all(data, 0).map_err(failed),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(zstd_unavailable()),
+    }
+}
+
+/// Decompresses the given data, whose compression is detected (see
+/// `Compression::detect()`); uncompressed data is returned unchanged.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Issue> {
+    let failed = |error: std::io::Error| Issue::Error(format!("unable to decompress outputs (`{}`)", error));
+    match Compression::detect(data) {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(data).read_to_end(&mut decompressed).map_err(failed)?;
+            Ok(decompressed)
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::decode_all(data).map_err(failed),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(zstd_unavailable()),
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_unavailable() -> Issue {
+    Issue::Error(String::from("Zstandard compression requires the `zstd` feature"))
+}
+
+impl OutputBatch {
+    /// Serializes the batch (as RON, like individual output files) and
+    /// writes it, compressed, to the given destination.
+    pub fn write_compressed<W: Write>(&self, mut writer: W, compression: Compression) -> Result<(), Issue> {
+        let serialized = match ron::ser::to_string(self) {
+            Ok(value) => value,
+            Err(error) => {
+                return Err(Issue::Error(format!("unable to serialize outputs (`{}`)", error)))
+            }
+        };
+        let compressed = compress(serialized.as_bytes(), compression)?;
+        writer
+            .write_all(&compressed)
+            .and_then(|_| writer.flush())
+            .map_err(|error| Issue::Error(format!("unable to write outputs (`{}`)", error)))
+    }
+
+    /// Reads a batch written by `write_compressed()`, with any compression
+    /// (or none).
+    pub fn read_compressed<R: Read>(mut reader: R) -> Result<OutputBatch, Issue> {
+        let mut data = Vec::new();
+        if let Err(error) = reader.read_to_end(&mut data) {
+            return Err(Issue::Error(format!("unable to read outputs (`{}`)", error)));
+        }
+        let decompressed = decompress(&data)?;
+        let serialized = match String::from_utf8(decompressed) {
+            Ok(value) => value,
+            Err(error) => return Err(Issue::Error(format!("invalid outputs (`{}`)", error))),
+        };
+        ron::de::from_str(&serialized)
+            .map_err(|error| Issue::Error(format!("unable to deserialize outputs (`{}`)", error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::output::{Output, OutputItem};
+
+    #[test]
+    fn test_compressed_batches() {
+        let content = "the quick brown fox jumps over the lazy dog. ".repeat(200);
+        let mut output = Output::from_url("https://example.com");
+        output.items.push(OutputItem::FullContent(Some(content)));
+        output.id = Some(String::from("a"));
+        let batch = OutputBatch::from(vec![output]);
+
+        let mut uncompressed = Vec::new();
+        batch.write_compressed(&mut uncompressed, Compression::None).unwrap();
+        let mut compressed = Vec::new();
+        batch.write_compressed(&mut compressed, Compression::Gzip).unwrap();
+        assert_eq!(Compression::detect(&compressed), Compression::Gzip);
+        assert!(compressed.len() * 10 < uncompressed.len());
+        assert_eq!(OutputBatch::read_compressed(&compressed[..]).unwrap(), batch);
+        assert_eq!(OutputBatch::read_compressed(&uncompressed[..]).unwrap(), batch);
+
+        let mut zstd = Vec::new();
+        let written = batch.write_compressed(&mut zstd, Compression::Zstd);
+        assert_eq!(written.is_ok(), cfg!(feature = "zstd"));
+        if written.is_ok() {
+            assert_eq!(Compression::detect(&zstd), Compression::Zstd);
+            assert_eq!(OutputBatch::read_compressed(&zstd[..]).unwrap(), batch);
+        }
+        assert!(Compression::parse("brotli").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! This file provides functionality related to comparing output batches,
+//! such as the outputs of a scan before and after a query was changed.
+
+use output::output::{DeduplicationKey, Output, OutputBatch, OutputItem};
+use std::collections::BTreeMap;
+
+/// Identifies the outputs that correspond to one another in two batches:
+/// the ID of the query that produced the output, and the identity of its
+/// document (its URL or, failing that, its hash; see `DeduplicationKey`).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OutputKey {
+    /// The ID of the query that produced the output, if present.
+    pub query_id: Option<String>,
+    /// The URL (or, failing that, the hash) of the output's document, if
+    /// the output includes either.
+    pub document: Option<String>,
+}
+
+/// An output that is present in both batches of an `OutputDiff`, but
+/// differs between them.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OutputChange {
+    /// The key of the output.
+    pub key: OutputKey,
+    /// The output in the first batch.
+    pub before: Output,
+    /// The output in the second batch.
+    pub after: Output,
+    /// What differs between the outputs: `kind`, `tags`, `score`, or
+    /// `labels`, or the name of each item that differs (such as
+    /// `Excerpt`), in that order.
+    pub differences: Vec<String>,
+}
+
+/// The difference between two output batches (see `OutputBatch::diff()`).
+/// Each list is sorted by key.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct OutputDiff {
+    /// The outputs that are only present in the second batch.
+    pub added: Vec<Output>,
+    /// The outputs that are only present in the first batch.
+    pub removed: Vec<Output>,
+    /// The outputs that are present in both batches, but differ.
+    pub changed: Vec<OutputChange>,
+    /// The number of outputs that are present in both batches, and do not
+    /// differ.
+    pub unchanged: usize,
+}
+
+impl OutputDiff {
+    /// Whether the batches have the same outputs (see `OutputBatch::diff()`).
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Output {
+    /// The key of the output in an `OutputDiff`.
+    pub fn diff_key(&self) -> OutputKey {
+        OutputKey {
+            query_id: self.query_id.clone(),
+            document: self
+                .document_identity(DeduplicationKey::Url)
+                .or_else(|| self.document_identity(DeduplicationKey::Hash))
+                .map(String::from),
+        }
+    }
+}
+
+/// Whether the item differs between scans of the same document with the
+/// same query regardless of any change—such as the time of the scan—and
+/// so is ignored when comparing outputs.
+fn is_volatile(item: &OutputItem) -> bool {
+    matches!(item, OutputItem::Timestamp(_))
+}
+
+/// Determines what differs between the given outputs, ignoring their IDs,
+/// provenance, and volatile items (see `is_volatile()`).
+fn differences(before: &Output, after: &Output) -> Vec<String> {
+    let mut differences: Vec<String> = Vec::new();
+    if before.kind != after.kind {
+        differences.push(String::from("kind"));
+    }
+    if before.tags != after.tags {
+        differences.push(String::from("tags"));
+    }
+    if before.score != after.score {
+        differences.push(String::from("score"));
+    }
+    if before.labels != after.labels {
+        differences.push(String::from("labels"));
+    }
+    let items = |output: &Output| -> BTreeMap<&'static str, Vec<OutputItem>> {
+        let mut items: BTreeMap<&'static str, Vec<OutputItem>> = BTreeMap::new();
+        for item in output.items.iter().filter(|item| !is_volatile(item)) {
+            items.entry(item.name()).or_default().push(item.clone());
+        }
+        items
+    };
+    let (before_items, after_items) = (items(before), items(after));
+    for name in before_items.keys().chain(after_items.keys()) {
+        if before_items.get(name) != after_items.get(name) && !differences.iter().any(|known| known == name) {
+            differences.push(String::from(*name));
+        }
+    }
+    differences
+}
+
+impl OutputBatch {
+    /// Compares the batch with the other batch—typically, the outputs of a
+    /// scan before and after a query was changed—and reports which outputs
+    /// were added, removed, or changed. Outputs correspond to one another
+    /// when they have the same key (see `OutputKey`); when several outputs
+    /// of a batch have the same key, they correspond in order.
+    ///
+    /// Output IDs and provenance, which vary from run to run, are ignored,
+    /// as are `Timestamp` items.
+    pub fn diff(&self, other: &OutputBatch) -> OutputDiff {
+        let group = |batch: &OutputBatch| -> BTreeMap<OutputKey, Vec<Output>> {
+            let mut groups: BTreeMap<OutputKey, Vec<Output>> = BTreeMap::new();
+            for output in &batch.outputs {
+                groups.entry(output.diff_key()).or_default().push(output.clone());
+            }
+            groups
+        };
+        let mut before = group(self);
+        let mut after = group(other);
+        let mut diff = OutputDiff::default();
+        for (key, before_outputs) in before.iter_mut() {
+            let after_outputs = after.remove(key).unwrap_or_default();
+            let mut after_outputs = after_outputs.into_iter();
+            for before_output in before_outputs.drain(..) {
+                let after_output = match after_outputs.next() {
+                    Some(value) => value,
+                    None => {
+                        diff.removed.push(before_output);
+                        continue;
+                    }
+                };
+                let differences = differences(&before_output, &after_output);
+                if differences.is_empty() {
+                    diff.unchanged += 1;
+                } else {
+                    diff.changed.push(OutputChange {
+                        key: key.clone(),
+                        before: before_output,
+                        after: after_output,
+                        differences,
+                    });
+                }
+            }
+            diff.added.extend(after_outputs);
+        }
+        for (_, outputs) in after {
+            diff.added.extend(outputs);
+        }
+        diff.added.sort_by_key(|output| output.diff_key());
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(query_id: &str, url: &str, score: f32) -> Output {
+        let mut output = Output::from_url(url);
+        output.items.push(OutputItem::Timestamp(1_600_000_000));
+        output.id = Some(format!("{}-{}", query_id, url));
+        output.query_id = Some(String::from(query_id));
+        output.score = Some(score);
+        output
+    }
+
+    #[test]
+    fn test_output_batch_diff() {
+        let before = OutputBatch::from(vec![
+            output("Q1", "https://example.com/a", 1.0),
+            output("Q1", "https://example.com/b", 1.0),
+            output("Q2", "https://example.com/a", 1.0),
+        ]);
+        let mut unchanged = output("Q2", "https://example.com/a", 1.0);
+        unchanged.id = Some(String::from("another run"));
+        unchanged.items[1] = OutputItem::Timestamp(1_700_000_000);
+        let mut changed = output("Q1", "https://example.com/a", 2.0);
+        changed.items.push(OutputItem::Count(1));
+        let after = OutputBatch::from(vec![
+            output("Q3", "https://example.com/c", 1.0),
+            unchanged,
+            changed,
+        ]);
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.unchanged, 1);
+        let keys = |outputs: &Vec<Output>| -> Vec<OutputKey> { outputs.iter().map(Output::diff_key).collect() };
+        assert_eq!(
+            keys(&diff.added),
+            vec![OutputKey {
+                query_id: Some(String::from("Q3")),
+                document: Some(String::from("https://example.com/c")),
+            }]
+        );
+        assert_eq!(keys(&diff.removed)[0].document.as_deref(), Some("https://example.com/b"));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].differences, vec!["score", "Count"]);
+        assert!(!diff.is_empty());
+        assert!(before.diff(&before).is_empty());
+    }
+}
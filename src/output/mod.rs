@@ -1,3 +1,13 @@
 //! This module provides functionality related to outputs.
 
-pub mod output;
\ No newline at end of file
+pub mod compression;
+pub mod diff;
+#[allow(clippy::module_inception)]
+pub mod output;
+pub mod report;
+pub mod sink;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod store;
+pub mod table;
+pub mod writer;
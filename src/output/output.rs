@@ -1,9 +1,19 @@
 //! This file provides functionality related to outputs.
 
+use common::checksum::Checksum;
 use common::pattern::PatternMatch;
-use input::document::CompiledDocument;
+use common::timestamp;
+use common::validation::Issue;
+use input::document::{CompiledDocument, Provenance};
 use query::query::CompiledQuery;
-use query::response::{ResponseItem, ResponseKind};
+use query::response::{
+    ExcerptMode, OutputIdPolicy, ResponseItem, ResponseKind, TemplatePart, DEFAULT_EXCERPT_LIMIT,
+};
+use rand;
+use ron;
+use query::threshold::ThresholdTrace;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use url::Url;
 
 /// `Output` represents a 'match' of a Query. It is the primary
 /// product of an IEQL scan, and contains many variable (and configurable)
@@ -17,10 +27,9 @@ use query::response::{ResponseItem, ResponseKind};
 /// name online. Partial outputs, however, are meant to be MapReduced.
 /// For example, if a linguist wanted to count the number of times a certain
 /// word appears online, they would configure their query to produce a `Partial`
-/// response, which they would then MapReduce.
-/// 
-/// There is not currently full support for partial IEQL outputs.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// response, which they would then MapReduce. (See `Output::reduce()` and
+/// `OutputBatch::reduce_by_query()`.)
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Output {
     /// Contains the data relevant for the user; for example, excerpts of the match.
     pub items: Vec<OutputItem>,
@@ -34,13 +43,28 @@ pub struct Output {
     /// will only be present when the query that created the output itself
     /// has an id.
     pub query_id: Option<String>,
+    /// The union of the tags of every trigger that fired, in sorted
+    /// order and without duplicates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The score of the query's threshold (see `Threshold::score()`),
+    /// when the query that created the output is scored (that is, has
+    /// a `cutoff`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    /// The labels of the query that created the output (see
+    /// `Query.labels`), such as its priority.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Where the document that matched the query came from, such as the
+    /// path it was loaded from, when it is known. (See `Provenance`.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
 }
 
 /// This enum specifies the output type of the query. For more information
 /// about each type of query, please see the specification.
-/// 
-/// **There is currently not full support for partial queries.**
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum OutputKind {
     Full,
     Partial,
@@ -52,7 +76,7 @@ pub enum OutputKind {
 /// 
 /// Much of this information is simply copied from the metadata of the document
 /// that produced it.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum OutputItem {
     /// Represents the URL of the document that matched the query, if present.
     /// There is _no guarantee_ that this will be a valid url; if the mechanism
@@ -66,30 +90,149 @@ pub enum OutputItem {
     /// will the domain be.
     Domain(Option<String>),
     /// Contains any number of `PatternMatch`es—in other words, excerpts.
+    /// Duplicate excerpts are removed, and the number of excerpts is
+    /// limited by the response's `excerpt_limit`.
     Excerpt(Vec<PatternMatch>),
+    /// Contains the number of matches that were omitted from the `Excerpt`
+    /// because they were duplicates or exceeded the `excerpt_limit`. This
+    /// item is only present when matches were omitted.
+    AdditionalMatches(usize),
     /// Contains the full content of the matched page
-    FullContent(Option<String>)
+    FullContent(Option<String>),
+    /// Contains a human-readable message rendered from the `template`
+    /// of the query's response
+    Message(String),
+    /// Contains a trace of the evaluation of the query's threshold
+    Trace(ThresholdTrace),
+    /// Contains `PatternMatch`es paired with the ID of the trigger that
+    /// produced each of them, so that consumers can tell which trigger
+    /// fired. Unlike `Excerpt`, duplicates are not removed, but the number
+    /// of matches is still limited by the response's `excerpt_limit`.
+    TriggerMatches(Vec<(String, PatternMatch)>),
+    /// Contains the number of times each of the query's triggers matched
+    /// the document, keyed by trigger ID. (A negated trigger counts as a
+    /// single match when it fires.)
+    TriggerStats(BTreeMap<String, usize>),
+    /// Contains the time at which the output was produced, in seconds
+    /// since the Unix epoch (UTC).
+    Timestamp(i64),
+    /// Contains the name and version of the engine that produced the
+    /// output (see `ENGINE`).
+    Engine(String),
+    /// Contains the SHA-256 hash of the content of the document that
+    /// matched the query, in lowercase hexadecimal.
+    DocumentHash(String),
+    /// Represents the title of the document that matched the query, if
+    /// it is an HTML document with a (non-empty) `<title>`.
+    Title(Option<String>),
+    /// Contains the metadata of the document that matched the query (see
+    /// `Document.metadata`), in order of its keys.
+    Metadata(BTreeMap<String, String>),
+    /// Contains the number of documents that matched the query: `1`, or,
+    /// for reduced partial outputs, the number of outputs reduced.
+    Count(usize),
+    /// Contains the number of documents that matched the query on each
+    /// domain. (Documents without a domain are only counted in `Count`.)
+    DomainHistogram(BTreeMap<String, usize>),
 }
 
+impl OutputItem {
+    /// The name of the item's variant, such as `Url` or `Excerpt`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputItem::Url(_) => "Url",
+            OutputItem::Mime(_) => "Mime",
+            OutputItem::Domain(_) => "Domain",
+            OutputItem::Excerpt(_) => "Excerpt",
+            OutputItem::AdditionalMatches(_) => "AdditionalMatches",
+            OutputItem::FullContent(_) => "FullContent",
+            OutputItem::Message(_) => "Message",
+            OutputItem::Trace(_) => "Trace",
+            OutputItem::TriggerMatches(_) => "TriggerMatches",
+            OutputItem::TriggerStats(_) => "TriggerStats",
+            OutputItem::Timestamp(_) => "Timestamp",
+            OutputItem::Engine(_) => "Engine",
+            OutputItem::DocumentHash(_) => "DocumentHash",
+            OutputItem::Title(_) => "Title",
+            OutputItem::Metadata(_) => "Metadata",
+            OutputItem::Count(_) => "Count",
+            OutputItem::DomainHistogram(_) => "DomainHistogram",
+        }
+    }
+}
+
+/// The name and version of this engine, such as `ieql 0.3.0`, as
+/// included in outputs by `ResponseItem::Engine`.
+pub const ENGINE: &str = concat!("ieql ", env!("CARGO_PKG_VERSION"));
+
 /// Represents a batch (collection) of outputs. This function tends to be
 /// helpful for multiprocessing, though it is somewhat infrequently used.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct OutputBatch {
     /// Contains the outputs.
     pub outputs: Vec<Output>,
+    /// The ID of the `DocumentReferenceBatch` whose documents produced
+    /// the outputs, when they all came from the same identified batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<String>,
+}
+
+/// Determines which outputs of an `OutputBatch` are duplicates of one
+/// another. (See `OutputBatch::deduplicate()`.)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeduplicationPolicy {
+    /// What identifies the document of an output.
+    pub key: DeduplicationKey,
+    /// Whether the excerpts of duplicates are merged into the output
+    /// that is kept (without repeating identical excerpts); otherwise,
+    /// they are discarded along with the duplicates.
+    pub merge_excerpts: bool,
+}
+
+/// Denotes what identifies the document of an output for deduplication.
+/// Outputs of the same query whose documents have the same identity are
+/// duplicates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DeduplicationKey {
+    /// The URL of the document, as included by `ResponseItem::Url`.
+    Url,
+    /// The hash of the document's content, as included by
+    /// `ResponseItem::DocumentHash`; this also collapses mirrors of the
+    /// same document at different URLs.
+    Hash,
+}
+
+/// Represents aggregate statistics of an `OutputBatch`, such as for
+/// monitoring dashboards. (See `OutputBatch::summarize()`.)
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct OutputSummary {
+    /// The total number of outputs.
+    pub outputs: usize,
+    /// The number of distinct documents that the outputs are about,
+    /// identified by their URL or, when the output does not include it,
+    /// by their hash (see `ResponseItem::DocumentHash`). Each output that
+    /// includes neither is counted as a distinct document.
+    pub documents: usize,
+    /// The number of outputs of each query, keyed by query ID. (Outputs
+    /// of queries without an ID are only counted in `outputs`.)
+    pub queries: BTreeMap<String, usize>,
+    /// The number of outputs about documents on each domain, taken from
+    /// the output's `Domain` or, failing that, its `Url`. (Outputs that
+    /// include neither are only counted in `outputs`.)
+    pub domains: BTreeMap<String, usize>,
 }
 
 impl From<Vec<Output>> for OutputBatch {
     fn from(outputs: Vec<Output>) -> OutputBatch {
-        OutputBatch { outputs: outputs }
+        OutputBatch {
+            outputs,
+            batch: None,
+        }
     }
 }
 
 fn string_clone_helper(to_clone: &Option<String>) -> Option<String> {
-    match to_clone {
-        Some(value) => Some(value.clone()),
-        None => None,
-    }
+    to_clone.clone()
 }
 
 impl Output {
@@ -99,12 +242,18 @@ impl Output {
     /// # Arguments
     /// * `document`: the compiled document that the query matched
     /// * `query`: the compiled query that matched the document
-    /// * `matches`: the `PatternMatch`es produced by the queries' triggers
+    /// * `matches`: the `PatternMatch`es produced by the queries' triggers (one
+    ///   for every occurrence of every matching trigger), each paired with the
+    ///   ID of the trigger that produced it
+    /// * `tags`: the tags of the triggers that fired (duplicates are removed)
+    /// * `triggers`: the number of times each of the query's triggers matched
     /// * `id`: the optional ID of the desired output
     pub fn new(
         document: &CompiledDocument,
         query: &CompiledQuery,
-        matches: Vec<PatternMatch>,
+        matches: Vec<(String, PatternMatch)>,
+        tags: Vec<String>,
+        triggers: &HashMap<&String, usize>,
         id: Option<String>,
     ) -> Output {
         // warning: expensive!
@@ -113,34 +262,209 @@ impl Output {
             ResponseKind::Partial => OutputKind::Partial,
         };
         let query_id = string_clone_helper(&query.id);
+        let (trigger_ids, matches): (Vec<String>, Vec<PatternMatch>) = matches.into_iter().unzip();
+        let matches: Vec<PatternMatch> = match query.response.excerpt_mode {
+            Some(ExcerptMode::Sentence) => matches.iter().map(PatternMatch::to_sentences).collect(),
+            Some(ExcerptMode::Window) | None => matches,
+        };
+        let limit = query.response.excerpt_limit.unwrap_or(DEFAULT_EXCERPT_LIMIT);
         let mut items: Vec<OutputItem> = Vec::new();
         for item in &query.response.include {
             match item {
-                ResponseItem::Domain => items.push(OutputItem::Domain((&document.domain).clone())),
+                ResponseItem::Domain => items.push(OutputItem::Domain(document.domain.clone())),
                 ResponseItem::Mime => {
                     items.push(OutputItem::Mime(string_clone_helper(&document.mime)))
                 }
                 ResponseItem::Url => {
                     items.push(OutputItem::Url(string_clone_helper(&document.url)))
                 }
-                ResponseItem::Excerpt => items.push(OutputItem::Excerpt(matches.clone())),
-                ResponseItem::FullContent => items.push(OutputItem::FullContent(Some((&document.raw).clone())))
+                ResponseItem::Excerpt => {
+                    let (excerpts, omitted) = condense_matches(&matches, limit);
+                    items.push(OutputItem::Excerpt(excerpts));
+                    if omitted > 0 {
+                        items.push(OutputItem::AdditionalMatches(omitted));
+                    }
+                }
+                ResponseItem::FullContent => items.push(OutputItem::FullContent(Some(
+                    query.response.prepare_content(&document.raw, &query.redactions),
+                ))),
+                ResponseItem::Trace => {
+                    if let Ok(trace) = query.threshold.explain(triggers) {
+                        items.push(OutputItem::Trace(trace));
+                    }
+                }
+                ResponseItem::TriggerMatches => items.push(OutputItem::TriggerMatches(
+                    trigger_ids
+                        .iter()
+                        .cloned()
+                        .zip(matches.iter().cloned())
+                        .take(limit)
+                        .collect(),
+                )),
+                ResponseItem::TriggerStats => items.push(OutputItem::TriggerStats(
+                    triggers
+                        .iter()
+                        .map(|(id, count)| ((*id).clone(), *count))
+                        .collect(),
+                )),
+                ResponseItem::Timestamp => items.push(OutputItem::Timestamp(timestamp::now())),
+                ResponseItem::Engine => items.push(OutputItem::Engine(String::from(ENGINE))),
+                ResponseItem::DocumentHash => items.push(OutputItem::DocumentHash(document.hash.clone())),
+                ResponseItem::Title => items.push(OutputItem::Title(document_title(document).map(String::from))),
+                ResponseItem::Metadata => items.push(OutputItem::Metadata(
+                    document.metadata.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+                )),
+                ResponseItem::Count => items.push(OutputItem::Count(1)),
+                ResponseItem::DomainHistogram => items.push(OutputItem::DomainHistogram(
+                    document.domain.iter().map(|domain| (domain.clone(), 1)).collect(),
+                )),
             }
         }
+        if let Some(template) = &query.response.template {
+            items.push(OutputItem::Message(render_template(
+                template, document, query, &matches,
+            )));
+        }
+        let mut tags = tags;
+        tags.sort();
+        tags.dedup();
         Output {
-            items: items,
-            kind: kind,
-            id: id,
-            query_id: query_id,
+            items,
+            kind,
+            id,
+            query_id,
+            tags,
+            score: None,
+            labels: query.labels.clone(),
+            provenance: document.provenance.clone(),
+        }
+    }
+}
+
+/// Generates the ID of the output of the given query on the given
+/// document, according to the `output_ids` policy of the query's response.
+pub fn generate_output_id(document: &CompiledDocument, query: &CompiledQuery) -> Option<String> {
+    match query.response.output_ids.unwrap_or(OutputIdPolicy::Derived) {
+        OutputIdPolicy::Derived => Some(derive_output_id(document, query)),
+        OutputIdPolicy::Random => Some(random_uuid()),
+        OutputIdPolicy::Omitted => None,
+    }
+}
+
+/// Generates a random (version 4) UUID in its hyphenated form.
+fn random_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Derives a deterministic ID for the output of the given query on the
+/// given document from the query's ID and the document's checksum. The
+/// same query matching the same document always produces the same ID,
+/// which makes the outputs of different runs comparable.
+pub fn derive_output_id(document: &CompiledDocument, query: &CompiledQuery) -> String {
+    let mut checksum = Checksum::new();
+    checksum.update_optional(query.id.as_deref());
+    checksum.update(&document.checksum().to_le_bytes());
+    format!("{:016x}", checksum.value())
+}
+
+/// Removes duplicate matches (those with identical excerpts and relevant
+/// portions, as produced by repetitive pages) and limits the number of
+/// matches to `limit`, returning the remaining matches and the number of
+/// matches that were omitted.
+fn condense_matches(matches: &[PatternMatch], limit: usize) -> (Vec<PatternMatch>, usize) {
+    let mut seen: HashSet<(&str, (usize, usize))> = HashSet::new();
+    let mut condensed: Vec<PatternMatch> = Vec::new();
+    for pattern_match in matches {
+        if condensed.len() >= limit {
+            break;
         }
+        if seen.insert((pattern_match.excerpt.as_str(), pattern_match.relevant)) {
+            condensed.push(pattern_match.clone());
+        }
+    }
+    let omitted = matches.len() - condensed.len();
+    (condensed, omitted)
+}
+
+/// The title of the given document, if it has a (non-empty) title.
+fn document_title(document: &CompiledDocument) -> Option<&str> {
+    match document.structure.title.as_str() {
+        "" => None,
+        title => Some(title),
+    }
+}
+
+/// Renders the given response template, replacing each of the
+/// placeholders in `TEMPLATE_PLACEHOLDERS` (and each `{metadata.<key>}`)
+/// with its value. Unknown placeholders, which validation rejects, are
+/// rendered as empty strings.
+fn render_template(
+    template: &str,
+    document: &CompiledDocument,
+    query: &CompiledQuery,
+    matches: &[PatternMatch],
+) -> String {
+    let first_match = matches.first();
+    let count = matches.len().to_string();
+    let values: [(&str, Option<&str>); 8] = [
+        ("query_id", query.id.as_deref()),
+        ("url", document.url.as_deref()),
+        ("domain", document.domain.as_deref()),
+        ("mime", document.mime.as_deref()),
+        ("excerpt", first_match.map(|value| value.excerpt.trim())),
+        (
+            "match",
+            first_match.and_then(|value| value.excerpt.get(value.relevant.0..value.relevant.1)),
+        ),
+        ("title", document_title(document)),
+        ("count", Some(count.as_str())),
+    ];
+    let mut rendered = String::with_capacity(template.len());
+    for part in TemplatePart::parse(template) {
+        let value = match part {
+            TemplatePart::Text(text) => {
+                rendered.push_str(&text);
+                continue;
+            }
+            // `{metadata.<key>}` is the value of the given key of the document's metadata
+            TemplatePart::Placeholder(name) => match name.strip_prefix("metadata.") {
+                Some(key) => document.metadata.get(key).map(String::as_str),
+                None => values
+                    .iter()
+                    .find(|(placeholder, _)| *placeholder == name)
+                    .and_then(|(_, value)| *value),
+            },
+        };
+        rendered.push_str(value.unwrap_or(""));
     }
+    rendered
 }
 
 impl OutputBatch {
     /// Merges the output batch with the other output
     /// batch. This function _consumes_ the other output
     /// batch, but involves no memory duplication.
+    ///
+    /// When the outputs of the two batches came from different document
+    /// batches, the merged batch has no `batch` ID (but each output
+    /// retains its `provenance`).
     pub fn merge_with(&mut self, other: OutputBatch) {
+        if self.outputs.is_empty() {
+            self.batch = other.batch;
+        } else if !other.outputs.is_empty() && self.batch != other.batch {
+            self.batch = None;
+        }
         self.outputs.extend(other.outputs);
     }
 
@@ -148,6 +472,267 @@ impl OutputBatch {
     pub fn new() -> OutputBatch {
         OutputBatch::from(vec![])
     }
+
+    /// Removes the outputs that are duplicates of an earlier output in the
+    /// batch (see `DeduplicationPolicy`), returning the number of outputs
+    /// that were removed. Outputs that do not include the item that
+    /// identifies their document (such as the `Url`) are never considered
+    /// duplicates. The remaining outputs keep their order.
+    pub fn deduplicate(&mut self, policy: DeduplicationPolicy) -> usize {
+        let mut kept: Vec<Output> = Vec::new();
+        let mut positions: HashMap<(Option<String>, String), usize> = HashMap::new();
+        let count = self.outputs.len();
+        for output in self.outputs.drain(..) {
+            let identity = match output.document_identity(policy.key) {
+                Some(identity) => (output.query_id.clone(), String::from(identity)),
+                None => {
+                    kept.push(output);
+                    continue;
+                }
+            };
+            match positions.get(&identity) {
+                Some(position) => {
+                    if policy.merge_excerpts {
+                        kept[*position].merge_excerpts_of(output);
+                    }
+                }
+                None => {
+                    positions.insert(identity, kept.len());
+                    kept.push(output);
+                }
+            }
+        }
+        self.outputs = kept;
+        count - self.outputs.len()
+    }
+
+    /// Sorts the outputs into their canonical order: by the URL of their
+    /// document (when the response includes it), then by query ID, then
+    /// by ID. Concurrent scans produce outputs in no particular order, so
+    /// their batches should be sorted before they are compared.
+    pub fn sort(&mut self) {
+        self.outputs.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    }
+
+    /// Serializes the batch canonically: one output per line, as compact
+    /// RON, in canonical order (see `sort()`; outputs with the same key
+    /// are ordered by their serialization). Scans that produce the same
+    /// outputs produce the same canonical serialization, regardless of
+    /// the order in which the outputs were produced, which makes it suitable
+    /// for diff-based regression testing. (Note that outputs only have
+    /// reproducible IDs when they are `Derived`; see `OutputIdPolicy`.)
+    pub fn to_canonical_string(&self) -> Result<String, Issue> {
+        let mut lines: Vec<(OutputSortKey, String)> = Vec::new();
+        for output in &self.outputs {
+            match ron::ser::to_string(output) {
+                Ok(serialized) => lines.push((output.sort_key(), serialized)),
+                Err(error) => {
+                    return Err(Issue::Error(format!("unable to serialize output (`{}`)", error)))
+                }
+            }
+        }
+        lines.sort();
+        let mut canonical = String::new();
+        for (_, line) in lines {
+            canonical.push_str(&line);
+            canonical.push('\n');
+        }
+        Ok(canonical)
+    }
+
+    /// Reduces the partial outputs of each query into a single output
+    /// (see `Output::reduce()`), in the order in which the queries first
+    /// appear in the batch; full outputs are kept as they are (and precede
+    /// the reduced outputs). Outputs of queries without an ID are reduced
+    /// together.
+    pub fn reduce_by_query(self) -> Result<OutputBatch, Issue> {
+        let batch = self.batch;
+        let mut outputs: Vec<Output> = Vec::new();
+        let mut groups: Vec<(Option<String>, Vec<Output>)> = Vec::new();
+        for output in self.outputs {
+            if output.kind == OutputKind::Full {
+                outputs.push(output);
+                continue;
+            }
+            match groups.iter_mut().find(|(query_id, _)| *query_id == output.query_id) {
+                Some((_, group)) => group.push(output),
+                None => groups.push((output.query_id.clone(), vec![output])),
+            }
+        }
+        for (_, group) in groups {
+            outputs.push(Output::reduce(group)?);
+        }
+        Ok(OutputBatch { outputs, batch })
+    }
+
+    /// Summarizes the batch: the number of outputs, of distinct documents,
+    /// and of outputs per query and per domain. (See `OutputSummary`.)
+    pub fn summarize(&self) -> OutputSummary {
+        let mut summary = OutputSummary::default();
+        let mut documents: HashSet<&str> = HashSet::new();
+        let mut unidentified = 0;
+        for output in &self.outputs {
+            summary.outputs += 1;
+            match output
+                .document_identity(DeduplicationKey::Url)
+                .or_else(|| output.document_identity(DeduplicationKey::Hash))
+            {
+                Some(identity) => {
+                    documents.insert(identity);
+                }
+                None => unidentified += 1,
+            }
+            if let Some(query_id) = &output.query_id {
+                *summary.queries.entry(query_id.clone()).or_insert(0) += 1;
+            }
+            if let Some(domain) = output.domain() {
+                *summary.domains.entry(domain).or_insert(0) += 1;
+            }
+        }
+        summary.documents = documents.len() + unidentified;
+        summary
+    }
+}
+
+/// The key by which outputs are ordered canonically: the URL of their
+/// document, their query ID, and their ID. (See `OutputBatch::sort()`.)
+type OutputSortKey<'a> = (Option<&'a str>, Option<&'a str>, Option<&'a str>);
+
+impl Output {
+    /// Reduces the given partial outputs of a single query into one
+    /// partial output, in the manner of MapReduce: counts, histograms,
+    /// and `TriggerStats` are summed, and `Timestamp`s reduce to the
+    /// latest. The reduced output has no ID and no provenance, and its
+    /// tags are the union of the outputs' tags.
+    ///
+    /// An `Issue::Error` is returned if there are no outputs, if any of
+    /// them is a full output or contains an item that cannot be reduced
+    /// (see `ResponseItem::is_reducible()`), or if they come from
+    /// different queries.
+    pub fn reduce(outputs: Vec<Output>) -> Result<Output, Issue> {
+        let mut outputs = outputs.into_iter();
+        let mut reduced = match outputs.next() {
+            Some(output) => Output {
+                id: None,
+                provenance: None,
+                ..output
+            },
+            None => return Err(Issue::Error(String::from("there are no outputs to reduce"))),
+        };
+        if reduced.kind != OutputKind::Partial {
+            return Err(Issue::Error(String::from("full outputs cannot be reduced")));
+        }
+        let mut items: Vec<OutputItem> = Vec::new();
+        for item in reduced.items.drain(..) {
+            reduce_item(&mut items, item)?;
+        }
+        for output in outputs {
+            if output.kind != OutputKind::Partial {
+                return Err(Issue::Error(String::from("full outputs cannot be reduced")));
+            }
+            if output.query_id != reduced.query_id {
+                return Err(Issue::Error(String::from(
+                    "outputs of different queries cannot be reduced together",
+                )));
+            }
+            for item in output.items {
+                reduce_item(&mut items, item)?;
+            }
+            reduced.tags.extend(output.tags);
+        }
+        reduced.items = items;
+        reduced.tags.sort();
+        reduced.tags.dedup();
+        reduced.score = None;
+        Ok(reduced)
+    }
+
+    /// The identity of the output's document according to the given key,
+    /// if the output includes it.
+    pub(crate) fn document_identity(&self, key: DeduplicationKey) -> Option<&str> {
+        self.items.iter().find_map(|item| match (key, item) {
+            (DeduplicationKey::Url, OutputItem::Url(url)) => url.as_deref(),
+            (DeduplicationKey::Hash, OutputItem::DocumentHash(hash)) => Some(hash.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The domain of the output's document, if the output includes it or
+    /// includes a URL from which it can be determined.
+    pub(crate) fn domain(&self) -> Option<String> {
+        let domain = self.items.iter().find_map(|item| match item {
+            OutputItem::Domain(domain) => domain.clone(),
+            _ => None,
+        });
+        domain.or_else(|| {
+            let url = Url::parse(self.sort_key().0?).ok()?;
+            url.host_str().map(String::from)
+        })
+    }
+
+    /// Appends the excerpts of the other output to those of this output,
+    /// skipping the excerpts that it already contains.
+    fn merge_excerpts_of(&mut self, other: Output) {
+        let excerpts = match self.items.iter_mut().find_map(|item| match item {
+            OutputItem::Excerpt(excerpts) => Some(excerpts),
+            _ => None,
+        }) {
+            Some(excerpts) => excerpts,
+            None => return, // the response does not include excerpts
+        };
+        for item in other.items {
+            if let OutputItem::Excerpt(others) = item {
+                for excerpt in others {
+                    if !excerpts.contains(&excerpt) {
+                        excerpts.push(excerpt);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The key by which the output is ordered canonically.
+    fn sort_key(&self) -> OutputSortKey<'_> {
+        let url = self.items.iter().find_map(|item| match item {
+            OutputItem::Url(url) => url.as_deref(),
+            _ => None,
+        });
+        (url, self.query_id.as_deref(), self.id.as_deref())
+    }
+}
+
+/// Combines the given item into the reduced items: with the item of the
+/// same kind, if there is one, and otherwise as a new item.
+fn reduce_item(items: &mut Vec<OutputItem>, item: OutputItem) -> Result<(), Issue> {
+    let add = |totals: &mut BTreeMap<String, usize>, counts: BTreeMap<String, usize>| {
+        for (key, count) in counts {
+            *totals.entry(key).or_insert(0) += count;
+        }
+    };
+    for existing in items.iter_mut() {
+        match (existing, &item) {
+            (OutputItem::Count(total), OutputItem::Count(count)) => *total += count,
+            (OutputItem::Timestamp(latest), OutputItem::Timestamp(timestamp)) => {
+                *latest = (*latest).max(*timestamp)
+            }
+            (OutputItem::DomainHistogram(totals), OutputItem::DomainHistogram(counts))
+            | (OutputItem::TriggerStats(totals), OutputItem::TriggerStats(counts)) => {
+                add(totals, counts.clone())
+            }
+            _ => continue,
+        }
+        return Ok(());
+    }
+    match item {
+        OutputItem::Count(_)
+        | OutputItem::Timestamp(_)
+        | OutputItem::DomainHistogram(_)
+        | OutputItem::TriggerStats(_) => {
+            items.push(item);
+            Ok(())
+        }
+        _ => Err(Issue::Error(format!("output item `{:?}` cannot be reduced", item))),
+    }
 }
 
 impl std::fmt::Display for Output {
@@ -164,9 +749,255 @@ impl std::fmt::Display for Output {
             Some(value) => format!(" from `{}`", value),
             None => String::from(""),
         };
+        let tags = if self.tags.is_empty() {
+            String::from("")
+        } else {
+            format!(" {:?}", self.tags)
+        };
+        let labels = if self.labels.is_empty() {
+            String::from("")
+        } else {
+            format!(" {:?}", self.labels)
+        };
+        let score = match self.score {
+            Some(score) => format!(" (score {:.2})", score),
+            None => String::from(""),
+        };
         let mut items: Vec<String> = Vec::new();
         for item in &self.items {
             items.push(format!("{:?}", item));
         }
-        write!(f, "{} {}{}: {:?}", id, kind, query_id, items)
-    }}
\ No newline at end of file
+        write!(f, "{} {}{}{}{}{}: {:?}", id, kind, query_id, tags, labels, score, items)
+    }}
+
+#[cfg(test)]
+impl Output {
+    /// A `Full` output of the query `Q1` whose only item is the given URL,
+    /// and which has no other information; shared by the tests throughout
+    /// the crate.
+    pub(crate) fn from_url(url: &str) -> Output {
+        Output {
+            items: vec![OutputItem::Url(Some(String::from(url)))],
+            kind: OutputKind::Full,
+            id: None,
+            query_id: Some(String::from("Q1")),
+            tags: vec![],
+            score: None,
+            labels: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn get_match(excerpt: &str) -> PatternMatch {
+        PatternMatch {
+            excerpt: String::from(excerpt),
+            relevant: (0, 3),
+            captures: BTreeMap::new(),
+            offsets: None,
+            content: None,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_condense_matches() {
+        let matches = vec![
+            get_match("row row row"),
+            get_match("row row row"),
+            get_match("row, the end"),
+            get_match("row row row"),
+            get_match("row boat"),
+        ];
+        let (condensed, omitted) = condense_matches(&matches, 32);
+        assert_eq!(condensed.len(), 3);
+        assert_eq!(omitted, 2);
+
+        let (condensed, omitted) = condense_matches(&matches, 1);
+        assert_eq!(condensed, vec![get_match("row row row")]);
+        assert_eq!(omitted, 4);
+    }
+
+    fn get_output(url: &str, query_id: &str, excerpt: &str) -> Output {
+        let mut output = Output::from_url(url);
+        output.items.push(OutputItem::Excerpt(vec![get_match(excerpt)]));
+        output.query_id = Some(String::from(query_id));
+        output
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let outputs = || {
+            vec![
+                get_output("https://example.com/b", "Q1", "row"),
+                get_output("https://example.com/a", "Q2", "row"),
+                get_output("https://example.com/a", "Q1", "oar"),
+                get_output("https://example.com/a", "Q1", "boat"),
+            ]
+        };
+        let mut batch = OutputBatch::from(outputs());
+        let mut reversed = OutputBatch::from(outputs().into_iter().rev().collect::<Vec<Output>>());
+        assert_eq!(batch.to_canonical_string().unwrap(), reversed.to_canonical_string().unwrap());
+        assert_eq!(batch.to_canonical_string().unwrap().lines().count(), 4);
+
+        batch.sort();
+        reversed.sort();
+        let keys: Vec<(&str, &str)> = batch
+            .outputs
+            .iter()
+            .map(|output| (output.sort_key().0.unwrap(), output.sort_key().1.unwrap()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("https://example.com/a", "Q1"),
+                ("https://example.com/a", "Q1"),
+                ("https://example.com/a", "Q2"),
+                ("https://example.com/b", "Q1"),
+            ]
+        );
+        assert_eq!(batch.outputs[0], reversed.outputs[1]); // equal keys keep their order
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let outputs = || {
+            let mut mirror = get_output("https://mirror.example/a", "Q1", "oar");
+            mirror.items.push(OutputItem::DocumentHash(String::from("abc")));
+            let mut original = get_output("https://example.com/a", "Q1", "row");
+            original.items.push(OutputItem::DocumentHash(String::from("abc")));
+            let mut unidentified = get_output("", "Q1", "row");
+            unidentified.items.remove(0);
+            vec![
+                original,
+                get_output("https://example.com/a", "Q2", "row"),
+                get_output("https://example.com/a", "Q1", "boat"),
+                get_output("https://example.com/a", "Q1", "row"),
+                mirror,
+                unidentified,
+            ]
+        };
+
+        let mut batch = OutputBatch::from(outputs());
+        let policy = DeduplicationPolicy {
+            key: DeduplicationKey::Url,
+            merge_excerpts: false,
+        };
+        assert_eq!(batch.deduplicate(policy), 2);
+        assert_eq!(batch.outputs.len(), 4);
+        assert_eq!(batch.outputs[0], outputs().remove(0));
+
+        let mut batch = OutputBatch::from(outputs());
+        let policy = DeduplicationPolicy {
+            key: DeduplicationKey::Url,
+            merge_excerpts: true,
+        };
+        assert_eq!(batch.deduplicate(policy), 2);
+        assert_eq!(
+            batch.outputs[0].items[1],
+            OutputItem::Excerpt(vec![get_match("row"), get_match("boat")])
+        );
+
+        let mut batch = OutputBatch::from(outputs());
+        let policy = DeduplicationPolicy {
+            key: DeduplicationKey::Hash,
+            merge_excerpts: true,
+        };
+        assert_eq!(batch.deduplicate(policy), 1); // only the mirror
+        assert_eq!(
+            batch.outputs[0].items[1],
+            OutputItem::Excerpt(vec![get_match("row"), get_match("oar")])
+        );
+    }
+
+    #[test]
+    fn test_reduce() {
+        let partial = |query_id: &str, domain: &str, timestamp: i64| {
+            let mut output = get_output("", query_id, "");
+            output.kind = OutputKind::Partial;
+            output.id = Some(String::from(domain));
+            output.tags = vec![String::from(domain)];
+            output.items = vec![
+                OutputItem::Count(1),
+                OutputItem::DomainHistogram(vec![(String::from(domain), 1)].into_iter().collect()),
+                OutputItem::Timestamp(timestamp),
+            ];
+            output
+        };
+        let reduced = Output::reduce(vec![
+            partial("Q1", "a.com", 5),
+            partial("Q1", "b.com", 9),
+            partial("Q1", "a.com", 7),
+        ])
+        .unwrap();
+        let histogram: BTreeMap<String, usize> =
+            vec![(String::from("a.com"), 2), (String::from("b.com"), 1)].into_iter().collect();
+        assert_eq!(
+            reduced.items,
+            vec![OutputItem::Count(3), OutputItem::DomainHistogram(histogram), OutputItem::Timestamp(9)]
+        );
+        assert_eq!(reduced.id, None);
+        assert_eq!(reduced.tags, vec![String::from("a.com"), String::from("b.com")]);
+        assert_eq!(reduced.query_id, Some(String::from("Q1")));
+
+        assert!(Output::reduce(vec![]).is_err());
+        assert!(Output::reduce(vec![partial("Q1", "a.com", 5), partial("Q2", "a.com", 5)]).is_err());
+        assert!(Output::reduce(vec![get_output("https://example.com/a", "Q1", "row")]).is_err());
+        let mut unreducible = partial("Q1", "a.com", 5);
+        unreducible.items.push(OutputItem::Url(None));
+        assert!(Output::reduce(vec![partial("Q1", "a.com", 5), unreducible]).is_err());
+
+        let batch = OutputBatch::from(vec![
+            partial("Q1", "a.com", 5),
+            get_output("https://example.com/a", "Q3", "row"),
+            partial("Q2", "a.com", 5),
+            partial("Q1", "b.com", 5),
+        ]);
+        let reduced = batch.reduce_by_query().unwrap();
+        let keys: Vec<(&str, &OutputItem)> = reduced
+            .outputs
+            .iter()
+            .map(|output| (output.query_id.as_deref().unwrap(), &output.items[0]))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("Q3", &OutputItem::Url(Some(String::from("https://example.com/a")))),
+                ("Q1", &OutputItem::Count(2)),
+                ("Q2", &OutputItem::Count(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let mut hashed = get_output("", "Q2", "row");
+        hashed.items[0] = OutputItem::DocumentHash(String::from("abc"));
+        let mut unidentified = get_output("", "Q1", "row");
+        unidentified.items[0] = OutputItem::Domain(Some(String::from("example.com")));
+        let mut anonymous = get_output("https://mirror.example/a", "", "oar");
+        anonymous.query_id = None;
+        let batch = OutputBatch::from(vec![
+            get_output("https://example.com/a", "Q1", "row"),
+            get_output("https://example.com/a", "Q2", "row"),
+            get_output("https://example.com/b", "Q1", "boat"),
+            hashed,
+            unidentified,
+            anonymous,
+        ]);
+        let summary = batch.summarize();
+        assert_eq!(summary.outputs, 6);
+        assert_eq!(summary.documents, 5);
+        let counts = |pairs: &[(&str, usize)]| -> BTreeMap<String, usize> {
+            pairs.iter().map(|(key, count)| (String::from(*key), *count)).collect()
+        };
+        assert_eq!(summary.queries, counts(&[("Q1", 3), ("Q2", 2)]));
+        assert_eq!(summary.domains, counts(&[("example.com", 4), ("mirror.example", 1)]));
+        assert_eq!(OutputBatch::new().summarize(), OutputSummary::default());
+    }
+}
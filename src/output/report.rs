@@ -0,0 +1,237 @@
+//! This file provides functionality related to rendering outputs as a
+//! self-contained HTML report, so that scan results can be shared with
+//! people who would rather not read RON. The report has summary tables
+//! (outputs per query and per domain), followed by a section for each
+//! query that lists its outputs with their excerpts, the matched text of
+//! which is highlighted.
+
+use common::pattern::PatternMatch;
+use htmlescape::{encode_attribute, encode_minimal};
+use output::output::{Output, OutputBatch, OutputItem};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The style sheet of the report, which is embedded so that the report
+/// is a single file.
+const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; margin: 2em auto; max-width: 60em; padding: 0 1em; color: #222; }
+h1, h2 { border-bottom: 1px solid #ddd; padding-bottom: 0.2em; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #ddd; padding: 0.3em 0.8em; text-align: left; }
+td.count { text-align: right; }
+.output { border: 1px solid #ddd; border-radius: 4px; margin: 1em 0; padding: 0.5em 1em; }
+.meta { color: #666; font-size: 0.9em; }
+.tag { background: #eef; border-radius: 3px; padding: 0 0.3em; margin-right: 0.3em; }
+blockquote { background: #f8f8f8; border-left: 3px solid #ccc; margin: 0.5em 0; padding: 0.3em 0.8em; white-space: pre-wrap; }
+mark { background: #ffe066; }
+";
+
+/// The name under which the outputs of queries without an ID are listed.
+const UNKNOWN_QUERY: &str = "unknown_query";
+
+/// Renders the given excerpt, highlighting its relevant portion.
+fn render_excerpt(rendered: &mut String, trigger_id: Option<&str>, pattern_match: &PatternMatch) {
+    let excerpt = &pattern_match.excerpt;
+    let (start, end) = pattern_match.relevant;
+    rendered.push_str("<blockquote>");
+    if let Some(trigger_id) = trigger_id {
+        let _ = write!(rendered, "<span class=\"tag\">{}</span>", encode_minimal(trigger_id));
+    }
+    match (excerpt.get(..start), excerpt.get(start..end), excerpt.get(end..)) {
+        (Some(before), Some(relevant), Some(after)) if start < end => {
+            let _ = write!(
+                rendered,
+                "{}<mark>{}</mark>{}",
+                encode_minimal(before),
+                encode_minimal(relevant),
+                encode_minimal(after)
+            );
+        }
+        _ => rendered.push_str(&encode_minimal(excerpt)),
+    }
+    rendered.push_str("</blockquote>\n");
+}
+
+/// Renders a link to the given URL when it is a web address, and the URL
+/// as text otherwise (such as a local path).
+fn render_url(url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        format!("<a href=\"{}\">{}</a>", encode_attribute(url), encode_minimal(url))
+    } else {
+        format!("<code>{}</code>", encode_minimal(url))
+    }
+}
+
+/// Renders the given output.
+fn render_output(rendered: &mut String, output: &Output) {
+    rendered.push_str("<div class=\"output\">\n");
+    let mut meta: Vec<String> = Vec::new();
+    let mut excerpts = String::new();
+    for item in &output.items {
+        match item {
+            OutputItem::Url(Some(url)) => {
+                let _ = writeln!(rendered, "<p>{}</p>", render_url(url));
+            }
+            OutputItem::Title(Some(title)) => {
+                let _ = writeln!(rendered, "<p><strong>{}</strong></p>", encode_minimal(title));
+            }
+            OutputItem::Message(message) => {
+                let _ = writeln!(rendered, "<p>{}</p>", encode_minimal(message));
+            }
+            OutputItem::Excerpt(matches) => {
+                for pattern_match in matches {
+                    render_excerpt(&mut excerpts, None, pattern_match);
+                }
+            }
+            OutputItem::TriggerMatches(matches) => {
+                for (trigger_id, pattern_match) in matches {
+                    render_excerpt(&mut excerpts, Some(trigger_id), pattern_match);
+                }
+            }
+            OutputItem::AdditionalMatches(count) => meta.push(format!("{} more match(es)", count)),
+            OutputItem::Domain(Some(domain)) => meta.push(encode_minimal(domain)),
+            OutputItem::Mime(Some(mime)) => meta.push(encode_minimal(mime)),
+            OutputItem::Count(count) => meta.push(format!("{} document(s)", count)),
+            OutputItem::Metadata(metadata) => {
+                for (key, value) in metadata {
+                    meta.push(format!("{}: {}", encode_minimal(key), encode_minimal(value)));
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(score) = output.score {
+        meta.push(format!("score {:.2}", score));
+    }
+    for (label, value) in &output.labels {
+        meta.push(format!("{}: {}", encode_minimal(label), encode_minimal(value)));
+    }
+    if !meta.is_empty() || !output.tags.is_empty() {
+        rendered.push_str("<p class=\"meta\">");
+        for tag in &output.tags {
+            let _ = write!(rendered, "<span class=\"tag\">{}</span>", encode_minimal(tag));
+        }
+        rendered.push_str(&meta.join(" &middot; "));
+        rendered.push_str("</p>\n");
+    }
+    rendered.push_str(&excerpts);
+    rendered.push_str("</div>\n");
+}
+
+/// Renders a summary table of the given counts.
+fn render_table(rendered: &mut String, heading: &str, counts: &[(String, usize)]) {
+    let _ = writeln!(rendered, "<table>\n<tr><th>{}</th><th>Outputs</th></tr>", heading);
+    for (name, count) in counts {
+        let _ = writeln!(rendered, "<tr><td>{}</td><td class=\"count\">{}</td></tr>", name, count);
+    }
+    rendered.push_str("</table>\n");
+}
+
+impl OutputBatch {
+    /// Renders the batch as a self-contained HTML report with the given
+    /// title (see the module-level documentation). Queries are listed in
+    /// order of their IDs, and the outputs of each query in canonical order
+    /// (see `OutputBatch::sort()`).
+    pub fn to_html_report(&self, title: &str) -> String {
+        let mut sorted = self.clone();
+        sorted.sort();
+        let mut queries: BTreeMap<&str, Vec<&Output>> = BTreeMap::new();
+        for output in &sorted.outputs {
+            let query_id = output.query_id.as_deref().unwrap_or(UNKNOWN_QUERY);
+            queries.entry(query_id).or_default().push(output);
+        }
+        let summary = self.summarize();
+
+        let mut rendered = String::new();
+        let title = encode_minimal(title);
+        let _ = writeln!(
+            rendered,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>",
+            title, STYLE
+        );
+        let _ = writeln!(rendered, "<h1>{}</h1>", title);
+        let _ = writeln!(
+            rendered,
+            "<p>{} output(s) about {} document(s) from {} quer{}.</p>",
+            summary.outputs,
+            summary.documents,
+            queries.len(),
+            if queries.len() == 1 { "y" } else { "ies" }
+        );
+
+        let query_counts: Vec<(String, usize)> = queries
+            .iter()
+            .enumerate()
+            .map(|(index, (query_id, outputs))| {
+                let link = format!("<a href=\"#query-{}\">{}</a>", index, encode_minimal(query_id));
+                (link, outputs.len())
+            })
+            .collect();
+        let mut domain_counts: Vec<(String, usize)> = summary
+            .domains
+            .iter()
+            .map(|(domain, count)| (encode_minimal(domain), *count))
+            .collect();
+        domain_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        rendered.push_str("<h2>Summary</h2>\n");
+        render_table(&mut rendered, "Query", &query_counts);
+        if !domain_counts.is_empty() {
+            render_table(&mut rendered, "Domain", &domain_counts);
+        }
+
+        for (index, (query_id, outputs)) in queries.iter().enumerate() {
+            let _ = writeln!(
+                rendered,
+                "<section id=\"query-{}\">\n<h2>{}</h2>",
+                index,
+                encode_minimal(query_id)
+            );
+            for output in outputs {
+                render_output(&mut rendered, output);
+            }
+            rendered.push_str("</section>\n");
+        }
+        rendered.push_str("</body>\n</html>\n");
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_report() {
+        let output = |query_id: &str, url: &str, excerpt: &str, relevant: (usize, usize)| {
+            let mut output = Output::from_url(url);
+            output.items.push(OutputItem::Excerpt(vec![PatternMatch {
+                excerpt: String::from(excerpt),
+                relevant,
+                captures: BTreeMap::new(),
+                offsets: None,
+                content: None,
+                location: None,
+            }]));
+            output.query_id = Some(String::from(query_id));
+            output.tags = vec![String::from("urgent")];
+            output
+        };
+        let batch = OutputBatch::from(vec![
+            output("Q2", "https://example.com/b", "say <script>hello</script>", (4, 12)),
+            output("Q1", "https://example.com/a", "hello world", (0, 5)),
+            output("Q1", "/local/path", "invalid range", (5, 100)),
+        ]);
+        let report = batch.to_html_report("Weekly <scan>");
+        assert!(report.starts_with("<!DOCTYPE html>"));
+        assert!(report.contains("<title>Weekly &lt;scan&gt;</title>"));
+        assert!(report.contains("3 output(s) about 3 document(s) from 2 queries."));
+        assert!(report.contains("<tr><td><a href=\"#query-0\">Q1</a></td><td class=\"count\">2</td></tr>"));
+        assert!(report.contains("<tr><td>example.com</td><td class=\"count\">2</td></tr>"));
+        assert!(report.contains("<mark>hello</mark> world"));
+        assert!(report.contains("say <mark>&lt;script&gt;</mark>hello&lt;/script&gt;"));
+        assert!(report.contains("<blockquote>invalid range</blockquote>"));
+        assert!(report.contains("<code>/local/path</code>"));
+        assert!(!report.contains("<script>"));
+        assert!(report.find("id=\"query-0\"").unwrap() < report.find("id=\"query-1\"").unwrap());
+    }
+}
@@ -0,0 +1,212 @@
+//! This file provides functionality related to signing outputs, so that
+//! archived matches can later be verified as unaltered and as produced by
+//! the holder of a particular key. Signatures are Ed25519 signatures over
+//! the canonical serialization of an output (or of a batch; see
+//! `OutputBatch::to_canonical_string()`), and are _detached_: they are
+//! kept alongside the outputs rather than within them. It is only
+//! available when the `signing` feature is enabled.
+
+use common::validation::Issue;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use output::output::{Output, OutputBatch};
+use ron;
+
+/// The name of the signature algorithm, as recorded in `OutputSignature`.
+pub const ALGORITHM: &str = "ed25519";
+
+/// A detached signature of an output or of an output batch.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OutputSignature {
+    /// The signature algorithm; currently always `ed25519`.
+    pub algorithm: String,
+    /// The public key of the signer, hex-encoded. This identifies the
+    /// signer, but a signature should only be trusted when this key is
+    /// known to belong to them (see `OutputVerifier`).
+    pub public_key: String,
+    /// The signature, hex-encoded.
+    pub signature: String,
+}
+
+/// An `OutputSigner` signs outputs using an Ed25519 secret key provided by
+/// the caller.
+pub struct OutputSigner {
+    key: SigningKey,
+}
+
+/// An `OutputVerifier` verifies the signatures of outputs made with the
+/// secret key that corresponds to its (trusted) public key.
+pub struct OutputVerifier {
+    key: VerifyingKey,
+}
+
+/// Encodes the given bytes as lowercase hexadecimal.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes exactly `N` bytes from the given hexadecimal, ignoring any
+/// surrounding whitespace.
+fn decode_hex<const N: usize>(hex: &str, description: &str) -> Result<[u8; N], Issue> {
+    let hex = hex.trim();
+    let invalid = || {
+        Issue::Error(format!(
+            "invalid {}; expected {} hexadecimal characters",
+            description,
+            N * 2
+        ))
+    };
+    if hex.len() != N * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; N];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(bytes)
+}
+
+/// The canonical serialization of the given output, over which its
+/// signature is made: the output as compact RON, as in
+/// `OutputBatch::to_canonical_string()`.
+pub fn canonical_output(output: &Output) -> Result<String, Issue> {
+    ron::ser::to_string(output)
+        .map_err(|error| Issue::Error(format!("unable to serialize output (`{}`)", error)))
+}
+
+impl OutputSigner {
+    /// Create a new `OutputSigner` from the given 32-byte Ed25519 secret
+    /// key.
+    pub fn from_secret_key(secret_key: [u8; 32]) -> OutputSigner {
+        OutputSigner {
+            key: SigningKey::from_bytes(&secret_key),
+        }
+    }
+
+    /// Create a new `OutputSigner` from the given hex-encoded 32-byte
+    /// Ed25519 secret key (such as the output of `openssl rand -hex 32`).
+    pub fn from_hex(secret_key: &str) -> Result<OutputSigner, Issue> {
+        Ok(OutputSigner::from_secret_key(decode_hex(secret_key, "signing key")?))
+    }
+
+    /// The hex-encoded public key that corresponds to the signer's secret
+    /// key, with which its signatures can be verified.
+    pub fn public_key(&self) -> String {
+        encode_hex(self.key.verifying_key().as_bytes())
+    }
+
+    /// Makes a detached signature of the given data.
+    fn sign_canonical(&self, canonical: &str) -> OutputSignature {
+        OutputSignature {
+            algorithm: String::from(ALGORITHM),
+            public_key: self.public_key(),
+            signature: encode_hex(&self.key.sign(canonical.as_bytes()).to_bytes()),
+        }
+    }
+
+    /// Signs the given output (see `canonical_output()`).
+    pub fn sign(&self, output: &Output) -> Result<OutputSignature, Issue> {
+        Ok(self.sign_canonical(&canonical_output(output)?))
+    }
+
+    /// Signs the given batch as a whole (see
+    /// `OutputBatch::to_canonical_string()`), so that the removal or
+    /// addition of outputs is detected as well as their alteration. The
+    /// order of the outputs does not affect the signature.
+    pub fn sign_batch(&self, batch: &OutputBatch) -> Result<OutputSignature, Issue> {
+        Ok(self.sign_canonical(&batch.to_canonical_string()?))
+    }
+}
+
+impl OutputVerifier {
+    /// Create a new `OutputVerifier` from the given hex-encoded Ed25519
+    /// public key (see `OutputSigner::public_key()`).
+    pub fn from_hex(public_key: &str) -> Result<OutputVerifier, Issue> {
+        match VerifyingKey::from_bytes(&decode_hex(public_key, "public key")?) {
+            Ok(key) => Ok(OutputVerifier { key }),
+            Err(error) => Err(Issue::Error(format!("invalid public key (`{}`)", error))),
+        }
+    }
+
+    /// Create a new `OutputVerifier` from the public key recorded in the
+    /// given signature. Such a verifier only establishes that the signed
+    /// outputs were not altered since they were signed—not who signed
+    /// them—since anyone can make a signature with their own key.
+    pub fn from_signature(signature: &OutputSignature) -> Result<OutputVerifier, Issue> {
+        OutputVerifier::from_hex(&signature.public_key)
+    }
+
+    /// Verifies the given signature of the given data.
+    fn verify_canonical(&self, canonical: &str, signature: &OutputSignature) -> Result<(), Issue> {
+        if signature.algorithm != ALGORITHM {
+            return Err(Issue::Error(format!(
+                "unsupported signature algorithm `{}`; expected `{}`",
+                signature.algorithm, ALGORITHM
+            )));
+        }
+        if decode_hex::<32>(&signature.public_key, "public key")? != *self.key.as_bytes() {
+            return Err(Issue::Error(String::from(
+                "the outputs were signed with a different key",
+            )));
+        }
+        let bytes: [u8; 64] = decode_hex(&signature.signature, "signature")?;
+        self.key
+            .verify(canonical.as_bytes(), &Signature::from_bytes(&bytes))
+            .map_err(|_| Issue::Error(String::from("the signature does not match the outputs")))
+    }
+
+    /// Verifies the given signature of the given output (see
+    /// `OutputSigner::sign()`).
+    pub fn verify(&self, output: &Output, signature: &OutputSignature) -> Result<(), Issue> {
+        self.verify_canonical(&canonical_output(output)?, signature)
+    }
+
+    /// Verifies the given signature of the given batch (see
+    /// `OutputSigner::sign_batch()`).
+    pub fn verify_batch(&self, batch: &OutputBatch, signature: &OutputSignature) -> Result<(), Issue> {
+        self.verify_canonical(&batch.to_canonical_string()?, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_signing() {
+        let output = |url: &str| Output {
+            score: Some(0.5),
+            ..Output::from_url(url)
+        };
+        // the first test vector of RFC 8032
+        let signer =
+            OutputSigner::from_hex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        assert_eq!(
+            signer.public_key(),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let verifier = OutputVerifier::from_hex(&signer.public_key()).unwrap();
+
+        let original = output("https://example.com/a");
+        let signature = signer.sign(&original).unwrap();
+        assert_eq!(signature.signature.len(), 128);
+        assert!(verifier.verify(&original, &signature).is_ok());
+        let mut altered = original.clone();
+        altered.score = Some(0.75);
+        assert!(verifier.verify(&altered, &signature).is_err());
+
+        // batch signatures are independent of the order of the outputs
+        let mut batch = OutputBatch::from(vec![original.clone(), output("https://example.com/b")]);
+        let signature = signer.sign_batch(&batch).unwrap();
+        batch.outputs.reverse();
+        assert!(verifier.verify_batch(&batch, &signature).is_ok());
+        batch.outputs.pop();
+        assert!(verifier.verify_batch(&batch, &signature).is_err());
+
+        // signatures are only accepted from the verifier's key
+        let other = OutputSigner::from_secret_key([7; 32]);
+        let forged = other.sign(&original).unwrap();
+        assert!(verifier.verify(&original, &forged).is_err());
+        assert!(OutputVerifier::from_signature(&forged).unwrap().verify(&original, &forged).is_ok());
+        assert!(OutputSigner::from_hex("not a key").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! This file provides a `MessagePublisher` for Kafka, so that outputs can
+//! be streamed to a Kafka topic with a `QueueSink`. It is only available
+//! when the `kafka` feature is enabled.
+
+use common::validation::Issue;
+use output::sink::queue::{MessagePublisher, QueueSink};
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use rdkafka::types::RDKafkaErrorCode;
+use rdkafka::ClientContext;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The longest time to wait for the messages published so far to be
+/// delivered when flushing.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Counts the messages that Kafka failed to deliver.
+#[derive(Default)]
+pub struct DeliveryFailures {
+    failures: AtomicUsize,
+}
+
+impl ClientContext for DeliveryFailures {}
+
+impl ProducerContext for DeliveryFailures {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _delivery_opaque: ()) {
+        if delivery_result.is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A `KafkaPublisher` publishes messages to Kafka. Messages are keyed as
+/// given, so that Kafka's default partitioner assigns messages with the
+/// same key to the same partition.
+pub struct KafkaPublisher {
+    producer: BaseProducer<DeliveryFailures>,
+}
+
+/// Converts the given Kafka error into an `Issue`, with the given
+/// description of what failed.
+fn kafka_issue(description: &str, error: KafkaError) -> Issue {
+    Issue::Error(format!("{} (`{}`)", description, error))
+}
+
+impl KafkaPublisher {
+    /// Create a new `KafkaPublisher` that connects to the given brokers
+    /// (a comma-separated list of `host:port` pairs).
+    pub fn new(brokers: &str) -> Result<KafkaPublisher, Issue> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers);
+        KafkaPublisher::from_config(&config)
+    }
+
+    /// Create a new `KafkaPublisher` from the given client configuration,
+    /// for deployments that need more than the brokers configured (such
+    /// as authentication).
+    pub fn from_config(config: &ClientConfig) -> Result<KafkaPublisher, Issue> {
+        match config.create_with_context(DeliveryFailures::default()) {
+            Ok(producer) => Ok(KafkaPublisher { producer }),
+            Err(error) => Err(kafka_issue("unable to create Kafka producer", error)),
+        }
+    }
+}
+
+impl MessagePublisher for KafkaPublisher {
+    fn publish(&mut self, topic: &str, key: Option<&str>, payload: &[u8]) -> Result<(), Issue> {
+        let mut record = BaseRecord::<str, [u8]>::to(topic);
+        record.key = key;
+        record.payload = Some(payload);
+        loop {
+            match self.producer.send(record) {
+                Ok(_) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    // wait for queued messages to be delivered
+                    self.producer.poll(Duration::from_millis(100));
+                    record = returned;
+                }
+                Err((error, _)) => {
+                    return Err(kafka_issue(
+                        &format!("unable to publish output to Kafka topic `{}`", topic),
+                        error,
+                    ))
+                }
+            }
+        }
+        self.producer.poll(Duration::from_millis(0)); // serve delivery reports
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        if let Err(error) = self.producer.flush(FLUSH_TIMEOUT) {
+            return Err(kafka_issue("unable to flush Kafka producer", error));
+        }
+        let failures = self.producer.context().failures.swap(0, Ordering::Relaxed);
+        match failures {
+            0 => Ok(()),
+            _ => Err(Issue::Error(format!(
+                "Kafka failed to deliver {} output(s)",
+                failures
+            ))),
+        }
+    }
+}
+
+/// A `QueueSink` that publishes outputs to a Kafka topic.
+pub type KafkaSink = QueueSink<KafkaPublisher>;
+
+impl KafkaSink {
+    /// Create a new `KafkaSink` that publishes outputs to the given topic
+    /// of the given brokers (see `KafkaPublisher::new()`).
+    pub fn connect(brokers: &str, topic: &str) -> Result<KafkaSink, Issue> {
+        Ok(QueueSink::new(KafkaPublisher::new(brokers)?, topic))
+    }
+}
@@ -0,0 +1,53 @@
+//! This module provides output sinks, which deliver outputs to systems
+//! outside of the scan engine—such as a SQLite database, an alerting
+//! system's webhook, a message queue, or an object storage bucket—as they
+//! are produced. Sinks that require additional dependencies are gated
+//! behind a feature of the same name.
+
+use common::validation::Issue;
+use output::output::OutputBatch;
+use output::writer::OutputWriter;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod queue;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+/// An `OutputSink` receives batches of outputs as the scan engine makes
+/// them available. Sending a batch may block—while a delivery is being
+/// retried, for example—which, when the engine's output capacity is
+/// bounded (see `ScanOptions.output_capacity`), slows the scan until the
+/// sink catches up.
+///
+/// Every `OutputWriter` is also an `OutputSink`.
+pub trait OutputSink {
+    /// Delivers the outputs of the batch. The sink may hold some outputs
+    /// back (to deliver them in larger batches, for example) until it is
+    /// flushed.
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue>;
+
+    /// Delivers every output that the sink has held back.
+    fn flush(&mut self) -> Result<(), Issue>;
+
+    /// Delivers every output that the sink has held back, and releases
+    /// the sink's resources (such as connections) where it can. No more
+    /// outputs will be sent to the sink once it is closed.
+    fn close(&mut self) -> Result<(), Issue> {
+        self.flush()
+    }
+}
+
+impl<W: OutputWriter> OutputSink for W {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        self.write_batch(batch)
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        OutputWriter::flush(self)
+    }
+}
@@ -0,0 +1,131 @@
+//! This file provides an output sink that publishes outputs to a message
+//! queue, such as a Kafka topic. The queue itself is abstracted by the
+//! `MessagePublisher` trait, so that any message queue can be supported;
+//! see `output::sink::kafka` for Kafka (with the `kafka` feature).
+
+use common::validation::Issue;
+use output::output::OutputBatch;
+use output::sink::OutputSink;
+use serde_json;
+
+/// A `MessagePublisher` publishes keyed messages to the topics of a
+/// message queue. Messages with the same key are expected to be
+/// delivered in order to the same partition (or its equivalent), where
+/// the queue supports it.
+pub trait MessagePublisher {
+    /// Publishes the payload to the given topic, with the given key. This
+    /// may block while the queue's buffers are full.
+    fn publish(&mut self, topic: &str, key: Option<&str>, payload: &[u8]) -> Result<(), Issue>;
+
+    /// Waits until every message published so far has been delivered.
+    fn flush(&mut self) -> Result<(), Issue>;
+}
+
+/// A `QueueSink` publishes each output, as JSON, as a separate message to
+/// a topic of a message queue. Messages are keyed by the ID of the query
+/// that produced the output, so that the outputs of each query are kept
+/// together (for example, in the same Kafka partition); outputs of
+/// queries without IDs are published without a key.
+pub struct QueueSink<P: MessagePublisher> {
+    /// The publisher of the messages.
+    publisher: P,
+    /// The topic to which outputs are published.
+    topic: String,
+    /// The number of outputs published so far.
+    published: usize,
+}
+
+impl<P: MessagePublisher> QueueSink<P> {
+    /// Create a new `QueueSink` that publishes outputs to the given
+    /// topic using the given publisher.
+    pub fn new(publisher: P, topic: &str) -> QueueSink<P> {
+        QueueSink {
+            publisher,
+            topic: String::from(topic),
+            published: 0,
+        }
+    }
+
+    /// The number of outputs published so far. (Published outputs may not
+    /// have been delivered until the sink is flushed.)
+    pub fn published(&self) -> usize {
+        self.published
+    }
+
+    /// Consumes the sink, returning its publisher.
+    pub fn into_inner(self) -> P {
+        self.publisher
+    }
+}
+
+impl<P: MessagePublisher> OutputSink for QueueSink<P> {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        for output in &batch.outputs {
+            let payload = match serde_json::to_vec(output) {
+                Ok(value) => value,
+                Err(error) => {
+                    return Err(Issue::Error(format!("unable to serialize output (`{}`)", error)))
+                }
+            };
+            self.publisher
+                .publish(&self.topic, output.query_id.as_deref(), &payload)?;
+            self.published += 1;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        self.publisher.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::output::Output;
+
+    /// Records every message that it publishes.
+    #[derive(Default)]
+    struct RecordingPublisher {
+        messages: Vec<(String, Option<String>, Output)>,
+        flushes: usize,
+    }
+
+    impl MessagePublisher for RecordingPublisher {
+        fn publish(&mut self, topic: &str, key: Option<&str>, payload: &[u8]) -> Result<(), Issue> {
+            let output: Output = serde_json::from_slice(payload).unwrap();
+            self.messages
+                .push((String::from(topic), key.map(String::from), output));
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Issue> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_queue_sink() {
+        let output = |query_id: Option<&str>| Output {
+            query_id: query_id.map(String::from),
+            ..Output::from_url("https://example.com")
+        };
+        let batch = OutputBatch::from(vec![output(Some("Q1")), output(None), output(Some("Q2"))]);
+        let mut sink = QueueSink::new(RecordingPublisher::default(), "matches");
+        sink.send(&batch).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(sink.published(), 3);
+
+        let publisher = sink.into_inner();
+        assert_eq!(publisher.flushes, 1);
+        let keys: Vec<Option<&str>> = publisher
+            .messages
+            .iter()
+            .map(|(_, key, _)| key.as_deref())
+            .collect();
+        assert_eq!(keys, vec![Some("Q1"), None, Some("Q2")]);
+        assert!(publisher.messages.iter().all(|(topic, _, _)| topic == "matches"));
+        assert_eq!(publisher.messages[1].2, batch.outputs[1]);
+    }
+}
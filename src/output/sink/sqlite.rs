@@ -0,0 +1,307 @@
+//! This file provides an output sink that streams outputs into a SQLite
+//! database, so that historical matches can be queried with SQL. It is
+//! only available when the `sqlite` feature is enabled.
+//!
+//! The database has three tables:
+//!
+//! * **outputs** — one row per output, with its IDs, kind, URL, domain,
+//!   score, tags (separated by semicolons), provenance, and the full
+//!   output as JSON.
+//! * **items** — one row per item of each output, with the item's kind
+//!   (such as `Url`) and its value as JSON.
+//! * **excerpts** — one row per match in each output's `Excerpt` (or,
+//!   failing that, its `TriggerMatches`), with the trigger, excerpt,
+//!   matched text, offsets, and location of the match, when known.
+//!
+//! The `items` and `excerpts` tables refer to the `id` of their row in
+//! `outputs` through their `output` column.
+
+use common::validation::Issue;
+use output::output::{Output, OutputItem};
+use output::table::output_matches;
+use output::writer::OutputWriter;
+use rusqlite::{params, Connection};
+use serde_json;
+use serde_json::Value;
+
+/// The schema of the database; every statement is idempotent, so that
+/// outputs can be appended to an existing database.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS outputs (
+        id INTEGER PRIMARY KEY,
+        output_id TEXT,
+        query_id TEXT,
+        kind TEXT NOT NULL,
+        url TEXT,
+        domain TEXT,
+        score REAL,
+        tags TEXT NOT NULL,
+        batch TEXT,
+        reference TEXT,
+        output TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        output INTEGER NOT NULL REFERENCES outputs(id),
+        position INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS excerpts (
+        output INTEGER NOT NULL REFERENCES outputs(id),
+        position INTEGER NOT NULL,
+        trigger_id TEXT,
+        excerpt TEXT NOT NULL,
+        matched TEXT,
+        start_offset INTEGER,
+        end_offset INTEGER,
+        line_number INTEGER,
+        column_number INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS outputs_query_id ON outputs (query_id);
+    CREATE INDEX IF NOT EXISTS outputs_domain ON outputs (domain);
+    CREATE INDEX IF NOT EXISTS items_output ON items (output);
+    CREATE INDEX IF NOT EXISTS excerpts_output ON excerpts (output);
+";
+
+/// A `SqliteSink` writes outputs into a SQLite database (see the
+/// module-level documentation for its tables). Outputs are inserted
+/// as they are written, within a transaction that is committed when the
+/// sink is flushed, so that each batch is written at once.
+pub struct SqliteSink {
+    /// The connection to the database.
+    connection: Connection,
+    /// The number of outputs that have been written but not yet flushed.
+    pending: usize,
+    /// The number of outputs flushed to the database so far.
+    written: usize,
+}
+
+/// Converts the given SQLite error into an `Issue`, with the given
+/// description of what failed.
+fn sqlite_issue(description: &str, error: rusqlite::Error) -> Issue {
+    Issue::Error(format!("{} (`{}`)", description, error))
+}
+
+impl SqliteSink {
+    /// Opens the SQLite database at the given path, creating it (and its
+    /// tables) if it does not exist.
+    pub fn open(path: &str) -> Result<SqliteSink, Issue> {
+        match Connection::open(path) {
+            Ok(connection) => SqliteSink::from_connection(connection),
+            Err(error) => Err(sqlite_issue(
+                &format!("unable to open SQLite database `{}`", path),
+                error,
+            )),
+        }
+    }
+
+    /// Creates a new `SqliteSink` that writes to the given connection,
+    /// creating the tables of the sink if they do not exist.
+    pub fn from_connection(connection: Connection) -> Result<SqliteSink, Issue> {
+        if let Err(error) = connection.execute_batch(SCHEMA) {
+            return Err(sqlite_issue("unable to create SQLite tables", error));
+        }
+        Ok(SqliteSink {
+            connection,
+            pending: 0,
+            written: 0,
+        })
+    }
+
+    /// The number of outputs flushed to the database so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// The connection to the database, such as for querying the outputs
+    /// that have been flushed.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+/// Inserts the given output (and its items and excerpts) using the
+/// given connection.
+fn insert_output(connection: &Connection, output: &Output) -> Result<(), Issue> {
+    let json = serde_json::to_string(output)
+        .map_err(|error| Issue::Error(format!("unable to serialize output (`{}`)", error)))?;
+    let url = output.items.iter().find_map(|item| match item {
+        OutputItem::Url(url) => url.clone(),
+        _ => None,
+    });
+    let (batch, reference) = match &output.provenance {
+        Some(provenance) => (provenance.batch.clone(), provenance.reference.clone()),
+        None => (None, None),
+    };
+    connection
+        .execute(
+            "INSERT INTO outputs (output_id, query_id, kind, url, domain, score, tags, batch, reference, output)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                output.id,
+                output.query_id,
+                format!("{:?}", output.kind),
+                url,
+                output.domain(),
+                output.score.map(f64::from),
+                output.tags.join(";"),
+                batch,
+                reference,
+                json,
+            ],
+        )
+        .map_err(|error| sqlite_issue("unable to insert output", error))?;
+    let row = connection.last_insert_rowid();
+
+    for (position, item) in output.items.iter().enumerate() {
+        // Items are serialized as `{"Kind": value}`, or as `"Kind"` when
+        // they have no value.
+        let (kind, value) = match serde_json::to_value(item) {
+            Ok(Value::Object(map)) => match map.into_iter().next() {
+                Some((kind, value)) => (kind, value),
+                None => continue,
+            },
+            Ok(Value::String(kind)) => (kind, Value::Null),
+            Ok(_) => continue,
+            Err(error) => {
+                return Err(Issue::Error(format!(
+                    "unable to serialize output item (`{}`)",
+                    error
+                )))
+            }
+        };
+        connection
+            .execute(
+                "INSERT INTO items (output, position, kind, value) VALUES (?1, ?2, ?3, ?4)",
+                params![row, position as i64, kind, value.to_string()],
+            )
+            .map_err(|error| sqlite_issue("unable to insert output item", error))?;
+    }
+
+    for (position, (trigger, pattern_match)) in output_matches(output).into_iter().enumerate() {
+        let (start, end) = pattern_match.relevant;
+        let offsets = pattern_match.offsets;
+        let location = pattern_match.location;
+        connection
+            .execute(
+                "INSERT INTO excerpts (output, position, trigger_id, excerpt, matched, start_offset, end_offset, line_number, column_number)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    row,
+                    position as i64,
+                    trigger,
+                    pattern_match.excerpt.trim(),
+                    pattern_match.excerpt.get(start..end),
+                    offsets.map(|value| value.0 as i64),
+                    offsets.map(|value| value.1 as i64),
+                    location.map(|value| value.0 as i64),
+                    location.map(|value| value.1 as i64),
+                ],
+            )
+            .map_err(|error| sqlite_issue("unable to insert excerpt", error))?;
+    }
+    Ok(())
+}
+
+impl OutputWriter for SqliteSink {
+    fn write(&mut self, output: &Output) -> Result<(), Issue> {
+        if self.connection.is_autocommit() {
+            self.connection
+                .execute_batch("BEGIN")
+                .map_err(|error| sqlite_issue("unable to begin SQLite transaction", error))?;
+        }
+        // The savepoint ensures that an output that cannot be inserted
+        // does not leave some of its rows behind.
+        self.connection
+            .execute_batch("SAVEPOINT output")
+            .map_err(|error| sqlite_issue("unable to insert output", error))?;
+        let result = insert_output(&self.connection, output);
+        let end = match result {
+            Ok(_) => "RELEASE output",
+            Err(_) => "ROLLBACK TO output; RELEASE output",
+        };
+        self.connection
+            .execute_batch(end)
+            .map_err(|error| sqlite_issue("unable to insert output", error))?;
+        result?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        if !self.connection.is_autocommit() {
+            self.connection
+                .execute_batch("COMMIT")
+                .map_err(|error| sqlite_issue("unable to commit SQLite transaction", error))?;
+        }
+        self.written += self.pending;
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        // As with buffered files, errors when flushing on drop are ignored;
+        // call `flush()` to handle them.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pattern::PatternMatch;
+    use output::output::OutputBatch;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_sqlite_sink() {
+        let output = |url: &str, excerpts: Vec<PatternMatch>| {
+            let mut output = Output::from_url(url);
+            output.items.push(OutputItem::Excerpt(excerpts));
+            output.tags = vec![String::from("a"), String::from("b")];
+            output
+        };
+        let excerpt = PatternMatch {
+            excerpt: String::from("a needle in a haystack"),
+            relevant: (2, 8),
+            captures: BTreeMap::new(),
+            offsets: Some((12, 18)),
+            content: None,
+            location: Some((2, 3)),
+        };
+
+        let mut sink = SqliteSink::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        sink.write(&output("https://example.com/a", vec![])).unwrap();
+        assert_eq!(sink.written(), 0);
+        let batch = OutputBatch::from(vec![output("https://example.com/b", vec![excerpt])]);
+        sink.write_batch(&batch).unwrap();
+        assert_eq!(sink.written(), 2);
+
+        let connection = sink.connection();
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM outputs WHERE query_id = 'Q1'"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM outputs WHERE domain = 'example.com'"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM items WHERE kind = 'Url'"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM excerpts"), 1);
+
+        let (url, matched, start, line): (String, String, i64, i64) = connection
+            .query_row(
+                "SELECT outputs.url, matched, start_offset, line_number FROM excerpts
+                 JOIN outputs ON outputs.id = excerpts.output",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(url, "https://example.com/b");
+        assert_eq!(matched, "needle");
+        assert_eq!((start, line), (12, 2));
+
+        let json: String = connection
+            .query_row("SELECT output FROM outputs WHERE url = 'https://example.com/b'", [], |row| row.get(0))
+            .unwrap();
+        let parsed: Output = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, batch.outputs[0]);
+    }
+}
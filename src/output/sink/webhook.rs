@@ -0,0 +1,250 @@
+//! This file provides an output sink that delivers outputs to an HTTP
+//! webhook, such as that of an alerting system. It is only available when
+//! the `webhook` feature is enabled.
+//!
+//! Outputs are delivered in batches, each as a `POST` request whose body
+//! is a JSON array of outputs (with the content type `application/json`).
+//! Deliveries that fail because of a network error, a `429 Too Many
+//! Requests` response, or a server error (`5xx`) are retried with
+//! exponential backoff; other responses are not retried.
+
+use common::validation::Issue;
+use output::output::OutputBatch;
+use output::sink::OutputSink;
+use serde_json;
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+use ureq;
+
+/// `WebhookOptions` configures how a `WebhookSink` delivers outputs.
+#[derive(Clone, Debug)]
+pub struct WebhookOptions {
+    /// The maximum number of outputs delivered in a single request. The
+    /// sink holds outputs back until it has this many, or until it is
+    /// flushed.
+    pub batch_size: usize,
+    /// The number of times that a failed delivery is retried before its
+    /// outputs are dropped.
+    pub max_retries: u32,
+    /// The time to wait before the first retry; each subsequent retry
+    /// waits twice as long as the last, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    /// The longest time to wait before a retry.
+    pub max_backoff: Duration,
+    /// The time after which a single request is abandoned (and, if
+    /// retries remain, retried).
+    pub timeout: Duration,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> WebhookOptions {
+        WebhookOptions {
+            batch_size: 100,
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A `WebhookSink` delivers outputs to an HTTP webhook (see the
+/// module-level documentation). Sending blocks while a delivery is being
+/// retried, so that a struggling webhook slows the scan engine instead of
+/// accumulating outputs in memory.
+pub struct WebhookSink {
+    /// The URL of the webhook.
+    url: String,
+    /// How outputs are delivered.
+    options: WebhookOptions,
+    /// The HTTP agent used for every request, so that connections can
+    /// be reused.
+    agent: ureq::Agent,
+    /// The outputs, serialized as JSON, that have not yet been delivered.
+    pending: Vec<String>,
+    /// The number of outputs delivered so far.
+    delivered: usize,
+}
+
+impl WebhookSink {
+    /// Create a new `WebhookSink` that delivers outputs to the given URL,
+    /// as configured by the given options.
+    pub fn new(url: &str, options: WebhookOptions) -> WebhookSink {
+        let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+        WebhookSink {
+            url: String::from(url),
+            options,
+            agent,
+            pending: Vec::new(),
+            delivered: 0,
+        }
+    }
+
+    /// The number of outputs delivered so far.
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// The time to wait before the given retry (counting from `0`).
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(retry);
+        cmp::min(
+            self.options.initial_backoff.saturating_mul(factor),
+            self.options.max_backoff,
+        )
+    }
+
+    /// Delivers the given outputs in a single request, retrying as
+    /// configured. When every attempt fails, the outputs are dropped.
+    fn deliver(&mut self, outputs: Vec<String>) -> Result<(), Issue> {
+        let body = format!("[{}]", outputs.join(","));
+        let mut retry = 0;
+        loop {
+            let response = self
+                .agent
+                .post(&self.url)
+                .set("Content-Type", "application/json")
+                .send_string(&body);
+            let failure = match response {
+                Ok(_) => {
+                    self.delivered += outputs.len();
+                    return Ok(());
+                }
+                Err(ureq::Error::Status(status, _)) if status == 429 || status >= 500 => {
+                    format!("status {}", status)
+                }
+                Err(ureq::Error::Status(status, _)) => {
+                    return Err(Issue::Error(format!(
+                        "webhook `{}` rejected {} output(s) with status {}",
+                        self.url,
+                        outputs.len(),
+                        status
+                    )));
+                }
+                Err(error) => error.to_string(),
+            };
+            if retry >= self.options.max_retries {
+                return Err(Issue::Error(format!(
+                    "unable to deliver {} output(s) to webhook `{}` after {} attempt(s) (`{}`)",
+                    outputs.len(),
+                    self.url,
+                    retry + 1,
+                    failure
+                )));
+            }
+            thread::sleep(self.backoff(retry));
+            retry += 1;
+        }
+    }
+}
+
+impl OutputSink for WebhookSink {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        for output in &batch.outputs {
+            match serde_json::to_string(output) {
+                Ok(value) => self.pending.push(value),
+                Err(error) => {
+                    return Err(Issue::Error(format!("unable to serialize output (`{}`)", error)))
+                }
+            }
+        }
+        let batch_size = cmp::max(self.options.batch_size, 1);
+        while self.pending.len() >= batch_size {
+            let outputs: Vec<String> = self.pending.drain(..batch_size).collect();
+            self.deliver(outputs)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let outputs: Vec<String> = self.pending.drain(..).collect();
+        self.deliver(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::output::Output;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Serves a request for each of the given statuses, in order, sending
+    /// the body of each request through the returned channel.
+    fn serve(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let lowercase = line.to_lowercase();
+                    if let Some(value) = lowercase.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                sender.send(String::from_utf8(body).unwrap()).unwrap();
+                write!(
+                    &stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_webhook_sink() {
+        let options = WebhookOptions {
+            batch_size: 2,
+            max_retries: 1,
+            initial_backoff: Duration::from_millis(1),
+            ..WebhookOptions::default()
+        };
+
+        // the first delivery is retried once; the second is delivered on flush
+        let (url, bodies) = serve(vec![503, 200, 200]);
+        let mut sink = WebhookSink::new(&url, options.clone());
+        let batch = OutputBatch::from(vec![
+            Output::from_url("https://example.com/a"),
+            Output::from_url("https://example.com/b"),
+            Output::from_url("https://example.com/c"),
+        ]);
+        sink.send(&batch).unwrap();
+        assert_eq!(sink.delivered(), 2);
+        sink.flush().unwrap();
+        assert_eq!(sink.delivered(), 3);
+        let bodies: Vec<Vec<Output>> = bodies
+            .iter()
+            .map(|body| serde_json::from_str(&body).unwrap())
+            .collect();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[0], bodies[1]);
+        assert_eq!(bodies[1].len(), 2);
+        assert_eq!(bodies[2], vec![Output::from_url("https://example.com/c")]);
+
+        // client errors are not retried
+        let (url, bodies) = serve(vec![400]);
+        let mut sink = WebhookSink::new(&url, options);
+        sink.send(&batch).unwrap_err();
+        assert_eq!(sink.delivered(), 0);
+        assert_eq!(bodies.iter().count(), 1);
+    }
+}
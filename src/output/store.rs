@@ -0,0 +1,404 @@
+//! This file provides the `OutputStore`, an append-only file of output
+//! batches. Long scans can append each batch to a store as it is produced,
+//! so that the outputs survive if the scan dies before it completes; stores
+//! can later be consolidated into a single batch, compacted, and merged
+//! with one another.
+//!
+//! A store is a sequence of frames, one per batch. Each frame consists of
+//! a header—the magic bytes `IQOB`, then the length and the CRC-32 of the
+//! payload, each as a little-endian `u32`—followed by the payload: the
+//! batch, serialized as JSON. Each frame is written at once, at the end of
+//! the file, so a crash can only leave the _last_ frame partially written;
+//! such a frame is shorter than its header claims, and is discarded when
+//! the store is next opened. Any other invalid frame—one with the wrong
+//! magic bytes, a bad checksum, or a payload that is not a batch—means
+//! that the store is corrupt, and is reported rather than discarded, so
+//! that the valid batches after it are not lost.
+
+use common::validation::Issue;
+use flate2::Crc;
+use output::output::{DeduplicationPolicy, OutputBatch};
+use output::sink::OutputSink;
+use serde_json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The magic bytes with which every frame starts.
+const FRAME_MAGIC: [u8; 4] = *b"IQOB";
+/// The length of a frame's header: its magic bytes, then the length and
+/// the checksum of its payload.
+const HEADER_LENGTH: u64 = 12;
+
+/// An `OutputStore` is an append-only file of output batches (see the
+/// module-level documentation for its format). Batches can be appended
+/// concurrently—from each of the scan engine's threads, for example—
+/// through a shared reference (or an `Arc<OutputStore>`, which is also an
+/// `OutputSink`).
+pub struct OutputStore {
+    /// The path of the store.
+    path: PathBuf,
+    /// The store's file, opened for appending.
+    file: Mutex<File>,
+    /// The number of bytes discarded from the end of the store when it was
+    /// opened, because they were not a complete, valid frame.
+    discarded: u64,
+}
+
+/// Converts the given I/O error into an `Issue`, with the given
+/// description of what failed.
+fn io_issue(description: &str, path: &Path, error: std::io::Error) -> Issue {
+    Issue::Error(format!(
+        "{} `{}` (`{}`)",
+        description,
+        path.to_string_lossy(),
+        error
+    ))
+}
+
+/// The `Issue` for an invalid frame at the given offset of the store at
+/// the given path, for the given reason.
+fn corrupt_frame_issue(path: &Path, offset: u64, reason: &str) -> Issue {
+    Issue::Error(format!(
+        "output store `{}` is corrupt at byte {} (`{}`)",
+        path.to_string_lossy(),
+        offset,
+        reason
+    ))
+}
+
+/// The canonical form of the given path, so that different paths to the
+/// same store (such as relative ones, or ones through symbolic links) can
+/// be recognized.
+fn canonical_path(path: &Path) -> Result<PathBuf, Issue> {
+    fs::canonicalize(path).map_err(|error| io_issue("unable to resolve output store", path, error))
+}
+
+/// Computes the CRC-32 of the given data.
+fn checksum(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+/// Serializes the given batch as a frame.
+fn encode_frame(batch: &OutputBatch) -> Result<Vec<u8>, Issue> {
+    let payload = match serde_json::to_vec(batch) {
+        Ok(value) => value,
+        Err(error) => return Err(Issue::Error(format!("unable to serialize outputs (`{}`)", error))),
+    };
+    if payload.len() > u32::MAX as usize {
+        return Err(Issue::Error(String::from(
+            "unable to store outputs (the batch is larger than 4 GiB)",
+        )));
+    }
+    let mut frame = Vec::with_capacity(HEADER_LENGTH as usize + payload.len());
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&checksum(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Reads the frames of the store at the given path, deserializing each
+/// valid frame and passing it to `visit`. Returns the length of the
+/// valid frames, and so the offset at which the partially written frame
+/// at the end of the store (if any) starts. Returns an `Error` if any
+/// other frame is invalid.
+fn read_frames<F: FnMut(OutputBatch)>(path: &Path, mut visit: F) -> Result<u64, Issue> {
+    let file = File::open(path).map_err(|error| io_issue("unable to open output store", path, error))?;
+    let length = file
+        .metadata()
+        .map_err(|error| io_issue("unable to read output store", path, error))?
+        .len();
+    let mut reader = BufReader::new(file);
+    let mut offset = 0;
+    while length - offset >= HEADER_LENGTH {
+        let mut header = [0u8; HEADER_LENGTH as usize];
+        reader
+            .read_exact(&mut header)
+            .map_err(|error| io_issue("unable to read output store", path, error))?;
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&header[4..8]);
+        let payload_length = u64::from(u32::from_le_bytes(word));
+        word.copy_from_slice(&header[8..12]);
+        let expected_checksum = u32::from_le_bytes(word);
+        if header[0..4] != FRAME_MAGIC {
+            return Err(corrupt_frame_issue(path, offset, "bad magic bytes"));
+        }
+        if length - offset - HEADER_LENGTH < payload_length {
+            break; // a torn tail
+        }
+        let mut payload = vec![0u8; payload_length as usize];
+        reader
+            .read_exact(&mut payload)
+            .map_err(|error| io_issue("unable to read output store", path, error))?;
+        if checksum(&payload) != expected_checksum {
+            return Err(corrupt_frame_issue(path, offset, "bad checksum"));
+        }
+        match serde_json::from_slice(&payload) {
+            Ok(batch) => visit(batch),
+            Err(error) => return Err(corrupt_frame_issue(path, offset, &error.to_string())),
+        }
+        offset += HEADER_LENGTH + payload_length;
+    }
+    Ok(offset)
+}
+
+impl OutputStore {
+    /// Opens the store at the given path for appending, creating it if it
+    /// does not exist. If the store ends with a partially written frame—
+    /// because the process writing it died, for example—the frame is
+    /// truncated, so that new batches are appended after the last valid
+    /// one; see `discarded()`. A store that is otherwise corrupt is left
+    /// as it is, and an `Error` is returned.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<OutputStore, Issue> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| io_issue("unable to open output store", path, error))?;
+        let length = file
+            .metadata()
+            .map_err(|error| io_issue("unable to read output store", path, error))?
+            .len();
+        let valid = read_frames(path, |_| ())?;
+        if valid < length {
+            file.set_len(valid)
+                .and_then(|_| file.sync_all())
+                .map_err(|error| io_issue("unable to recover output store", path, error))?;
+        }
+        Ok(OutputStore {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            discarded: length - valid,
+        })
+    }
+
+    /// The path of the store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of bytes that were discarded from the end of the store
+    /// when it was opened, because they were a partially written frame.
+    /// When this is nonzero, the batch that was being appended when the
+    /// store was last written has been lost.
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Appends the given batch to the store. The batch is written to the
+    /// file immediately (so that it survives if the process dies), but it
+    /// may not survive a crash of the operating system until the store is
+    /// synced (see `sync()`). Empty batches are not stored.
+    pub fn append(&self, batch: &OutputBatch) -> Result<(), Issue> {
+        if batch.outputs.is_empty() {
+            return Ok(());
+        }
+        let frame = encode_frame(batch)?;
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&frame)
+            .map_err(|error| io_issue("unable to append to output store", &self.path, error))
+    }
+
+    /// Ensures that every batch appended so far has been written to disk.
+    pub fn sync(&self) -> Result<(), Issue> {
+        let file = self.file.lock().unwrap();
+        file.sync_data()
+            .map_err(|error| io_issue("unable to sync output store", &self.path, error))
+    }
+
+    /// Reads every valid batch of the store at the given path, in the
+    /// order in which they were appended. A partially written frame at the
+    /// end of the store is ignored (but not truncated; see `open()`); any
+    /// other invalid frame is an `Error`.
+    pub fn read_batches<P: AsRef<Path>>(path: P) -> Result<Vec<OutputBatch>, Issue> {
+        let mut batches = Vec::new();
+        read_frames(path.as_ref(), |batch| batches.push(batch))?;
+        Ok(batches)
+    }
+
+    /// Reads every valid batch of the store at the given path, merged into
+    /// a single batch (see `OutputBatch::merge_with()`).
+    pub fn consolidate<P: AsRef<Path>>(path: P) -> Result<OutputBatch, Issue> {
+        let mut consolidated = OutputBatch::new();
+        read_frames(path.as_ref(), |batch| consolidated.merge_with(batch))?;
+        Ok(consolidated)
+    }
+
+    /// Appends every valid batch of each of the stores at the given paths
+    /// to this store, returning the number of outputs appended. Merging
+    /// can be repeated safely if it is interrupted, though the batches of
+    /// the sources that were already merged are then appended again (see
+    /// `compact()` to remove the duplicates).
+    pub fn merge_from<P: AsRef<Path>>(&self, sources: &[P]) -> Result<usize, Issue> {
+        let mut merged = 0;
+        let destination = canonical_path(&self.path)?;
+        for source in sources {
+            if canonical_path(source.as_ref())? == destination {
+                return Err(Issue::Error(format!(
+                    "unable to merge output store `{}` into itself",
+                    self.path.to_string_lossy()
+                )));
+            }
+            for batch in OutputStore::read_batches(source)? {
+                self.append(&batch)?;
+                merged += batch.outputs.len();
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Rewrites the store as a single batch, deduplicated according to the
+    /// given policy (if any), returning the number of outputs that remain.
+    /// The store is rewritten to a temporary file that then replaces it,
+    /// so the store is intact if the process dies while compacting. No
+    /// batches can be appended while the store is being compacted.
+    pub fn compact(&self, deduplication: Option<DeduplicationPolicy>) -> Result<usize, Issue> {
+        let mut file = self.file.lock().unwrap();
+        let mut batch = OutputStore::consolidate(&self.path)?;
+        if let Some(policy) = deduplication {
+            batch.deduplicate(policy);
+        }
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(".compacting");
+        let temporary_path = PathBuf::from(temporary_path);
+        let frame = if batch.outputs.is_empty() {
+            Vec::new()
+        } else {
+            encode_frame(&batch)?
+        };
+        File::create(&temporary_path)
+            .and_then(|mut temporary| {
+                temporary.write_all(&frame)?;
+                temporary.sync_all()
+            })
+            .and_then(|_| fs::rename(&temporary_path, &self.path))
+            .map_err(|error| io_issue("unable to compact output store", &self.path, error))?;
+        *file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|error| io_issue("unable to reopen output store", &self.path, error))?;
+        Ok(batch.outputs.len())
+    }
+}
+
+impl OutputSink for OutputStore {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        self.append(batch)
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        self.sync()
+    }
+}
+
+impl OutputSink for Arc<OutputStore> {
+    fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        self.append(batch)
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        self.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::output::{DeduplicationKey, Output};
+    use std::env;
+    use std::thread;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ieql-store-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_output_store() {
+        let path = temporary_path("appends");
+        let store = Arc::new(OutputStore::open(&path).unwrap());
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let mut sink = store.clone();
+                thread::spawn(move || {
+                    for index in 0..10 {
+                        let url = format!("https://example.com/{}/{}", thread, index);
+                        sink.send(&OutputBatch::from(vec![Output::from_url(&url)])).unwrap();
+                    }
+                    sink.flush().unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(OutputStore::read_batches(&path).unwrap().len(), 40);
+
+        // a partially written frame at the end is discarded
+        let length = fs::metadata(&path).unwrap().len();
+        let frame = encode_frame(&OutputBatch::from(vec![Output::from_url("https://example.com/torn")])).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&frame[..frame.len() - 3]).unwrap();
+        drop(file);
+        assert_eq!(OutputStore::consolidate(&path).unwrap().outputs.len(), 40);
+        drop(store);
+        let store = OutputStore::open(&path).unwrap();
+        assert_eq!(store.discarded(), frame.len() as u64 - 3);
+        assert_eq!(fs::metadata(&path).unwrap().len(), length);
+        store.append(&OutputBatch::from(vec![Output::from_url("https://example.com/0/0")])).unwrap();
+        assert_eq!(OutputStore::consolidate(&path).unwrap().outputs.len(), 41);
+
+        // merging and compacting
+        let merged_path = temporary_path("merged");
+        let merged = OutputStore::open(&merged_path).unwrap();
+        assert_eq!(merged.merge_from(&[&path]).unwrap(), 41);
+        assert!(merged.merge_from(&[&merged_path]).is_err());
+        let indirect = merged_path.parent().unwrap().join(".").join(merged_path.file_name().unwrap());
+        assert!(merged.merge_from(&[&indirect]).is_err());
+        let policy = DeduplicationPolicy {
+            key: DeduplicationKey::Url,
+            merge_excerpts: false,
+        };
+        assert_eq!(merged.compact(Some(policy)).unwrap(), 40);
+        assert_eq!(OutputStore::read_batches(&merged_path).unwrap().len(), 1);
+        merged.append(&OutputBatch::from(vec![Output::from_url("https://example.com/new")])).unwrap();
+        assert_eq!(OutputStore::consolidate(&merged_path).unwrap().outputs.len(), 41);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&merged_path).unwrap();
+    }
+
+    #[test]
+    fn test_output_store_corruption() {
+        let path = temporary_path("corrupt");
+        let store = OutputStore::open(&path).unwrap();
+        for index in 0..3 {
+            let url = format!("https://example.com/{}", index);
+            store.append(&OutputBatch::from(vec![Output::from_url(&url)])).unwrap();
+        }
+        drop(store);
+
+        // a corrupt frame in the middle is reported, and nothing is truncated
+        let mut data = fs::read(&path).unwrap();
+        let frame_length = data.len() / 3;
+        data[frame_length + HEADER_LENGTH as usize] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        let expected = format!("output store `{}` is corrupt at byte {} (`bad checksum`)", path.display(), frame_length);
+        assert_eq!(OutputStore::read_batches(&path).err(), Some(Issue::Error(expected)));
+        assert!(OutputStore::open(&path).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), data.len() as u64);
+
+        data[frame_length] = b'X';
+        fs::write(&path, &data).unwrap();
+        let expected = format!("output store `{}` is corrupt at byte {} (`bad magic bytes`)", path.display(), frame_length);
+        assert_eq!(OutputStore::consolidate(&path).err(), Some(Issue::Error(expected)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
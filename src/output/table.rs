@@ -0,0 +1,460 @@
+//! This file provides functionality related to exporting outputs as
+//! tables, such as CSV files for spreadsheets.
+
+use common::pattern::PatternMatch;
+use common::validation::Issue;
+use output::output::{Output, OutputBatch, OutputItem};
+use output::writer::OutputWriter;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+/// Denotes the format of a table of outputs. In either format, cells
+/// that a spreadsheet would evaluate as a formula (those that begin with
+/// `=`, `+`, `-`, `@`, a tab, or a carriage return, and are not numbers)
+/// are prefixed with `'` so that they are displayed as text instead.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TableFormat {
+    /// Comma-separated values, as per RFC 4180: cells that contain
+    /// commas, quotes, or line breaks are quoted.
+    Csv,
+    /// Tab-separated values: tabs and line breaks within cells are
+    /// replaced with spaces.
+    Tsv,
+}
+
+/// Denotes a column of a table of outputs. Each output has a row for
+/// every match in its `Excerpt` (or, failing that, its `TriggerMatches`),
+/// or a single row when it has neither; the match columns of such rows
+/// are empty, as are the columns of items that the output does not include.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TableColumn {
+    /// The ID of the output.
+    Id,
+    /// The ID of the query that produced the output.
+    QueryId,
+    /// The URL of the document, from the output's `Url`.
+    Url,
+    /// The domain of the document, from the output's `Domain`.
+    Domain,
+    /// The MIME type of the document, from the output's `Mime`.
+    Mime,
+    /// The title of the document, from the output's `Title`.
+    Title,
+    /// The tags of the output, separated by semicolons.
+    Tags,
+    /// The metadata of the document, from the output's `Metadata`, as
+    /// `key=value` pairs separated by semicolons.
+    Metadata,
+    /// The score of the output, if it is scored.
+    Score,
+    /// The ID of the trigger that produced the match, for matches from
+    /// `TriggerMatches`.
+    Trigger,
+    /// The excerpt of the match.
+    Excerpt,
+    /// The matched text.
+    Match,
+    /// The byte offset at which the match starts within its content.
+    Start,
+    /// The byte offset at which the match ends within its content.
+    End,
+    /// The line at which the match starts.
+    Line,
+    /// The column at which the match starts.
+    Column,
+}
+
+/// The columns included in a table when none are selected.
+pub const DEFAULT_COLUMNS: [TableColumn; 6] = [
+    TableColumn::QueryId,
+    TableColumn::Url,
+    TableColumn::Domain,
+    TableColumn::Excerpt,
+    TableColumn::Start,
+    TableColumn::End,
+];
+
+impl TableColumn {
+    /// The name of the column, as it appears in the table's header (such
+    /// as `query_id`).
+    pub fn name(self) -> &'static str {
+        match self {
+            TableColumn::Id => "id",
+            TableColumn::QueryId => "query_id",
+            TableColumn::Url => "url",
+            TableColumn::Domain => "domain",
+            TableColumn::Mime => "mime",
+            TableColumn::Title => "title",
+            TableColumn::Tags => "tags",
+            TableColumn::Metadata => "metadata",
+            TableColumn::Score => "score",
+            TableColumn::Trigger => "trigger",
+            TableColumn::Excerpt => "excerpt",
+            TableColumn::Match => "match",
+            TableColumn::Start => "start",
+            TableColumn::End => "end",
+            TableColumn::Line => "line",
+            TableColumn::Column => "column",
+        }
+    }
+
+    /// Parses a comma-separated list of column names, such as
+    /// `query_id,url,excerpt`.
+    pub fn parse_list(list: &str) -> Result<Vec<TableColumn>, Issue> {
+        const COLUMNS: [TableColumn; 16] = [
+            TableColumn::Id,
+            TableColumn::QueryId,
+            TableColumn::Url,
+            TableColumn::Domain,
+            TableColumn::Mime,
+            TableColumn::Title,
+            TableColumn::Tags,
+            TableColumn::Metadata,
+            TableColumn::Score,
+            TableColumn::Trigger,
+            TableColumn::Excerpt,
+            TableColumn::Match,
+            TableColumn::Start,
+            TableColumn::End,
+            TableColumn::Line,
+            TableColumn::Column,
+        ];
+        list.split(',')
+            .map(|name| {
+                let name = name.trim();
+                match COLUMNS.iter().find(|column| column.name() == name) {
+                    Some(column) => Ok(*column),
+                    None => Err(Issue::Error(format!("unknown table column `{}`", name))),
+                }
+            })
+            .collect()
+    }
+}
+
+impl TableFormat {
+    /// The header row of a table with the given columns, including its
+    /// line break.
+    pub fn header(self, columns: &[TableColumn]) -> String {
+        let names: Vec<String> = columns.iter().map(|column| String::from(column.name())).collect();
+        self.row(&names)
+    }
+
+    /// The rows of the given output, including their line breaks.
+    pub fn rows(self, output: &Output, columns: &[TableColumn]) -> String {
+        let mut rows = String::new();
+        let matches = output_matches(output);
+        if matches.is_empty() {
+            rows.push_str(&self.row(&cells(output, None, columns)));
+        }
+        for pattern_match in matches {
+            rows.push_str(&self.row(&cells(output, Some(pattern_match), columns)));
+        }
+        rows
+    }
+
+    /// Formats a single row of the given cells.
+    fn row(self, cells: &[String]) -> String {
+        let cells: Vec<String> = cells.iter().map(|cell| self.escape(cell)).collect();
+        let separator = match self {
+            TableFormat::Csv => ",",
+            TableFormat::Tsv => "\t",
+        };
+        let mut row = cells.join(separator);
+        row.push_str(match self {
+            TableFormat::Csv => "\r\n", // as per RFC 4180
+            TableFormat::Tsv => "\n",
+        });
+        row
+    }
+
+    /// Escapes the given cell for the format.
+    fn escape(self, cell: &str) -> String {
+        let neutralized;
+        let cell = if is_formula(cell) {
+            neutralized = format!("'{}", cell);
+            neutralized.as_str()
+        } else {
+            cell
+        };
+        match self {
+            TableFormat::Csv if cell.contains(&[',', '"', '\n', '\r'][..]) => {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            }
+            TableFormat::Csv => String::from(cell),
+            TableFormat::Tsv => cell.replace(&['\t', '\n', '\r'][..], " "),
+        }
+    }
+}
+
+/// Whether a spreadsheet would evaluate the given cell as a formula.
+/// (Numbers, such as negative scores, are left alone.)
+fn is_formula(cell: &str) -> bool {
+    cell.starts_with(&['=', '+', '-', '@', '\t', '\r'][..]) && cell.parse::<f64>().is_err()
+}
+
+/// The matches of the given output, each with the ID of the trigger that
+/// produced it, when known.
+pub(crate) fn output_matches(output: &Output) -> Vec<(Option<&str>, &PatternMatch)> {
+    let excerpts = output.items.iter().find_map(|item| match item {
+        OutputItem::Excerpt(excerpts) => Some(excerpts.iter().map(|excerpt| (None, excerpt)).collect()),
+        _ => None,
+    });
+    let trigger_matches = || {
+        output.items.iter().find_map(|item| match item {
+            OutputItem::TriggerMatches(matches) => Some(
+                matches
+                    .iter()
+                    .map(|(trigger, pattern_match)| (Some(trigger.as_str()), pattern_match))
+                    .collect(),
+            ),
+            _ => None,
+        })
+    };
+    excerpts.or_else(trigger_matches).unwrap_or_default()
+}
+
+/// The cells of the row of the given output and match.
+fn cells(
+    output: &Output,
+    pattern_match: Option<(Option<&str>, &PatternMatch)>,
+    columns: &[TableColumn],
+) -> Vec<String> {
+    let item = |find: &dyn Fn(&OutputItem) -> Option<String>| -> String {
+        output.items.iter().find_map(find).unwrap_or_default()
+    };
+    let optional = |value: Option<usize>| value.map(|value| value.to_string()).unwrap_or_default();
+    let (trigger, pattern_match) = match pattern_match {
+        Some((trigger, pattern_match)) => (trigger, Some(pattern_match)),
+        None => (None, None),
+    };
+    columns
+        .iter()
+        .map(|column| match column {
+            TableColumn::Id => output.id.clone().unwrap_or_default(),
+            TableColumn::QueryId => output.query_id.clone().unwrap_or_default(),
+            TableColumn::Url => item(&|item| match item {
+                OutputItem::Url(url) => url.clone(),
+                _ => None,
+            }),
+            TableColumn::Domain => item(&|item| match item {
+                OutputItem::Domain(domain) => domain.clone(),
+                _ => None,
+            }),
+            TableColumn::Mime => item(&|item| match item {
+                OutputItem::Mime(mime) => mime.clone(),
+                _ => None,
+            }),
+            TableColumn::Title => item(&|item| match item {
+                OutputItem::Title(title) => title.clone(),
+                _ => None,
+            }),
+            TableColumn::Tags => output.tags.join(";"),
+            TableColumn::Metadata => item(&|item| match item {
+                OutputItem::Metadata(metadata) => Some(
+                    metadata
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect::<Vec<String>>()
+                        .join(";"),
+                ),
+                _ => None,
+            }),
+            TableColumn::Score => output.score.map(|score| score.to_string()).unwrap_or_default(),
+            TableColumn::Trigger => trigger.map(String::from).unwrap_or_default(),
+            TableColumn::Excerpt => pattern_match
+                .map(|pattern_match| String::from(pattern_match.excerpt.trim()))
+                .unwrap_or_default(),
+            TableColumn::Match => pattern_match
+                .and_then(|pattern_match| {
+                    let (start, end) = pattern_match.relevant;
+                    pattern_match.excerpt.get(start..end).map(String::from)
+                })
+                .unwrap_or_default(),
+            TableColumn::Start => optional(pattern_match.and_then(|value| value.offsets).map(|value| value.0)),
+            TableColumn::End => optional(pattern_match.and_then(|value| value.offsets).map(|value| value.1)),
+            TableColumn::Line => optional(pattern_match.and_then(|value| value.location).map(|value| value.0)),
+            TableColumn::Column => optional(pattern_match.and_then(|value| value.location).map(|value| value.1)),
+        })
+        .collect()
+}
+
+impl OutputBatch {
+    /// Exports the batch as a table in the given format, with a header
+    /// row followed by the rows of each output. (See `TableColumn`.)
+    pub fn to_table(&self, format: TableFormat, columns: &[TableColumn]) -> String {
+        let mut table = format.header(columns);
+        for output in &self.outputs {
+            table.push_str(&format.rows(output, columns));
+        }
+        table
+    }
+}
+
+/// A `TableWriter` writes outputs as the rows of a table, such as a CSV
+/// file, writing the header before the first row unless the destination
+/// already contains a table.
+pub struct TableWriter<W: Write> {
+    /// The destination of the rows.
+    writer: W,
+    /// The format of the table.
+    format: TableFormat,
+    /// The columns of the table.
+    columns: Vec<TableColumn>,
+    /// Whether the header has been written (or was already present).
+    has_header: bool,
+}
+
+impl<W: Write> TableWriter<W> {
+    /// Create a new `TableWriter` that writes a new table to the given
+    /// destination.
+    pub fn new(writer: W, format: TableFormat, columns: &[TableColumn]) -> TableWriter<W> {
+        TableWriter {
+            writer,
+            format,
+            columns: columns.to_vec(),
+            has_header: false,
+        }
+    }
+
+    /// Consumes the writer, returning its destination. (The destination
+    /// is not flushed.)
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl TableWriter<BufWriter<File>> {
+    /// Create a new `TableWriter` that appends rows to the file at the
+    /// given path, creating it (and writing its header) if it does not
+    /// exist or is empty.
+    pub fn append(
+        path: &str,
+        format: TableFormat,
+        columns: &[TableColumn],
+    ) -> Result<TableWriter<BufWriter<File>>, Issue> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| Issue::Error(format!("unable to open `{}` for writing (`{}`)", path, error)))?;
+        let is_empty = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+        let mut writer = TableWriter::new(BufWriter::new(file), format, columns);
+        writer.has_header = !is_empty;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> OutputWriter for TableWriter<W> {
+    fn write(&mut self, output: &Output) -> Result<(), Issue> {
+        let mut rows = String::new();
+        if !self.has_header {
+            rows.push_str(&self.format.header(&self.columns));
+        }
+        rows.push_str(&self.format.rows(output, &self.columns));
+        match self.writer.write_all(rows.as_bytes()) {
+            Ok(_) => {
+                self.has_header = true;
+                Ok(())
+            }
+            Err(error) => Err(Issue::Error(format!("unable to write output (`{}`)", error))),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        self.writer
+            .flush()
+            .map_err(|error| Issue::Error(format!("unable to flush outputs (`{}`)", error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn get_output(excerpts: Vec<PatternMatch>) -> Output {
+        let mut output = Output::from_url("https://example.com/a");
+        output.items.push(OutputItem::Excerpt(excerpts));
+        output
+    }
+
+    fn get_match(excerpt: &str, relevant: (usize, usize)) -> PatternMatch {
+        PatternMatch {
+            excerpt: String::from(excerpt),
+            relevant,
+            captures: BTreeMap::new(),
+            offsets: Some((relevant.0 + 100, relevant.1 + 100)),
+            content: None,
+            location: Some((3, relevant.0 + 1)),
+        }
+    }
+
+    #[test]
+    fn test_table_export() {
+        let batch = OutputBatch::from(vec![
+            get_output(vec![get_match("say \"hi\", all", (5, 7)), get_match("hi\tthere", (0, 2))]),
+            get_output(vec![]),
+        ]);
+        let columns = TableColumn::parse_list("query_id, url,match,excerpt,start,line").unwrap();
+        assert_eq!(
+            batch.to_table(TableFormat::Csv, &columns),
+            "query_id,url,match,excerpt,start,line\r\n\
+             Q1,https://example.com/a,hi,\"say \"\"hi\"\", all\",105,3\r\n\
+             Q1,https://example.com/a,hi,hi\tthere,100,3\r\n\
+             Q1,https://example.com/a,,,,\r\n"
+        );
+        assert_eq!(
+            batch.to_table(TableFormat::Tsv, &[TableColumn::Match, TableColumn::Excerpt]),
+            "match\texcerpt\nhi\tsay \"hi\", all\nhi\thi there\n\t\n"
+        );
+        assert!(TableColumn::parse_list("url,offsets").is_err());
+
+        let mut output = get_output(vec![]);
+        let metadata: BTreeMap<String, String> =
+            vec![(String::from("crawl"), String::from("CC-1")), (String::from("status"), String::from("200"))]
+                .into_iter()
+                .collect();
+        output.items.push(OutputItem::Metadata(metadata));
+        let columns = TableColumn::parse_list("metadata").unwrap();
+        assert_eq!(
+            OutputBatch::from(vec![output]).to_table(TableFormat::Tsv, &columns),
+            "metadata\ncrawl=CC-1;status=200\n"
+        );
+
+        let mut writer = TableWriter::new(Vec::new(), TableFormat::Csv, &DEFAULT_COLUMNS);
+        writer.write_batch(&batch).unwrap();
+        writer.write(&batch.outputs[1]).unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(written.matches("query_id").count(), 1); // the header is written once
+        assert_eq!(written.lines().count(), 5);
+    }
+
+    #[test]
+    fn test_table_formulas() {
+        let batch = OutputBatch::from(vec![get_output(vec![
+            get_match("=HYPERLINK(\"http://evil\")", (0, 1)),
+            get_match("@SUM(A1)", (0, 1)),
+            get_match("\t+1+2", (1, 2)),
+        ])]);
+        let columns = [TableColumn::Excerpt, TableColumn::Match];
+        assert_eq!(
+            batch.to_table(TableFormat::Csv, &columns),
+            "excerpt,match\r\n\
+             \"'=HYPERLINK(\"\"http://evil\"\")\",'=\r\n\
+             '@SUM(A1),'@\r\n\
+             '+1+2,'+\r\n"
+        );
+        assert_eq!(
+            batch.to_table(TableFormat::Tsv, &[TableColumn::Match]),
+            "match\n'=\n'@\n'+\n"
+        );
+
+        let mut output = get_output(vec![]);
+        output.score = Some(-1.5);
+        output.tags = vec![String::from("-draft")];
+        assert_eq!(
+            OutputBatch::from(vec![output]).to_table(TableFormat::Csv, &[TableColumn::Score, TableColumn::Tags]),
+            "score,tags\r\n-1.5,'-draft\r\n"
+        );
+    }
+}
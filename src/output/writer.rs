@@ -0,0 +1,126 @@
+//! This file provides functionality related to writing outputs as they
+//! are produced, such as to a single file of newline-delimited JSON.
+
+use common::validation::Issue;
+use output::output::{Output, OutputBatch};
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+/// An `OutputWriter` persists outputs as they are produced—for example,
+/// as the scan engine makes them available—rather than once a scan
+/// completes.
+pub trait OutputWriter {
+    /// Writes a single output. The output may be buffered until the
+    /// writer is flushed.
+    fn write(&mut self, output: &Output) -> Result<(), Issue>;
+
+    /// Ensures that every output written so far has been persisted.
+    fn flush(&mut self) -> Result<(), Issue>;
+
+    /// Writes every output of the batch, then flushes the writer, so that
+    /// the outputs are persisted incrementally during long scans.
+    fn write_batch(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+        for output in &batch.outputs {
+            self.write(output)?;
+        }
+        self.flush()
+    }
+}
+
+/// An `NdjsonWriter` writes outputs as newline-delimited JSON: one output
+/// per line, as compact JSON. Unlike a file per output, a single NDJSON
+/// file scales to millions of outputs, and can be processed line by line
+/// with standard tools.
+pub struct NdjsonWriter<W: Write> {
+    /// The destination of the outputs.
+    writer: W,
+    /// The number of outputs written so far.
+    written: usize,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    /// Create a new `NdjsonWriter` that writes to the given destination.
+    pub fn new(writer: W) -> NdjsonWriter<W> {
+        NdjsonWriter { writer, written: 0 }
+    }
+
+    /// The number of outputs written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// A reference to the writer's destination.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Consumes the writer, returning its destination. (The destination
+    /// is not flushed.)
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl NdjsonWriter<BufWriter<File>> {
+    /// Create a new `NdjsonWriter` that appends to the file at the given
+    /// path, creating it if it does not exist.
+    pub fn append(path: &str) -> Result<NdjsonWriter<BufWriter<File>>, Issue> {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Ok(NdjsonWriter::new(BufWriter::new(file))),
+            Err(error) => Err(Issue::Error(format!(
+                "unable to open `{}` for writing (`{}`)",
+                path, error
+            ))),
+        }
+    }
+}
+
+impl<W: Write> OutputWriter for NdjsonWriter<W> {
+    fn write(&mut self, output: &Output) -> Result<(), Issue> {
+        let mut line = match serde_json::to_string(output) {
+            Ok(value) => value,
+            Err(error) => {
+                return Err(Issue::Error(format!("unable to serialize output (`{}`)", error)))
+            }
+        };
+        line.push('\n');
+        match self.writer.write_all(line.as_bytes()) {
+            Ok(_) => {
+                self.written += 1;
+                Ok(())
+            }
+            Err(error) => Err(Issue::Error(format!("unable to write output (`{}`)", error))),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Issue> {
+        self.writer
+            .flush()
+            .map_err(|error| Issue::Error(format!("unable to flush outputs (`{}`)", error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ndjson_writer() {
+        let mut writer = NdjsonWriter::new(Vec::new());
+        writer.write(&Output::from_url("https://example.com/a")).unwrap();
+        let batch = OutputBatch::from(vec![
+            Output::from_url("https://example.com/b"),
+            Output::from_url("https://example.com/c"),
+        ]);
+        writer.write_batch(&batch).unwrap();
+        assert_eq!(writer.written(), 3);
+
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let parsed: Output = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(parsed, batch.outputs[0]);
+        assert!(written.ends_with('\n'));
+    }
+}
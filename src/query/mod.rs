@@ -5,4 +5,6 @@ pub mod trigger;
 pub mod scope;
 pub mod response;
 pub mod threshold;
+pub mod prefilter;
+#[allow(clippy::module_inception)]
 pub mod query;
\ No newline at end of file
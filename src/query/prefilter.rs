@@ -0,0 +1,286 @@
+//! This file provides the collected RegEx prefilter used by
+//! `CompiledQueryGroup`s to determine which queries to run.
+
+use aho_corasick::AhoCorasick;
+use regex;
+use regex::RegexSet;
+use regex_syntax;
+use regex_syntax::hir::literal::{ExtractKind, Extractor};
+#[cfg(feature = "hyperscan")]
+use hyperscan::prelude::{
+    BlockDatabase, Builder, CompileFlags, Matching, Pattern, Patterns, Scratch,
+};
+#[cfg(feature = "hyperscan")]
+use std::sync::{Arc, Mutex};
+
+/// A `RegexPrefilter` determines which of many RegEx expressions match
+/// a text in a single pass.
+///
+/// By default, this is a `RegexSet`. When it is compiled, each expression
+/// is analyzed to find the literals with which every one of its matches
+/// must begin (for example, `hello|goodbye` for `(hello|goodbye) world`).
+/// Expressions with such literals are _gated_: they are only evaluated
+/// when an Aho-Corasick automaton of the literals finds one of them in
+/// the text, which is far cheaper than evaluating the `RegexSet`. Every
+/// other expression is always evaluated.
+///
+/// When the `hyperscan` feature is enabled, the expressions are also
+/// compiled into a Hyperscan database, whose SIMD matching is much faster
+/// for large groups. Expressions that Hyperscan cannot compile, and scans
+/// that Hyperscan cannot complete, fall back to the `RegexSet`s.
+#[derive(Clone)]
+pub struct RegexPrefilter {
+    /// The number of expressions in the prefilter.
+    len: usize,
+    /// The expressions that could be gated, if any.
+    gated: Option<GatedRegexSet>,
+    /// The expressions that could not be gated, as a `RegexSet`.
+    ungated: RegexSet,
+    /// The index of each expression in `ungated` among all expressions.
+    ungated_indices: Vec<usize>,
+    /// The collected expressions as a Hyperscan database, if they could
+    /// be compiled as such.
+    #[cfg(feature = "hyperscan")]
+    database: Option<Arc<HyperscanDatabase>>,
+}
+
+/// A Hyperscan database, along with a pool of the scratch space that
+/// scanning it requires. Scratch space is expensive to allocate and
+/// cannot be used by two scans at once, so each scan takes one from the
+/// pool and returns it afterwards; the pool only grows to the number of
+/// threads scanning concurrently.
+#[cfg(feature = "hyperscan")]
+struct HyperscanDatabase {
+    /// The compiled expressions.
+    database: BlockDatabase,
+    /// The scratch space not currently in use by a scan.
+    scratch: Mutex<Vec<Scratch>>,
+}
+
+/// A `GatedRegexSet` is a `RegexSet` that is only evaluated when one of
+/// its required literals is present in the text.
+#[derive(Clone)]
+struct GatedRegexSet {
+    /// The literals that every match must begin with.
+    literals: AhoCorasick,
+    /// The expressions, as a `RegexSet`.
+    set: RegexSet,
+    /// The index of each expression in `set` among all expressions.
+    indices: Vec<usize>,
+}
+
+/// The maximum number of literals extracted from a single expression;
+/// expressions with more (such as case-insensitive ones) are not gated.
+const LITERAL_LIMIT: usize = 64;
+
+/// Determines the literals with which every match of the given expression
+/// must begin, returning `None` when there is no such (finite, non-empty)
+/// set of literals.
+fn required_literals(expression: &str) -> Option<Vec<Vec<u8>>> {
+    let hir = regex_syntax::Parser::new().parse(expression).ok()?;
+    let sequence = Extractor::new()
+        .kind(ExtractKind::Prefix)
+        .limit_total(LITERAL_LIMIT)
+        .extract(&hir);
+    let literals = sequence.literals()?;
+    if literals.is_empty() || literals.iter().any(|literal| literal.is_empty()) {
+        return None; // an empty literal would match everywhere
+    }
+    Some(literals.iter().map(|literal| literal.as_bytes().to_vec()).collect())
+}
+
+impl RegexPrefilter {
+    /// Compiles the given expressions into a prefilter. The indices of
+    /// the expressions are those reported by `matches()`.
+    pub fn new(expressions: &[String]) -> Result<RegexPrefilter, regex::Error> {
+        let mut literals: Vec<Vec<u8>> = Vec::new();
+        let mut gated_expressions: Vec<&String> = Vec::new();
+        let mut gated_indices: Vec<usize> = Vec::new();
+        let mut ungated_expressions: Vec<&String> = Vec::new();
+        let mut ungated_indices: Vec<usize> = Vec::new();
+        for (index, expression) in expressions.iter().enumerate() {
+            match required_literals(expression) {
+                Some(required) => {
+                    literals.extend(required);
+                    gated_expressions.push(expression);
+                    gated_indices.push(index);
+                }
+                None => {
+                    ungated_expressions.push(expression);
+                    ungated_indices.push(index);
+                }
+            }
+        }
+        let gated = if gated_expressions.is_empty() {
+            None
+        } else {
+            match AhoCorasick::new(&literals) {
+                Ok(automaton) => Some(GatedRegexSet {
+                    literals: automaton,
+                    set: RegexSet::new(gated_expressions)?,
+                    indices: gated_indices,
+                }),
+                Err(_) => {
+                    // the literals are too numerous for an automaton; evaluate everything
+                    ungated_indices = (0..expressions.len()).collect();
+                    ungated_expressions = expressions.iter().collect();
+                    None
+                }
+            }
+        };
+        Ok(RegexPrefilter {
+            len: expressions.len(),
+            gated,
+            ungated: RegexSet::new(ungated_expressions)?,
+            ungated_indices,
+            #[cfg(feature = "hyperscan")]
+            database: compile_database(expressions),
+        })
+    }
+
+    /// Creates a prefilter that never matches.
+    pub fn empty() -> RegexPrefilter {
+        RegexPrefilter {
+            len: 0,
+            gated: None,
+            ungated: RegexSet::empty(),
+            ungated_indices: vec![],
+            #[cfg(feature = "hyperscan")]
+            database: None,
+        }
+    }
+
+    /// The number of expressions in the prefilter.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the prefilter contains no expressions.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of expressions that are gated by their required
+    /// literals. (See `RegexPrefilter`.)
+    pub fn gated_len(&self) -> usize {
+        match &self.gated {
+            Some(gated) => gated.indices.len(),
+            None => 0,
+        }
+    }
+
+    /// Returns the indices of every expression that matches the given
+    /// text, in ascending order.
+    pub fn matches(&self, text: &str) -> Vec<usize> {
+        #[cfg(feature = "hyperscan")]
+        {
+            if let Some(database) = &self.database {
+                if let Some(matches) = scan_database(database, text) {
+                    return matches;
+                }
+            }
+        }
+        let mut matches: Vec<usize> = Vec::new();
+        if let Some(gated) = &self.gated {
+            if gated.literals.is_match(text) {
+                matches.extend(
+                    gated
+                        .set
+                        .matches(text)
+                        .into_iter()
+                        .map(|index| gated.indices[index]),
+                );
+            }
+        }
+        if self.ungated.is_match(text) {
+            matches.extend(
+                self.ungated
+                    .matches(text)
+                    .into_iter()
+                    .map(|index| self.ungated_indices[index]),
+            );
+        }
+        matches.sort_unstable();
+        matches
+    }
+}
+
+/// Compiles the given expressions into a Hyperscan database, returning
+/// `None` if any of them is unsupported.
+///
+/// Expressions are compiled in prefilter mode, so that some constructs
+/// which Hyperscan does not support exactly (such as backreferences)
+/// are still accepted; prefilter mode may report false positives, but
+/// every reported query is fully evaluated afterwards anyway.
+#[cfg(feature = "hyperscan")]
+fn compile_database(expressions: &[String]) -> Option<Arc<HyperscanDatabase>> {
+    if expressions.is_empty() {
+        return None;
+    }
+    let flags = CompileFlags::UTF8
+        | CompileFlags::UCP
+        | CompileFlags::SINGLEMATCH
+        | CompileFlags::ALLOWEMPTY
+        | CompileFlags::PREFILTER;
+    let mut patterns: Vec<Pattern> = Vec::new();
+    for (index, expression) in expressions.iter().enumerate() {
+        let mut pattern = Pattern::with_flags(expression.as_str(), flags).ok()?;
+        pattern.id = Some(index);
+        patterns.push(pattern);
+    }
+    let database: BlockDatabase = Patterns(patterns).build().ok()?;
+    Some(Arc::new(HyperscanDatabase {
+        database,
+        scratch: Mutex::new(Vec::new()),
+    }))
+}
+
+/// Scans the given text with the Hyperscan database, returning `None`
+/// if the scan could not be completed.
+#[cfg(feature = "hyperscan")]
+fn scan_database(database: &HyperscanDatabase, text: &str) -> Option<Vec<usize>> {
+    let pooled = match database.scratch.lock() {
+        Ok(mut pool) => pool.pop(),
+        Err(_) => None,
+    };
+    let scratch = match pooled {
+        Some(scratch) => scratch,
+        None => database.database.alloc_scratch().ok()?,
+    };
+    let mut matches: Vec<usize> = Vec::new();
+    let result = database
+        .database
+        .scan(text, &scratch, |id: u32, _from: u64, _to: u64, _flags: u32| {
+            matches.push(id as usize);
+            Matching::Continue
+        });
+    if let Ok(mut pool) = database.scratch.lock() {
+        pool.push(scratch);
+    }
+    result.ok()?;
+    matches.sort_unstable();
+    matches.dedup();
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_gating() {
+        let expressions = vec![
+            String::from(r"(hello|goodbye) \w+"),
+            String::from(r"\d+"),
+            String::from(r"world"),
+        ];
+        let prefilter = RegexPrefilter::new(&expressions).unwrap();
+        assert_eq!(prefilter.len(), 3);
+        assert_eq!(prefilter.gated_len(), 2);
+        assert_eq!(prefilter.matches("goodbye world 42"), vec![0, 1, 2]);
+        assert_eq!(prefilter.matches("hello there"), vec![0]);
+        assert_eq!(prefilter.matches("nothing 7 here"), vec![1]);
+        assert!(prefilter.matches("nothing here").is_empty());
+        assert!(RegexPrefilter::empty().matches("anything").is_empty());
+    }
+}
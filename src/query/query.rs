@@ -1,16 +1,22 @@
 //! This file contains functionality related to queries.
 
-use query::response::Response;
+use query::prefilter::RegexPrefilter;
+use query::response::{Response, ResponseItem};
 use query::scope::{CompiledScope, Scope, ScopeContent};
 use query::threshold::{Threshold, ThresholdConsideration};
 use query::trigger::{CompiledTrigger, Trigger};
 
 use common::compilation::CompilableTo;
+use common::format;
+use common::pattern::{CompiledPattern, PatternKind};
 use common::validation::{Issue, Validatable};
+use input::document::hash_bytes;
 
-use regex::RegexSet;
+use aho_corasick::AhoCorasick;
+use regex::Regex;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
 
 /// `Query` represents an uncompiled query. This type is
 /// typically interstitial; it cannot perform scans, and has
@@ -19,7 +25,7 @@ use std::collections::HashMap;
 ///
 /// This type is part of the public API, and therefore must
 /// comply with the structure defined in the specification.
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Query {
     /// Represents the desired `Response` of the query when
     /// it matches `Document`s. In other words, this is the
@@ -40,6 +46,13 @@ pub struct Query {
     ///
     /// For more information, see the `Threshold` documentation.
     pub threshold: Threshold,
+    /// When present, the query is _scored_: rather than being
+    /// evaluated, its threshold is scored (see `Threshold::score()`),
+    /// and the query matches when the score is at least this cutoff,
+    /// which must be between `0.0` and `1.0`. The score is recorded
+    /// in the `Output`, so that matches can be sorted by strength.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutoff: Option<f32>,
     /// Represents the `Triggers` that will be checked against
     /// the document and then processed by the `Threshold` in
     /// order to determine whether a match is made.
@@ -50,6 +63,11 @@ pub struct Query {
     /// but highly recommended, as it will be copied to the
     /// outputs created by this query.
     pub id: Option<String>,
+    /// Represents arbitrary metadata about the query, such as its
+    /// `priority` or the `team` that owns it, which is copied to the
+    /// outputs created by this query.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 /// Represents a collection of queries. This type is useful in
@@ -62,6 +80,20 @@ pub struct QueryGroup {
     /// The type of content that, when compiled, the query group
     /// should be optimized for.
     pub optimized_content: ScopeContent,
+    /// Thresholds that the queries of the group may refer to by name
+    /// (using `ThresholdConsideration::Named`), so that a complex
+    /// threshold can be shared instead of copied between queries.
+    /// Names are resolved when the group is compiled.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub thresholds: BTreeMap<String, Threshold>,
+    /// Triggers that the queries of the group may consider by ID
+    /// without defining them, so that a large block of patterns can
+    /// be shared instead of copied between queries. When the group is
+    /// compiled, each query receives the shared triggers that its
+    /// threshold refers to (unless it defines a trigger of the same ID
+    /// itself), and each distinct pattern is only compiled once.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<Trigger>,
 }
 
 /// Represents a compiled query which is ready to scan (compiled)
@@ -74,8 +106,25 @@ pub struct CompiledQuery {
     pub response: Response,
     pub scope: CompiledScope,
     pub threshold: Threshold,
+    pub cutoff: Option<f32>,
     pub triggers: Vec<CompiledTrigger>,
     pub id: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    /// The `threshold`, with its considerations ordered from cheapest
+    /// to most expensive (see `Threshold::ordered_by_cost()`); the scan
+    /// engine evaluates this threshold lazily.
+    pub ordered_threshold: Threshold,
+    /// Whether producing an `Output` requires the results of _every_
+    /// trigger—for example, to include their excerpts or tags—rather
+    /// than only those needed to decide the threshold.
+    pub needs_all_triggers: bool,
+    /// The compiled `redactions` of the response.
+    pub redactions: Vec<Regex>,
+    /// The SHA-256 hash, in hexadecimal, of the query that was compiled
+    /// and of the terms of its dictionary patterns, which changes whenever
+    /// the query (or a dictionary file that it loads) does. (See
+    /// `CompiledQueryGroup::fingerprint()`.)
+    pub fingerprint: String,
 }
 
 /// Represents a group of compiled queries. This type has several
@@ -109,8 +158,9 @@ pub struct CompiledQueryGroup {
     /// allows for the `CompiledQueryGroup`'s scanning mechanism
     /// to know in advance which of its queries will _potentially
     /// match_ on a document, without having to execute every
-    /// individual query.
-    pub regex_collected: RegexSet,
+    /// individual query. (See `RegexPrefilter` for the available
+    /// matching backends.)
+    pub regex_collected: RegexPrefilter,
     /// This index relates every RegEx pattern in `regex_collected`
     /// to its source query in `queries`.
     ///
@@ -118,6 +168,28 @@ pub struct CompiledQueryGroup {
     /// 1st RegEx pattern in `regex_collected` and denotes the index
     /// of its source query in `queries`.
     pub regex_collected_query_index: Vec<usize>,
+    /// This index relates every RegEx pattern in `regex_collected`
+    /// to the ID of its source trigger.
+    ///
+    /// Together with `regex_collected_query_index`, this allows the
+    /// scan engine to determine which triggers of each query are able
+    /// to match, and to skip queries whose threshold cannot be met.
+    pub regex_collected_trigger_ids: Vec<String>,
+    /// Contains every single `Raw` pattern (and every term of every
+    /// `Dictionary` pattern) of every query's triggers, compiled into a
+    /// single Aho-Corasick automaton.
+    ///
+    /// Plaintext patterns are far cheaper to match using a dedicated
+    /// automaton than as escaped RegEx in `regex_collected`, especially
+    /// in groups with many thousands of keyword triggers. Otherwise, this
+    /// automaton serves the same purpose as `regex_collected`.
+    pub literal_collected: AhoCorasick,
+    /// This index relates every pattern in `literal_collected` to its
+    /// source query in `queries`. (See `regex_collected_query_index`.)
+    pub literal_collected_query_index: Vec<usize>,
+    /// This index relates every pattern in `literal_collected` to the ID
+    /// of its source trigger. (See `regex_collected_trigger_ids`.)
+    pub literal_collected_trigger_ids: Vec<String>,
     /// Contains the queries that cannot be optimized using the
     /// methods above, and therefore must be run on every document.
     ///
@@ -130,7 +202,7 @@ pub struct CompiledQueryGroup {
     /// are also included here as unoptimizable.
     pub always_run_queries: Vec<CompiledQuery>,
     /// The type of content that should be fed to the RegEx patterns
-    /// in `regex_collected`.
+    /// in `regex_collected` (and the patterns in `literal_collected`).
     pub regex_feed: ScopeContent,
 }
 
@@ -138,37 +210,178 @@ impl CompilableTo<CompiledQuery> for Query {
     /// Compiles the `Query` into a `CompiledQuery`. Like all compilation
     /// operations, this is expensive.
     fn compile(&self) -> Result<CompiledQuery, Issue> {
-        let scope = match self.scope.compile() {
-            Ok(compiled) => compiled,
-            Err(issue) => return Err(issue),
-        };
+        self.compile_cached(&mut HashMap::new())
+    }
+}
+
+impl Query {
+    /// Compiles the `Query` like `compile()`, but reuses the compiled
+    /// patterns of its triggers from `patterns` when possible. (See
+    /// `Trigger::compile_cached()`.)
+    pub fn compile_cached(
+        &self,
+        patterns: &mut HashMap<String, CompiledPattern>,
+    ) -> Result<CompiledQuery, Issue> {
+        let scope = self.scope.compile()?;
+
+        if let Some(cutoff) = self.cutoff {
+            if !(0.0..=1.0).contains(&cutoff) {
+                return Err(Issue::Error(format!(
+                    "invalid cutoff {}; the cutoff of a scored query must be between 0 and 1",
+                    cutoff
+                )));
+            }
+        }
 
         let mut triggers: Vec<CompiledTrigger> = Vec::new();
         for trigger in &self.triggers {
-            let compiled_trigger = match trigger.compile() {
+            let mut compiled_trigger = match trigger.compile_cached(patterns) {
                 Ok(compiled) => compiled,
+                Err(Issue::Error(message)) => {
+                    return Err(Issue::Error(format!(
+                        "unable to compile trigger `{}` of query `{}`: {}",
+                        trigger.id,
+                        match &self.id {
+                            Some(value) => value.as_str(),
+                            None => "unknown_query",
+                        },
+                        message
+                    )))
+                }
                 Err(issue) => return Err(issue),
             };
+            if let Some(transforms) = compiled_trigger.transforms.take() {
+                let mut combined = self.scope.transforms.clone();
+                combined.extend(transforms);
+                compiled_trigger.transforms = Some(combined);
+            }
+            if compiled_trigger.content == Some(self.scope.content) {
+                compiled_trigger.content = None; // no different from the scope
+            }
             triggers.push(compiled_trigger)
         }
 
+        let mut costs: HashMap<String, usize> = self
+            .triggers
+            .iter()
+            .map(|trigger| (trigger.id.clone(), trigger.pattern.estimated_cost()))
+            .collect();
+        // Named thresholds can only be resolved by a query group (see
+        // `QueryGroup::compile()`), which resolves them before compiling.
+        let mut aliases: Vec<(String, String, ScopeContent)> = Vec::new();
+        let threshold = self
+            .threshold
+            .resolve(&BTreeMap::new())?
+            .with_tags_expanded(&self.triggers)?;
+        self.check_trigger_ids(&threshold)?;
+        let threshold = threshold.with_content_aliases(self.scope.content, &mut aliases);
+        for (alias, id, content) in aliases {
+            if let Some(trigger) = triggers.iter().find(|trigger| trigger.id == id).cloned() {
+                triggers.push(CompiledTrigger {
+                    id: alias.clone(),
+                    content: trigger.content.or(Some(content)), // the trigger's own content prevails
+                    ..trigger
+                });
+                let cost = costs.get(&id).cloned().unwrap_or(0);
+                costs.insert(alias, cost);
+            }
+        }
+        let ordered_threshold = threshold.ordered_by_cost(&|id: &String| costs.get(id).cloned().unwrap_or(0));
+        let needs_all_triggers = self.response.template.is_some()
+            || self.response.include.iter().any(|item| {
+                *item == ResponseItem::Excerpt
+                    || *item == ResponseItem::Trace
+                    || *item == ResponseItem::TriggerMatches
+                    || *item == ResponseItem::TriggerStats
+            })
+            || self.triggers.iter().any(|trigger| !trigger.tags.is_empty());
+
+        let fingerprint = self.fingerprint(&scope, &triggers);
         Ok(CompiledQuery {
             response: self.response.clone(),
-            scope: scope,
-            threshold: self.threshold.clone(),
-            triggers: triggers,
+            scope,
+            threshold,
+            cutoff: self.cutoff,
+            triggers,
             id: self.id.clone(),
+            labels: self.labels.clone(),
+            ordered_threshold,
+            needs_all_triggers,
+            redactions: self.response.compile_redactions()?,
+            fingerprint,
         })
     }
 }
 
+impl Query {
+    /// The fingerprint of the query once compiled into the given scope and
+    /// triggers (see `CompiledQuery.fingerprint`). Dictionary patterns may
+    /// load their terms from files, whose paths alone do not change when
+    /// the files do, so the loaded terms are hashed too.
+    fn fingerprint(&self, scope: &CompiledScope, triggers: &[CompiledTrigger]) -> String {
+        let mut bytes = serde_json::to_vec(self).unwrap_or_default();
+        let patterns = scope
+            .patterns()
+            .chain(triggers.iter().map(|trigger| &trigger.pattern));
+        for terms in patterns.filter_map(CompiledPattern::dictionary_terms) {
+            for term in terms {
+                bytes.push(0);
+                bytes.extend_from_slice(term.as_bytes());
+            }
+        }
+        hash_bytes(&bytes)
+    }
+
+    /// Checks that the IDs of the query's triggers are unique, and that
+    /// the given threshold (the query's own, once resolved and expanded)
+    /// only refers to triggers that the query defines. Otherwise, the
+    /// query could only fail while scanning, where its errors are silent.
+    fn check_trigger_ids(&self, threshold: &Threshold) -> Result<(), Issue> {
+        let query_id = self.id.as_ref().map_or("unknown_query", |id| id.as_str());
+        let mut defined: HashSet<&String> = HashSet::new();
+        for trigger in &self.triggers {
+            if !defined.insert(&trigger.id) {
+                return Err(Issue::Error(format!(
+                    "query `{}` defines more than one trigger with the ID `{}`",
+                    query_id, trigger.id
+                )));
+            }
+        }
+        let mut undefined: Vec<&String> = threshold
+            .referenced_triggers()
+            .into_iter()
+            .filter(|id| !defined.contains(id))
+            .collect();
+        undefined.sort();
+        match undefined.first() {
+            Some(id) => Err(Issue::Error(format!(
+                "the threshold of query `{}` refers to undefined trigger `{}`",
+                query_id, id
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
 impl CompilableTo<CompiledQueryGroup> for QueryGroup {
     /// Compiles the `QueryGroup` into a `CompiledQueryGroup`. Like
     /// all compilation operations, this is expensive.
+    /// 
+    /// Groups without any queries cannot match anything, so they
+    /// fail to compile.
     fn compile(&self) -> Result<CompiledQueryGroup, Issue> {
+        if self.queries.is_empty() {
+            return Err(Issue::Error(String::from(
+                "query group contains no queries",
+            )));
+        }
         let mut queries: Vec<CompiledQuery> = Vec::new();
         let mut sub_regexes: Vec<String> = Vec::new();
         let mut sub_regexes_index: Vec<usize> = Vec::new();
+        let mut sub_regexes_trigger_ids: Vec<String> = Vec::new();
+        let mut sub_literals: Vec<String> = Vec::new();
+        let mut sub_literals_index: Vec<usize> = Vec::new();
+        let mut sub_literals_trigger_ids: Vec<String> = Vec::new();
         let mut always_runs: Vec<CompiledQuery> = Vec::new();
 
         // Returns a tuple of the 0) relevant trigger IDs and 2) whether the query is an always-run
@@ -178,16 +391,30 @@ impl CompilableTo<CompiledQueryGroup> for QueryGroup {
             let mut always_run_count = 0;
 
             for consideration in &threshold.considers {
-                match consideration {
+                match consideration.unweighted() {
                     ThresholdConsideration::NestedThreshold(nested_threshold) => {
                         let (nested_triggers, is_always) =
-                            recursively_analyze_threshold(&nested_threshold);
+                            recursively_analyze_threshold(nested_threshold);
                         if is_always {
                             always_run_count += 1;
                         }
                         relevant_triggers.extend(nested_triggers);
                     }
                     ThresholdConsideration::Trigger(id) => relevant_triggers.push(id), // cloning is OK
+                    ThresholdConsideration::TriggerCount(id, minimum) => {
+                        if *minimum == 0 {
+                            always_run_count += 1;
+                        }
+                        relevant_triggers.push(id)
+                    }
+                    ThresholdConsideration::Named(_) => always_run_count += 1, // resolved already
+                    ThresholdConsideration::Tag(_) | ThresholdConsideration::AtLeastTagged(_, _) => {
+                        always_run_count += 1 // expanded already
+                    }
+                    // A negated consideration may be satisfied without any match, and
+                    // matches of its triggers do not make the query a candidate.
+                    ThresholdConsideration::Negated(_) => always_run_count += 1,
+                    ThresholdConsideration::Weighted(_, _) => unreachable!(), // see `unweighted()`
                 }
             }
 
@@ -201,47 +428,173 @@ impl CompilableTo<CompiledQueryGroup> for QueryGroup {
             (relevant_triggers, is_always)
         }
 
+        let mut patterns: HashMap<String, CompiledPattern> = HashMap::new();
         for query in &self.queries {
-            let compiled_query = match query.compile() {
-                Ok(compiled_query) => compiled_query,
-                Err(issue) => return Err(issue), // kill early; compilation is expensive!
+            let threshold = query.threshold.resolve(&self.thresholds)?;
+            let mut triggers = query.triggers.clone();
+            let referenced = threshold.referenced_triggers();
+            for shared in &self.triggers {
+                if referenced.contains(&shared.id)
+                    && !triggers.iter().any(|trigger| trigger.id == shared.id)
+                {
+                    triggers.push(shared.clone());
+                }
+            }
+            let resolved = Query {
+                threshold: threshold.with_tags_expanded(&triggers)?,
+                triggers,
+                ..query.clone()
             };
-            let (relevant_trigger_ids, is_inverse) =
+            let query = &resolved;
+            let compiled_query = query.compile_cached(&mut patterns)?;
+            let (mut relevant_trigger_ids, mut is_inverse) =
                 recursively_analyze_threshold(&query.threshold);
-            if is_inverse || (query.scope.content != self.optimized_content) {
+            if let Some(cutoff) = query.cutoff {
+                // A scored query may match without satisfying `requires`; it
+                // must always run when its score without any trigger firing
+                // already reaches the cutoff (or when it has negated triggers,
+                // which fire without being collected), and otherwise whenever
+                // any of its triggers match.
+                let baseline = query.threshold.score_with(&mut |_, _| Ok(0));
+                is_inverse = query.triggers.iter().any(|trigger| trigger.negate)
+                    || baseline.map(|score| score >= cutoff).unwrap_or(true);
+                relevant_trigger_ids = query.threshold.referenced_triggers().into_iter().collect();
+            }
+            // The collected patterns are fed untransformed, unnormalized text,
+            // so they cannot be relied upon for scopes (or triggers) that
+            // transform their content, for triggers that are evaluated against another content
+            // (or only a part of it), or for triggers that normalize their input,
+            // match bytes, or select HTML elements.
+            let is_unfilterable = !query.scope.transforms.is_empty()
+                || compiled_query
+                    .triggers
+                    .iter()
+                    .any(|trigger| trigger.content.is_some() || trigger.transforms.is_some())
+                || query.triggers.iter().any(|trigger| {
+                    trigger.pattern.normalization.is_some()
+                        || trigger.position.is_some()
+                        || trigger.pattern.kind == PatternKind::Bytes
+                        || trigger.pattern.kind == PatternKind::Selector
+                });
+            // Negated triggers fire when their pattern does _not_ match, so
+            // they cannot be collected; queries that they alone can satisfy
+            // must always run.
+            let negated_trigger_ids: HashSet<&String> = query
+                .triggers
+                .iter()
+                .filter(|trigger| trigger.negate)
+                .map(|trigger| &trigger.id)
+                .collect();
+            let is_negated =
+                !negated_trigger_ids.is_empty() && query.threshold.is_achievable(&negated_trigger_ids);
+            if is_inverse
+                || is_unfilterable
+                || is_negated
+                || (query.scope.content == ScopeContent::Bytes)
+                || (query.scope.content != self.optimized_content)
+            {
                 always_runs.push(compiled_query);
             } else {
                 let query_index = queries.len();
                 for trigger in &query.triggers {
-                    if relevant_trigger_ids.contains(&&trigger.id) {
-                        let regex_smart = trigger.pattern.get_as_safe_regex();
+                    if trigger.negate || !relevant_trigger_ids.contains(&&trigger.id) {
+                        continue;
+                    }
+                    if trigger.pattern.kind == PatternKind::Raw
+                        || trigger.pattern.kind == PatternKind::Dictionary
+                    {
+                        // dictionaries were loaded when the query compiled
+                        let terms = match compiled_query
+                            .triggers
+                            .iter()
+                            .find(|compiled| compiled.id == trigger.id)
+                            .and_then(|compiled| compiled.pattern.dictionary_terms())
+                        {
+                            Some(terms) => terms.to_vec(),
+                            None => trigger.pattern.get_dictionary_terms()?,
+                        };
+                        for term in terms {
+                            sub_literals.push(term);
+                            sub_literals_index.push(query_index);
+                            sub_literals_trigger_ids.push(trigger.id.clone());
+                        }
+                    } else {
+                        let regex_smart = trigger.pattern.get_as_safe_regex()?;
                         sub_regexes.push(regex_smart);
                         sub_regexes_index.push(query_index);
+                        sub_regexes_trigger_ids.push(trigger.id.clone());
                     }
                 }
                 queries.push(compiled_query);
             }
         }
 
-        let regex_set = match RegexSet::new(sub_regexes) {
+        let regex_set = match RegexPrefilter::new(&sub_regexes) {
             Ok(set) => set,
-            Err(_iss) => {
-                return Err(Issue::Error(String::from(
-                    "unable to compile master regex set",
+            Err(error) => {
+                // Try to find the pattern responsible so that the user
+                // knows which query (and trigger) to fix.
+                for (index, expression) in sub_regexes.iter().enumerate() {
+                    if let Err(pattern_error) = Regex::new(expression) {
+                        let query_id = match &queries[sub_regexes_index[index]].id {
+                            Some(value) => value.as_str(),
+                            None => "unknown_query",
+                        };
+                        return Err(Issue::Error(format!(
+                            "unable to compile master regex set: trigger `{}` of query `{}` is invalid (`{}`)",
+                            sub_regexes_trigger_ids[index], query_id, pattern_error
+                        )));
+                    }
+                }
+                return Err(Issue::Error(format!(
+                    "unable to compile master regex set (`{}`)",
+                    error
+                )));
+            }
+        };
+
+        let literal_set = match AhoCorasick::new(&sub_literals) {
+            Ok(automaton) => automaton,
+            Err(error) => {
+                return Err(Issue::Error(format!(
+                    "unable to compile master literal automaton (`{}`)",
+                    error
                 )))
             }
         };
 
         Ok(CompiledQueryGroup {
-            queries: queries,
+            queries,
             regex_collected: regex_set,
             regex_collected_query_index: sub_regexes_index,
+            regex_collected_trigger_ids: sub_regexes_trigger_ids,
+            literal_collected: literal_set,
+            literal_collected_query_index: sub_literals_index,
+            literal_collected_trigger_ids: sub_literals_trigger_ids,
             always_run_queries: always_runs,
             regex_feed: self.optimized_content,
         })
     }
 }
 
+impl CompiledQueryGroup {
+    /// A fingerprint of the group: the SHA-256 hash, in hexadecimal, of the
+    /// fingerprints of its queries (see `CompiledQuery.fingerprint`) and of
+    /// the version of this crate. It changes whenever the group's queries
+    /// (including the named thresholds and shared triggers that they refer
+    /// to, and the dictionary files that they load) or the scan engine
+    /// do, so that a document that a group has scanned need not be
+    /// scanned again by a group with the same fingerprint. (See
+    /// `ScanCache` and `ScanOptions::cache_fingerprint()`.)
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprints = vec![env!("CARGO_PKG_VERSION")];
+        for query in self.queries.iter().chain(self.always_run_queries.iter()) {
+            fingerprints.push(&query.fingerprint);
+        }
+        hash_bytes(fingerprints.join(" ").as_bytes())
+    }
+}
+
 impl From<CompiledQuery> for CompiledQueryGroup {
     /// This helper function creates a `CompiledQueryGroup`
     /// for single queries, enabling multithreading support for
@@ -252,14 +605,106 @@ impl From<CompiledQuery> for CompiledQueryGroup {
             // than to perform optimizations designed with
             // multiple queries in mind
             queries: vec![],
-            regex_collected: RegexSet::new(vec![""]).unwrap(),
+            regex_collected: RegexPrefilter::empty(),
             regex_collected_query_index: vec![],
+            regex_collected_trigger_ids: vec![],
+            literal_collected: AhoCorasick::new(Vec::<String>::new()).unwrap(),
+            literal_collected_query_index: vec![],
+            literal_collected_trigger_ids: vec![],
             always_run_queries: vec![query], // for unoptimizable queries
             regex_feed: ScopeContent::Raw,
         }
     }
 }
 
+impl Query {
+    /// Estimates the relative cost of running the query, based on the
+    /// estimated costs of its scope and triggers. (See `Pattern::estimated_cost()`.)
+    pub fn estimated_cost(&self) -> usize {
+        self.scope
+            .patterns()
+            .map(|pattern| pattern.estimated_cost())
+            .sum::<usize>()
+            + self
+                .triggers
+                .iter()
+                .map(|trigger| trigger.pattern.estimated_cost())
+                .sum::<usize>()
+    }
+}
+
+impl QueryGroup {
+    /// Splits the query group into `count` partitions whose estimated costs
+    /// (see `Query::estimated_cost()`) are as balanced as possible, so that
+    /// a very large group can be divided between multiple machines scanning
+    /// the same documents.
+    ///
+    /// Partitioning is deterministic: the same group always produces the
+    /// same partitions, and queries retain their relative order. Note that
+    /// when there are fewer queries than partitions, some partitions will
+    /// be empty.
+    pub fn partition(self, count: usize) -> Result<Vec<QueryGroup>, Issue> {
+        if count == 0 {
+            return Err(Issue::Error(String::from(
+                "a query group must be split into at least one partition",
+            )));
+        }
+        // Assign the most expensive queries first, each to the partition
+        // with the lowest total cost (ties go to the lowest index).
+        let mut order: Vec<(usize, usize)> = self
+            .queries
+            .iter()
+            .map(Query::estimated_cost)
+            .enumerate()
+            .collect();
+        order.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut costs: Vec<usize> = vec![0; count];
+        let mut assignments: Vec<usize> = vec![0; self.queries.len()];
+        for (query_index, cost) in order {
+            let (partition_index, _) = costs
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, total)| (**total, *index))
+                .unwrap(); // `count` is at least 1
+            costs[partition_index] += cost;
+            assignments[query_index] = partition_index;
+        }
+
+        let mut partitions: Vec<QueryGroup> = (0..count)
+            .map(|_| QueryGroup {
+                queries: vec![],
+                optimized_content: self.optimized_content,
+                thresholds: self.thresholds.clone(),
+                triggers: self.triggers.clone(),
+            })
+            .collect();
+        for (query, partition_index) in self.queries.into_iter().zip(assignments) {
+            partitions[partition_index].queries.push(query);
+        }
+        Ok(partitions)
+    }
+}
+
+impl FromStr for Query {
+    type Err = Issue;
+
+    /// Deserializes a `Query` from RON, JSON, or YAML, detecting
+    /// the format automatically.
+    fn from_str(serialized: &str) -> Result<Query, Issue> {
+        format::deserialize(serialized)
+    }
+}
+
+impl FromStr for QueryGroup {
+    type Err = Issue;
+
+    /// Deserializes a `QueryGroup` from RON, JSON, or YAML, detecting
+    /// the format automatically.
+    fn from_str(serialized: &str) -> Result<QueryGroup, Issue> {
+        format::deserialize(serialized)
+    }
+}
+
 impl Validatable for Query {
     /// Validates the query, as well as all of its sub-components.
     ///
@@ -270,64 +715,129 @@ impl Validatable for Query {
     fn validate(&self) -> Option<Vec<Issue>> {
         let mut issues: Vec<Issue> = Vec::new();
 
-        // Check if it compiles
+        // Check threshold validity (unknown tags are reported by compilation)
+        let threshold = self
+            .threshold
+            .with_tags_expanded(&self.triggers)
+            .unwrap_or_else(|_| self.threshold.clone());
+
+        // Check if it compiles (trigger ID errors are reported in detail below)
         match self.compile() {
             Ok(_) => (),
             Err(issue) => {
-                issues.push(issue);
+                if self.check_trigger_ids(&threshold).err().as_ref() != Some(&issue) {
+                    issues.push(issue);
+                }
+            }
+        }
+
+        // Check pattern validity (errors are already reported by compilation)
+        let patterns = self
+            .scope
+            .patterns()
+            .chain(self.triggers.iter().map(|trigger| &trigger.pattern));
+        for pattern in patterns {
+            if let Some(problems) = pattern.validate() {
+                issues.extend(
+                    problems
+                        .into_iter()
+                        .filter(|problem| matches!(problem, Issue::Warning(_))),
+                );
             }
         }
 
         // Check response validity
-        match self.response.validate() {
-            Some(problems) => issues.extend(problems),
-            None => (),
+        if let Some(problems) = self.response.validate() {
+            issues.extend(problems)
         }
 
-        // Check threshold validity
-        let mut trigger_responses: HashMap<&String, bool> = HashMap::new();
-        for trigger in &self.triggers {
-            trigger_responses.insert(&trigger.id, false);
+        // Check trigger IDs
+        let mut defined: HashMap<&String, usize> = HashMap::new();
+        for (index, trigger) in self.triggers.iter().enumerate() {
+            if let Err(Issue::Error(message)) = trigger.check_id() {
+                issues.push(Issue::Error(format!("triggers[{}]: {}", index, message)));
+            }
+            if let Some(first) = defined.insert(&trigger.id, index) {
+                issues.push(Issue::Error(format!(
+                    "triggers[{}]: duplicate trigger ID `{}` (also used by triggers[{}])",
+                    index, trigger.id, first
+                )));
+            }
         }
-        match self.threshold.evaluate(&trigger_responses) {
-            Ok(value) => {
-                if value == true {
-                    issues.push(Issue::Warning(String::from("query will match if all triggers do not match; this can be dangerous in certain situations")));
-                }
+
+        let defined_ids: HashSet<&String> = defined.keys().cloned().collect();
+        issues.extend(threshold.analyze(&defined_ids));
+        let referenced = threshold.referenced_triggers();
+        // Named thresholds may reference any trigger, but are only known to the group
+        let refers_to_named = self.threshold.resolve(&BTreeMap::new()).is_err();
+        for (index, trigger) in self.triggers.iter().enumerate() {
+            if !refers_to_named && !referenced.contains(&trigger.id) {
+                issues.push(Issue::Warning(format!(
+                    "triggers[{}]: trigger `{}` is never referenced by the threshold",
+                    index, trigger.id
+                )));
             }
-            Err(issue) => issues.push(issue),
-        };
-        if issues.len() > 0 {
-            return Some(issues);
+        }
+        let mut trigger_responses: HashMap<&String, usize> = HashMap::new();
+        for trigger in &self.triggers {
+            trigger_responses.insert(&trigger.id, 0);
+        }
+        if let Ok(true) = threshold.evaluate(&trigger_responses) {
+            issues.push(Issue::Warning(String::from("query will match if all triggers do not match; this can be dangerous in certain situations")));
+        } // evaluation errors are reported above
+        if !issues.is_empty() {
+            Some(issues)
         } else {
-            return None;
+            None
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use common::pattern::*;
     use query::response::*;
     use query::scope::*;
     use query::threshold::*;
     use query::trigger::*;
+    use input::document::Document;
+    use output::output::Output;
+    use scan::scanner::Scanner;
 
     use ron;
+    use serde_json;
+    use serde_yaml;
 
-    fn get_basic_query() -> Query {
+    pub(crate) fn get_basic_query() -> Query {
         Query {
             response: Response {
                 kind: ResponseKind::Full,
                 include: vec![ResponseItem::Excerpt, ResponseItem::Url],
+                template: None,
+                excerpt_limit: None,
+                excerpt_mode: None,
+                output_ids: None,
+                content_limit: None,
+                redactions: vec![],
             },
             scope: Scope {
                 pattern: Pattern {
                     content: String::from(".+"),
                     kind: PatternKind::RegEx,
+                    normalization: None,
                 },
                 content: ScopeContent::Raw,
+                include: vec![],
+                exclude: vec![],
+                target: ScopeTarget::Url,
+                language: vec![],
+                sample: None,
+                window: None,
+                match_when_no_url: None,
+                transforms: vec![],
+                source: None,
+                respect_robots: false,
             },
             threshold: Threshold {
                 considers: vec![
@@ -339,35 +849,60 @@ mod tests {
                         ],
                         inverse: false,
                         requires: 1,
+                        content: None,
                     }),
                 ],
                 inverse: false,
                 requires: 2,
+                content: None,
             },
+            cutoff: None,
             triggers: vec![
                 Trigger {
                     pattern: Pattern {
                         content: String::from("hello"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("A"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
                 Trigger {
                     pattern: Pattern {
                         content: String::from("everyone"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("B"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
                 Trigger {
                     pattern: Pattern {
                         content: String::from("around"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("C"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
             ],
             id: Some(String::from("Test Trigger #1")),
+            labels: BTreeMap::new(),
         }
     }
 
@@ -383,6 +918,23 @@ mod tests {
         assert_eq!(get_basic_query(), basic_query);
     }
 
+    #[test]
+    fn test_format_detection() {
+        let from_ron: Query = "Query (response:(kind:Full,include:[Excerpt,Url,],),scope:(pattern:(content:\".+\",kind:RegEx,),content:Raw,),threshold:(considers:[Trigger(\"A\"),NestedThreshold((considers:[Trigger(\"B\"),Trigger(\"C\"),],requires:1,inverse:false,)),],requires:2,inverse:false,),triggers:[(pattern:(content:\"hello\",kind:RegEx,),id:\"A\",),(pattern:(content:\"everyone\",kind:RegEx,),id:\"B\",),(pattern:(content:\"around\",kind:RegEx,),id:\"C\",),],id:Some(\"Test Trigger #1\"),)".parse().unwrap();
+        assert_eq!(from_ron, get_basic_query());
+
+        let serialized_json = serde_json::to_string(&get_basic_query()).unwrap();
+        let from_json: Query = serialized_json.parse().unwrap();
+        assert_eq!(from_json, get_basic_query());
+
+        let serialized_yaml = serde_yaml::to_string(&get_basic_query()).unwrap();
+        let from_yaml: Query = serialized_yaml.parse().unwrap();
+        assert_eq!(from_yaml, get_basic_query());
+
+        let pattern: Pattern = "content: hello\nkind: Word".parse().unwrap();
+        assert_eq!(pattern.kind, PatternKind::Word);
+    }
+
     #[test]
     fn test_basic_compilation() {
         let basic_query = get_basic_query();
@@ -399,13 +951,30 @@ mod tests {
             response: Response {
                 kind: ResponseKind::Full,
                 include: vec![ResponseItem::Excerpt, ResponseItem::Url],
+                template: None,
+                excerpt_limit: None,
+                excerpt_mode: None,
+                output_ids: None,
+                content_limit: None,
+                redactions: vec![],
             },
             scope: Scope {
                 pattern: Pattern {
                     content: String::from(".+"),
                     kind: PatternKind::RegEx,
+                    normalization: None,
                 },
                 content: ScopeContent::Raw,
+                include: vec![],
+                exclude: vec![],
+                target: ScopeTarget::Url,
+                language: vec![],
+                sample: None,
+                window: None,
+                match_when_no_url: None,
+                transforms: vec![],
+                source: None,
+                respect_robots: false,
             },
             threshold: Threshold {
                 considers: vec![
@@ -417,37 +986,301 @@ mod tests {
                         ],
                         inverse: true,
                         requires: 1,
+                        content: None,
                     }),
                 ],
                 inverse: false,
                 requires: 2,
+                content: None,
             },
+            cutoff: None,
             triggers: vec![
                 Trigger {
                     pattern: Pattern {
                         content: String::from("hello"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("A"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
                 Trigger {
                     pattern: Pattern {
                         content: String::from("everyone"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("B"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
                 Trigger {
                     pattern: Pattern {
                         content: String::from("around"),
                         kind: PatternKind::RegEx,
+                        normalization: None,
                     },
                     id: String::from("C"),
+                    tags: vec![],
+                    negate: false,
+                    content: None,
+                    position: None,
+                    range: None,
+                    transforms: vec![],
                 },
             ],
             id: Some(String::from("Test Trigger #2 (inverse)")),
+            labels: BTreeMap::new(),
+        };
+        let group = QueryGroup {
+            queries,
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
         };
-        let group = QueryGroup { queries: queries };
         assert!(group.compile().is_ok());
     }
+
+    #[test]
+    fn test_group_compile_empty() {
+        let group = QueryGroup {
+            queries: vec![],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        };
+        assert!(group.compile().is_err());
+    }
+
+    #[test]
+    fn test_query_validation() {
+        assert_eq!(get_basic_query().validate(), None);
+
+        let mut query = get_basic_query();
+        query.triggers[2].id = String::from("A"); // `C` becomes undefined
+        query.triggers.push(Trigger {
+            pattern: Pattern {
+                content: String::from("unused"),
+                kind: PatternKind::Raw,
+                normalization: None,
+            },
+            id: String::from("D"),
+            tags: vec![],
+            negate: false,
+            content: None,
+            position: None,
+            range: None,
+            transforms: vec![],
+        });
+        if let ThresholdConsideration::NestedThreshold(nested) = &mut query.threshold.considers[1] {
+            nested.requires = 3;
+        }
+        let issues = query.validate().unwrap();
+        assert!(issues.contains(&Issue::Error(String::from(
+            "triggers[2]: duplicate trigger ID `A` (also used by triggers[0])"
+        ))));
+        assert!(issues.contains(&Issue::Error(String::from(
+            "threshold.considers[1]: requires 3 of 2 considerations, so it can never be met"
+        ))));
+        assert!(issues.contains(&Issue::Error(String::from(
+            "threshold.considers[1].considers[1]: refers to undefined trigger `C`"
+        ))));
+        assert!(issues.contains(&Issue::Warning(String::from(
+            "triggers[3]: trigger `D` is never referenced by the threshold"
+        ))));
+        assert_eq!(issues.len(), 4);
+
+        let mut query = get_basic_query();
+        query.triggers[1].id = String::from("B?");
+        let issues = query.validate().unwrap();
+        assert!(issues.contains(&Issue::Error(String::from(
+            "triggers[1]: trigger ID `B?` must consist only of ASCII letters, digits, `_`, `-`, and `.`"
+        ))));
+        assert!(issues.contains(&Issue::Error(String::from(
+            "threshold.considers[1].considers[0]: refers to undefined trigger `B`"
+        ))));
+        assert_eq!(issues.len(), 3); // `B?` is also never referenced
+    }
+
+    #[test]
+    fn test_group_partition() {
+        let mut queries: Vec<Query> = Vec::new();
+        for index in 0..5 {
+            let mut query = get_basic_query();
+            query.id = Some(format!("query {}", index));
+            if index == 0 {
+                // far more expensive than the others
+                query.triggers[0].pattern.content = "(hello|goodbye)+".repeat(64);
+            }
+            queries.push(query);
+        }
+        let group = QueryGroup {
+            queries,
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        };
+        let partitions = group.partition(2).unwrap();
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(partitions[0].queries.len(), 1);
+        assert_eq!(partitions[0].queries[0].id, Some(String::from("query 0")));
+        let ids: Vec<&Option<String>> = partitions[1].queries.iter().map(|query| &query.id).collect();
+        assert_eq!(ids.len(), 4);
+        assert_eq!(ids[0], &Some(String::from("query 1")));
+        assert_eq!(ids[3], &Some(String::from("query 4")));
+
+        let empty = QueryGroup {
+            queries: vec![],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        };
+        assert!(empty.partition(0).is_err());
+    }
+
+    #[test]
+    fn test_group_negated_consideration() {
+        let mut query = get_basic_query();
+        query.threshold = Threshold {
+            considers: vec![
+                ThresholdConsideration::Trigger(String::from("A")),
+                ThresholdConsideration::Negated(Box::new(ThresholdConsideration::Trigger(
+                    String::from("B"),
+                ))),
+            ],
+            requires: 2,
+            inverse: false,
+            content: None,
+        };
+        let compiled_query = query.compile().unwrap();
+        let group = QueryGroup {
+            queries: vec![query],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        };
+        let compiled_group = group.compile().unwrap();
+        assert_eq!(compiled_group.always_run_queries.len(), 0); // `A` implies a candidate
+
+        for (text, expected) in &[("hello", 1), ("hello everyone", 0), ("everyone", 0), ("around", 0)] {
+            let document = Document::from_text("https://example.com", text).compile().unwrap();
+            assert_eq!(compiled_group.scan_single(&document).outputs.len(), *expected);
+            assert_eq!(compiled_query.scan_single(&document).outputs.len(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_group_shared_triggers() {
+        let mut query = get_basic_query();
+        let shared = query.triggers.split_off(1); // `B` and `C`
+        let mut other = get_basic_query();
+        other.triggers[1].pattern.content = String::from("nobody"); // overrides the shared `B`
+        other.triggers.truncate(2);
+        let group = QueryGroup {
+            queries: vec![query.clone(), other],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: shared,
+        };
+        let serialized = ron::ser::to_string(&group).unwrap();
+        assert_eq!(serialized.parse::<QueryGroup>().unwrap(), group);
+        assert_eq!(
+            query.compile().err(),
+            Some(Issue::Error(String::from(
+                "the threshold of query `Test Trigger #1` refers to undefined trigger `B`"
+            )))
+        ); // only the group defines `B` and `C`
+        let compiled_group = group.compile().unwrap();
+        let ids = |index: usize| -> Vec<&str> {
+            compiled_group.queries[index].triggers.iter().map(|trigger| trigger.id.as_str()).collect()
+        };
+        assert_eq!(ids(0), vec!["A", "B", "C"]);
+        assert_eq!(ids(1), vec!["A", "B", "C"]);
+
+        let document = Document::from_text("https://example.com", "hello everyone")
+            .compile()
+            .unwrap();
+        let outputs = compiled_group.scan_single(&document).outputs;
+        assert_eq!(outputs.len(), 1); // the other query's `B` does not match
+    }
+
+    #[test]
+    fn test_group_duplicate_triggers() {
+        let query = get_basic_query();
+        let mut negated = get_basic_query();
+        negated.id = Some(String::from("Negated"));
+        negated.triggers[1].negate = true; // shares the pattern of the other `B`
+        let mut counted = get_basic_query();
+        counted.id = Some(String::from("Counted"));
+        counted.threshold = Threshold::from(ThresholdExpression::TriggerCount(String::from("A"), 2));
+        let queries = vec![query, negated, counted];
+        let compiled_queries: Vec<CompiledQuery> =
+            queries.iter().map(|query| query.compile().unwrap()).collect();
+        assert_eq!(compiled_queries[0].triggers[1].key, compiled_queries[1].triggers[1].key);
+        let group = QueryGroup {
+            queries,
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+
+        for text in &["hello everyone", "hello around", "hello, hello everyone", "around"] {
+            let document = Document::from_text("https://example.com", text).compile().unwrap();
+            let mut expected: Vec<Output> = Vec::new();
+            for compiled_query in &compiled_queries {
+                expected.extend(compiled_query.scan_single(&document).outputs);
+            }
+            let mut outputs = group.scan_single(&document).outputs;
+            outputs.sort_by(|a, b| a.id.cmp(&b.id));
+            expected.sort_by(|a, b| a.id.cmp(&b.id));
+            assert_eq!(outputs, expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_group_named_thresholds() {
+        let mut query = get_basic_query();
+        query.threshold = Threshold {
+            considers: vec![ThresholdConsideration::Named(String::from("greeting"))],
+            requires: 1,
+            inverse: false,
+            content: None,
+        };
+        assert!(query.compile().is_err()); // only a group can resolve names
+
+        let mut thresholds: BTreeMap<String, Threshold> = BTreeMap::new();
+        thresholds.insert(String::from("greeting"), get_basic_query().threshold);
+        let group = QueryGroup {
+            queries: vec![query],
+            optimized_content: ScopeContent::Raw,
+            thresholds,
+            triggers: vec![],
+        };
+        let serialized = ron::ser::to_string(&group).unwrap();
+        assert_eq!(group, serialized.parse::<QueryGroup>().unwrap());
+
+        let compiled_group = group.compile().unwrap();
+        assert_eq!(compiled_group.always_run_queries.len(), 0);
+        let scan = |text: &str| {
+            let document = Document::from_text("https://example.com", text);
+            compiled_group.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(scan("hello everyone"), 1);
+        assert_eq!(scan("hello"), 0);
+
+        let mut unknown = group;
+        unknown.thresholds.clear();
+        assert!(unknown.compile().is_err());
+    }
 }
@@ -1,6 +1,7 @@
 //! This file provides functionality related to responses.
 
 use common::validation::{Issue, Validatable};
+use regex::Regex;
 
 /// Represents a response—in other words, the parameters for
 /// outputs.
@@ -8,7 +9,8 @@ use common::validation::{Issue, Validatable};
 /// This type _does not compile_ as it has 'no moving
 /// parts'—it is simply information that is passed along
 /// to the scanning system to guide it as it generates
-/// outputs.
+/// outputs. (The exception is its `redactions`, which are
+/// compiled along with the query; see `compile_redactions()`.)
 ///
 /// **This type is parallel, but _different_, from the
 /// `Output` type.** You can think of a `Response` as the
@@ -28,6 +30,140 @@ pub struct Response {
     /// For more information about response items, see the
     /// documentation for `ResponseItem` and `OutputItem`.
     pub include: Vec<ResponseItem>,
+    /// Represents an optional template for a human-readable message
+    /// that will be included in the `Output` as an `OutputItem::Message`.
+    ///
+    /// Templates may contain the placeholders listed in `TEMPLATE_PLACEHOLDERS`,
+    /// such as `"{query_id} matched {domain}: {excerpt}"`; placeholders
+    /// whose values are not present are rendered as empty strings. Literal
+    /// braces are written `{{` and `}}`. (See `TemplatePart::parse()`.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Represents the maximum number of distinct excerpts that will be
+    /// included in the `Output`; when `None`, `DEFAULT_EXCERPT_LIMIT`
+    /// applies. Matches beyond the limit (and duplicate excerpts) are
+    /// counted in an `OutputItem::AdditionalMatches` instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excerpt_limit: Option<usize>,
+    /// Represents how much of the text surrounding each match is
+    /// included in its excerpt; when `None`, `ExcerptMode::Window`
+    /// applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excerpt_mode: Option<ExcerptMode>,
+    /// Represents how the IDs of the `Output`s are generated; when
+    /// `None`, `OutputIdPolicy::Derived` applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_ids: Option<OutputIdPolicy>,
+    /// Represents the maximum length, in bytes, of the content included
+    /// by `ResponseItem::FullContent`; longer content is truncated (at a
+    /// character boundary) after it is redacted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_limit: Option<usize>,
+    /// Represents RegEx expressions whose matches are replaced with
+    /// `REDACTION` in the content included by `ResponseItem::FullContent`,
+    /// such as `\b\d{3}-\d{2}-\d{4}\b` for social security numbers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<String>,
+}
+
+/// The text that replaces each match of a `Response`'s `redactions`.
+pub const REDACTION: &str = "[REDACTED]";
+
+/// Denotes how the IDs of `Output`s are generated. (See
+/// `Response.output_ids`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum OutputIdPolicy {
+    /// IDs are derived from the query's ID and the document's checksum
+    /// (see `derive_output_id()`), so the same query matching the same
+    /// document always produces the same ID, even across runs.
+    Derived,
+    /// IDs are random (version 4) UUIDs, such as
+    /// `0b4e7f2c-93a1-4d5e-8f60-1c2d3e4f5a6b`, so every output has a
+    /// distinct ID, even when a document is scanned more than once.
+    Random,
+    /// Outputs have no ID.
+    Omitted,
+}
+
+/// Denotes how much of the surrounding text is included in the excerpt
+/// of each match. (See `Response.excerpt_mode`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ExcerptMode {
+    /// Excerpts include a window of up to `EXCERPT_CONTEXT` bytes on each
+    /// side of the match, regardless of word or sentence boundaries.
+    Window,
+    /// Excerpts include the sentence (or sentences) that contain the
+    /// match. (See `PatternMatch::to_sentences()`.)
+    Sentence,
+}
+
+/// The maximum number of distinct excerpts included in an `Output` when
+/// the `Response` does not specify an `excerpt_limit`.
+pub const DEFAULT_EXCERPT_LIMIT: usize = 32;
+
+/// The placeholders that may be used in a `Response`'s `template`.
+///
+/// * `{query_id}` — the ID of the query
+/// * `{url}` — the URL of the document
+/// * `{domain}` — the domain of the document
+/// * `{mime}` — the MIME type of the document
+/// * `{excerpt}` — the excerpt of the first match
+/// * `{match}` — the matched text of the first match
+/// * `{title}` — the title of the document
+/// * `{count}` — the number of matches
+///
+/// In addition, `{metadata.<key>}` is the value of the given key of the
+/// document's metadata (see `Document.metadata`), such as `{metadata.crawl_id}`.
+pub const TEMPLATE_PLACEHOLDERS: [&str; 8] = [
+    "query_id", "url", "domain", "mime", "excerpt", "match", "title", "count",
+];
+
+/// A part of a `Response`'s template: either literal text or a placeholder.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplatePart<'a> {
+    /// Text that is rendered as is.
+    Text(String),
+    /// The name of a placeholder (such as `query_id` for `{query_id}`),
+    /// which is rendered as its value.
+    Placeholder(&'a str),
+}
+
+impl<'a> TemplatePart<'a> {
+    /// Splits the given template into its parts, in a single pass: `{{`
+    /// and `}}` are literal braces, `{name}` is a placeholder, and a `{`
+    /// without a closing `}` is literal. Templates are rendered part by
+    /// part, so the values of placeholders (which may come from the
+    /// document, such as `{excerpt}`) are never themselves rendered.
+    pub fn parse(template: &'a str) -> Vec<TemplatePart<'a>> {
+        let mut parts: Vec<TemplatePart<'a>> = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(index) = rest.find(['{', '}']) {
+            text.push_str(&rest[..index]);
+            let brace = &rest[index..index + 1];
+            rest = &rest[index + 1..];
+            if rest.starts_with(brace) {
+                text.push_str(brace); // an escaped brace
+                rest = &rest[1..];
+                continue;
+            }
+            match (brace, rest.find('}')) {
+                ("{", Some(end)) if !rest[..end].contains('{') => {
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(TemplatePart::Placeholder(&rest[..end]));
+                    rest = &rest[end + 1..];
+                }
+                _ => text.push_str(brace),
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+        parts
+    }
 }
 
 /// Represents the kind of output that should be produced by the
@@ -59,31 +195,174 @@ pub enum ResponseItem {
     Excerpt,
     /// Denotes that the full content of the web page should be included
     FullContent,
+    /// Denotes that a trace of the evaluation of the query's threshold
+    /// (see `Threshold::explain()`) should be included; useful for
+    /// debugging complex thresholds.
+    Trace,
+    /// Denotes that the `PatternMatch`es should be included along with
+    /// the ID of the trigger that produced each of them.
+    TriggerMatches,
+    /// Denotes that the number of times each of the query's triggers
+    /// matched should be included.
+    TriggerStats,
+    /// Denotes that the time at which the output was produced should be
+    /// included; useful for long-running monitoring pipelines.
+    Timestamp,
+    /// Denotes that the name and version of the engine that produced the
+    /// output (see `ENGINE`) should be included.
+    Engine,
+    /// Denotes that the SHA-256 hash of the document's content (see
+    /// `CompiledDocument.hash`) should be included, so that consumers
+    /// can deduplicate identical documents found at different URLs.
+    DocumentHash,
+    /// Denotes that the title of the document (its `<title>`) should be
+    /// included, if present.
+    Title,
+    /// Denotes that the metadata of the document (see `Document.metadata`)
+    /// should be included.
+    Metadata,
+    /// Denotes that the number of documents that matched the query should
+    /// be included; it is `1` for each output, and sums when outputs are
+    /// reduced. (See `Output::reduce()`.)
+    Count,
+    /// Denotes that the number of documents that matched the query on
+    /// each domain should be included; it sums per domain when outputs
+    /// are reduced.
+    DomainHistogram,
+}
+
+impl ResponseItem {
+    /// Whether the item can be combined across the outputs of a partial
+    /// response (see `Output::reduce()`): counts and histograms sum, and
+    /// timestamps reduce to the latest. Only reducible items are allowed
+    /// in partial responses.
+    pub fn is_reducible(self) -> bool {
+        match self {
+            ResponseItem::TriggerStats
+            | ResponseItem::Timestamp
+            | ResponseItem::Count
+            | ResponseItem::DomainHistogram => true,
+            ResponseItem::Url
+            | ResponseItem::Mime
+            | ResponseItem::Domain
+            | ResponseItem::Excerpt
+            | ResponseItem::FullContent
+            | ResponseItem::Trace
+            | ResponseItem::TriggerMatches
+            | ResponseItem::Engine
+            | ResponseItem::DocumentHash
+            | ResponseItem::Title
+            | ResponseItem::Metadata => false,
+        }
+    }
+}
+
+impl Response {
+    /// Compiles the response's `redactions`, returning an `Issue::Error`
+    /// if any of them is not a valid RegEx.
+    pub fn compile_redactions(&self) -> Result<Vec<Regex>, Issue> {
+        self.redactions
+            .iter()
+            .map(|expression| {
+                Regex::new(expression).map_err(|error| {
+                    Issue::Error(format!("redaction `{}` could not compile (`{}`)", expression, error))
+                })
+            })
+            .collect()
+    }
+
+    /// Prepares the given content for inclusion in an output: every
+    /// match of the given (compiled) redactions is replaced with
+    /// `REDACTION`, and the content is then truncated to the response's
+    /// `content_limit`, if any.
+    pub fn prepare_content(&self, content: &str, redactions: &[Regex]) -> String {
+        let mut prepared = String::from(content);
+        for redaction in redactions {
+            prepared = redaction.replace_all(&prepared, REDACTION).into_owned();
+        }
+        if let Some(limit) = self.content_limit {
+            if prepared.len() > limit {
+                let mut end = limit;
+                while !prepared.is_char_boundary(end) {
+                    end -= 1;
+                }
+                prepared.truncate(end);
+            }
+        }
+        prepared
+    }
 }
 
 impl Validatable for Response {
     /// Validates the Response and ensures that no invalid parameters 
     /// are present.
     /// 
-    /// More specifically, this function ensures that `Excerpt` and `Url`,
-    /// which are not reducable, are not present in `include`.
+    /// More specifically, this function ensures that partial responses
+    /// only include items that are reducible (see `ResponseItem::is_reducible()`),
+    /// such as `Count`, and have no `template`, whose messages cannot be
+    /// reduced.
     fn validate(&self) -> Option<Vec<Issue>> {
         let mut issues: Vec<Issue> = Vec::new();
         if self.kind == ResponseKind::Partial {
-            let disallowed_items = vec![ResponseItem::Excerpt, ResponseItem::Url];
             for item in &self.include {
-                if disallowed_items.contains(&item) {
+                if !item.is_reducible() {
                     issues.push(Issue::Error(format!(
                         "include `{:?}` is not allowed in partial responses",
                         item
                     )))
                 }
             }
+            if self.template.is_some() {
+                issues.push(Issue::Error(String::from(
+                    "templates are not allowed in partial responses",
+                )));
+            }
         }
-        if issues.len() == 0 {
-            return None;
+        if let Some(template) = &self.template {
+            for part in TemplatePart::parse(template) {
+                if let TemplatePart::Placeholder(name) = part {
+                    if !TEMPLATE_PLACEHOLDERS.contains(&name) && !name.starts_with("metadata.") {
+                        issues.push(Issue::Error(format!(
+                            "template placeholder `{{{}}}` is unknown (write `{{{{` for a literal brace)",
+                            name
+                        )))
+                    }
+                }
+            }
+        }
+        if issues.is_empty() {
+            None
         } else {
-            return Some(issues);
+            Some(issues)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_template() {
+        let text = |text: &str| TemplatePart::Text(String::from(text));
+        assert_eq!(
+            TemplatePart::parse("{query_id} matched {{domain}}: {excerpt}"),
+            vec![
+                TemplatePart::Placeholder("query_id"),
+                text(" matched {domain}: "),
+                TemplatePart::Placeholder("excerpt"),
+            ]
+        );
+        assert_eq!(
+            TemplatePart::parse("{{{metadata.crawl}}} {open {url}"),
+            vec![
+                text("{"),
+                TemplatePart::Placeholder("metadata.crawl"),
+                text("} {open "),
+                TemplatePart::Placeholder("url"),
+            ]
+        );
+        assert_eq!(TemplatePart::parse("} {metadata.source"), vec![text("} {metadata.source")]);
+        assert_eq!(TemplatePart::parse(""), vec![]);
+    }
+}
@@ -3,6 +3,20 @@
 use common::pattern::{CompiledPattern, Pattern};
 use common::compilation::CompilableTo;
 use common::validation::Issue;
+use common::checksum::Checksum;
+use common::timestamp::parse_timestamp;
+use input::document::{is_detectable_language, CompiledDocument};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref PUNCTUATION_REGEX: Regex = Regex::new(r"\p{P}+").unwrap();
+    static ref WHITESPACE_REGEX: Regex = Regex::new(r"\s+").unwrap();
+    static ref BOILERPLATE_REGEXES: Vec<Regex> = ["script", "style", "noscript", "nav", "header", "footer", "aside"]
+        .iter()
+        .map(|tag| Regex::new(&format!(r"(?is)<{0}(?:\s[^>]*)?>.*?</{0}\s*>", tag)).unwrap())
+        .collect();
+}
 
 /// A `Scope` describes the kind of data that will be passed
 /// to the queries, and which queries will be invoked.
@@ -14,18 +28,186 @@ pub struct Scope {
     /// The scope's `Pattern` is applied to each URL being passed
     /// through the scan engine. In order for a query to be run on
     /// a particular piece of data, the pattern must match
-    /// that data's URL.
+    /// that data's URL (or, depending on the `target`, its domain).
     pub pattern: Pattern,
+    /// Additional patterns, any of which may match the data's URL
+    /// instead of `pattern`. This is more maintainable (and faster)
+    /// than joining many patterns into a single giant alternation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Pattern>,
+    /// Patterns that exclude data from the scope: if any of them
+    /// matches the data's URL, the query will not be run on that data,
+    /// even if `pattern` (or one of `include`) matches. This takes the
+    /// place of negative lookaheads, which are not supported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<Pattern>,
     /// The content defines the type of content that the query's
     /// triggers will be run on. (Possible options include `Raw`
     /// and `Text`; for more information, see `ScopeContent`.)
-    pub content: ScopeContent
+    pub content: ScopeContent,
+    /// The target defines what the scope's `pattern` is applied to:
+    /// the document's full URL (the default) or only its domain. (For
+    /// more information, see `ScopeTarget`.)
+    #[serde(default, skip_serializing_if = "ScopeTarget::is_url")]
+    pub target: ScopeTarget,
+    /// The ISO 639-1 codes (such as `en` or `fr`) of the languages of
+    /// the documents that are in scope. When empty, documents in any
+    /// language (or whose language cannot be detected) are in scope;
+    /// otherwise, documents whose language cannot be detected are not.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub language: Vec<String>,
+    /// The fraction (between `0.0` and `1.0`) of in-scope documents on
+    /// which the query will run, if present. Sampling is deterministic:
+    /// whether a document is sampled depends only on its URL (or, when it
+    /// has none, its content), so that repeated scans—and different
+    /// queries with the same rate—sample the same documents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<f32>,
+    /// The window of time in which documents must have been published
+    /// or fetched (see `Document.timestamp`) to be in scope, if present.
+    /// Documents whose timestamp is unknown are not in scope.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<TimeWindow>,
+    /// Determines whether documents without a URL (or, when the `target`
+    /// is `Domain`, without a domain) are in scope—for example, in-memory
+    /// snippets scanned by library users. When `None`, the patterns are
+    /// matched against an empty string, so documents without a URL are
+    /// only in scope if the patterns match the empty string. (The other
+    /// constraints of the scope, such as its `language`, still apply.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_when_no_url: Option<NoUrlPolicy>,
+    /// The transforms that are applied, in order, to the `content` before
+    /// it is fed to the triggers. (For more information, see
+    /// `ContentTransform`.) Each distinct pipeline is applied only once per
+    /// document, so queries that share a pipeline share its cost.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<ContentTransform>,
+    /// A pattern that the source tag of documents (see `Document.source`)
+    /// must match for them to be in scope, if present—for example, the
+    /// `Glob` pattern `rss:*`. Documents without a source tag are not in
+    /// scope when it is present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Pattern>,
+    /// Whether documents whose robots directives restrict their use (with
+    /// `noindex` or `noarchive`; see `RobotsDirectives`) are excluded from
+    /// the scope.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub respect_robots: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Denotes a transformation of a document's content. (See `Scope.transforms`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum ContentTransform {
+    /// Converts the content to lowercase.
+    Lowercase,
+    /// Removes all punctuation from the content.
+    StripPunctuation,
+    /// Replaces every run of whitespace with a single space, and removes
+    /// leading and trailing whitespace.
+    CollapseWhitespace,
+    /// Removes boilerplate elements—scripts, styles, navigation, headers,
+    /// footers, and asides—from HTML documents. When the content is `Text`,
+    /// the text is extracted from the stripped HTML before any other
+    /// transform is applied. This transform has no effect on documents
+    /// that are not HTML, nor on contents other than `Raw` and `Text`.
+    StripBoilerplate,
+}
+
+impl ContentTransform {
+    /// Applies the transform to the given text. (`StripBoilerplate` is
+    /// applied to HTML markup; see `CompiledDocument::transformed_content()`.)
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            ContentTransform::Lowercase => text.to_lowercase(),
+            ContentTransform::StripPunctuation => PUNCTUATION_REGEX.replace_all(text, "").into_owned(),
+            ContentTransform::CollapseWhitespace => {
+                String::from(WHITESPACE_REGEX.replace_all(text, " ").trim())
+            }
+            ContentTransform::StripBoilerplate => {
+                let mut stripped = String::from(text);
+                for regex in BOILERPLATE_REGEXES.iter() {
+                    stripped = regex.replace_all(&stripped, " ").into_owned();
+                }
+                stripped
+            }
+        }
+    }
+}
+
+/// Represents whether documents without a URL are in scope. (See
+/// `Scope.match_when_no_url`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum NoUrlPolicy {
+    /// Documents without a URL are in scope.
+    Include,
+    /// Documents without a URL are not in scope.
+    Exclude,
+}
+
+/// A `TimeWindow` is a range of time. Its bounds are RFC 3339 timestamps,
+/// such as `2019-05-01T12:30:00Z`, or dates, such as `2019-05-01` (which
+/// denote their midnight, UTC).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TimeWindow {
+    /// The (inclusive) start of the window; when `None`, the window
+    /// has no start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    /// The (exclusive) end of the window; when `None`, the window has
+    /// no end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
 }
 
 #[derive(Clone)]
 pub struct CompiledScope {
     pub pattern: CompiledPattern,
-    pub content: ScopeContent
+    pub include: Vec<CompiledPattern>,
+    pub exclude: Vec<CompiledPattern>,
+    pub content: ScopeContent,
+    pub target: ScopeTarget,
+    pub language: Vec<String>,
+    pub sample: Option<f32>,
+    /// The start and end of the `window`, in seconds since the Unix
+    /// epoch, if present.
+    pub window: Option<(Option<i64>, Option<i64>)>,
+    pub match_when_no_url: Option<NoUrlPolicy>,
+    pub transforms: Vec<ContentTransform>,
+    pub source: Option<CompiledPattern>,
+    pub respect_robots: bool,
+    /// A key that is identical for scopes that contain the same
+    /// documents (regardless of their `content`), which allows the scan
+    /// engine to evaluate such scopes only once per document.
+    pub key: String,
+}
+
+/// Denotes the part of a document's address that a scope's pattern
+/// is matched against.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ScopeTarget {
+    /// The full URL of the document, such as
+    /// `https://example.com/news/today.html`. This is the default.
+    #[default]
+    Url,
+    /// Only the domain of the document, such as `example.com`. (For
+    /// more information, see `CompiledDocument.domain`.)
+    ///
+    /// Pinning a domain with a `Url` pattern is error-prone, as the
+    /// domain may also appear in the path or query string of another
+    /// site's URL; the pattern `^(www\.)?example\.com$` on the `Domain`
+    /// does not have this problem.
+    Domain,
+}
+
+impl ScopeTarget {
+    /// Whether the target is `Url`, the default.
+    pub fn is_url(&self) -> bool {
+        *self == ScopeTarget::Url
+    }
 }
 
 /// Denotes a form of text data to be passed to the query.
@@ -37,17 +219,236 @@ pub enum ScopeContent {
     /// Intelligently extracted text from the document. For HTML
     /// documents, for example, the `Text` is found by passing the
     /// content through an HTML engine and extracting _all_ the text.AsMut
+    /// For PDF documents, it is the text of their pages (when the `pdf`
+    /// feature is enabled; otherwise, it is empty). For JSON documents, it
+    /// is their string values, one per line. (See `Fields`.)
     /// 
     /// Note that sometimes JavaScript text is also included.
-    Text
+    Text,
+    /// The original bytes of the document, which may not be valid
+    /// text. This is useful for detecting file signatures or embedded
+    /// payloads using `Bytes` patterns; other patterns are matched
+    /// against the `Raw` data instead.
+    Bytes,
+    /// The title of the document. (For this and the other structural
+    /// contents, see `DocumentStructure`; they are only available for
+    /// HTML documents, and are empty otherwise.)
+    Title,
+    /// The headings of the document, one per line.
+    Headings,
+    /// The links of the document, one per line, each of the form
+    /// `href text`.
+    Links,
+    /// The distinct targets of the document's links, one absolute URL
+    /// per line, with relative links resolved against the document's URL.
+    /// (See `DocumentStructure::link_targets`.)
+    LinkTargets,
+    /// The metadata of the document, one `<meta>` tag per line, each
+    /// of the form `name: content`.
+    Metadata,
+    /// The paragraphs (`<p>` elements) of the document, separated by
+    /// blank lines. (See `TriggerPosition::Paragraph`.)
+    Paragraphs,
+    /// The string values of a JSON document, one per line, each of the
+    /// form `key: value`, where the key is the dotted path of the value
+    /// (such as `items.0.title`). Unlike `Text`, which only has the values,
+    /// this allows triggers to match a particular field. Empty for other
+    /// documents.
+    Fields,
+    /// The main content of an HTML page, such as the body of an article,
+    /// without its navigation, advertisements, comments, and footers,
+    /// one block of text per line. Boilerplate is a common source of
+    /// false positives, such as a headline in a "related stories" box.
+    /// It is only extracted for documents that a query scans with this
+    /// content. (See `input::readability`.) For other documents, it is
+    /// the same as `Text`.
+    MainContent,
+}
+
+impl Scope {
+    /// Returns every pattern of the scope: `pattern`, followed by the
+    /// patterns of `include` and `exclude`, and the `source` pattern.
+    pub fn patterns(&self) -> impl Iterator<Item = &Pattern> {
+        Some(&self.pattern)
+            .into_iter()
+            .chain(self.include.iter())
+            .chain(self.exclude.iter())
+            .chain(self.source.iter())
+    }
+}
+
+impl CompiledScope {
+    /// The compiled patterns of the scope, like `Scope::patterns()`.
+    pub fn patterns(&self) -> impl Iterator<Item = &CompiledPattern> {
+        Some(&self.pattern)
+            .into_iter()
+            .chain(self.include.iter())
+            .chain(self.exclude.iter())
+            .chain(self.source.iter())
+    }
+
+    /// Determines whether the given document is in scope, considering
+    /// every constraint of the scope.
+    pub fn is_in_scope(&self, document: &CompiledDocument) -> bool {
+        let address = match self.target {
+            ScopeTarget::Url => &document.url,
+            ScopeTarget::Domain => &document.domain,
+        };
+        let is_address_in_scope = match (address, self.match_when_no_url) {
+            (Some(value), _) => self.matches(value),
+            (None, Some(NoUrlPolicy::Include)) => true,
+            (None, Some(NoUrlPolicy::Exclude)) => false,
+            (None, None) => self.matches(""),
+        };
+        is_address_in_scope
+            && self.matches_language(document.language.as_deref())
+            && self.matches_window(document.timestamp)
+            && self.matches_source(document.source.as_deref())
+            && !(self.respect_robots && document.robots.is_restricted())
+            && self.matches_sample(document)
+    }
+
+    /// Determines whether the given address (a URL or domain, depending
+    /// on the `target`) is in scope: that is, whether `pattern` or any
+    /// of `include` matches it, and none of `exclude` does.
+    pub fn matches(&self, address: &str) -> bool {
+        let included = self.pattern.quick_check(address)
+            || self.include.iter().any(|pattern| pattern.quick_check(address));
+        included && !self.exclude.iter().any(|pattern| pattern.quick_check(address))
+    }
+
+    /// Determines whether a document in the given language (see
+    /// `CompiledDocument.language`) is in scope.
+    pub fn matches_language(&self, language: Option<&str>) -> bool {
+        if self.language.is_empty() {
+            return true;
+        }
+        match language {
+            Some(code) => self.language.iter().any(|allowed| allowed == code),
+            None => false,
+        }
+    }
+
+    /// Determines whether a document with the given source tag (see
+    /// `CompiledDocument.source`) is in scope.
+    pub fn matches_source(&self, source: Option<&str>) -> bool {
+        match (&self.source, source) {
+            (None, _) => true,
+            (Some(pattern), Some(tag)) => pattern.quick_check(tag),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Determines whether a document with the given timestamp (see
+    /// `CompiledDocument.timestamp`) is within the scope's `window`.
+    pub fn matches_window(&self, timestamp: Option<i64>) -> bool {
+        let (after, before) = match self.window {
+            Some(bounds) => bounds,
+            None => return true,
+        };
+        match timestamp {
+            Some(time) => {
+                after.is_none_or(|start| time >= start) && before.is_none_or(|end| time < end)
+            }
+            None => false,
+        }
+    }
+
+    /// Determines whether the given document is part of the scope's
+    /// sample. (See `Scope.sample`.)
+    pub fn matches_sample(&self, document: &CompiledDocument) -> bool {
+        let rate = match self.sample {
+            Some(value) => f64::from(value),
+            None => return true,
+        };
+        let position = match &document.url {
+            Some(url) => {
+                let mut checksum = Checksum::new();
+                checksum.update(url.as_bytes());
+                checksum.value()
+            }
+            None => document.checksum(),
+        };
+        // mix the bits of the checksum (as in SplitMix64), whose high bits
+        // vary little between similar URLs, then scale it into [0, 1)
+        let mut mixed = position;
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        mixed ^= mixed >> 31;
+        let fraction = (mixed >> 11) as f64 / (1_u64 << 53) as f64;
+        fraction < rate
+    }
 }
 
 impl CompilableTo<CompiledScope> for Scope {
     fn compile(&self) -> Result<CompiledScope, Issue> {
+        let compile_all = |patterns: &Vec<Pattern>| -> Result<Vec<CompiledPattern>, Issue> {
+            patterns.iter().map(|pattern| pattern.compile()).collect()
+        };
+        let mut language: Vec<String> = Vec::new();
+        for code in &self.language {
+            let code = code.trim().to_lowercase();
+            if !is_detectable_language(&code) {
+                return Err(Issue::Error(format!(
+                    "scope language `{}` is not a detectable ISO 639-1 language code",
+                    code
+                )));
+            }
+            language.push(code);
+        }
+        if let Some(rate) = self.sample {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(Issue::Error(format!(
+                    "scope sample rate `{}` must be between 0 and 1",
+                    rate
+                )));
+            }
+        }
+        let window = match &self.window {
+            Some(window) => {
+                let parse = |bound: &Option<String>| -> Result<Option<i64>, Issue> {
+                    match bound {
+                        Some(value) => Ok(Some(parse_timestamp(value)?)),
+                        None => Ok(None),
+                    }
+                };
+                Some((parse(&window.after)?, parse(&window.before)?))
+            }
+            None => None,
+        };
+        let key = format!(
+            "{:?}",
+            (
+                &self.pattern,
+                &self.include,
+                &self.exclude,
+                self.target,
+                &self.language,
+                self.sample.map(f32::to_bits),
+                &self.window,
+                self.match_when_no_url,
+                &self.source,
+                self.respect_robots,
+            )
+        );
         match self.pattern.compile() {
             Ok(compiled_pattern) => Ok(CompiledScope {
                 pattern: compiled_pattern,
+                include: compile_all(&self.include)?,
+                exclude: compile_all(&self.exclude)?,
                 content: self.content,
+                target: self.target,
+                language,
+                sample: self.sample,
+                window,
+                match_when_no_url: self.match_when_no_url,
+                transforms: self.transforms.clone(),
+                source: match &self.source {
+                    Some(pattern) => Some(pattern.compile()?),
+                    None => None,
+                },
+                respect_robots: self.respect_robots,
+                key,
             }),
             Err(issue) => Err(issue)
         }
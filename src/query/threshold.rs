@@ -1,8 +1,12 @@
 //! This document provides functionality related to
 //! thresholds.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
 use common::validation::Issue;
+use query::scope::ScopeContent;
+use query::trigger::Trigger;
 
 /// The `Threshold` struct allows for the boolean output of
 /// triggers to be composed so that only certain combinations
@@ -10,44 +14,490 @@ use common::validation::Issue;
 /// 
 /// You can think of the `Threshold` as a boolean expression
 /// that defines when a query matches.
+///
+/// Instead of `considers`, `requires`, and `inverse`, a threshold
+/// may also be written as an `expression`—for example,
+/// `(expression: All([Trigger("A"), Any([Trigger("B"), Trigger("C")])]))`—
+/// which is converted into this form. (See `ThresholdExpression`.)
+///
+/// A threshold can also be _scored_ rather than evaluated (see
+/// `Threshold::score()`), which yields the weighted fraction of its
+/// considerations that are satisfied.
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(try_from = "ThresholdSource")]
 pub struct Threshold {
     pub considers: Vec<ThresholdConsideration>,
     pub requires: usize,
-    pub inverse: bool
+    pub inverse: bool,
+    /// The content that the triggers of this threshold (and of its
+    /// nested thresholds, unless they specify their own) are evaluated
+    /// against, overriding the `ScopeContent` of the query's scope. This
+    /// allows, for example, "keyword in the title and keyword in the
+    /// body" within a single query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<ScopeContent>,
 }
 
 /// A consideration by the threshold that evaluates to
 /// either `true` or `false`. This can be a `Trigger`
-/// identified by its `id`, or a `NestedThreshold`.
+/// identified by its `id`, a `TriggerCount`, a
+/// `NestedThreshold`, a `Named` threshold, a family of
+/// triggers identified by their `Tag`, or the negation
+/// or weighting of any of these.
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum ThresholdConsideration {
     /// Refers to a Trigger in the query by its ID.
     Trigger(String),
+    /// Refers to a Trigger in the query by its ID, and
+    /// requires it to match at least the given number of
+    /// times in the document. (A negated trigger counts
+    /// as a single match when it fires.)
+    TriggerCount(String, usize),
     /// Contains a Threshold which will then itself
     /// be independently evaluated by the scan engine.
     NestedThreshold(Threshold),
+    /// Refers to a threshold defined by name in the
+    /// `QueryGroup`, which is substituted for this
+    /// consideration when the group is compiled. (See
+    /// `Threshold::resolve()`.)
+    Named(String),
+    /// Is satisfied when the given consideration is not,
+    /// such as `Negated(Trigger("B"))`. Unlike an inverse
+    /// threshold, the other considerations of the threshold
+    /// are unaffected.
+    Negated(Box<ThresholdConsideration>),
+    /// Gives the consideration the given weight when the
+    /// threshold is scored, such as `Weighted(2.0, Trigger("A"))`.
+    /// (Unweighted considerations have a weight of `1.0`.) The
+    /// weight has no effect on `Threshold::evaluate()`.
+    Weighted(f32, Box<ThresholdConsideration>),
+    /// Is satisfied when any of the query's triggers with
+    /// the given tag fires, such as `Tag("greeting")`. Tags
+    /// are expanded into their triggers when the query is
+    /// compiled. (See `Threshold::with_tags_expanded()`.)
+    Tag(String),
+    /// Is satisfied when at least the given number of the
+    /// query's triggers with the given tag fire, such as
+    /// `AtLeastTagged(2, "greeting")`.
+    AtLeastTagged(usize, String),
+}
+
+/// A `ThresholdExpression` is an alternative representation of a
+/// `Threshold` that uses explicit boolean combinators, which are easier
+/// to author (and to read) than `requires` and `inverse`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ThresholdExpression {
+    /// Refers to a Trigger in the query by its ID.
+    Trigger(String),
+    /// Refers to a Trigger in the query by its ID, and requires it
+    /// to match at least the given number of times.
+    TriggerCount(String, usize),
+    /// Matches when all of the expressions match.
+    All(Vec<ThresholdExpression>),
+    /// Matches when any of the expressions match.
+    Any(Vec<ThresholdExpression>),
+    /// Matches when none of the expressions match.
+    None(Vec<ThresholdExpression>),
+    /// Matches when at least the given number of the expressions match.
+    AtLeast(usize, Vec<ThresholdExpression>),
+    /// Matches when the expression does not match.
+    Not(Box<ThresholdExpression>),
+    /// Refers to a threshold defined by name in the `QueryGroup`.
+    Named(String),
+    /// Matches when the expression matches, with its triggers evaluated
+    /// against the given content. (See `Threshold::content`.)
+    Within(ScopeContent, Box<ThresholdExpression>),
+    /// Matches when the expression matches, and gives it the given
+    /// weight when the threshold is scored. (See `Threshold::score()`.)
+    Weighted(f32, Box<ThresholdExpression>),
+    /// Matches when any of the triggers with the given tag match.
+    Tag(String),
+    /// Matches when at least the given number of the triggers with
+    /// the given tag match.
+    AtLeastTagged(usize, String),
+}
+
+/// The serialized form of a `Threshold`, which is either the `considers`,
+/// `requires`, and `inverse` form, or an `expression`.
+///
+/// Optional fields must be written as `Some(...)` in RON, which would
+/// break existing thresholds, so absent fields are detected by their
+/// defaults instead.
+#[derive(Deserialize)]
+struct ThresholdSource {
+    #[serde(default)]
+    considers: Vec<ThresholdConsideration>,
+    #[serde(default = "absent_requires")]
+    requires: usize,
+    #[serde(default)]
+    inverse: bool,
+    #[serde(default)]
+    expression: Option<ThresholdExpression>,
+    #[serde(default)]
+    content: Option<ScopeContent>,
+}
+
+/// The value of `requires` when it is absent from a `ThresholdSource`.
+fn absent_requires() -> usize {
+    usize::MAX
+}
+
+impl TryFrom<ThresholdSource> for Threshold {
+    type Error = String;
+
+    fn try_from(source: ThresholdSource) -> Result<Threshold, String> {
+        match source.expression {
+            Some(expression) => {
+                if !source.considers.is_empty()
+                    || source.requires != absent_requires()
+                    || source.inverse
+                {
+                    return Err(String::from(
+                        "a threshold with an `expression` cannot also have `considers`, `requires`, or `inverse`",
+                    ));
+                }
+                let mut threshold = Threshold::from(expression);
+                if source.content.is_some() {
+                    threshold.content = source.content;
+                }
+                Ok(threshold)
+            }
+            None => {
+                if source.requires == absent_requires() {
+                    return Err(String::from(
+                        "a threshold must have either `requires` (and `considers`) or an `expression`",
+                    ));
+                }
+                Ok(Threshold {
+                    considers: source.considers,
+                    requires: source.requires,
+                    inverse: source.inverse,
+                    content: source.content,
+                })
+            }
+        }
+    }
+}
+
+impl ThresholdExpression {
+    /// Converts the expression into a consideration of a `Threshold`.
+    fn into_consideration(self) -> ThresholdConsideration {
+        match self {
+            ThresholdExpression::Trigger(id) => ThresholdConsideration::Trigger(id),
+            ThresholdExpression::TriggerCount(id, count) => {
+                ThresholdConsideration::TriggerCount(id, count)
+            }
+            ThresholdExpression::Named(name) => ThresholdConsideration::Named(name),
+            ThresholdExpression::Tag(tag) => ThresholdConsideration::Tag(tag),
+            ThresholdExpression::AtLeastTagged(requires, tag) => {
+                ThresholdConsideration::AtLeastTagged(requires, tag)
+            }
+            ThresholdExpression::Not(expression) => {
+                ThresholdConsideration::Negated(Box::new(expression.into_consideration()))
+            }
+            ThresholdExpression::Weighted(weight, expression) => {
+                ThresholdConsideration::Weighted(weight, Box::new(expression.into_consideration()))
+            }
+            other => ThresholdConsideration::NestedThreshold(Threshold::from(other)),
+        }
+    }
+}
+
+impl From<ThresholdExpression> for Threshold {
+    fn from(expression: ThresholdExpression) -> Threshold {
+        let considering = |expressions: Vec<ThresholdExpression>| -> Vec<ThresholdConsideration> {
+            expressions
+                .into_iter()
+                .map(ThresholdExpression::into_consideration)
+                .collect()
+        };
+        match expression {
+            ThresholdExpression::Trigger(_)
+            | ThresholdExpression::TriggerCount(_, _)
+            | ThresholdExpression::Named(_)
+            | ThresholdExpression::Weighted(_, _)
+            | ThresholdExpression::Tag(_)
+            | ThresholdExpression::AtLeastTagged(_, _) => Threshold {
+                considers: vec![expression.into_consideration()],
+                requires: 1,
+                inverse: false,
+                content: None,
+            },
+            ThresholdExpression::All(expressions) => Threshold {
+                requires: expressions.len(),
+                considers: considering(expressions),
+                inverse: false,
+                content: None,
+            },
+            ThresholdExpression::Any(expressions) => Threshold {
+                considers: considering(expressions),
+                requires: 1,
+                inverse: false,
+                content: None,
+            },
+            ThresholdExpression::None(expressions) => Threshold {
+                considers: considering(expressions),
+                requires: 1,
+                inverse: true,
+                content: None,
+            },
+            ThresholdExpression::AtLeast(requires, expressions) => Threshold {
+                considers: considering(expressions),
+                requires,
+                inverse: false,
+                content: None,
+            },
+            ThresholdExpression::Within(content, expression) => Threshold {
+                content: Some(content),
+                ..Threshold::from(*expression)
+            },
+            ThresholdExpression::Not(expression) => Threshold {
+                considers: vec![expression.into_consideration()],
+                requires: 1,
+                inverse: true,
+                content: None,
+            },
+        }
+    }
+}
+
+impl<'a> From<&'a Threshold> for ThresholdExpression {
+    fn from(threshold: &'a Threshold) -> ThresholdExpression {
+        let expressions: Vec<ThresholdExpression> = threshold
+            .considers
+            .iter()
+            .map(ThresholdExpression::from)
+            .collect();
+        let is_none = threshold.inverse && threshold.requires == 1 && expressions.len() > 1;
+        let expression = if is_none {
+            ThresholdExpression::None(expressions)
+        } else if expressions.len() == 1 && threshold.requires == 1 {
+            expressions.into_iter().next().unwrap() // a single expression is its own `Any`
+        } else if threshold.requires == 1 {
+            ThresholdExpression::Any(expressions)
+        } else if threshold.requires == expressions.len() {
+            ThresholdExpression::All(expressions)
+        } else {
+            ThresholdExpression::AtLeast(threshold.requires, expressions)
+        };
+        let expression = match threshold.inverse && !is_none {
+            true => ThresholdExpression::Not(Box::new(expression)),
+            false => expression,
+        };
+        match threshold.content {
+            Some(content) => ThresholdExpression::Within(content, Box::new(expression)),
+            None => expression,
+        }
+    }
+}
+
+impl<'a> From<&'a ThresholdConsideration> for ThresholdExpression {
+    fn from(consideration: &'a ThresholdConsideration) -> ThresholdExpression {
+        match consideration {
+            ThresholdConsideration::Trigger(id) => ThresholdExpression::Trigger(id.clone()),
+            ThresholdConsideration::TriggerCount(id, count) => {
+                ThresholdExpression::TriggerCount(id.clone(), *count)
+            }
+            ThresholdConsideration::NestedThreshold(nested) => ThresholdExpression::from(nested),
+            ThresholdConsideration::Named(name) => ThresholdExpression::Named(name.clone()),
+            ThresholdConsideration::Negated(consideration) => {
+                ThresholdExpression::Not(Box::new(ThresholdExpression::from(&**consideration)))
+            }
+            ThresholdConsideration::Weighted(weight, consideration) => ThresholdExpression::Weighted(
+                *weight,
+                Box::new(ThresholdExpression::from(&**consideration)),
+            ),
+            ThresholdConsideration::Tag(tag) => ThresholdExpression::Tag(tag.clone()),
+            ThresholdConsideration::AtLeastTagged(requires, tag) => {
+                ThresholdExpression::AtLeastTagged(*requires, tag.clone())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ThresholdExpression {
+    /// Explains the expression in human-readable form, such as
+    /// ``all of (`A`, any of (`B`, `C`))``.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |expressions: &Vec<ThresholdExpression>| -> String {
+            expressions
+                .iter()
+                .map(|expression| expression.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match self {
+            ThresholdExpression::Trigger(id) => write!(f, "`{}`", id),
+            ThresholdExpression::TriggerCount(id, count) => {
+                write!(f, "`{}` at least {} times", id, count)
+            }
+            ThresholdExpression::All(expressions) => write!(f, "all of ({})", list(expressions)),
+            ThresholdExpression::Any(expressions) => write!(f, "any of ({})", list(expressions)),
+            ThresholdExpression::None(expressions) => write!(f, "none of ({})", list(expressions)),
+            ThresholdExpression::AtLeast(requires, expressions) => {
+                write!(f, "at least {} of ({})", requires, list(expressions))
+            }
+            ThresholdExpression::Not(expression) => write!(f, "not {}", expression),
+            ThresholdExpression::Named(name) => write!(f, "threshold `{}`", name),
+            ThresholdExpression::Within(content, expression) => {
+                write!(f, "{} (in {:?})", expression, content)
+            }
+            ThresholdExpression::Weighted(weight, expression) => {
+                write!(f, "{} (weight {})", expression, weight)
+            }
+            ThresholdExpression::Tag(tag) => write!(f, "any trigger tagged `{}`", tag),
+            ThresholdExpression::AtLeastTagged(requires, tag) => {
+                write!(f, "at least {} triggers tagged `{}`", requires, tag)
+            }
+        }
+    }
+}
+
+/// A `ThresholdTrace` records how a `Threshold` was evaluated: which
+/// of its considerations were satisfied, and how the final verdict was
+/// reached. It is produced by `Threshold::explain()`, and can be
+/// attached to an `Output` using `ResponseItem::Trace`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ThresholdTrace {
+    /// The final verdict of the threshold (after `inverse` is applied).
+    pub matched: bool,
+    /// The number of considerations that were satisfied.
+    pub satisfied: usize,
+    /// The `requires` of the threshold.
+    pub requires: usize,
+    /// The `inverse` of the threshold.
+    pub inverse: bool,
+    /// The traces of the threshold's considerations, in order.
+    pub considers: Vec<ConsiderationTrace>,
+}
+
+/// Records how a single `ThresholdConsideration` was evaluated.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum ConsiderationTrace {
+    /// A `Trigger` or `TriggerCount` consideration.
+    Trigger {
+        /// The ID of the trigger.
+        id: String,
+        /// The number of times the trigger matched.
+        matches: usize,
+        /// The number of matches that the consideration required
+        /// (`1` for a plain `Trigger`).
+        requires: usize,
+        /// Whether the consideration was satisfied.
+        satisfied: bool,
+    },
+    /// A `NestedThreshold` consideration.
+    NestedThreshold(ThresholdTrace),
+    /// A `Negated` consideration, which is satisfied when the given
+    /// consideration is not.
+    Negated(Box<ConsiderationTrace>),
+}
+
+impl ConsiderationTrace {
+    /// Whether the consideration was satisfied.
+    pub fn is_satisfied(&self) -> bool {
+        match self {
+            ConsiderationTrace::Trigger { satisfied, .. } => *satisfied,
+            ConsiderationTrace::NestedThreshold(trace) => trace.matched,
+            ConsiderationTrace::Negated(trace) => !trace.is_satisfied(),
+        }
+    }
+}
+
+impl ThresholdTrace {
+    /// Writes the trace, indented by the given depth.
+    fn write_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{}{} ({} of {} satisfied, {} required{})",
+            "  ".repeat(depth),
+            if self.matched { "matched" } else { "did not match" },
+            self.satisfied,
+            self.considers.len(),
+            self.requires,
+            if self.inverse { ", inverted" } else { "" }
+        )?;
+        for consideration in &self.considers {
+            consideration.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConsiderationTrace {
+    /// Writes the trace, indented by the given depth.
+    fn write_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        match self {
+            ConsiderationTrace::Trigger { id, matches, requires, satisfied } => writeln!(
+                f,
+                "{}[{}] `{}` matched {} times ({} required)",
+                "  ".repeat(depth),
+                if *satisfied { "x" } else { " " },
+                id,
+                matches,
+                requires
+            ),
+            ConsiderationTrace::NestedThreshold(trace) => trace.write_indented(f, depth),
+            ConsiderationTrace::Negated(trace) => {
+                writeln!(
+                    f,
+                    "{}[{}] not:",
+                    "  ".repeat(depth),
+                    if self.is_satisfied() { "x" } else { " " }
+                )?;
+                trace.write_indented(f, depth + 1)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ThresholdTrace {
+    /// Renders the trace as an indented tree, one consideration per line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
 }
 
 impl Threshold {
+    /// Describes the threshold in human-readable form, such as
+    /// ``all of (`A`, any of (`B`, `C`))``. (See `ThresholdExpression`.)
+    pub fn describe(&self) -> String {
+        ThresholdExpression::from(self).to_string()
+    }
+
+    /// Evaluates the threshold like `evaluate()`, but returns a
+    /// `ThresholdTrace` of the evaluation rather than only its verdict.
+    /// This is slower than `evaluate()`, and is meant for debugging.
+    ///
+    /// # Arguments
+    /// * triggers: a `HashMap` where the keys are Trigger IDs and the values are the number of times they matched
+    pub fn explain(&self, triggers: &HashMap<&String, usize>) -> Result<ThresholdTrace, Issue> {
+        let mut considers: Vec<ConsiderationTrace> = Vec::new();
+        for consideration in &self.considers {
+            considers.push(consideration.explain(triggers)?);
+        }
+        let satisfied = considers
+            .iter()
+            .filter(|consideration| consideration.is_satisfied())
+            .count();
+        Ok(ThresholdTrace {
+            matched: (satisfied >= self.requires) != self.inverse,
+            satisfied,
+            requires: self.requires,
+            inverse: self.inverse,
+            considers,
+        })
+    }
+
     /// Evaluates the threshold based on the given data.
     /// 
     /// # Arguments
-    /// * triggers: a `HashMap` where the keys are Trigger IDs and the values are whether they matched or not
-    pub fn evaluate(&self, triggers: &HashMap<&String, bool>) -> Result<bool, Issue> {
+    /// * triggers: a `HashMap` where the keys are Trigger IDs and the values are the number of times they matched
+    pub fn evaluate(&self, triggers: &HashMap<&String, usize>) -> Result<bool, Issue> {
         let mut matched = 0;
         
         for consideration in &self.considers {
-            if match consideration {
-                ThresholdConsideration::Trigger(id) => match triggers.get(id) {
-                    Some(res) => *res,
-                    None => return Err(Issue::Error(format!("unable to find trigger `{}` in given triggers", id)))
-                },
-                ThresholdConsideration::NestedThreshold(threshold) => match threshold.evaluate(triggers) {
-                    Ok(res) => res,
-                    Err(issue) => return Err(issue)
-                }
-            } {
+            if consideration.evaluate(triggers)? {
                 matched += 1;
             }
         }
@@ -60,4 +510,884 @@ impl Threshold {
 
         Ok(does_match)
     }
-}
\ No newline at end of file
+
+    /// Scores the threshold based on the given data: rather than a strict
+    /// verdict, the score is the fraction of the considerations that are
+    /// satisfied, weighted by their `Weighted` weights, from `0.0` to
+    /// `1.0`. `requires` is not used; a nested threshold contributes its
+    /// own score, a negated consideration the complement of its score,
+    /// and an inverse threshold the complement of its score. A threshold
+    /// without any considerations scores `0.0` (or, when inverse, `1.0`).
+    ///
+    /// # Arguments
+    /// * triggers: a `HashMap` where the keys are Trigger IDs and the values are the number of times they matched
+    pub fn score(&self, triggers: &HashMap<&String, usize>) -> Result<f32, Issue> {
+        self.score_with(&mut |id, _| count(triggers, id))
+    }
+
+    /// Scores the threshold like `score()`, but requests the match counts
+    /// of triggers from the given function (see `evaluate_lazily()`). As
+    /// every consideration contributes to the score, every trigger that
+    /// the threshold considers is requested.
+    pub fn score_with<F>(&self, count: &mut F) -> Result<f32, Issue>
+    where
+        F: FnMut(&String, usize) -> Result<usize, Issue>,
+    {
+        let mut satisfied = 0.0;
+        let mut total = 0.0;
+        for consideration in &self.considers {
+            let weight = consideration.weight();
+            satisfied += weight * consideration.score_with(count)?;
+            total += weight;
+        }
+        let score = if total > 0.0 { satisfied / total } else { 0.0 };
+        Ok(if self.inverse { 1.0 - score } else { score })
+    }
+
+    /// Evaluates the threshold like `evaluate()`, but only requests the
+    /// match counts of triggers as they are needed: considerations are
+    /// evaluated in order, and evaluation stops as soon as the outcome
+    /// of the threshold is decided. The scan engine uses this to avoid
+    /// running triggers whose results cannot change the outcome.
+    ///
+    /// # Arguments
+    /// * count: a function that returns the number of times the trigger
+    ///   with the given ID matched. It is also given the number of
+    ///   matches the consideration requires, so it may stop counting
+    ///   once that number is reached.
+    pub fn evaluate_lazily<F>(&self, count: &mut F) -> Result<bool, Issue>
+    where
+        F: FnMut(&String, usize) -> Result<usize, Issue>,
+    {
+        let mut matched = 0;
+        let mut remaining = self.considers.len();
+
+        for consideration in &self.considers {
+            if matched >= self.requires || matched + remaining < self.requires {
+                break; // the outcome is decided
+            }
+            remaining -= 1;
+            if consideration.evaluate_lazily(count)? {
+                matched += 1;
+            }
+        }
+
+        Ok((matched >= self.requires) != self.inverse)
+    }
+
+    /// Returns an equivalent threshold whose considerations (and those
+    /// of its nested thresholds) are ordered from cheapest to most
+    /// expensive to evaluate, so that `evaluate_lazily()` tends to
+    /// decide the outcome before the expensive triggers are run. The
+    /// cost of a nested threshold is the total cost of its triggers.
+    ///
+    /// # Arguments
+    /// * cost: a function that estimates the cost of running the trigger
+    ///   with the given ID
+    pub fn ordered_by_cost<F>(&self, cost: &F) -> Threshold
+    where
+        F: Fn(&String) -> usize,
+    {
+        let mut considers: Vec<(usize, ThresholdConsideration)> = self
+            .considers
+            .iter()
+            .map(|consideration| {
+                let ordered = consideration.ordered_by_cost(cost);
+                (ordered.total_cost(cost), ordered)
+            })
+            .collect();
+        considers.sort_by_key(|(cost, _)| *cost); // stable
+        Threshold {
+            considers: considers.into_iter().map(|(_, consideration)| consideration).collect(),
+            requires: self.requires,
+            inverse: self.inverse,
+            content: self.content,
+        }
+    }
+
+    /// Returns the threshold with the trigger IDs of every consideration
+    /// that is evaluated against a content other than the given (default)
+    /// content—because it is within a threshold that specifies its own
+    /// `content`—replaced by an alias of the form `id@Content`, such as
+    /// `A@Title`. Each alias is recorded (once) in `aliases` as a tuple
+    /// of the alias, the original trigger ID, and the content.
+    ///
+    /// The scan engine evaluates each alias as a copy of its trigger,
+    /// so that the same trigger can be evaluated against several
+    /// contents within a single threshold.
+    pub fn with_content_aliases(
+        &self,
+        content: ScopeContent,
+        aliases: &mut Vec<(String, String, ScopeContent)>,
+    ) -> Threshold {
+        self.alias_within(content, content, aliases)
+    }
+
+    /// Aliases the triggers of the threshold (see `with_content_aliases()`),
+    /// given the default content and the content of the enclosing threshold.
+    fn alias_within(
+        &self,
+        default: ScopeContent,
+        enclosing: ScopeContent,
+        aliases: &mut Vec<(String, String, ScopeContent)>,
+    ) -> Threshold {
+        let content = self.content.unwrap_or(enclosing);
+        Threshold {
+            considers: self
+                .considers
+                .iter()
+                .map(|consideration| consideration.alias_within(default, content, aliases))
+                .collect(),
+            requires: self.requires,
+            inverse: self.inverse,
+            content: self.content,
+        }
+    }
+
+    /// Returns the threshold with every `Named` consideration (including
+    /// those of nested and named thresholds) replaced by the threshold of
+    /// that name, as a `NestedThreshold`.
+    ///
+    /// An `Issue::Error` is returned when a name is not defined, or when
+    /// a named threshold refers to itself (directly or indirectly).
+    ///
+    /// # Arguments
+    /// * named: the named thresholds, typically of a `QueryGroup`
+    pub fn resolve(&self, named: &BTreeMap<String, Threshold>) -> Result<Threshold, Issue> {
+        self.resolve_within(named, &mut Vec::new())
+    }
+
+    /// Resolves the threshold (see `resolve()`), given the names of the
+    /// named thresholds that are currently being resolved.
+    fn resolve_within<'a>(
+        &self,
+        named: &'a BTreeMap<String, Threshold>,
+        resolving: &mut Vec<&'a String>,
+    ) -> Result<Threshold, Issue> {
+        let mut considers: Vec<ThresholdConsideration> = Vec::new();
+        for consideration in &self.considers {
+            considers.push(consideration.resolve_within(named, resolving)?);
+        }
+        Ok(Threshold {
+            considers,
+            requires: self.requires,
+            inverse: self.inverse,
+            content: self.content,
+        })
+    }
+
+    /// Returns the threshold with every `Tag` and `AtLeastTagged`
+    /// consideration (including those of nested thresholds) replaced by
+    /// a `NestedThreshold` that considers each of the given triggers with
+    /// that tag, and requires one of them (or the given number of them).
+    ///
+    /// An `Issue::Error` is returned when no trigger has a given tag.
+    ///
+    /// # Arguments
+    /// * triggers: the triggers of the query
+    pub fn with_tags_expanded(&self, triggers: &[Trigger]) -> Result<Threshold, Issue> {
+        let mut considers: Vec<ThresholdConsideration> = Vec::new();
+        for consideration in &self.considers {
+            considers.push(consideration.expand_tags(triggers)?);
+        }
+        Ok(Threshold {
+            considers,
+            requires: self.requires,
+            inverse: self.inverse,
+            content: self.content,
+        })
+    }
+
+    /// Statically analyzes the threshold (and its nested thresholds) for
+    /// problems that would otherwise only surface at scan time, given
+    /// the IDs of the triggers that are defined. Each issue is prefixed
+    /// with the path of the offending threshold, such as
+    /// `threshold.considers[1]`:
+    ///
+    /// * an `Error` for each consideration of an undefined trigger
+    /// * an `Error` for each weight that is not a positive number
+    /// * an `Error` for each threshold that requires more considerations
+    ///   than it has, and therefore can never be met (or, when inverse,
+    ///   a `Warning`, as it is always met)
+    pub fn analyze(&self, triggers: &HashSet<&String>) -> Vec<Issue> {
+        let mut issues: Vec<Issue> = Vec::new();
+        self.analyze_within("threshold", triggers, &mut issues);
+        issues
+    }
+
+    /// Analyzes the threshold at the given path (see `analyze()`).
+    fn analyze_within(&self, path: &str, triggers: &HashSet<&String>, issues: &mut Vec<Issue>) {
+        if self.requires > self.considers.len() {
+            let message = format!(
+                "{}: requires {} of {} considerations",
+                path,
+                self.requires,
+                self.considers.len()
+            );
+            issues.push(match self.inverse {
+                true => Issue::Warning(format!("{}, so (as it is inverse) it is always met", message)),
+                false => Issue::Error(format!("{}, so it can never be met", message)),
+            });
+        }
+        for (index, consideration) in self.considers.iter().enumerate() {
+            consideration.analyze_within(&format!("{}.considers[{}]", path, index), triggers, issues);
+        }
+    }
+
+    /// The IDs of the triggers that the threshold (or any of its nested
+    /// thresholds) considers.
+    pub fn referenced_triggers(&self) -> HashSet<&String> {
+        let mut referenced: HashSet<&String> = HashSet::new();
+        for consideration in &self.considers {
+            consideration.collect_triggers(&mut referenced);
+        }
+        referenced
+    }
+
+    /// Determines whether the threshold can _possibly_ be met, given
+    /// the set of triggers that are able to match. Triggers that are
+    /// not present in `possible` are assumed not to match.
+    ///
+    /// This function is conservative: inverse thresholds (and negated
+    /// considerations) are always considered achievable, as their
+    /// outcome cannot be bounded by the triggers that match alone. It
+    /// is used by the scan engine to skip queries whose threshold is
+    /// out of reach before any excerpts are extracted.
+    ///
+    /// # Arguments
+    /// * possible: the IDs of the triggers that are able to match
+    pub fn is_achievable(&self, possible: &HashSet<&String>) -> bool {
+        if self.inverse {
+            return true;
+        }
+
+        let mut achievable = 0;
+        for consideration in &self.considers {
+            if consideration.is_achievable(possible) {
+                achievable += 1;
+            }
+        }
+
+        achievable >= self.requires
+    }
+}
+
+impl ThresholdConsideration {
+    /// Evaluates the consideration (see `Threshold::evaluate()`).
+    fn evaluate(&self, triggers: &HashMap<&String, usize>) -> Result<bool, Issue> {
+        Ok(match self {
+            ThresholdConsideration::Trigger(id) => count(triggers, id)? > 0,
+            ThresholdConsideration::TriggerCount(id, minimum) => count(triggers, id)? >= *minimum,
+            ThresholdConsideration::NestedThreshold(threshold) => threshold.evaluate(triggers)?,
+            ThresholdConsideration::Negated(consideration) => !consideration.evaluate(triggers)?,
+            ThresholdConsideration::Weighted(_, consideration) => consideration.evaluate(triggers)?,
+            ThresholdConsideration::Named(name) => return Err(unresolved(name)),
+            ThresholdConsideration::Tag(tag) | ThresholdConsideration::AtLeastTagged(_, tag) => {
+                return Err(unexpanded(tag))
+            }
+        })
+    }
+
+    /// Evaluates the consideration lazily (see `Threshold::evaluate_lazily()`).
+    fn evaluate_lazily<F>(&self, count: &mut F) -> Result<bool, Issue>
+    where
+        F: FnMut(&String, usize) -> Result<usize, Issue>,
+    {
+        Ok(match self {
+            ThresholdConsideration::Trigger(id) => count(id, 1)? > 0,
+            ThresholdConsideration::TriggerCount(id, minimum) => count(id, *minimum)? >= *minimum,
+            ThresholdConsideration::NestedThreshold(threshold) => threshold.evaluate_lazily(count)?,
+            ThresholdConsideration::Negated(consideration) => !consideration.evaluate_lazily(count)?,
+            ThresholdConsideration::Weighted(_, consideration) => consideration.evaluate_lazily(count)?,
+            ThresholdConsideration::Named(name) => return Err(unresolved(name)),
+            ThresholdConsideration::Tag(tag) | ThresholdConsideration::AtLeastTagged(_, tag) => {
+                return Err(unexpanded(tag))
+            }
+        })
+    }
+
+    /// Scores the consideration, ignoring its weight (see `Threshold::score_with()`).
+    fn score_with<F>(&self, count: &mut F) -> Result<f32, Issue>
+    where
+        F: FnMut(&String, usize) -> Result<usize, Issue>,
+    {
+        let satisfied = match self {
+            ThresholdConsideration::Trigger(id) => count(id, 1)? > 0,
+            ThresholdConsideration::TriggerCount(id, minimum) => count(id, *minimum)? >= *minimum,
+            ThresholdConsideration::NestedThreshold(threshold) => return threshold.score_with(count),
+            ThresholdConsideration::Negated(consideration) => {
+                return Ok(1.0 - consideration.score_with(count)?)
+            }
+            ThresholdConsideration::Weighted(_, consideration) => return consideration.score_with(count),
+            ThresholdConsideration::Named(name) => return Err(unresolved(name)),
+            ThresholdConsideration::Tag(tag) | ThresholdConsideration::AtLeastTagged(_, tag) => {
+                return Err(unexpanded(tag))
+            }
+        };
+        Ok(if satisfied { 1.0 } else { 0.0 })
+    }
+
+    /// The weight of the consideration when its threshold is scored.
+    fn weight(&self) -> f32 {
+        match self {
+            ThresholdConsideration::Weighted(weight, consideration) => weight * consideration.weight(),
+            _ => 1.0,
+        }
+    }
+
+    /// The consideration without any `Weighted` wrappers.
+    pub fn unweighted(&self) -> &ThresholdConsideration {
+        match self {
+            ThresholdConsideration::Weighted(_, consideration) => consideration.unweighted(),
+            other => other,
+        }
+    }
+
+    /// Traces the evaluation of the consideration (see `Threshold::explain()`).
+    fn explain(&self, triggers: &HashMap<&String, usize>) -> Result<ConsiderationTrace, Issue> {
+        let (id, requires) = match self {
+            ThresholdConsideration::Trigger(id) => (id, 1),
+            ThresholdConsideration::TriggerCount(id, minimum) => (id, *minimum),
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                return Ok(ConsiderationTrace::NestedThreshold(threshold.explain(triggers)?))
+            }
+            ThresholdConsideration::Negated(consideration) => {
+                return Ok(ConsiderationTrace::Negated(Box::new(consideration.explain(triggers)?)))
+            }
+            ThresholdConsideration::Weighted(_, consideration) => return consideration.explain(triggers),
+            ThresholdConsideration::Named(name) => return Err(unresolved(name)),
+            ThresholdConsideration::Tag(tag) | ThresholdConsideration::AtLeastTagged(_, tag) => {
+                return Err(unexpanded(tag))
+            }
+        };
+        let matches = count(triggers, id)?;
+        Ok(ConsiderationTrace::Trigger {
+            id: id.clone(),
+            matches,
+            requires,
+            satisfied: matches >= requires,
+        })
+    }
+
+    /// Orders the nested thresholds of the consideration by cost (see
+    /// `Threshold::ordered_by_cost()`).
+    fn ordered_by_cost<F>(&self, cost: &F) -> ThresholdConsideration
+    where
+        F: Fn(&String) -> usize,
+    {
+        match self {
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                ThresholdConsideration::NestedThreshold(threshold.ordered_by_cost(cost))
+            }
+            ThresholdConsideration::Negated(consideration) => {
+                ThresholdConsideration::Negated(Box::new(consideration.ordered_by_cost(cost)))
+            }
+            ThresholdConsideration::Weighted(weight, consideration) => {
+                ThresholdConsideration::Weighted(*weight, Box::new(consideration.ordered_by_cost(cost)))
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// The total cost of the triggers considered by the consideration.
+    fn total_cost<F>(&self, cost: &F) -> usize
+    where
+        F: Fn(&String) -> usize,
+    {
+        match self {
+            ThresholdConsideration::Trigger(id) | ThresholdConsideration::TriggerCount(id, _) => cost(id),
+            ThresholdConsideration::NestedThreshold(threshold) => threshold
+                .considers
+                .iter()
+                .map(|consideration| consideration.total_cost(cost))
+                .sum(),
+            ThresholdConsideration::Negated(consideration)
+            | ThresholdConsideration::Weighted(_, consideration) => consideration.total_cost(cost),
+            ThresholdConsideration::Named(_)
+            | ThresholdConsideration::Tag(_)
+            | ThresholdConsideration::AtLeastTagged(_, _) => 0,
+        }
+    }
+
+    /// Aliases the triggers of the consideration (see
+    /// `Threshold::with_content_aliases()`).
+    fn alias_within(
+        &self,
+        default: ScopeContent,
+        content: ScopeContent,
+        aliases: &mut Vec<(String, String, ScopeContent)>,
+    ) -> ThresholdConsideration {
+        let mut alias = |id: &String| -> String {
+            if content == default {
+                return id.clone();
+            }
+            let alias = format!("{}@{:?}", id, content);
+            if !aliases.iter().any(|(existing, _, _)| *existing == alias) {
+                aliases.push((alias.clone(), id.clone(), content));
+            }
+            alias
+        };
+        match self {
+            ThresholdConsideration::Trigger(id) => ThresholdConsideration::Trigger(alias(id)),
+            ThresholdConsideration::TriggerCount(id, minimum) => {
+                ThresholdConsideration::TriggerCount(alias(id), *minimum)
+            }
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                ThresholdConsideration::NestedThreshold(threshold.alias_within(default, content, aliases))
+            }
+            ThresholdConsideration::Negated(consideration) => ThresholdConsideration::Negated(Box::new(
+                consideration.alias_within(default, content, aliases),
+            )),
+            ThresholdConsideration::Weighted(weight, consideration) => ThresholdConsideration::Weighted(
+                *weight,
+                Box::new(consideration.alias_within(default, content, aliases)),
+            ),
+            ThresholdConsideration::Named(_) => self.clone(), // aliased once resolved
+            ThresholdConsideration::Tag(_) | ThresholdConsideration::AtLeastTagged(_, _) => {
+                self.clone() // aliased once expanded
+            }
+        }
+    }
+
+    /// Resolves the named thresholds of the consideration (see
+    /// `Threshold::resolve()`).
+    fn resolve_within<'a>(
+        &self,
+        named: &'a BTreeMap<String, Threshold>,
+        resolving: &mut Vec<&'a String>,
+    ) -> Result<ThresholdConsideration, Issue> {
+        Ok(match self {
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                ThresholdConsideration::NestedThreshold(threshold.resolve_within(named, resolving)?)
+            }
+            ThresholdConsideration::Negated(consideration) => {
+                ThresholdConsideration::Negated(Box::new(consideration.resolve_within(named, resolving)?))
+            }
+            ThresholdConsideration::Weighted(weight, consideration) => ThresholdConsideration::Weighted(
+                *weight,
+                Box::new(consideration.resolve_within(named, resolving)?),
+            ),
+            ThresholdConsideration::Named(name) => {
+                let (name, threshold) = match named.get_key_value(name) {
+                    Some(value) => value,
+                    None => return Err(Issue::Error(format!(
+                        "unknown named threshold `{}`; named thresholds must be defined by the query group",
+                        name
+                    ))),
+                };
+                if resolving.contains(&name) {
+                    return Err(Issue::Error(format!("named threshold `{}` refers to itself", name)));
+                }
+                resolving.push(name);
+                let resolved = threshold.resolve_within(named, resolving)?;
+                resolving.pop();
+                ThresholdConsideration::NestedThreshold(resolved)
+            }
+            other => other.clone(),
+        })
+    }
+
+    /// Expands the tags of the consideration (see `Threshold::with_tags_expanded()`).
+    fn expand_tags(&self, triggers: &[Trigger]) -> Result<ThresholdConsideration, Issue> {
+        let (tag, requires) = match self {
+            ThresholdConsideration::Tag(tag) => (tag, 1),
+            ThresholdConsideration::AtLeastTagged(requires, tag) => (tag, *requires),
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                return Ok(ThresholdConsideration::NestedThreshold(threshold.with_tags_expanded(triggers)?))
+            }
+            ThresholdConsideration::Negated(consideration) => {
+                return Ok(ThresholdConsideration::Negated(Box::new(consideration.expand_tags(triggers)?)))
+            }
+            ThresholdConsideration::Weighted(weight, consideration) => {
+                return Ok(ThresholdConsideration::Weighted(
+                    *weight,
+                    Box::new(consideration.expand_tags(triggers)?),
+                ))
+            }
+            other => return Ok(other.clone()),
+        };
+        let mut considers: Vec<ThresholdConsideration> = Vec::new();
+        for trigger in triggers.iter().filter(|trigger| trigger.tags.contains(tag)) {
+            let consideration = ThresholdConsideration::Trigger(trigger.id.clone());
+            if !considers.contains(&consideration) {
+                considers.push(consideration);
+            }
+        }
+        if considers.is_empty() {
+            return Err(Issue::Error(format!("no trigger has the tag `{}`", tag)));
+        }
+        Ok(ThresholdConsideration::NestedThreshold(Threshold {
+            considers,
+            requires,
+            inverse: false,
+            content: None,
+        }))
+    }
+
+    /// Analyzes the consideration at the given path (see `Threshold::analyze()`).
+    fn analyze_within(&self, path: &str, triggers: &HashSet<&String>, issues: &mut Vec<Issue>) {
+        match self {
+            ThresholdConsideration::Trigger(id) | ThresholdConsideration::TriggerCount(id, _) => {
+                if !triggers.contains(id) {
+                    issues.push(Issue::Error(format!("{}: refers to undefined trigger `{}`", path, id)));
+                }
+            }
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                threshold.analyze_within(path, triggers, issues)
+            }
+            ThresholdConsideration::Negated(consideration) => {
+                consideration.analyze_within(path, triggers, issues)
+            }
+            ThresholdConsideration::Weighted(weight, consideration) => {
+                if !(weight.is_finite() && *weight > 0.0) {
+                    issues.push(Issue::Error(format!(
+                        "{}: has weight {}, but weights must be positive numbers",
+                        path, weight
+                    )));
+                }
+                consideration.analyze_within(path, triggers, issues)
+            }
+            ThresholdConsideration::Named(_) => (), // analyzed where it is defined
+            ThresholdConsideration::Tag(_) | ThresholdConsideration::AtLeastTagged(_, _) => (), // once expanded
+        }
+    }
+
+    /// Collects the IDs of the triggers that the consideration considers.
+    fn collect_triggers<'a>(&'a self, referenced: &mut HashSet<&'a String>) {
+        match self {
+            ThresholdConsideration::Trigger(id) | ThresholdConsideration::TriggerCount(id, _) => {
+                referenced.insert(id);
+            }
+            ThresholdConsideration::NestedThreshold(threshold) => {
+                for consideration in &threshold.considers {
+                    consideration.collect_triggers(referenced);
+                }
+            }
+            ThresholdConsideration::Negated(consideration)
+            | ThresholdConsideration::Weighted(_, consideration) => consideration.collect_triggers(referenced),
+            ThresholdConsideration::Named(_)
+            | ThresholdConsideration::Tag(_)
+            | ThresholdConsideration::AtLeastTagged(_, _) => (),
+        }
+    }
+
+    /// Determines whether the consideration can possibly be satisfied
+    /// (see `Threshold::is_achievable()`).
+    fn is_achievable(&self, possible: &HashSet<&String>) -> bool {
+        match self {
+            ThresholdConsideration::Trigger(id) => possible.contains(id),
+            ThresholdConsideration::TriggerCount(id, minimum) => *minimum == 0 || possible.contains(id),
+            ThresholdConsideration::NestedThreshold(threshold) => threshold.is_achievable(possible),
+            ThresholdConsideration::Negated(_) => true, // satisfied when its consideration is not
+            ThresholdConsideration::Weighted(_, consideration) => consideration.is_achievable(possible),
+            ThresholdConsideration::Named(_) => true, // unknown until resolved
+            ThresholdConsideration::Tag(_) | ThresholdConsideration::AtLeastTagged(_, _) => true, // until expanded
+        }
+    }
+}
+
+/// Looks up the number of times the trigger with the given ID matched.
+fn count(triggers: &HashMap<&String, usize>, id: &String) -> Result<usize, Issue> {
+    match triggers.get(id) {
+        Some(res) => Ok(*res),
+        None => Err(Issue::Error(format!("unable to find trigger `{}` in given triggers", id)))
+    }
+}
+
+/// The error for a `Tag` or `AtLeastTagged` consideration that was
+/// evaluated before being expanded.
+fn unexpanded(tag: &str) -> Issue {
+    Issue::Error(format!("tag `{}` has not been expanded", tag))
+}
+
+/// The error for a `Named` consideration that was evaluated before being
+/// resolved.
+fn unresolved(name: &str) -> Issue {
+    Issue::Error(format!("named threshold `{}` has not been resolved", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ron;
+    use serde_json;
+
+    #[test]
+    fn test_threshold_expression() {
+        let expression = ThresholdExpression::All(vec![
+            ThresholdExpression::Trigger(String::from("A")),
+            ThresholdExpression::Any(vec![
+                ThresholdExpression::Trigger(String::from("B")),
+                ThresholdExpression::Trigger(String::from("C")),
+            ]),
+            ThresholdExpression::Not(Box::new(ThresholdExpression::Trigger(String::from("D")))),
+            ThresholdExpression::Not(Box::new(ThresholdExpression::AtLeast(
+                2,
+                vec![
+                    ThresholdExpression::Trigger(String::from("E")),
+                    ThresholdExpression::Trigger(String::from("F")),
+                    ThresholdExpression::Trigger(String::from("G")),
+                ],
+            ))),
+        ]);
+        let threshold = Threshold::from(expression.clone());
+        assert_eq!(ThresholdExpression::from(&threshold), expression);
+        assert_eq!(
+            threshold.describe(),
+            "all of (`A`, any of (`B`, `C`), not `D`, not at least 2 of (`E`, `F`, `G`))"
+        );
+        let none = ThresholdExpression::None(vec![
+            ThresholdExpression::Trigger(String::from("A")),
+            ThresholdExpression::Trigger(String::from("B")),
+        ]);
+        assert_eq!(ThresholdExpression::from(&Threshold::from(none.clone())), none);
+
+        let (a, b, c, d, e, f, g) = (
+            String::from("A"),
+            String::from("B"),
+            String::from("C"),
+            String::from("D"),
+            String::from("E"),
+            String::from("F"),
+            String::from("G"),
+        );
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        for (id, value) in &[(&a, 1), (&b, 0), (&c, 1), (&d, 0), (&e, 1), (&f, 0), (&g, 0)] {
+            triggers.insert(id, *value);
+        }
+        assert!(threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&f, 1);
+        assert!(!threshold.evaluate(&triggers).unwrap());
+    }
+
+    #[test]
+    fn test_threshold_trigger_count() {
+        let threshold: Threshold = ron::de::from_str(
+            "(considers:[TriggerCount(\"A\", 3),Trigger(\"B\")],requires:2,inverse:false)",
+        )
+        .unwrap();
+        assert_eq!(threshold.describe(), "all of (`A` at least 3 times, `B`)");
+        let (a, b) = (String::from("A"), String::from("B"));
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        triggers.insert(&a, 2);
+        triggers.insert(&b, 1);
+        assert!(!threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&a, 3);
+        assert!(threshold.evaluate(&triggers).unwrap());
+
+        let mut possible: HashSet<&String> = HashSet::new();
+        possible.insert(&b);
+        assert!(!threshold.is_achievable(&possible));
+        possible.insert(&a);
+        assert!(threshold.is_achievable(&possible));
+    }
+
+    #[test]
+    fn test_threshold_explain() {
+        let threshold = Threshold::from(ThresholdExpression::All(vec![
+            ThresholdExpression::TriggerCount(String::from("A"), 2),
+            ThresholdExpression::Not(Box::new(ThresholdExpression::Trigger(String::from("B")))),
+        ]));
+        let (a, b) = (String::from("A"), String::from("B"));
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        triggers.insert(&a, 2);
+        triggers.insert(&b, 1);
+
+        let trace = threshold.explain(&triggers).unwrap();
+        assert_eq!(trace.matched, threshold.evaluate(&triggers).unwrap());
+        assert!(!trace.matched);
+        assert_eq!(trace.satisfied, 1);
+        assert!(trace.considers[0].is_satisfied());
+        assert!(!trace.considers[1].is_satisfied());
+        assert_eq!(
+            trace.to_string(),
+            "did not match (1 of 2 satisfied, 2 required)\n  \
+             [x] `A` matched 2 times (2 required)\n  \
+             [ ] not:\n    \
+             [x] `B` matched 1 times (1 required)\n"
+        );
+
+        triggers.insert(&b, 0);
+        assert!(threshold.explain(&triggers).unwrap().matched);
+        triggers.remove(&b);
+        assert!(threshold.explain(&triggers).is_err());
+    }
+
+    #[test]
+    fn test_threshold_lazy_evaluation() {
+        let threshold = Threshold::from(ThresholdExpression::Any(vec![
+            ThresholdExpression::All(vec![
+                ThresholdExpression::Trigger(String::from("C")),
+                ThresholdExpression::Trigger(String::from("D")),
+            ]),
+            ThresholdExpression::Trigger(String::from("B")),
+            ThresholdExpression::TriggerCount(String::from("A"), 2),
+        ]));
+        let costs: HashMap<String, usize> = [("A", 1), ("B", 2), ("C", 3), ("D", 4)]
+            .iter()
+            .map(|(id, cost)| (String::from(*id), *cost))
+            .collect();
+        let ordered = threshold.ordered_by_cost(&|id: &String| costs[id]);
+        assert_eq!(ordered.describe(), "any of (`A` at least 2 times, `B`, all of (`C`, `D`))");
+
+        let counts: HashMap<String, usize> = [("A", 0), ("B", 1), ("C", 0), ("D", 1)]
+            .iter()
+            .map(|(id, count)| (String::from(*id), *count))
+            .collect();
+        let mut requested: Vec<String> = Vec::new();
+        let matched = ordered
+            .evaluate_lazily(&mut |id: &String, _| {
+                requested.push(id.clone());
+                Ok(counts[id])
+            })
+            .unwrap();
+        assert!(matched);
+        assert_eq!(requested, vec![String::from("A"), String::from("B")]);
+
+        let borrowed: HashMap<&String, usize> = counts.iter().map(|(id, count)| (id, *count)).collect();
+        assert_eq!(threshold.evaluate(&borrowed).unwrap(), matched);
+    }
+
+    #[test]
+    fn test_threshold_negated() {
+        let threshold: Threshold = ron::de::from_str(
+            "(considers:[Trigger(\"A\"),Negated(Trigger(\"B\"))],requires:2,inverse:false)",
+        )
+        .unwrap();
+        assert_eq!(threshold.describe(), "all of (`A`, not `B`)");
+        let (a, b) = (String::from("A"), String::from("B"));
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        triggers.insert(&a, 1);
+        triggers.insert(&b, 0);
+        assert!(threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&b, 1);
+        assert!(!threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&a, 0);
+        triggers.insert(&b, 0);
+        assert!(!threshold.evaluate(&triggers).unwrap());
+
+        let mut possible: HashSet<&String> = HashSet::new();
+        assert!(!threshold.is_achievable(&possible));
+        possible.insert(&a);
+        assert!(threshold.is_achievable(&possible));
+    }
+
+    #[test]
+    fn test_threshold_score() {
+        let threshold: Threshold = ron::de::from_str(
+            "(considers:[Weighted(3.0,Trigger(\"A\")),Trigger(\"B\"),Negated(Trigger(\"C\"))],requires:3,inverse:false)",
+        )
+        .unwrap();
+        assert_eq!(threshold.describe(), "all of (`A` (weight 3), `B`, not `C`)");
+        assert_eq!(Threshold::from(ThresholdExpression::from(&threshold)), threshold);
+        let (a, b, c) = (String::from("A"), String::from("B"), String::from("C"));
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        triggers.insert(&a, 1);
+        triggers.insert(&b, 0);
+        triggers.insert(&c, 0);
+        assert_eq!(threshold.score(&triggers).unwrap(), 0.8);
+        assert!(!threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&b, 1);
+        assert_eq!(threshold.score(&triggers).unwrap(), 1.0);
+        assert!(threshold.evaluate(&triggers).unwrap());
+        triggers.insert(&a, 0);
+        triggers.insert(&c, 1);
+        assert_eq!(threshold.score(&triggers).unwrap(), 0.2);
+
+        let inverse = Threshold {
+            inverse: true,
+            ..threshold.clone()
+        };
+        assert_eq!(inverse.score(&triggers).unwrap(), 0.8);
+
+        let defined: HashSet<&String> = [&a, &b, &c].iter().cloned().collect();
+        assert!(threshold.analyze(&defined).is_empty());
+        let negative: Threshold =
+            ron::de::from_str("(considers:[Weighted(-1.0,Trigger(\"A\"))],requires:1,inverse:false)").unwrap();
+        assert_eq!(
+            negative.analyze(&defined),
+            vec![Issue::Error(String::from(
+                "threshold.considers[0]: has weight -1, but weights must be positive numbers"
+            ))]
+        );
+    }
+
+    #[test]
+    fn test_threshold_content() {
+        let threshold: Threshold = ron::de::from_str(
+            "(expression:Some(All([Within(Title,Any([Trigger(\"A\"),Trigger(\"B\")])),Trigger(\"A\")])))",
+        )
+        .unwrap();
+        assert_eq!(threshold.describe(), "all of (any of (`A`, `B`) (in Title), `A`)");
+        match &threshold.considers[0] {
+            ThresholdConsideration::NestedThreshold(nested) => {
+                assert_eq!(nested.content, Some(ScopeContent::Title))
+            }
+            other => panic!("unexpected consideration {:?}", other),
+        }
+        assert_eq!(
+            Threshold::from(ThresholdExpression::from(&threshold)),
+            threshold
+        );
+
+        let mut aliases: Vec<(String, String, ScopeContent)> = Vec::new();
+        let aliased = threshold.with_content_aliases(ScopeContent::Text, &mut aliases);
+        assert_eq!(aliased.describe(), "all of (any of (`A@Title`, `B@Title`) (in Title), `A`)");
+        assert_eq!(aliases[0], (String::from("A@Title"), String::from("A"), ScopeContent::Title));
+        assert_eq!(aliases.len(), 2);
+
+        aliases.clear();
+        assert_eq!(threshold.with_content_aliases(ScopeContent::Title, &mut aliases), threshold);
+        assert!(aliases.is_empty());
+    }
+
+    #[test]
+    fn test_threshold_resolve() {
+        let mut named: BTreeMap<String, Threshold> = BTreeMap::new();
+        named.insert(
+            String::from("greeting"),
+            ron::de::from_str("(expression:Some(Any([Trigger(\"A\"),Named(\"farewell\")])))").unwrap(),
+        );
+        named.insert(
+            String::from("farewell"),
+            ron::de::from_str("(considers:[Trigger(\"B\")],requires:1,inverse:false)").unwrap(),
+        );
+        let threshold: Threshold =
+            ron::de::from_str("(considers:[Named(\"greeting\"),Trigger(\"C\")],requires:2,inverse:false)")
+                .unwrap();
+        assert_eq!(threshold.describe(), "all of (threshold `greeting`, `C`)");
+        let (b, c) = (String::from("B"), String::from("C"));
+        let mut triggers: HashMap<&String, usize> = HashMap::new();
+        triggers.insert(&b, 1);
+        triggers.insert(&c, 1);
+        assert!(threshold.evaluate(&triggers).is_err());
+
+        let resolved = threshold.resolve(&named).unwrap();
+        assert_eq!(resolved.describe(), "all of (any of (`A`, `B`), `C`)");
+
+        named.insert(
+            String::from("farewell"),
+            ron::de::from_str("(expression:Some(Not(Named(\"greeting\"))))").unwrap(),
+        );
+        assert!(threshold.resolve(&named).is_err()); // cyclic
+        assert!(threshold.resolve(&BTreeMap::new()).is_err()); // undefined
+    }
+
+    #[test]
+    fn test_threshold_deserialization() {
+        let counting: Threshold =
+            ron::de::from_str("(considers:[Trigger(\"A\"),Trigger(\"B\")],requires:1,inverse:false)").unwrap();
+        let expression: Threshold =
+            ron::de::from_str("(expression:Some(Any([Trigger(\"A\"),Trigger(\"B\")])))").unwrap();
+        assert_eq!(counting, expression);
+        let from_json: Threshold =
+            serde_json::from_str(r#"{"expression": {"Any": [{"Trigger": "A"}, {"Trigger": "B"}]}}"#).unwrap();
+        assert_eq!(counting, from_json);
+
+        assert!(ron::de::from_str::<Threshold>("(considers:[Trigger(\"A\")],inverse:false)").is_err());
+        assert!(ron::de::from_str::<Threshold>(
+            "(requires:1,expression:Some(Any([Trigger(\"A\")])))"
+        )
+        .is_err());
+    }
+}
@@ -2,7 +2,9 @@
 
 use common::pattern::{Pattern, CompiledPattern, PatternMatch};
 use common::compilation::CompilableTo;
-use common::validation::Issue;
+use common::validation::{Issue, Validatable};
+use query::scope::{ContentTransform, ScopeContent};
+use std::collections::HashMap;
 
 /// Represents a trigger, which is itself mostly a smart 
 /// wrapper for JSON expressions.
@@ -12,22 +14,193 @@ pub struct Trigger {
     pub pattern: Pattern,
     /// The ID of the Trigger (used for `Threshold` evaluation)
     pub id: String,
+    /// Arbitrary tags (such as `pii` or `brand:acme`) that are
+    /// attached to the `Output` whenever this trigger fires
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether the trigger is negated; negated triggers are considered
+    /// to match (for `Threshold` evaluation) when their pattern does
+    /// _not_ match, and never produce excerpts
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub negate: bool,
+    /// The content that the trigger is evaluated against, overriding
+    /// the content of the query's scope (and of any threshold that
+    /// considers it); for example, a `Raw` trigger that matches HTML
+    /// attributes within a query whose other triggers match `Text`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<ScopeContent>,
+    /// The part of the content that the trigger is evaluated against,
+    /// such as only its first 500 characters or only its first
+    /// paragraph; when absent, the whole content is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<TriggerPosition>,
+    /// When present, the trigger only matches numbers within the given
+    /// range: the number of each match of the pattern is read from its
+    /// `number` capture group (or, failing that, its first capture group
+    /// or the whole match), and matches whose number is out of range (or
+    /// that contain no number) are discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<NumericRange>,
+    /// The transforms (such as `Lowercase` or `CollapseWhitespace`)
+    /// applied to the content before the trigger is evaluated, after
+    /// those of the query's scope; as with the scope's transforms, the
+    /// pattern is matched against the transformed content as written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<ContentTransform>,
+}
+
+/// Denotes an inclusive range of numbers, such as `(min: Some(1000000.0))`
+/// for numbers of at least one million. (See `Trigger.range`.)
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct NumericRange {
+    /// The smallest number in the range, if any.
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// The largest number in the range, if any.
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+/// Denotes the part of its content that a trigger is evaluated against.
+/// (See `Trigger.position`.)
+///
+/// Paragraphs are runs of non-blank lines separated by blank lines, as
+/// in plain text documents and the `Paragraphs` content of HTML documents.
+/// For `Bytes` content, lengths are measured in bytes rather than
+/// characters.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum TriggerPosition {
+    /// Only the given number of characters at the start of the content.
+    First(usize),
+    /// Only the given number of characters at the end of the content.
+    Last(usize),
+    /// Only the paragraph with the given number, counting from `1`; for
+    /// example, `Paragraph(1)` is the lead paragraph.
+    Paragraph(usize),
+    /// Only the last paragraph.
+    LastParagraph,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 #[derive(Clone)]
 pub struct CompiledTrigger {
     pub pattern: CompiledPattern,
     pub id: String,
+    pub tags: Vec<String>,
+    pub negate: bool,
+    /// The content that the trigger is evaluated against, when it
+    /// differs from the content of the query's scope.
+    pub content: Option<ScopeContent>,
+    pub position: Option<TriggerPosition>,
+    pub range: Option<NumericRange>,
+    /// The transforms applied to the content before the trigger is
+    /// evaluated, when the trigger has its own (see `Trigger.transforms`);
+    /// once compiled as part of a query, these are the transforms of the
+    /// query's scope followed by the trigger's own.
+    pub transforms: Option<Vec<ContentTransform>>,
+    /// A key that is identical for triggers with identical patterns
+    /// (and positions and ranges),
+    /// which allows the scan engine to check such patterns only once
+    /// per document, even when they belong to different queries.
+    pub key: String,
 }
 
 impl CompilableTo<CompiledTrigger> for Trigger {
     fn compile(&self) -> Result<CompiledTrigger, Issue> {
-        match self.pattern.compile() {
-            Ok(compiled_pattern) => Ok(CompiledTrigger {
-                pattern: compiled_pattern,
-                id: self.id.clone()
-            }),
-            Err(issue) => Err(issue)
+        self.compile_cached(&mut HashMap::new())
+    }
+}
+
+impl Trigger {
+    /// Checks that the trigger's ID is a safe identifier: a non-empty
+    /// sequence of ASCII letters, digits, `_`, `-`, and `.`. (IDs appear
+    /// in thresholds, outputs, and logs, where other characters are easily
+    /// confused or mangled.)
+    pub fn check_id(&self) -> Result<(), Issue> {
+        let is_safe = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.';
+        if self.id.is_empty() || !self.id.chars().all(is_safe) {
+            return Err(Issue::Error(format!(
+                "trigger ID `{}` must consist only of ASCII letters, digits, `_`, `-`, and `.`",
+                self.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Compiles the trigger like `compile()`, but reuses the compiled
+    /// pattern of an identical pattern from `patterns` (keyed by the
+    /// pattern's debug representation), and records the compiled pattern
+    /// there otherwise. Compiling many triggers that share patterns—such
+    /// as the shared triggers of a `QueryGroup`—with the same `patterns`
+    /// compiles each pattern only once.
+    pub fn compile_cached(
+        &self,
+        patterns: &mut HashMap<String, CompiledPattern>,
+    ) -> Result<CompiledTrigger, Issue> {
+        if self.position == Some(TriggerPosition::Paragraph(0)) {
+            return Err(Issue::Error(String::from(
+                "invalid position `Paragraph(0)`; paragraphs are numbered from 1",
+            )));
+        }
+        if let Some(NumericRange { min: Some(min), max: Some(max) }) = self.range {
+            if min > max {
+                return Err(Issue::Error(format!(
+                    "invalid range; the minimum ({}) exceeds the maximum ({})",
+                    min, max
+                )));
+            }
+        }
+        let key = format!("{:?}", self.pattern);
+        let compiled_pattern = match patterns.get(&key) {
+            Some(compiled_pattern) => compiled_pattern.clone(),
+            None => {
+                let compiled_pattern = self.pattern.compile()?;
+                patterns.insert(key, compiled_pattern.clone());
+                compiled_pattern
+            }
+        };
+        Ok(CompiledTrigger {
+            pattern: compiled_pattern,
+            id: self.id.clone(),
+            tags: self.tags.clone(),
+            negate: self.negate,
+            content: self.content,
+            position: self.position,
+            range: self.range,
+            transforms: match self.transforms.is_empty() {
+                true => None,
+                false => Some(self.transforms.clone()),
+            },
+            key: format!("{:?} {:?} {:?}", self.pattern, self.position, self.range),
+        })
+    }
+}
+
+impl Validatable for Trigger {
+    /// Validates the trigger: its ID must be a safe identifier (see
+    /// `check_id()`), it must compile, and its pattern must be valid.
+    ///
+    /// Returns `None` if there is no issue; otherwise, `Some(Vec<Issue>)`.
+    fn validate(&self) -> Option<Vec<Issue>> {
+        let mut issues: Vec<Issue> = Vec::new();
+        if let Err(issue) = self.check_id() {
+            issues.push(issue);
+        }
+        match self.compile() {
+            Ok(_) => {
+                if let Some(problems) = self.pattern.validate() {
+                    issues.extend(problems);
+                }
+            }
+            Err(issue) => issues.push(issue),
+        }
+        if !issues.is_empty() {
+            Some(issues)
+        } else {
+            None
         }
     }
 }
@@ -36,10 +209,40 @@ impl CompiledTrigger {
     /// Checks if the `Trigger` matches the given string
     /// without extracting any type of excerpt.
     /// 
+    /// Note that this checks the _pattern_; for negated
+    /// triggers, use `evaluate()` to determine whether
+    /// the trigger fires.
+    /// 
     /// This is typically much faster than performing a
-    /// `full_check()`.
-    pub fn quick_check(&self, other: &String) -> bool {
-        self.pattern.quick_check(other)
+    /// `full_check()`, except for triggers with a `range`,
+    /// whose matches must be extracted to be checked.
+    pub fn quick_check(&self, other: &str) -> bool {
+        match self.range {
+            Some(_) => self.full_check(other).is_some(),
+            None => self.pattern.quick_check(self.window(other)),
+        }
+    }
+
+    /// Checks if the `Trigger` matches the given bytes, like
+    /// `quick_check()`.
+    pub fn quick_check_bytes(&self, other: &[u8]) -> bool {
+        match self.range {
+            Some(_) => !self.full_check_all_bytes(other).is_empty(),
+            None => self.pattern.quick_check_bytes(self.window_bytes(other)),
+        }
+    }
+
+    /// Determines whether the `Trigger` fires on the given
+    /// string, taking negation into account. This is the
+    /// value used for `Threshold` evaluation.
+    pub fn evaluate(&self, other: &str) -> bool {
+        self.quick_check(other) != self.negate
+    }
+
+    /// Determines whether the `Trigger` fires on the given
+    /// bytes, like `evaluate()`.
+    pub fn evaluate_bytes(&self, other: &[u8]) -> bool {
+        self.quick_check_bytes(other) != self.negate
     }
 
     /// Checks if the `Trigger` matches the given string
@@ -48,7 +251,287 @@ impl CompiledTrigger {
     /// This is typically slower than `quick_check()`, and
     /// in most scenarios it makes sense to run `quick_check()`
     /// first before running this function.
-    pub fn full_check(&self, other: &String) -> Option<PatternMatch> {
-        self.pattern.full_check(other)
+    pub fn full_check(&self, other: &str) -> Option<PatternMatch> {
+        match self.range {
+            Some(_) => self.full_check_all(other).into_iter().next(),
+            None => {
+                let window = self.window(other);
+                let start = offset_of(window.as_bytes(), other.as_bytes());
+                let found = self.pattern.full_check(window);
+                found.map(|pattern_match| shifted(pattern_match, start))
+            }
+        }
+    }
+
+    /// Checks if the `Trigger` matches the given string
+    /// and extracts an excerpt for every match.
+    /// 
+    /// Like `full_check()`, this is typically slower than
+    /// `quick_check()`.
+    pub fn full_check_all(&self, other: &str) -> Vec<PatternMatch> {
+        let window = self.window(other);
+        let start = offset_of(window.as_bytes(), other.as_bytes());
+        let found = self.pattern.full_check_all(window);
+        let found = found.into_iter().map(|pattern_match| shifted(pattern_match, start));
+        self.within_range(found.collect())
+    }
+
+    /// Checks if the `Trigger` matches the given bytes
+    /// and extracts an excerpt for every match, like
+    /// `full_check_all()`.
+    pub fn full_check_all_bytes(&self, other: &[u8]) -> Vec<PatternMatch> {
+        let window = self.window_bytes(other);
+        let start = offset_of(window, other);
+        let found = self.pattern.full_check_all_bytes(window);
+        let found = found.into_iter().map(|pattern_match| shifted(pattern_match, start));
+        self.within_range(found.collect())
+    }
+
+    /// Discards the matches whose number is not within the trigger's
+    /// `range` (if any). (See `Trigger.range`.)
+    fn within_range(&self, matches: Vec<PatternMatch>) -> Vec<PatternMatch> {
+        let range = match self.range {
+            Some(range) => range,
+            None => return matches,
+        };
+        matches
+            .into_iter()
+            .filter(|pattern_match| match parse_number(number_text(pattern_match)) {
+                Some(number) => range.contains(number),
+                None => false,
+            })
+            .collect()
+    }
+
+    /// The part of the given text that the trigger is evaluated
+    /// against. (See `Trigger.position`.)
+    fn window<'a>(&self, text: &'a str) -> &'a str {
+        let character_offset = |count: usize| -> usize {
+            text.char_indices().nth(count).map_or(text.len(), |(index, _)| index)
+        };
+        match self.position {
+            None => text,
+            Some(TriggerPosition::First(count)) => &text[..character_offset(count)],
+            Some(TriggerPosition::Last(count)) => {
+                let total = text.chars().count();
+                &text[character_offset(total.saturating_sub(count))..]
+            }
+            Some(position) => {
+                let (start, end) = position.paragraph(text.as_bytes());
+                &text[start..end] // paragraphs begin and end at line breaks
+            }
+        }
+    }
+
+    /// The part of the given bytes that the trigger is evaluated against.
+    fn window_bytes<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        match self.position {
+            None => bytes,
+            Some(TriggerPosition::First(count)) => &bytes[..count.min(bytes.len())],
+            Some(TriggerPosition::Last(count)) => &bytes[bytes.len().saturating_sub(count)..],
+            Some(position) => {
+                let (start, end) = position.paragraph(bytes);
+                &bytes[start..end]
+            }
+        }
+    }
+}
+
+/// The offset of the given window within the given bytes, of which it
+/// must be a part.
+fn offset_of(window: &[u8], bytes: &[u8]) -> usize {
+    window.as_ptr() as usize - bytes.as_ptr() as usize
+}
+
+/// Shifts the offsets of the given match, which was found in a window
+/// that begins at the given offset, so that they refer to the whole content.
+fn shifted(pattern_match: PatternMatch, start: usize) -> PatternMatch {
+    PatternMatch {
+        offsets: pattern_match.offsets.map(|(from, to)| (from + start, to + start)),
+        ..pattern_match
+    }
+}
+
+impl NumericRange {
+    /// Whether the given number is within the range.
+    pub fn contains(&self, number: f64) -> bool {
+        self.min.is_none_or(|min| number >= min) && self.max.is_none_or(|max| number <= max)
+    }
+}
+
+impl TriggerPosition {
+    /// The start and end of the paragraph that the position denotes in
+    /// the given content, or an empty range when there is no such
+    /// paragraph. (This is only meaningful for `Paragraph` and
+    /// `LastParagraph` positions.)
+    fn paragraph(self, content: &[u8]) -> (usize, usize) {
+        let paragraphs = paragraphs(content);
+        let paragraph = match self {
+            TriggerPosition::Paragraph(number) => number.checked_sub(1).and_then(|index| paragraphs.get(index)),
+            _ => paragraphs.last(),
+        };
+        paragraph.cloned().unwrap_or((0, 0))
+    }
+}
+
+/// The text of the number within the given match: its `number` capture,
+/// its first capture, or the relevant portion of its excerpt.
+fn number_text(pattern_match: &PatternMatch) -> &str {
+    match pattern_match
+        .captures
+        .get("number")
+        .or_else(|| pattern_match.captures.get("1"))
+    {
+        Some(capture) => capture,
+        None => pattern_match
+            .excerpt
+            .get(pattern_match.relevant.0..pattern_match.relevant.1)
+            .unwrap_or(""),
+    }
+}
+
+/// Parses the first number in the given text, such as `$1,250,000.50` or
+/// `-3.5%`, ignoring thousands separators and any characters before and
+/// after it (such as currency symbols), so that `2019-2020` is `2019`.
+fn parse_number(text: &str) -> Option<f64> {
+    let mut number = String::new();
+    for character in text.chars() {
+        match character {
+            '0'..='9' | '.' => number.push(character),
+            '-' if number.is_empty() => number.push(character),
+            ',' if !number.is_empty() => (), // a thousands separator
+            _ if number.contains(|character: char| character.is_ascii_digit()) => break,
+            _ => number.clear(), // a stray sign or decimal point
+        }
+    }
+    number.parse().ok()
+}
+
+/// Finds the paragraphs of the given content—runs of lines that are not
+/// blank—as pairs of their start and end offsets.
+fn paragraphs(content: &[u8]) -> Vec<(usize, usize)> {
+    let mut paragraphs: Vec<(usize, usize)> = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    let mut line_start = 0;
+    for line in content.split(|byte| *byte == b'\n') {
+        let line_end = line_start + line.len();
+        if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+            paragraphs.extend(current.take());
+        } else {
+            let start = current.map_or(line_start, |(start, _)| start);
+            current = Some((start, line_end));
+        }
+        line_start = line_end + 1;
+    }
+    paragraphs.extend(current);
+    paragraphs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::pattern::PatternKind;
+
+    fn get_trigger(content: &str, position: TriggerPosition) -> CompiledTrigger {
+        Trigger {
+            pattern: Pattern {
+                content: String::from(content),
+                kind: PatternKind::RegEx,
+                normalization: None,
+            },
+            id: String::from("A"),
+            tags: vec![],
+            negate: false,
+            content: None,
+            position: Some(position),
+            range: None,
+            transforms: vec![],
+        }
+        .compile()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_trigger_position() {
+        let text = "Breaking: café opens\n\n  \nIt serves coffee.\nAnd tea.\n\nClosing remarks";
+        assert!(get_trigger("café", TriggerPosition::First(15)).evaluate(text));
+        assert!(!get_trigger("opens", TriggerPosition::First(15)).evaluate(text));
+        assert!(get_trigger("remarks$", TriggerPosition::Last(7)).evaluate(text));
+        assert!(!get_trigger("Closing", TriggerPosition::Last(7)).evaluate(text));
+        assert!(get_trigger("^It.*\\n.*tea\\.$", TriggerPosition::Paragraph(2)).evaluate(text));
+        assert!(!get_trigger("Breaking", TriggerPosition::Paragraph(2)).evaluate(text));
+        assert!(!get_trigger(".", TriggerPosition::Paragraph(4)).evaluate(text));
+        assert!(get_trigger("^Closing", TriggerPosition::LastParagraph).evaluate(text));
+        assert!(get_trigger("ing", TriggerPosition::LastParagraph).quick_check_bytes(text.as_bytes()));
+        assert!(!get_trigger("Breaking", TriggerPosition::Last(10)).quick_check_bytes(text.as_bytes()));
+
+        let mut trigger = get_trigger("a", TriggerPosition::First(1));
+        assert_ne!(trigger.key, get_trigger("a", TriggerPosition::First(2)).key);
+        trigger.position = None;
+        assert!(trigger.evaluate(text));
+        assert!(Trigger {
+            position: Some(TriggerPosition::Paragraph(0)),
+            ..Trigger {
+                pattern: Pattern {
+                    content: String::from("a"),
+                    kind: PatternKind::RegEx,
+                    normalization: None,
+                },
+                id: String::from("A"),
+                tags: vec![],
+                negate: false,
+                content: None,
+                position: None,
+                range: None,
+                transforms: vec![],
+            }
+        }
+        .compile()
+        .is_err());
+    }
+
+    #[test]
+    fn test_trigger_range() {
+        let mut trigger = Trigger {
+            pattern: Pattern {
+                content: String::from(r"\$(?P<number>[\d,.]+)"),
+                kind: PatternKind::RegEx,
+                normalization: None,
+            },
+            id: String::from("A"),
+            tags: vec![],
+            negate: false,
+            content: None,
+            position: None,
+            range: Some(NumericRange {
+                min: Some(1_000_000.0),
+                max: None,
+            }),
+            transforms: vec![],
+        };
+        let compiled = trigger.compile().unwrap();
+        let text = "They paid $250,000 in 2019 and $1,250,000.50 in 2020.";
+        assert!(compiled.evaluate(text));
+        let matches = compiled.full_check_all(text);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].captures["number"], "1,250,000.50");
+        assert!(!compiled.evaluate("They paid $250,000."));
+
+        trigger.range = Some(NumericRange {
+            min: Some(100.0),
+            max: Some(300_000.0),
+        });
+        let compiled = trigger.compile().unwrap();
+        assert_eq!(compiled.full_check_all(text).len(), 1);
+        assert_eq!(parse_number("-3.5%"), Some(-3.5));
+        assert_eq!(parse_number("$1,250,000.50"), Some(1_250_000.5));
+        assert_eq!(parse_number("2019-2020"), Some(2019.0));
+        assert_eq!(parse_number("n/a"), None);
+
+        trigger.range = Some(NumericRange {
+            min: Some(2.0),
+            max: Some(1.0),
+        });
+        assert!(trigger.compile().is_err());
     }
 }
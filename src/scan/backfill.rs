@@ -0,0 +1,188 @@
+//! This file provides functionality related to backfilling: draining
+//! a historical source of documents before monitoring a live one.
+
+use common::checksum::Checksum;
+use common::validation::Issue;
+use input::document::{DocumentReference, DocumentReferenceBatch};
+use output::output::OutputBatch;
+use scan::scanner::AsyncScanInterface;
+use std::collections::HashSet;
+
+/// Represents the phase of a `Backfill`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BackfillPhase {
+    /// The historical (archive) source is being drained.
+    Archive,
+    /// The archive has been drained, and the live source is being
+    /// monitored.
+    Live,
+}
+
+/// A `Backfill` feeds a scan engine first from a historical source (such
+/// as a directory of archived documents) and then from a live source
+/// (such as a watched directory), the standard "catch up, then monitor"
+/// deployment pattern.
+///
+/// Both sources are scanned by the same engine—and therefore the same
+/// query group—and share the same deduplication state: a document that
+/// appears in both sources (or more than once in either) is only scanned
+/// once. Documents are identified by their path or URL, or, when they
+/// have neither, by a checksum of their data.
+pub struct Backfill {
+    /// The identities of the documents that have been sent to the engine.
+    seen: HashSet<String>,
+    /// The current phase of the backfill.
+    phase: BackfillPhase,
+}
+
+impl Backfill {
+    /// Create a new `Backfill` in the `Archive` phase that has not yet
+    /// seen any documents.
+    pub fn new() -> Backfill {
+        Backfill {
+            seen: HashSet::new(),
+            phase: BackfillPhase::Archive,
+        }
+    }
+
+    /// The current phase of the backfill.
+    pub fn phase(&self) -> BackfillPhase {
+        self.phase
+    }
+
+    /// The number of distinct documents that have been admitted so far.
+    pub fn documents_seen(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Removes the documents that have already been admitted from the
+    /// given batch, and records the rest as admitted. The batch keeps
+    /// its ID.
+    pub fn admit(&mut self, batch: DocumentReferenceBatch) -> DocumentReferenceBatch {
+        let mut documents: Vec<DocumentReference> = Vec::new();
+        for document_reference in batch.documents {
+            if self.seen.insert(identify(&document_reference)) {
+                documents.push(document_reference);
+            }
+        }
+        DocumentReferenceBatch {
+            documents,
+            id: batch.id,
+        }
+    }
+
+    /// Drains the `archive` into the given scan engine, then switches to
+    /// the `live` source, which may be endless. Outputs are passed to
+    /// `on_outputs` as they become available, without waiting for the
+    /// engine; once the sources are exhausted, the caller should shut
+    /// down the engine and collect the remaining outputs as usual.
+    ///
+    /// Batches that contain only already-admitted documents are not sent.
+    /// An `Issue::Error` is returned if the engine stops accepting
+    /// documents.
+    pub fn run<A, L, F>(
+        &mut self,
+        interface: &AsyncScanInterface,
+        archive: A,
+        live: L,
+        mut on_outputs: F,
+    ) -> Result<(), Issue>
+    where
+        A: IntoIterator<Item = DocumentReferenceBatch>,
+        L: IntoIterator<Item = DocumentReferenceBatch>,
+        F: FnMut(OutputBatch),
+    {
+        self.phase = BackfillPhase::Archive;
+        for batch in archive {
+            self.send(interface, batch)?;
+            for outputs in interface.outputs() {
+                on_outputs(outputs);
+            }
+        }
+        self.phase = BackfillPhase::Live;
+        for batch in live {
+            self.send(interface, batch)?;
+            for outputs in interface.outputs() {
+                on_outputs(outputs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the unseen documents of the batch to the scan engine.
+    fn send(
+        &mut self,
+        interface: &AsyncScanInterface,
+        batch: DocumentReferenceBatch,
+    ) -> Result<(), Issue> {
+        let batch = self.admit(batch);
+        if batch.documents.is_empty() {
+            return Ok(());
+        }
+        interface.process(batch).map_err(|_| {
+            Issue::Error(String::from(
+                "unable to transmit batch to scan engine; is it shut down?",
+            ))
+        })
+    }
+}
+
+impl Default for Backfill {
+    fn default() -> Backfill {
+        Backfill::new()
+    }
+}
+
+/// Determines the identity used to deduplicate the given document.
+fn identify(document_reference: &DocumentReference) -> String {
+    match document_reference {
+        DocumentReference::Unpopulated(path) => path.clone(),
+        DocumentReference::Populated(document) => match &document.url {
+            Some(url) => url.clone(),
+            None => {
+                let mut checksum = Checksum::new();
+                checksum.update(&document.data);
+                format!("#{:016x}", checksum.value())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compilation::CompilableTo;
+    use input::document::Document;
+    use query::query::tests::get_basic_query;
+    use scan::scanner::Scanner;
+
+    #[test]
+    fn test_scan_backfill() {
+        let document = |url: &str| {
+            DocumentReference::Populated(Document::from_text(url, "hello everyone"))
+        };
+        let archive = vec![
+            DocumentReferenceBatch::from(vec![document("a"), document("b")]),
+            DocumentReferenceBatch::from(vec![document("a")]),
+        ];
+        // `b` was already scanned in the archive, so only `c` is new
+        let live = vec![DocumentReferenceBatch::from(vec![document("b"), document("c")])];
+
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently(2);
+        let mut backfill = Backfill::new();
+        let mut outputs = OutputBatch::new();
+        backfill
+            .run(&engine, archive, live, |batch| outputs.merge_with(batch))
+            .unwrap();
+        assert_eq!(backfill.phase(), BackfillPhase::Live);
+        assert_eq!(backfill.documents_seen(), 3);
+        engine.shutdown();
+        while let Ok(batch) = engine.lock_for_outputs() {
+            outputs.merge_with(batch);
+        }
+        assert_eq!(outputs.outputs.len(), 3);
+        let late = vec![DocumentReferenceBatch::from(vec![document("d")])];
+        assert!(backfill.run(&engine, vec![], late, |_| ()).is_err());
+    }
+}
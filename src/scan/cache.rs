@@ -0,0 +1,188 @@
+//! This file provides an incremental scan cache, which records the
+//! documents that a query group has scanned so that later scans of the
+//! same corpus—nightly re-scans of a largely static archive, say—can skip
+//! the documents that have not changed since.
+
+use common::validation::Issue;
+use input::document::Document;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Read, Write};
+use std::sync::Mutex;
+
+/// The length of fingerprints and keys, which are SHA-256 hashes in
+/// hexadecimal.
+const HASH_LENGTH: usize = 64;
+
+/// A `ScanCache` records which documents have been scanned by which query
+/// groups: each entry is a query group's fingerprint (see
+/// `ScanOptions::cache_fingerprint()`) and a document's key (see
+/// `ScanCache::key()`), which changes whenever the document's URL or
+/// content does. A scan engine with a cache (see `ScanOptions.cache`)
+/// skips the documents that its query group has already scanned, and
+/// records the ones that it scans.
+///
+/// Caches are either held in memory, for the life of a process, or on
+/// disk, in a file to which each entry is appended as a line of the form
+/// `<fingerprint> <key>`, so that they persist between runs. Entries of
+/// other query groups are kept, so that one cache can serve several.
+///
+/// Documents are keyed by their bytes, before they are compiled; the
+/// fingerprint includes the options that change how they are compiled
+/// (such as `ScanOptions.text_extraction`), so scans with different
+/// options do not share entries.
+pub struct ScanCache {
+    /// The entries of the cache, each of the form `<fingerprint> <key>`.
+    entries: Mutex<HashSet<String>>,
+    /// The file to which new entries are appended, for caches on disk.
+    file: Option<Mutex<LineWriter<File>>>,
+}
+
+impl ScanCache {
+    /// Create a new, empty `ScanCache` that is held in memory.
+    pub fn in_memory() -> ScanCache {
+        ScanCache {
+            entries: Mutex::new(HashSet::new()),
+            file: None,
+        }
+    }
+
+    /// Opens the `ScanCache` in the file at the given path, creating the
+    /// file if it does not exist. Lines that are not entries (such as a
+    /// line that was only partially written when a scan crashed) are
+    /// ignored.
+    pub fn open(path: &str) -> Result<ScanCache, Issue> {
+        let failed = |error: std::io::Error| Issue::Error(format!("unable to open scan cache `{}` (`{}`)", path, error));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(failed)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(failed)?;
+        let entries: HashSet<String> = contents
+            .lines()
+            .filter(|line| {
+                let parts: Vec<&str> = line.split(' ').collect();
+                parts.len() == 2
+                    && parts
+                        .iter()
+                        .all(|part| part.len() == HASH_LENGTH && part.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            })
+            .map(String::from)
+            .collect();
+        // a partially written line must not run into the next entry
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            file.write_all(b"\n").map_err(failed)?;
+        }
+        Ok(ScanCache {
+            entries: Mutex::new(entries),
+            file: Some(Mutex::new(LineWriter::new(file))),
+        })
+    }
+
+    /// The key of the given document in the cache: the SHA-256 hash, in
+    /// hexadecimal, of its URL and its data.
+    pub fn key(document: &Document) -> String {
+        let mut hasher = Sha256::new();
+        match &document.url {
+            Some(url) => {
+                hasher.update((url.len() as u64).to_le_bytes());
+                hasher.update(url.as_bytes());
+            }
+            None => hasher.update([0xff; 8]),
+        }
+        hasher.update(&document.data);
+        hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Whether the query group with the given fingerprint has scanned the
+    /// document with the given key.
+    pub fn contains(&self, fingerprint: &str, key: &str) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .contains(&format!("{} {}", fingerprint, key))
+    }
+
+    /// Records that the query group with the given fingerprint has scanned
+    /// the document with the given key, appending the entry to the cache's
+    /// file (if any) when it is new. Returns an error when the entry cannot
+    /// be written, in which case it is still recorded in memory.
+    pub fn insert(&self, fingerprint: &str, key: &str) -> Result<(), Issue> {
+        let entry = format!("{} {}", fingerprint, key);
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains(&entry) {
+            return Ok(());
+        }
+        let written = match &self.file {
+            Some(file) => writeln!(file.lock().unwrap(), "{}", entry)
+                .map_err(|error| Issue::Error(format!("unable to write to scan cache (`{}`)", error))),
+            None => Ok(()),
+        };
+        entries.insert(entry);
+        written
+    }
+
+    /// The number of entries in the cache, across every query group.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for ScanCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScanCache")
+            .field("entries", &self.len())
+            .field("on_disk", &self.file.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_scan_cache() {
+        let key = ScanCache::key(&Document::from_text("/corpus/a.html", "hello"));
+        assert_ne!(key, ScanCache::key(&Document::from_text("/corpus/a.html", "hello!")));
+        assert_ne!(key, ScanCache::key(&Document::from_text("/corpus/b.html", "hello")));
+        assert_eq!(key, ScanCache::key(&Document::from_text("/corpus/a.html", "hello")));
+
+        // any hash will do as a fingerprint
+        let first = ScanCache::key(&Document::from_text("first", ""));
+        let second = ScanCache::key(&Document::from_text("second", ""));
+        let path = std::env::temp_dir().join(format!("ieql-scan-cache-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let cache = ScanCache::open(path).unwrap();
+        assert!(cache.is_empty());
+        cache.insert(&first, &key).unwrap();
+        cache.insert(&first, &key).unwrap();
+        assert!(cache.contains(&first, &key));
+        assert!(!cache.contains(&second, &key));
+        drop(cache);
+
+        // entries persist, and a partially written entry is ignored
+        let partial = format!("{} {}", first, &key[..10]);
+        fs::OpenOptions::new().append(true).open(path).unwrap().write_all(partial.as_bytes()).unwrap();
+        let cache = ScanCache::open(path).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains(&first, &key));
+        cache.insert(&second, &key).unwrap();
+        drop(cache);
+        let contents = fs::read_to_string(path).unwrap();
+        assert_eq!(contents, format!("{} {}\n{}\n{} {}\n", first, key, partial, second, key));
+        let _ = fs::remove_file(path);
+    }
+}
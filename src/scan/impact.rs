@@ -0,0 +1,128 @@
+//! This file provides functionality related to simulating the impact
+//! of query changes.
+
+use input::document::CompiledDocumentBatch;
+use scan::scanner::Scanner;
+use std::collections::BTreeSet;
+
+/// Identifies a single match: a query (by its ID) matching a document
+/// (by its URL).
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MatchIdentity {
+    /// The ID of the query that matched, if present.
+    pub query_id: Option<String>,
+    /// The URL of the document that was matched, if present.
+    pub url: Option<String>,
+}
+
+/// An `ImpactReport` describes how the set of matches over a corpus
+/// changes when one set of queries is replaced by another—for example,
+/// when a query is edited. It allows query authors to safely iterate on
+/// production monitoring rules.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ImpactReport {
+    /// Matches produced only by the proposed queries.
+    pub new_matches: Vec<MatchIdentity>,
+    /// Matches produced only by the current queries.
+    pub lost_matches: Vec<MatchIdentity>,
+    /// Matches produced by both the current and the proposed queries.
+    pub retained_matches: Vec<MatchIdentity>,
+}
+
+/// Scans the given documents with both the `current` and the `proposed`
+/// scanners (typically `CompiledQuery`s or `CompiledQueryGroup`s) and
+/// reports how the matches differ.
+///
+/// Matches are identified by their query ID and document URL, so the
+/// proposed queries should keep the IDs of the queries they replace.
+/// The matches in the report are sorted.
+pub fn simulate_impact<C: Scanner, P: Scanner>(
+    current: &C,
+    proposed: &P,
+    documents: &CompiledDocumentBatch,
+) -> ImpactReport {
+    let mut current_matches: BTreeSet<MatchIdentity> = BTreeSet::new();
+    let mut proposed_matches: BTreeSet<MatchIdentity> = BTreeSet::new();
+    for document in &documents.documents {
+        for output in current.scan_single(document).outputs {
+            current_matches.insert(MatchIdentity {
+                query_id: output.query_id,
+                url: document.url.clone(),
+            });
+        }
+        for output in proposed.scan_single(document).outputs {
+            proposed_matches.insert(MatchIdentity {
+                query_id: output.query_id,
+                url: document.url.clone(),
+            });
+        }
+    }
+    ImpactReport {
+        new_matches: proposed_matches
+            .difference(&current_matches)
+            .cloned()
+            .collect(),
+        lost_matches: current_matches
+            .difference(&proposed_matches)
+            .cloned()
+            .collect(),
+        retained_matches: current_matches
+            .intersection(&proposed_matches)
+            .cloned()
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compilation::CompilableTo;
+    use input::document::{Document, DocumentBatch};
+    use query::query::tests::get_basic_query;
+
+    fn identity(url: &str) -> MatchIdentity {
+        MatchIdentity {
+            query_id: Some(String::from("Test Trigger #1")),
+            url: Some(String::from(url)),
+        }
+    }
+
+    #[test]
+    fn test_simulate_impact() {
+        let documents = DocumentBatch::from(vec![
+            Document::from_text("https://example.com/d", "hello everyone"),
+            Document::from_text("https://example.com/c", "hello around"),
+            Document::from_text("https://example.com/b", "hello there"),
+            Document::from_text("https://example.com/a", "nothing to see"),
+        ])
+        .compile()
+        .unwrap();
+        let current = get_basic_query().compile().unwrap();
+        let mut edited = get_basic_query();
+        edited.triggers[2].pattern.content = String::from("there"); // rather than `around`
+        let proposed = edited.compile().unwrap();
+
+        let report = simulate_impact(&current, &proposed, &documents);
+        assert_eq!(report.new_matches, vec![identity("https://example.com/b")]);
+        assert_eq!(report.lost_matches, vec![identity("https://example.com/c")]);
+        assert_eq!(report.retained_matches, vec![identity("https://example.com/d")]);
+
+        // unchanged queries retain every match
+        let report = simulate_impact(&current, &current, &documents);
+        assert!(report.new_matches.is_empty());
+        assert!(report.lost_matches.is_empty());
+        assert_eq!(
+            report.retained_matches,
+            vec![identity("https://example.com/c"), identity("https://example.com/d")]
+        );
+
+        // a renamed query loses its old matches and gains them under its new ID
+        let mut renamed = get_basic_query();
+        renamed.id = Some(String::from("Renamed"));
+        let report = simulate_impact(&current, &renamed.compile().unwrap(), &documents);
+        assert_eq!(report.new_matches.len(), 2);
+        assert_eq!(report.lost_matches.len(), 2);
+        assert!(report.retained_matches.is_empty());
+        assert_eq!(report.new_matches[0].query_id.as_deref(), Some("Renamed"));
+    }
+}
@@ -0,0 +1,253 @@
+//! This file provides metrics of the scan engine—how many documents it
+//! has scanned, how long they took, and how many outputs each query has
+//! produced—which can be rendered in the Prometheus text exposition
+//! format for monitoring.
+
+use output::output::OutputBatch;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds, in seconds, of the buckets of the histogram of
+/// document scan durations.
+pub const DURATION_BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+/// `ScanMetrics` records the activity of a scan engine as it happens. It
+/// is shared by the engine's threads, which update it live; see
+/// `AsyncScanInterface::metrics()`. Take a `snapshot()` to read it.
+#[derive(Debug, Default)]
+pub struct ScanMetrics {
+    snapshot: Mutex<MetricsSnapshot>,
+}
+
+/// A `MetricsSnapshot` is the state of a `ScanMetrics` at a point in time.
+/// Every count is cumulative over the life of the engine, so rates (such
+/// as documents scanned per second) are derived by comparing snapshots—
+/// or, once exported with `to_prometheus()`, with Prometheus's `rate()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// The number of documents that have been loaded and compiled, and so
+    /// are either scanned or waiting to be scanned.
+    pub documents_loaded: u64,
+    /// The number of documents that could not be loaded or compiled.
+    pub documents_failed: u64,
+    /// The number of documents that have been scanned.
+    pub documents_scanned: u64,
+    /// The number of documents that were skipped for exceeding the time
+    /// budget (see `ScanOptions.document_time_budget`).
+    pub documents_skipped: u64,
+    /// The number of documents that were not scanned because they had not
+    /// changed since they were last scanned (see `ScanOptions.cache`).
+    pub documents_unchanged: u64,
+    /// The number of outputs produced by each query, keyed by query ID.
+    /// (Outputs of queries without an ID are keyed `unknown_query`.)
+    pub outputs: BTreeMap<String, u64>,
+    /// The number of document scans whose duration fell into each bucket
+    /// of `DURATION_BUCKETS`, followed by the number that exceeded every
+    /// bucket. (Unlike Prometheus's buckets, these are not cumulative.)
+    pub duration_buckets: Vec<u64>,
+    /// The total time spent scanning documents (including skipped
+    /// documents).
+    pub duration_total: Duration,
+}
+
+impl ScanMetrics {
+    /// Create a new `ScanMetrics`, with every count at zero.
+    pub fn new() -> ScanMetrics {
+        ScanMetrics::default()
+    }
+
+    /// Takes a snapshot of the metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+
+    /// Records that a document was loaded and compiled.
+    pub fn record_loaded(&self) {
+        self.snapshot.lock().unwrap().documents_loaded += 1;
+    }
+
+    /// Records that a document could not be loaded or compiled.
+    pub fn record_failed(&self) {
+        self.snapshot.lock().unwrap().documents_failed += 1;
+    }
+
+    /// Records that a document was scanned in the given time, producing
+    /// the given outputs.
+    pub fn record_scanned(&self, duration: Duration, outputs: &OutputBatch) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.documents_scanned += 1;
+        snapshot.record_duration(duration);
+        for output in &outputs.outputs {
+            let query_id = output.query_id.as_deref().unwrap_or("unknown_query");
+            *snapshot.outputs.entry(String::from(query_id)).or_insert(0) += 1;
+        }
+    }
+
+    /// Records that a document was skipped for exceeding the time budget
+    /// after the given time.
+    pub fn record_skipped(&self, duration: Duration) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.documents_skipped += 1;
+        snapshot.record_duration(duration);
+    }
+
+    /// Records that a document was not scanned because it had not changed
+    /// since it was last scanned.
+    pub fn record_unchanged(&self) {
+        self.snapshot.lock().unwrap().documents_unchanged += 1;
+    }
+}
+
+/// Escapes the given value of a Prometheus label.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the help and type lines of a metric.
+fn write_header(rendered: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(rendered, "# HELP {} {}", name, help);
+    let _ = writeln!(rendered, "# TYPE {} {}", name, kind);
+}
+
+impl MetricsSnapshot {
+    /// Counts a document scan of the given duration.
+    fn record_duration(&mut self, duration: Duration) {
+        if self.duration_buckets.is_empty() {
+            self.duration_buckets = vec![0; DURATION_BUCKETS.len() + 1];
+        }
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket] += 1;
+        self.duration_total += duration;
+    }
+
+    /// The total number of outputs, across every query.
+    pub fn outputs_total(&self) -> u64 {
+        self.outputs.values().sum()
+    }
+
+    /// The number of documents that have been loaded but not yet scanned
+    /// (or skipped). When this grows, the scanners are not keeping up with
+    /// the loaders.
+    pub fn documents_in_flight(&self) -> u64 {
+        self.documents_loaded
+            .saturating_sub(self.documents_scanned + self.documents_skipped)
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format
+    /// (version 0.0.4). Every metric is prefixed with `ieql_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut rendered = String::new();
+        let counters = [
+            ("ieql_documents_loaded_total", "The number of documents loaded and compiled.", self.documents_loaded),
+            ("ieql_documents_failed_total", "The number of documents that could not be loaded or compiled.", self.documents_failed),
+            ("ieql_documents_scanned_total", "The number of documents scanned.", self.documents_scanned),
+            ("ieql_documents_skipped_total", "The number of documents skipped for exceeding the time budget.", self.documents_skipped),
+            ("ieql_documents_unchanged_total", "The number of documents not scanned because they were unchanged.", self.documents_unchanged),
+        ];
+        for (name, help, value) in counters.iter() {
+            write_header(&mut rendered, name, "counter", help);
+            let _ = writeln!(rendered, "{} {}", name, value);
+        }
+
+        write_header(
+            &mut rendered,
+            "ieql_documents_in_flight",
+            "gauge",
+            "The number of documents loaded but not yet scanned.",
+        );
+        let _ = writeln!(rendered, "ieql_documents_in_flight {}", self.documents_in_flight());
+
+        write_header(
+            &mut rendered,
+            "ieql_outputs_total",
+            "counter",
+            "The number of outputs produced, by query.",
+        );
+        for (query_id, count) in &self.outputs {
+            let _ = writeln!(
+                rendered,
+                "ieql_outputs_total{{query=\"{}\"}} {}",
+                escape_label(query_id),
+                count
+            );
+        }
+
+        write_header(
+            &mut rendered,
+            "ieql_document_scan_duration_seconds",
+            "histogram",
+            "The time spent scanning each document.",
+        );
+        let mut cumulative = 0;
+        for (index, bound) in DURATION_BUCKETS.iter().enumerate() {
+            cumulative += self.duration_buckets.get(index).cloned().unwrap_or(0);
+            let _ = writeln!(
+                rendered,
+                "ieql_document_scan_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let count: u64 = self.duration_buckets.iter().sum();
+        let _ = writeln!(
+            rendered,
+            "ieql_document_scan_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            rendered,
+            "ieql_document_scan_duration_seconds_sum {}",
+            self.duration_total.as_secs_f64()
+        );
+        let _ = writeln!(rendered, "ieql_document_scan_duration_seconds_count {}", count);
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::output::Output;
+
+    #[test]
+    fn test_metrics_to_prometheus() {
+        let output = |query_id: Option<&str>| Output {
+            query_id: query_id.map(String::from),
+            ..Output::from_url("https://example.com")
+        };
+        let metrics = ScanMetrics::new();
+        for _ in 0..4 {
+            metrics.record_loaded();
+        }
+        metrics.record_failed();
+        let outputs = OutputBatch::from(vec![output(Some("Q\"1")), output(Some("Q\"1")), output(None)]);
+        metrics.record_scanned(Duration::from_millis(2), &outputs);
+        metrics.record_scanned(Duration::from_millis(20), &OutputBatch::new());
+        metrics.record_skipped(Duration::from_secs(30));
+        metrics.record_unchanged();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.outputs_total(), 3);
+        assert_eq!(snapshot.documents_in_flight(), 1);
+        let rendered = snapshot.to_prometheus();
+        assert!(rendered.contains("# TYPE ieql_documents_scanned_total counter\nieql_documents_scanned_total 2\n"));
+        assert!(rendered.contains("ieql_documents_failed_total 1\n"));
+        assert!(rendered.contains("ieql_documents_unchanged_total 1\n"));
+        assert!(rendered.contains("ieql_outputs_total{query=\"Q\\\"1\"} 2\n"));
+        assert!(rendered.contains("ieql_outputs_total{query=\"unknown_query\"} 1\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_sum 30.022\n"));
+        assert!(rendered.contains("ieql_document_scan_duration_seconds_count 3\n"));
+    }
+}
@@ -1,4 +1,8 @@
 //! This module provides functionality related to scanning
 //! and scan engines.
 
-pub mod scanner;
\ No newline at end of file
+pub mod scanner;
+pub mod impact;
+pub mod backfill;
+pub mod metrics;
+pub mod cache;
@@ -1,18 +1,26 @@
 //! This file provides functionality related to scanning.
 
-use common::compilation::CompilableTo;
-use common::pattern::PatternMatch;
+use common::pattern::{locate_matches, PatternMatch};
 use common::retrieve::load_document;
+use common::validation::{Issue, Validatable};
 use input::document::{
-    CompiledDocument, CompiledDocumentBatch, Document, DocumentBatch, DocumentReference,
-    DocumentReferenceBatch,
+    hash_bytes, CompiledDocument, CompiledDocumentBatch, Document, DocumentReference,
+    DocumentReferenceBatch, Provenance, TextExtraction,
 };
-use output::output::{Output, OutputBatch};
+use input::stream::{DocumentWindows, StreamingOptions};
+use output::output::{generate_output_id, Output, OutputBatch};
+use output::sink::OutputSink;
 use query::query::{CompiledQuery, CompiledQueryGroup};
+use scan::cache::ScanCache;
+use scan::metrics::ScanMetrics;
+use query::scope::{ContentTransform, ScopeContent};
+use query::trigger::CompiledTrigger;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 /// This trait specifies basic scanning functionality.
 pub trait Scanner: Clone + Send {
@@ -27,7 +35,358 @@ pub trait Scanner: Clone + Send {
     /// For more information about how to interact with the scanning system
     /// (sometimes referred to as the _scan engine_), please see the documentation
     /// pertaining to `AsyncScanInterface`.
-    fn scan_concurrently(&self, threads: u8) -> AsyncScanInterface;
+    ///
+    /// An engine cannot make progress without any threads, so `0` threads
+    /// is treated as `1`; a `Warning` is made available through
+    /// `AsyncScanInterface::issues()` when this happens.
+    fn scan_concurrently(&self, threads: u8) -> AsyncScanInterface {
+        self.scan_concurrently_with(ScanOptions {
+            threads,
+            ..ScanOptions::default()
+        })
+    }
+    /// Launch a 'scan engine' configured by the given `ScanOptions`. This
+    /// is identical to `scan_concurrently()`, but allows for the engine's
+    /// behavior to be tuned. (See `ScanOptions` for more information.)
+    fn scan_concurrently_with(&self, options: ScanOptions) -> AsyncScanInterface {
+        self.scan_concurrently_into(options, Vec::new())
+    }
+    /// Launch a 'scan engine' configured by the given `ScanOptions` that
+    /// delivers its outputs to the given sinks. Outputs are sent to every
+    /// sink directly from the scanner threads (each sink is only used by
+    /// one thread at a time), rather than through the `AsyncScanInterface`.
+    ///
+    /// When sinks are given, outputs are _not_ available through
+    /// `AsyncScanInterface::lock_for_outputs()` or `outputs()`; however,
+    /// `lock_for_outputs()` still returns an error once the engine has
+    /// finished, by which point every sink has been closed. Issues that
+    /// sinks encounter are made available through `issues()`.
+    fn scan_concurrently_into(
+        &self,
+        options: ScanOptions,
+        sinks: Vec<Box<dyn OutputSink + Send>>,
+    ) -> AsyncScanInterface;
+}
+
+/// `ScanOptions` configures the behavior of the scan engine launched
+/// by `scan_concurrently_with()`.
+///
+/// The scan engine is a pipeline: _loader_ threads read (for unpopulated
+/// document references) and compile documents, which are then passed to
+/// _scanner_ threads that run the queries. Because loading is typically
+/// I/O-bound and scanning CPU-bound, the two are sized separately so that
+/// disk or network latency does not starve the scanners.
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// The number of threads that will scan documents.
+    pub threads: u8,
+    /// The number of threads that will load and compile documents.
+    pub loader_threads: u8,
+    /// The maximum number of bytes of document content that the engine
+    /// may hold in memory at once—that is, documents that have been loaded
+    /// but not yet scanned. When the budget is exhausted, the engine waits
+    /// for in-flight documents to be scanned before loading more.
+    ///
+    /// A single document larger than the budget is still scanned, but only
+    /// once it is the only document in flight. When `None`, memory usage
+    /// is unbounded.
+    pub memory_budget: Option<usize>,
+    /// The maximum amount of time that may be spent scanning a single
+    /// document. Documents that exceed this budget are skipped, and a
+    /// diagnostic `Issue` is made available through `AsyncScanInterface::issues()`.
+    /// When `None`, documents may take arbitrarily long to scan.
+    pub document_time_budget: Option<Duration>,
+    /// The caps on the number of outputs that the engine produces per
+    /// document and per query. (See `OutputLimits`.)
+    pub output_limits: OutputLimits,
+    /// The maximum number of output batches that may wait to be received
+    /// through `AsyncScanInterface`. When the capacity is reached, scanner
+    /// threads wait for outputs to be received before sending more, so
+    /// that a slow consumer—such as a webhook sink that is retrying a
+    /// delivery—slows the scan rather than accumulating outputs in memory.
+    /// When `None`, any number of outputs may wait.
+    pub output_capacity: Option<usize>,
+    /// How the loader threads extract the text of HTML documents. (See
+    /// `TextExtraction`.)
+    pub text_extraction: TextExtraction,
+    /// Whether (and how) the loader threads stream files that are too
+    /// large to load whole, scanning them in overlapping windows rather
+    /// than as one document. Each window is a document for the purposes
+    /// of the memory budget, the time budget, output limits, and metrics.
+    /// (See `StreamingOptions`.) When `None`, files are always loaded whole.
+    pub streaming: Option<StreamingOptions>,
+    /// The cache of documents that the query group has already scanned,
+    /// if any. The loader threads skip documents that are in the cache
+    /// (before compiling them), and the scanner threads record documents
+    /// in the cache once they have been scanned and their outputs have
+    /// been accepted by every sink (or by the output channel). Documents
+    /// that exceed the time budget, or whose outputs a sink fails to
+    /// accept, are not recorded, and so are scanned again next time. (See
+    /// `ScanCache`.) When `None`, every document is scanned.
+    pub cache: Option<Arc<ScanCache>>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            threads: 8,
+            loader_threads: 2,
+            memory_budget: None,
+            document_time_budget: None,
+            output_limits: OutputLimits::default(),
+            output_capacity: None,
+            text_extraction: TextExtraction::default(),
+            streaming: None,
+            cache: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// The fingerprint under which the given group's scans are recorded
+    /// in the cache: the group's own fingerprint (see
+    /// `CompiledQueryGroup::fingerprint()`), combined with the options
+    /// that change what the group matches—how text is extracted, and
+    /// whether large files are streamed in windows.
+    pub fn cache_fingerprint(&self, group: &CompiledQueryGroup) -> String {
+        let options = format!("{:?} {:?}", self.text_extraction, self.streaming);
+        hash_bytes(format!("{} {}", group.fingerprint(), options).as_bytes())
+    }
+}
+
+impl Validatable for ScanOptions {
+    /// Ensures that the engine will be able to make progress. Engines
+    /// without any scanner or loader threads are invalid; a memory budget
+    /// of zero bytes is permitted, but only one document will be held in
+    /// memory at a time.
+    fn validate(&self) -> Option<Vec<Issue>> {
+        let mut issues: Vec<Issue> = Vec::new();
+        if self.threads == 0 {
+            issues.push(Issue::Error(String::from(
+                "the scan engine requires at least one scanner thread",
+            )));
+        }
+        if self.loader_threads == 0 {
+            issues.push(Issue::Error(String::from(
+                "the scan engine requires at least one loader thread",
+            )));
+        }
+        if self.memory_budget == Some(0) {
+            issues.push(Issue::Warning(String::from(
+                "a memory budget of zero bytes permits only one document in memory at a time",
+            )));
+        }
+        if let Some(problems) = self.streaming.and_then(|streaming| streaming.validate()) {
+            issues.extend(problems);
+        }
+        if self.output_limits.per_document == Some(0) || self.output_limits.per_query == Some(0) {
+            issues.push(Issue::Warning(String::from(
+                "an output cap of zero discards every output",
+            )));
+        }
+        if issues.is_empty() {
+            None
+        } else {
+            Some(issues)
+        }
+    }
+}
+
+/// `OutputLimits` caps the number of outputs that a scan produces, so
+/// that a single pathological document (such as a page that matches
+/// hundreds of queries) or an overly broad query does not flood the
+/// output channel and the systems downstream of it. Outputs beyond a cap
+/// are dropped, and a `Warning` is issued when a cap is first exceeded.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct OutputLimits {
+    /// The maximum number of outputs per document. Queries are run in the
+    /// order of the group, so the outputs of its earlier queries are kept.
+    pub per_document: Option<usize>,
+    /// The maximum number of outputs that each query may produce over
+    /// the course of a scan (that is, across all of its documents).
+    pub per_query: Option<usize>,
+}
+
+/// `OutputCounts` records the number of outputs that each query of a
+/// `CompiledQueryGroup` has produced during a scan, in order to enforce
+/// `OutputLimits.per_query`. It is shared by the threads of an engine.
+#[derive(Debug, Default)]
+pub struct OutputCounts {
+    counts: Mutex<HashMap<usize, usize>>,
+}
+
+impl OutputCounts {
+    /// The number of outputs that the query at the given position has
+    /// produced so far.
+    fn get(&self, position: usize) -> usize {
+        *self.counts.lock().unwrap().get(&position).unwrap_or(&0)
+    }
+
+    /// Counts an output of the query at the given position, returning the
+    /// number of outputs that it had produced before.
+    fn increment(&self, position: usize) -> usize {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(position).or_insert(0);
+        *count += 1;
+        *count - 1
+    }
+}
+
+/// `OutputLimiter` enforces `OutputLimits` on the outputs of a single
+/// document.
+struct OutputLimiter<'a> {
+    limits: OutputLimits,
+    counts: &'a OutputCounts,
+    /// The number of outputs of the document that have been kept.
+    kept: usize,
+    /// Whether the document has exceeded `OutputLimits.per_document`.
+    overflowed: bool,
+}
+
+impl<'a> OutputLimiter<'a> {
+    /// Whether the query at the given position need not be run, because
+    /// any outputs that it would produce are dropped (and the drop has
+    /// already been reported).
+    fn skips(&self, position: usize) -> bool {
+        self.overflowed
+            || match self.limits.per_query {
+                Some(cap) => self.counts.get(position) > cap,
+                None => false,
+            }
+    }
+
+    /// Adds the outputs of the query at the given position to the batch,
+    /// except for those beyond a cap, reporting the first drop of each.
+    fn admit(
+        &mut self,
+        position: usize,
+        query: &CompiledQuery,
+        document: &CompiledDocument,
+        outputs: OutputBatch,
+        batch: &mut OutputBatch,
+        issues: &mut Vec<Issue>,
+    ) {
+        for output in outputs.outputs {
+            if let Some(cap) = self.limits.per_document {
+                if self.kept >= cap {
+                    if !self.overflowed {
+                        self.overflowed = true;
+                        issues.push(Issue::Warning(format!(
+                            "document `{}` exceeded the cap of {} output(s); dropped its further outputs",
+                            document_name(document),
+                            cap
+                        )));
+                    }
+                    return;
+                }
+            }
+            if let Some(cap) = self.limits.per_query {
+                let count = self.counts.increment(position);
+                if count >= cap {
+                    if count == cap {
+                        issues.push(Issue::Warning(format!(
+                            "query `{}` exceeded the cap of {} output(s); dropped its further outputs",
+                            query.id.as_deref().unwrap_or("unknown_query"),
+                            cap
+                        )));
+                    }
+                    continue;
+                }
+            }
+            self.kept += 1;
+            batch.outputs.push(output);
+        }
+    }
+}
+
+/// `MemoryBudget` tracks the number of bytes of document content held by
+/// the scan engine, blocking threads that would exceed its limit.
+struct MemoryBudget {
+    limit: usize,
+    in_use: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A `MemoryReservation` represents bytes reserved from a `MemoryBudget`;
+/// the bytes are released when the reservation is dropped.
+struct MemoryReservation {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+/// A `LoadedDocument` is a compiled document that is waiting to be
+/// scanned, along with the memory that it has reserved (if any) and its
+/// key in the engine's `ScanCache` (if any).
+struct LoadedDocument {
+    document: CompiledDocument,
+    _reservation: Option<MemoryReservation>,
+    cache_key: Option<String>,
+}
+
+impl MemoryBudget {
+    fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Reserve the given number of bytes, blocking the current thread
+    /// until they are available. Oversized reservations are granted when
+    /// nothing else is in use, so that they cannot block forever.
+    fn reserve(budget: &Arc<MemoryBudget>, bytes: usize) -> MemoryReservation {
+        let mut in_use = budget.in_use.lock().unwrap();
+        while *in_use > 0 && *in_use + bytes > budget.limit {
+            in_use = budget.released.wait(in_use).unwrap();
+        }
+        *in_use += bytes;
+        MemoryReservation {
+            budget: budget.clone(),
+            bytes,
+        }
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Determines the number of bytes that the referenced document occupies
+/// (or will occupy, once loaded) in memory.
+fn reference_size(reference: &DocumentReference) -> usize {
+    match reference {
+        DocumentReference::Populated(document) => document.data.len(),
+        DocumentReference::Unpopulated(path) => match fs::metadata(path) {
+            Ok(metadata) => metadata.len() as usize,
+            Err(_) => 0, // loading will fail anyway
+        },
+    }
+}
+
+/// The documents that a loader thread loads from a single reference:
+/// the referenced document or, when it is streamed, its windows.
+type DocumentStream = Box<dyn Iterator<Item = Result<Document, Issue>>>;
+
+/// The sending half of the engine's output channel, which is bounded
+/// when the engine has an `output_capacity`.
+#[derive(Clone)]
+enum OutputSender {
+    Unbounded(mpsc::Sender<OutputBatch>),
+    Bounded(mpsc::SyncSender<OutputBatch>),
+}
+
+impl OutputSender {
+    /// Sends the batch, waiting for capacity if the channel is bounded.
+    fn send(&self, batch: OutputBatch) -> Result<(), mpsc::SendError<OutputBatch>> {
+        match self {
+            OutputSender::Unbounded(sender) => sender.send(batch),
+            OutputSender::Bounded(sender) => sender.send(batch),
+        }
+    }
 }
 
 /// `AsyncScanInterface` provides a simple interface, free of channels
@@ -35,12 +394,15 @@ pub trait Scanner: Clone + Send {
 pub struct AsyncScanInterface {
     outgoing_batches: Option<mpsc::Sender<DocumentReferenceBatch>>,
     incoming_outputs: mpsc::Receiver<OutputBatch>,
+    incoming_issues: mpsc::Receiver<Issue>,
     pending_processing: Arc<Mutex<isize>>, // having as `isize` avoids panics
+    metrics: Arc<ScanMetrics>,
 }
 
 impl AsyncScanInterface {
     /// Process the given documents. Note that this will temporarily lock
     /// the thread in order to increment the number of items processing.
+    #[allow(clippy::result_unit_err)]
     pub fn process(&self, batch: DocumentReferenceBatch) -> Result<(), ()> {
         match &self.outgoing_batches {
             Some(value) => match value.send(batch) {
@@ -63,7 +425,7 @@ impl AsyncScanInterface {
     /// that are currently processing (i.e. the total size of the current
     /// inter-thread queue).
     pub fn batches_pending_processing(&self) -> isize {
-        self.pending_processing.lock().unwrap().clone() // unsafe?
+        *self.pending_processing.lock().unwrap() // unsafe?
     }
 
     /// Retrieve the current outputs, if available. This will never lock
@@ -72,19 +434,28 @@ impl AsyncScanInterface {
     /// safe!
     pub fn outputs(&self) -> Vec<OutputBatch> {
         let mut outputs: Vec<OutputBatch> = Vec::new();
-        let mut received = self.incoming_outputs.try_recv();
-        loop {
-            match received {
-                Ok(values) => {
-                    outputs.push(values);
-                    received = self.incoming_outputs.try_recv();
-                },
-                Err(_) => break,
-            }
+        while let Ok(values) = self.incoming_outputs.try_recv() {
+            outputs.push(values);
         }
         outputs
+    }
+
+    /// Retrieve the issues encountered by the scan engine so far—for
+    /// example, documents that were skipped for exceeding the time budget.
+    /// Like `outputs()`, this will never lock the calling thread, and
+    /// issues are no longer present once they are received.
+    pub fn issues(&self) -> Vec<Issue> {
+        let mut issues: Vec<Issue> = Vec::new();
+        while let Ok(issue) = self.incoming_issues.try_recv() {
+            issues.push(issue);
+        }
+        issues
+    }
 
-        // Could this be compressed to one line? I imagine so.
+    /// The metrics of the scan engine, which its threads update live. The
+    /// metrics remain available (and complete) once the engine finishes.
+    pub fn metrics(&self) -> Arc<ScanMetrics> {
+        self.metrics.clone()
     }
 
     /// Signal to the scan engine to shut down. Sending documents
@@ -96,35 +467,10 @@ impl AsyncScanInterface {
 
 impl Scanner for CompiledQuery {
     fn scan_single(&self, document: &CompiledDocument) -> OutputBatch {
-        let placeholder_string_no_url = String::from("");
-        let url = match &document.url {
-            Some(value) => &value,
-            None => &placeholder_string_no_url, // potentially undefined behavior; TODO: document
-        };
-        if !(&self.scope.pattern.quick_check(url)) {
-            return OutputBatch::from(vec![]); // scope doesn't match; TODO: optimize this so that this function is only called in the first place on things that match
-        }
-        let input = document.content(self.scope.content);
-        let mut matches: HashMap<&String, bool> = HashMap::new();
-        let mut match_results: Vec<PatternMatch> = Vec::new();
-        for trigger in &self.triggers {
-            let does_match = trigger.quick_check(&input);
-            if does_match {
-                match_results.push(match trigger.full_check(&input) {
-                    Some(value) => value,
-                    None => return OutputBatch::from(vec![]), // no match on this trigger...but there was earlier?
-                });
-            }
-            matches.insert(&trigger.id, does_match);
-        }
-        if match self.threshold.evaluate(&matches) {
-            Ok(evaluation) => evaluation,
-            Err(_) => return OutputBatch::from(vec![]), // TODO: make this not fail silently
-        } {
-            return OutputBatch::from(vec![Output::new(&document, &self, match_results, None)]);
-        } else {
-            return OutputBatch::from(vec![]);
+        if !self.scope.is_in_scope(document) {
+            return OutputBatch::from(vec![]); // scope doesn't match
         }
+        self.scan_in_scope(document)
     }
 
     fn scan_batch(&self, documents: &CompiledDocumentBatch) -> OutputBatch {
@@ -136,43 +482,457 @@ impl Scanner for CompiledQuery {
         OutputBatch::from(outputs)
     }
 
-    fn scan_concurrently(&self, threads: u8) -> AsyncScanInterface {
+    fn scan_concurrently_into(
+        &self,
+        options: ScanOptions,
+        sinks: Vec<Box<dyn OutputSink + Send>>,
+    ) -> AsyncScanInterface {
         let query_group = CompiledQueryGroup::from(self.clone());
-        query_group.scan_concurrently(threads)
+        query_group.scan_concurrently_into(options, sinks)
     }
 }
 
-impl Scanner for CompiledQueryGroup {
-    fn scan_single(&self, document: &CompiledDocument) -> OutputBatch {
+impl CompiledQuery {
+    /// Scan a single document that is already known to be in scope
+    /// (see `CompiledScope::is_in_scope()`) and return the output.
+    pub fn scan_in_scope(&self, document: &CompiledDocument) -> OutputBatch {
+        match self.scan_in_scope_with(document, &mut TriggerCache::default(), None) {
+            Ok(outputs) => outputs,
+            Err(_) => OutputBatch::new(), // impossible without a deadline
+        }
+    }
+
+    /// Scan a single document that is already known to be in scope,
+    /// reusing (and recording) the results of trigger patterns that
+    /// were already checked on the document by other queries.
+    ///
+    /// The deadline (if any) is checked before each trigger pattern is
+    /// run; once it has passed, a `Warning` is returned instead.
+    fn scan_in_scope_with<'a>(
+        &'a self,
+        document: &'a CompiledDocument,
+        cache: &mut TriggerCache<'a>,
+        deadline: Option<Instant>,
+    ) -> Result<OutputBatch, Issue> {
+        let mut triggers = LazyTriggers {
+            triggers: &self.triggers,
+            document,
+            content: self.scope.content,
+            transforms: &self.scope.transforms,
+            fired: vec![None; self.triggers.len()],
+            matches: vec![None; self.triggers.len()],
+            cache,
+            deadline,
+            expired: false,
+        };
+        let mut count = |id: &String, minimum: usize| {
+            match self.triggers.iter().position(|trigger| &trigger.id == id) {
+                Some(index) => Ok(triggers.count(index, minimum)),
+                None => Err(Issue::Error(format!("unable to find trigger `{}` in given triggers", id))),
+            }
+        };
+        let (matched, score) = match self.cutoff {
+            Some(cutoff) => match self.ordered_threshold.score_with(&mut count) {
+                Ok(score) => (score >= cutoff, Some(score)),
+                Err(_) => (false, None),
+            },
+            None => (self.ordered_threshold.evaluate_lazily(&mut count).unwrap_or(false), None),
+        };
+        if triggers.expired {
+            return Err(deadline_issue(document));
+        }
+        if !matched {
+            return Ok(OutputBatch::from(vec![])); // TODO: make errors not fail silently
+        }
+
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        let mut match_results: Vec<(String, PatternMatch)> = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+        for (index, trigger) in self.triggers.iter().enumerate() {
+            if self.needs_all_triggers {
+                triggers.count(index, usize::MAX);
+            }
+            let count = match triggers.fired[index] {
+                Some(true) => {
+                    tags.extend(trigger.tags.iter().cloned());
+                    match triggers.matches[index].take() {
+                        Some(trigger_matches) => {
+                            let count = trigger_matches.len().max(1);
+                            match_results.extend(
+                                trigger_matches
+                                    .into_iter()
+                                    .map(|trigger_match| (trigger.id.clone(), trigger_match)),
+                            );
+                            count
+                        }
+                        None => 1, // negated, or not counted
+                    }
+                }
+                _ => 0,
+            };
+            counts.insert(&trigger.id, count);
+        }
+        if triggers.expired {
+            return Err(deadline_issue(document));
+        }
+        let id = generate_output_id(document, self);
+        let mut output = Output::new(document, self, match_results, tags, &counts, id);
+        output.score = score;
+        Ok(OutputBatch::from(vec![output]))
+    }
+}
+
+/// The key of a `TriggerCache`: the key of a trigger's pattern (see
+/// `CompiledTrigger.key`), and the content (and transforms) that it is
+/// checked against.
+type TriggerCacheKey<'a> = (&'a str, ScopeContent, &'a [ContentTransform]);
+
+/// Caches the results of trigger patterns on a single document, so that
+/// identical patterns in different queries are only checked once per
+/// document (and per content that they are checked against).
+///
+/// Results are recorded before negation, so negated and non-negated
+/// triggers with the same pattern share them as well.
+#[derive(Default)]
+struct TriggerCache<'a> {
+    /// Whether each pattern matches.
+    checks: HashMap<TriggerCacheKey<'a>, bool>,
+    /// The matches of each pattern, once extracted.
+    matches: HashMap<TriggerCacheKey<'a>, Vec<PatternMatch>>,
+}
+
+/// Evaluates the triggers of a query on a single document lazily, so that
+/// each trigger is only run once it is needed—and at most once.
+struct LazyTriggers<'a, 'c> {
+    triggers: &'a [CompiledTrigger],
+    document: &'a CompiledDocument,
+    /// The content of the query's scope, which triggers are evaluated
+    /// against unless they specify their own.
+    content: ScopeContent,
+    /// The transforms of the query's scope, which are applied unless
+    /// triggers specify their own.
+    transforms: &'a [ContentTransform],
+    /// Whether each trigger fired, once known.
+    fired: Vec<Option<bool>>,
+    /// The matches of each (non-negated) trigger, once extracted.
+    matches: Vec<Option<Vec<PatternMatch>>>,
+    /// The results of the patterns that were already checked on the
+    /// document, which may be shared with other queries.
+    cache: &'c mut TriggerCache<'a>,
+    /// The time by which the document must be scanned, if any.
+    deadline: Option<Instant>,
+    /// Whether a trigger was not run because the deadline had passed.
+    expired: bool,
+}
+
+impl<'a, 'c> LazyTriggers<'a, 'c> {
+    /// Returns the number of times the trigger at the given index matched,
+    /// counting no further than `minimum` when possible: matches are only
+    /// extracted when more than one is required. (A negated trigger counts
+    /// as a single match when it fires.)
+    ///
+    /// Once the deadline has passed, patterns are no longer run: triggers
+    /// that have not been run count as zero matches, and `expired` is set.
+    fn count(&mut self, index: usize, minimum: usize) -> usize {
+        if self.expired || self.deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            self.expired = true;
+            return 0;
+        }
+        let trigger: &'a CompiledTrigger = &self.triggers[index];
+        let content = trigger.content.unwrap_or(self.content);
+        let transforms: &'a [ContentTransform] = match &trigger.transforms {
+            Some(transforms) => transforms,
+            None => self.transforms,
+        };
+        let key: TriggerCacheKey<'a> = (trigger.key.as_str(), content, transforms);
+        let transformed: Arc<String>;
+        let input = match transforms.is_empty() {
+            true => self.document.content(content),
+            false => {
+                transformed = self.document.transformed_content(content, transforms);
+                &transformed
+            }
+        };
+        let is_bytes = content == ScopeContent::Bytes;
+        let fired = match self.fired[index] {
+            Some(fired) => fired,
+            None => {
+                let document = self.document;
+                let matched = *self.cache.checks.entry(key).or_insert_with(|| match is_bytes {
+                    true => trigger.quick_check_bytes(document.bytes()),
+                    false => trigger.quick_check(input),
+                });
+                let fired = matched != trigger.negate;
+                self.fired[index] = Some(fired);
+                fired
+            }
+        };
+        if !fired {
+            return 0;
+        }
+        if trigger.negate || minimum <= 1 {
+            return 1;
+        }
+        if self.matches[index].is_none() {
+            let bytes = self.document.bytes();
+            let matches = self.cache.matches.entry(key).or_insert_with(|| {
+                let mut matches = match is_bytes {
+                    true => trigger.full_check_all_bytes(bytes),
+                    false => trigger.full_check_all(input),
+                };
+                for pattern_match in &mut matches {
+                    pattern_match.content = Some(content);
+                }
+                if !is_bytes {
+                    locate_matches(input, &mut matches);
+                }
+                matches
+            });
+            self.matches[index] = Some(matches.clone());
+        }
+        self.matches[index].as_ref().map_or(1, |matches| matches.len().max(1))
+    }
+}
+
+impl CompiledQueryGroup {
+    /// Scan a single document and return the output, giving up on the
+    /// document once the given deadline (if any) has passed.
+    ///
+    /// RegEx matching cannot be interrupted, so the deadline is checked
+    /// before each query and before each of its trigger patterns is run;
+    /// a document may therefore take somewhat longer than its deadline
+    /// (but no longer than its slowest pattern) to be skipped. When the
+    /// deadline passes, the document is skipped _entirely_ and a `Warning`
+    /// is returned.
+    ///
+    /// Queries often share scopes, so each distinct scope (see
+    /// `CompiledScope.key`) is only evaluated once per document.
+    pub fn scan_single_within(
+        &self,
+        document: &CompiledDocument,
+        deadline: Option<Instant>,
+    ) -> Result<OutputBatch, Issue> {
+        let counts = OutputCounts::default();
+        self.scan_single_limited(document, deadline, OutputLimits::default(), &counts, &mut vec![])
+    }
+
+    /// Scan a single document like `scan_single_within()`, but drop the
+    /// outputs beyond the given `OutputLimits`, recording a `Warning` in
+    /// `issues` when a cap is first exceeded. The `counts` carry the
+    /// number of outputs of each query from one document of a scan to the
+    /// next; once a query has exceeded its cap, it is no longer run.
+    pub fn scan_single_limited(
+        &self,
+        document: &CompiledDocument,
+        deadline: Option<Instant>,
+        limits: OutputLimits,
+        counts: &OutputCounts,
+        issues: &mut Vec<Issue>,
+    ) -> Result<OutputBatch, Issue> {
         let mut output_batch = OutputBatch::new();
+        output_batch.batch = document
+            .provenance
+            .as_ref()
+            .and_then(|provenance| provenance.batch.clone());
+        let mut limiter = OutputLimiter {
+            limits,
+            counts,
+            kept: 0,
+            overflowed: false,
+        };
+        let mut scope_cache: HashMap<&str, bool> = HashMap::new();
+        let mut trigger_cache = TriggerCache::default();
 
         // Regex Set evaluation
         let to_feed = document.content(self.regex_feed);
-        if self.regex_collected.is_match(to_feed) {
-            let matches: Vec<_> = self.regex_collected.matches(to_feed).into_iter().collect();
-            let mut queries_to_run: HashSet<usize> = HashSet::new();
-            for match_item in matches {
-                queries_to_run.insert(match self.regex_collected_query_index.get(match_item) {
-                    Some(index) => *index,
-                    None => return OutputBatch::from(vec![]), // this should never happen; should we panic? TODO
-                });
+        let mut queries_to_run: HashMap<usize, HashSet<&String>> = HashMap::new();
+        for match_item in self.regex_collected.matches(to_feed) {
+            let query_index = match self.regex_collected_query_index.get(match_item) {
+                Some(index) => *index,
+                None => return Err(collected_pattern_issue("regex", match_item)),
+            };
+            let trigger_id = match self.regex_collected_trigger_ids.get(match_item) {
+                Some(id) => id,
+                None => return Err(collected_pattern_issue("regex", match_item)),
+            };
+            queries_to_run
+                .entry(query_index)
+                .or_default()
+                .insert(trigger_id);
+        }
+
+        // Literal automaton evaluation
+        // (Each pattern only needs to be found once, so the search stops as soon
+        // as every pattern has been seen.)
+        let literal_count = self.literal_collected.patterns_len();
+        let mut literals_seen = vec![false; literal_count];
+        let mut literals_remaining = literal_count;
+        for finding in self.literal_collected.find_overlapping_iter(to_feed) {
+            let match_item = finding.pattern().as_usize();
+            if literals_seen[match_item] {
+                continue;
             }
-            for query_index in queries_to_run {
-                let query = match self.queries.get(query_index) {
-                    Some(value) => value,
-                    None => return OutputBatch::from(vec![]), // this should also never happen; should we panic? TODO
-                };
-                output_batch.merge_with(query.scan_single(document));
+            literals_seen[match_item] = true;
+            literals_remaining -= 1;
+            let query_index = match self.literal_collected_query_index.get(match_item) {
+                Some(index) => *index,
+                None => return Err(collected_pattern_issue("literal", match_item)),
+            };
+            let trigger_id = match self.literal_collected_trigger_ids.get(match_item) {
+                Some(id) => id,
+                None => return Err(collected_pattern_issue("literal", match_item)),
+            };
+            queries_to_run
+                .entry(query_index)
+                .or_default()
+                .insert(trigger_id);
+            if literals_remaining == 0 {
+                break;
             }
+        }
 
+        // (Queries are run in order, so that output caps are deterministic.)
+        let mut queries_to_run: Vec<(usize, HashSet<&String>)> = queries_to_run.into_iter().collect();
+        queries_to_run.sort_by_key(|(query_index, _)| *query_index);
+        for (query_index, mut possible_triggers) in queries_to_run {
+            if limiter.skips(query_index) {
+                continue;
+            }
+            let query = match self.queries.get(query_index) {
+                Some(value) => value,
+                None => {
+                    return Err(Issue::Error(format!(
+                        "collected query index {} is out of range; the query group is inconsistent",
+                        query_index
+                    )))
+                }
+            };
+            // The collected patterns already tell us which triggers are able to match
+            // (negated triggers are not collected, so any of them may fire); if the
+            // threshold is out of reach, there is no use in scanning.
+            possible_triggers.extend(
+                query
+                    .triggers
+                    .iter()
+                    .filter(|trigger| trigger.negate)
+                    .map(|trigger| &trigger.id),
+            );
+            // (Scored queries may match without their threshold being met.)
+            if query.cutoff.is_none() && !query.threshold.is_achievable(&possible_triggers) {
+                continue;
+            }
+            if !is_in_scope_cached(&mut scope_cache, query, document) {
+                continue;
+            }
+            check_deadline(document, deadline)?;
+            let outputs = query.scan_in_scope_with(document, &mut trigger_cache, deadline)?;
+            limiter.admit(query_index, query, document, outputs, &mut output_batch, issues);
         }
 
         // Always runs
-        for query in &self.always_run_queries {
-            output_batch.merge_with(query.scan_single(document));
+        for (index, query) in self.always_run_queries.iter().enumerate() {
+            let position = self.queries.len() + index;
+            if limiter.skips(position) || !is_in_scope_cached(&mut scope_cache, query, document) {
+                continue;
+            }
+            check_deadline(document, deadline)?;
+            let outputs = query.scan_in_scope_with(document, &mut trigger_cache, deadline)?;
+            limiter.admit(position, query, document, outputs, &mut output_batch, issues);
         }
 
-        output_batch
+        Ok(output_batch)
+    }
+}
+
+impl CompiledQueryGroup {
+    /// Scan a batch of documents like `scan_batch()`, but drop the outputs
+    /// beyond the given `OutputLimits`, returning the `Warning`s issued
+    /// when caps were exceeded along with the outputs. (The batch is a
+    /// single scan for the purposes of `OutputLimits.per_query`.)
+    pub fn scan_batch_limited(
+        &self,
+        documents: &CompiledDocumentBatch,
+        limits: OutputLimits,
+    ) -> (OutputBatch, Vec<Issue>) {
+        let counts = OutputCounts::default();
+        let mut issues: Vec<Issue> = Vec::new();
+        let mut output_batch = OutputBatch::new();
+        for document in &documents.documents {
+            if let Ok(outputs) = self.scan_single_limited(document, None, limits, &counts, &mut issues) {
+                output_batch.merge_with(outputs); // impossible to fail without a deadline
+            }
+        }
+        (output_batch, issues)
+    }
+}
+
+/// Determines whether the given document is in the scope of the given
+/// query, using (and filling) the given cache of scope evaluations.
+fn is_in_scope_cached<'a>(
+    cache: &mut HashMap<&'a str, bool>,
+    query: &'a CompiledQuery,
+    document: &CompiledDocument,
+) -> bool {
+    *cache
+        .entry(query.scope.key.as_str())
+        .or_insert_with(|| query.scope.is_in_scope(document))
+}
+
+/// Returns a `Warning` if the given deadline has passed.
+fn check_deadline(document: &CompiledDocument, deadline: Option<Instant>) -> Result<(), Issue> {
+    match deadline {
+        Some(value) if Instant::now() > value => Err(deadline_issue(document)),
+        _ => Ok(()),
+    }
+}
+
+/// The `Warning` for a document that exceeded the time budget.
+fn deadline_issue(document: &CompiledDocument) -> Issue {
+    Issue::Warning(format!(
+        "skipped document `{}`: exceeded the time budget",
+        document_name(document)
+    ))
+}
+
+/// The `Error` for a collected pattern without a query or trigger, which
+/// means that the query group is inconsistent.
+fn collected_pattern_issue(kind: &str, index: usize) -> Issue {
+    Issue::Error(format!(
+        "collected {} pattern {} has no query or trigger; the query group is inconsistent",
+        kind, index
+    ))
+}
+
+/// The `Issue` for a document that could not be compiled, which names
+/// the document (as the issues of compilation do not).
+fn compile_issue(document: &Document, issue: Issue) -> Issue {
+    let name = document.url.as_deref().unwrap_or("unknown_document");
+    match issue {
+        Issue::Error(message) => Issue::Error(format!(
+            "unable to compile `{}` (`{}`), skipping...",
+            name, message
+        )),
+        Issue::Warning(message) => Issue::Warning(format!(
+            "unable to compile `{}` (`{}`), skipping...",
+            name, message
+        )),
+    }
+}
+
+/// The name of the given document in diagnostics: its URL, if any.
+fn document_name(document: &CompiledDocument) -> &str {
+    match &document.url {
+        Some(url) => url.as_str(),
+        None => "unknown_document",
+    }
+}
+
+impl Scanner for CompiledQueryGroup {
+    fn scan_single(&self, document: &CompiledDocument) -> OutputBatch {
+        match self.scan_single_within(document, None) {
+            Ok(outputs) => outputs,
+            Err(_) => OutputBatch::new(), // impossible without a deadline
+        }
     }
 
     fn scan_batch(&self, documents: &CompiledDocumentBatch) -> OutputBatch {
@@ -183,78 +943,247 @@ impl Scanner for CompiledQueryGroup {
         output_batch
     }
 
-    fn scan_concurrently(&self, threads: u8) -> AsyncScanInterface {
+    fn scan_concurrently_into(
+        &self,
+        options: ScanOptions,
+        sinks: Vec<Box<dyn OutputSink + Send>>,
+    ) -> AsyncScanInterface {
         let (incoming_transmitter, incoming_receiver) = mpsc::channel::<DocumentReferenceBatch>();
-        let pending_processing = Arc::new(Mutex::new(0 as isize));
+        let pending_processing = Arc::new(Mutex::new(0_isize));
+
+        let (ultimate_transmitter, ultimate_receiver) = match options.output_capacity {
+            Some(capacity) => {
+                let (sender, receiver) = mpsc::sync_channel::<OutputBatch>(capacity);
+                (OutputSender::Bounded(sender), receiver)
+            }
+            None => {
+                let (sender, receiver) = mpsc::channel::<OutputBatch>();
+                (OutputSender::Unbounded(sender), receiver)
+            }
+        };
+        let (issue_transmitter, issue_receiver) = mpsc::channel::<Issue>();
 
-        // println!("scanning concurrently");
-        let (ultimate_transmitter, ultimate_receiver) = mpsc::channel::<OutputBatch>();
+        // an engine without threads would wait forever; correct it instead
+        let mut options = options;
+        if options.threads == 0 {
+            options.threads = 1;
+            let _ = issue_transmitter.send(Issue::Warning(String::from(
+                "the scan engine requires at least one scanner thread; using 1",
+            )));
+        }
+        if options.loader_threads == 0 {
+            options.loader_threads = 1;
+            let _ = issue_transmitter.send(Issue::Warning(String::from(
+                "the scan engine requires at least one loader thread; using 1",
+            )));
+        }
         let cloned_self = self.clone();
         let pending_processing_cloned = pending_processing.clone();
+        let budget = options.memory_budget.map(|limit| Arc::new(MemoryBudget::new(limit)));
+        let output_counts = Arc::new(OutputCounts::default());
+        let metrics = Arc::new(ScanMetrics::new());
+        let engine_metrics = metrics.clone();
+        let sinks: Arc<Vec<Mutex<Box<dyn OutputSink + Send>>>> =
+            Arc::new(sinks.into_iter().map(Mutex::new).collect());
+        let fingerprint = Arc::new(options.cache_fingerprint(self));
 
         thread::spawn(move || {
             let (tx_requests, rx_requests) = mpsc::channel::<thread::ThreadId>();
-            let mut handles: Vec<thread::JoinHandle<_>> = Vec::new();
+            let (tx_compiled, rx_compiled) =
+                mpsc::sync_channel::<LoadedDocument>(options.threads as usize * 2);
+            let rx_compiled = Arc::new(Mutex::new(rx_compiled));
             let mut outgoing: HashMap<thread::ThreadId, mpsc::Sender<DocumentReferenceBatch>> =
                 HashMap::new();
 
-            // create threads
-            for _ in 0..threads {
-                let (tx_inputs, rx_inputs) = mpsc::channel::<DocumentReferenceBatch>();
-                let tx_request_documents = tx_requests.clone();
+            // create scanner threads (CPU-bound)
+            for _ in 0..options.threads {
+                let rx_scan_documents = rx_compiled.clone();
                 let tx_send_output = ultimate_transmitter.clone();
+                let tx_send_issue = issue_transmitter.clone();
                 let supercloned_self = cloned_self.clone(); // TODO: optimize
-                let handle = thread::spawn(move || {
-                    let id = thread::current().id();
+                let time_budget = options.document_time_budget;
+                let output_limits = options.output_limits;
+                let output_counts = output_counts.clone();
+                let thread_sinks = sinks.clone();
+                let thread_metrics = engine_metrics.clone();
+                let thread_cache = options.cache.clone();
+                let thread_fingerprint = fingerprint.clone();
+                thread::spawn(move || {
                     loop {
-                        match tx_request_documents.send(id) {
-                            Ok(_) => (),
-                            Err(_) => break,
+                        // the lock is only held while waiting for the next document
+                        let received = rx_scan_documents.lock().unwrap().recv();
+                        let loaded_document = match received {
+                            Ok(value) => value,
+                            Err(_) => break, // loaders are done; end the thread
                         };
+                        let started = Instant::now();
+                        let deadline = time_budget.map(|budget| started + budget);
+                        let mut issues: Vec<Issue> = Vec::new();
+                        let result = supercloned_self.scan_single_limited(
+                            &loaded_document.document,
+                            deadline,
+                            output_limits,
+                            &output_counts,
+                            &mut issues,
+                        );
+                        let cache_key = loaded_document.cache_key.clone();
+                        drop(loaded_document); // release its memory before sending
+                        for issue in issues {
+                            let _ = tx_send_issue.send(issue); // nobody may be listening
+                        }
+                        let outputs = match result {
+                            Ok(value) => {
+                                thread_metrics.record_scanned(started.elapsed(), &value);
+                                value
+                            }
+                            Err(issue) => {
+                                thread_metrics.record_skipped(started.elapsed());
+                                let _ = tx_send_issue.send(issue); // nobody may be listening
+                                continue;
+                            }
+                        };
+                        let delivered = if outputs.outputs.is_empty() {
+                            true
+                        } else if !thread_sinks.is_empty() {
+                            let mut delivered = true;
+                            for sink in thread_sinks.iter() {
+                                if let Err(issue) = sink.lock().unwrap().send(&outputs) {
+                                    delivered = false;
+                                    let _ = tx_send_issue.send(issue); // nobody may be listening
+                                }
+                            }
+                            delivered
+                        } else {
+                            match tx_send_output.send(outputs) {
+                                Ok(_) => true,
+                                Err(_) => break, // receiver has been killed; thread is done
+                            }
+                        };
+                        // documents are only cached once their outputs have been
+                        // delivered, so that undelivered outputs are produced again
+                        if let (true, Some(cache), Some(key)) = (delivered, &thread_cache, cache_key) {
+                            if let Err(issue) = cache.insert(&thread_fingerprint, &key) {
+                                let _ = tx_send_issue.send(issue); // nobody may be listening
+                            }
+                        }
+                    }
+                    // the last scanner thread closes the sinks; until it is
+                    // done, the output channel remains open
+                    if let Some(sinks) = Arc::into_inner(thread_sinks) {
+                        for sink in sinks {
+                            if let Err(issue) = sink.into_inner().unwrap().close() {
+                                let _ = tx_send_issue.send(issue); // nobody may be listening
+                            }
+                        }
+                    }
+                    drop(tx_send_output);
+                });
+            }
+
+            drop(sinks); // the scanner threads hold the sinks
+
+            // create loader threads (I/O-bound)
+            for _ in 0..options.loader_threads {
+                let (tx_inputs, rx_inputs) = mpsc::channel::<DocumentReferenceBatch>();
+                let tx_request_documents = tx_requests.clone();
+                let tx_scan_documents = tx_compiled.clone();
+                let thread_budget = budget.clone();
+                let thread_metrics = engine_metrics.clone();
+                let tx_send_issue = issue_transmitter.clone();
+                let text_extraction = options.text_extraction;
+                let streaming = options.streaming;
+                let thread_cache = options.cache.clone();
+                let thread_fingerprint = fingerprint.clone();
+                let handle = thread::spawn(move || {
+                    let id = thread::current().id();
+                    while tx_request_documents.send(id).is_ok() {
                         let batch = match rx_inputs.recv() {
                             Ok(values) => values,
                             Err(_) => break, // no more values; end the thread
                         };
-                        let mut documents: Vec<Document> = Vec::new();
                         for document_reference in batch.documents {
-                            documents.push(match document_reference {
-                                DocumentReference::Populated(document) => document,
-                                DocumentReference::Unpopulated(path) => {
-                                    match load_document(&path) {
-                                        Ok(document) => document,
-                                        Err(_issue) => {
-                                            // println!("{}", issue);
+                            let size = reference_size(&document_reference);
+                            let provenance = Provenance {
+                                batch: batch.id.clone(),
+                                reference: document_reference.reference().map(String::from),
+                            };
+                            // large files are streamed in windows, each of which is
+                            // loaded (and reserves memory) separately
+                            let (mut documents, reserved): (DocumentStream, usize) =
+                                match document_reference {
+                                    DocumentReference::Populated(document) => {
+                                        (Box::new(std::iter::once(Ok(document))), size)
+                                    }
+                                    DocumentReference::Unpopulated(path) => match streaming {
+                                        Some(streaming) if size > streaming.threshold => {
+                                            match DocumentWindows::open(&path, streaming) {
+                                                Ok(windows) => (Box::new(windows), streaming.window),
+                                                Err(issue) => (Box::new(std::iter::once(Err(issue))), 0),
+                                            }
+                                        }
+                                        _ => (Box::new(std::iter::once(load_document(&path))), size),
+                                    },
+                                };
+                            loop {
+                                let document = match documents.next() {
+                                    Some(Ok(document)) => document,
+                                    Some(Err(issue)) => {
+                                        thread_metrics.record_failed();
+                                        let _ = tx_send_issue.send(issue); // nobody may be listening
+                                        continue;
+                                    }
+                                    None => break,
+                                };
+                                let cache_key = match &thread_cache {
+                                    Some(cache) => {
+                                        let key = ScanCache::key(&document);
+                                        if cache.contains(&thread_fingerprint, &key) {
+                                            thread_metrics.record_unchanged();
                                             continue;
-                                        } // silent failure
+                                        }
+                                        Some(key)
                                     }
+                                    None => None,
+                                };
+                                let reservation = thread_budget
+                                    .as_ref()
+                                    .map(|budget| MemoryBudget::reserve(budget, reserved));
+                                let mut compiled_document = match document.compile_with(text_extraction) {
+                                    Ok(value) => value,
+                                    Err(issue) => {
+                                        thread_metrics.record_failed();
+                                        let _ = tx_send_issue.send(compile_issue(&document, issue));
+                                        continue;
+                                    }
+                                };
+                                thread_metrics.record_loaded();
+                                for issue in compiled_document.issues.drain(..) {
+                                    let _ = tx_send_issue.send(issue); // nobody may be listening
+                                }
+                                compiled_document.provenance = Some(provenance.clone());
+                                let loaded_document = LoadedDocument {
+                                    document: compiled_document,
+                                    _reservation: reservation,
+                                    cache_key,
+                                };
+                                if tx_scan_documents.send(loaded_document).is_err() {
+                                    return; // scanners have been killed; thread is done
                                 }
-                            });
+                            }
                         }
-                        let populated_batch = DocumentBatch::from(documents);
-                        let compiled_batch = match populated_batch.compile() {
-                            Ok(value) => value,
-                            Err(_) => continue, // silent failure; TODO: fix
-                        };
-                        let outputs = supercloned_self.scan_batch(&compiled_batch);
-                        // println!("sending {} outputs...", outputs.outputs.len());
-                        match tx_send_output.send(outputs) {
-                            Ok(_) => (),
-                            Err(_) => break, // receiver has been killed; thread is done
-                        };
                     }
-                    drop(tx_send_output);
                 });
                 outgoing.insert(handle.thread().id(), tx_inputs);
-                handles.push(handle);
             }
 
+            // scanners stop once every loader is done, and the coordinator
+            // stops once every loader has stopped requesting batches
+            drop(tx_compiled);
+            drop(tx_requests);
+
             // listen and coordinate threads
             // TODO: figure out how to deal with these silent failures
-            loop {
-                let request = match rx_requests.recv() {
-                    Ok(request) => request,
-                    Err(_error) => break,
-                };
+            while let Ok(request) = rx_requests.recv() {
                 let batch_to_send = match incoming_receiver.recv() {
                     Ok(batch) => {
                         *pending_processing_cloned.lock().unwrap() -= 1;
@@ -278,14 +1207,1460 @@ impl Scanner for CompiledQueryGroup {
             }
 
             // Thread clean-up
-            for outgoing_sender in outgoing.values() {
-                drop(outgoing_sender);
-            }
+            drop(outgoing);
         });
         AsyncScanInterface {
             incoming_outputs: ultimate_receiver,
+            incoming_issues: issue_receiver,
             outgoing_batches: Some(incoming_transmitter),
-            pending_processing: pending_processing,
+            pending_processing,
+            metrics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::compilation::CompilableTo;
+    use common::pattern::*;
+    use common::timestamp;
+    use common::validation::Issue;
+    use query::query::tests::get_basic_query;
+    use query::query::{Query, QueryGroup};
+    use query::response::*;
+    use query::scope::*;
+    use query::threshold::*;
+    use query::trigger::*;
+    use input::document::{Document, DocumentBatch, Provenance};
+    use output::output::{OutputItem, ENGINE};
+    use std::collections::BTreeMap;
+
+    use ron;
+
+    /// The URL of the HTML documents that the tests scan.
+    const URL: &str = "https://example.com/a.html";
+
+    /// The group of the given query alone, optimized for the content of
+    /// the query's scope.
+    fn group_of(query: &Query) -> CompiledQueryGroup {
+        QueryGroup {
+            queries: vec![query.clone()],
+            optimized_content: query.scope.content,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap()
+    }
+
+    /// Scans each of the given documents with the given query, both alone
+    /// and in its group (see `group_of()`); asserts that the two produce
+    /// the same number of outputs, and returns those numbers.
+    fn assert_group_agrees(query: &Query, documents: Vec<Document>) -> Vec<usize> {
+        let compiled_query = query.compile().unwrap();
+        let compiled_group = group_of(query);
+        documents
+            .iter()
+            .map(|document| {
+                let document = document.compile().unwrap();
+                let outputs = compiled_query.scan_single(&document).outputs.len();
+                assert_eq!(compiled_group.scan_single(&document).outputs.len(), outputs);
+                outputs
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_group_scan_unachievable_threshold() {
+        let group = QueryGroup {
+            queries: vec![get_basic_query()],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
         }
+        .compile()
+        .unwrap();
+        let document = |data: &str| {
+            Document::from_text("https://example.com", data).compile().unwrap()
+        };
+
+        // Only `A` matches, so `requires: 2` is out of reach
+        assert_eq!(group.scan_single(&document("hello")).outputs.len(), 0);
+        assert_eq!(
+            group.scan_single(&document("hello everyone")).outputs.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_group_scan_raw_literals() {
+        let mut query = get_basic_query();
+        for trigger in &mut query.triggers {
+            trigger.pattern.kind = PatternKind::Raw;
+        }
+        let group = group_of(&query);
+        assert_eq!(group.literal_collected.patterns_len(), 3);
+        assert_eq!(group.regex_collected.len(), 0);
+
+        let document = Document::from_text("https://example.com", "hello everyone")
+            .compile()
+            .unwrap();
+        assert_eq!(group.scan_single(&document).outputs.len(), 1);
+    }
+
+    #[test]
+    fn test_scan_trigger_tags() {
+        let mut query = get_basic_query();
+        query.triggers[0].tags = vec![String::from("pii"), String::from("brand:acme")];
+        query.triggers[1].tags = vec![String::from("pii")];
+        query.triggers[2].tags = vec![String::from("unfired")];
+        let compiled_query = query.compile().unwrap();
+        let document = Document::from_text("https://example.com", "hello everyone")
+            .compile()
+            .unwrap();
+        let outputs = compiled_query.scan_single(&document).outputs;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].tags, vec![String::from("brand:acme"), String::from("pii")]);
+    }
+
+    #[test]
+    fn test_scan_time_budget() {
+        let compiled_query = get_basic_query().compile().unwrap();
+        let group = CompiledQueryGroup::from(compiled_query.clone());
+        let document = Document::from_text("https://example.com", "hello everyone")
+            .compile()
+            .unwrap();
+        let past = Instant::now() - Duration::from_secs(1);
+        let future = Instant::now() + Duration::from_secs(3600);
+        let skipped = Err(Issue::Warning(String::from(
+            "skipped document `https://example.com`: exceeded the time budget",
+        )));
+        assert_eq!(group.scan_single_within(&document, Some(past)).map(|batch| batch.outputs), skipped);
+        assert_eq!(group.scan_single_within(&document, Some(future)).unwrap().outputs.len(), 1);
+
+        // the deadline is also checked within a query, before each trigger
+        let scan = |deadline| {
+            compiled_query
+                .scan_in_scope_with(&document, &mut TriggerCache::default(), deadline)
+                .map(|batch| batch.outputs)
+        };
+        assert_eq!(scan(Some(past)), skipped);
+        assert_eq!(scan(Some(future)).unwrap().len(), 1);
+        assert_eq!(scan(None).unwrap().len(), 1);
+        let mut cache = TriggerCache::default();
+        let mut triggers = LazyTriggers {
+            triggers: &compiled_query.triggers,
+            document: &document,
+            content: ScopeContent::Raw,
+            transforms: &[],
+            fired: vec![None; 3],
+            matches: vec![None; 3],
+            cache: &mut cache,
+            deadline: Some(future),
+            expired: false,
+        };
+        assert_eq!(triggers.count(0, 1), 1);
+        triggers.deadline = Some(past);
+        assert_eq!(triggers.count(1, 1), 0); // not run
+        assert!(triggers.expired);
+        assert_eq!(triggers.fired, vec![Some(true), None, None]);
+    }
+
+    #[test]
+    fn test_scan_concurrently_zero_threads() {
+        let options = ScanOptions {
+            threads: 0,
+            loader_threads: 0,
+            ..ScanOptions::default()
+        };
+        assert_eq!(options.validate().unwrap().len(), 2);
+
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently_with(options);
+        let document = Document::from_text("https://example.com", "hello everyone");
+        engine
+            .process(DocumentReferenceBatch::from(vec![DocumentReference::Populated(document)]))
+            .unwrap();
+        engine.shutdown();
+        assert_eq!(engine.lock_for_outputs().unwrap().outputs.len(), 1);
+        assert_eq!(engine.issues().len(), 2);
+    }
+
+    #[test]
+    fn test_scan_concurrently_load_failures() {
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently(1);
+        let undecodable = Document {
+            content_encoding: Some(String::from("lzma")),
+            ..Document::from_text("https://example.com/packed", "hello everyone")
+        };
+        engine
+            .process(DocumentReferenceBatch::from(vec![
+                DocumentReference::Unpopulated(String::from("/nonexistent/document.html")),
+                DocumentReference::Populated(undecodable),
+            ]))
+            .unwrap();
+        engine.shutdown();
+        assert!(engine.lock_for_outputs().is_err());
+
+        // documents that fail to load or compile are reported, not dropped
+        let issues = engine.issues();
+        assert_eq!(issues.len(), 2);
+        assert!(issues.iter().any(|issue| issue.to_string().contains("/nonexistent/document.html")));
+        assert!(issues.contains(&Issue::Error(String::from(
+            "unable to compile `https://example.com/packed` (`unsupported content encoding `lzma``), skipping..."
+        ))));
+        assert_eq!(engine.metrics().snapshot().documents_failed, 2);
+    }
+
+    #[test]
+    fn test_scan_concurrently_output_capacity() {
+        let options = ScanOptions {
+            threads: 2,
+            output_capacity: Some(1),
+            ..ScanOptions::default()
+        };
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently_with(options);
+        let documents: Vec<DocumentReference> = (0..8)
+            .map(|index| {
+                DocumentReference::Populated(Document::from_text(
+                    &format!("https://example.com/{}", index),
+                    "hello everyone",
+                ))
+            })
+            .collect();
+        engine.process(DocumentReferenceBatch::from(documents)).unwrap();
+        engine.shutdown();
+
+        // scanners wait while the output is not received, but none is lost
+        let mut outputs = 0;
+        while let Ok(batch) = engine.lock_for_outputs() {
+            outputs += batch.outputs.len();
+        }
+        assert_eq!(outputs, 8);
+    }
+
+    #[test]
+    fn test_scan_concurrently_metrics() {
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently(2);
+        let mut documents: Vec<DocumentReference> = (0..6)
+            .map(|index| {
+                let text = match index % 3 {
+                    0 => "nothing to see",
+                    _ => "hello everyone",
+                };
+                DocumentReference::Populated(Document::from_text(
+                    &format!("https://example.com/{}", index),
+                    text,
+                ))
+            })
+            .collect();
+        documents.push(DocumentReference::Unpopulated(String::from("/nonexistent/ieql/document")));
+        engine.process(DocumentReferenceBatch::from(documents)).unwrap();
+        engine.shutdown();
+        while engine.lock_for_outputs().is_ok() {}
+
+        let snapshot = engine.metrics().snapshot();
+        assert_eq!(snapshot.documents_loaded, 6);
+        assert_eq!(snapshot.documents_failed, 1);
+        assert_eq!(snapshot.documents_scanned, 6);
+        assert_eq!(snapshot.documents_in_flight(), 0);
+        assert_eq!(snapshot.outputs_total(), 4);
+        assert!(snapshot
+            .to_prometheus()
+            .contains("ieql_document_scan_duration_seconds_count 6\n"));
+    }
+
+    #[test]
+    fn test_scan_concurrently_streaming() {
+        // "hello everyone" (bytes 90-103) straddles the end of the second
+        // window (bytes 32-95), but is whole in the third (bytes 64-127)
+        let mut data = vec![b'.'; 90];
+        data.extend_from_slice(b"hello everyone");
+        data.extend(vec![b'.'; 100]);
+        let path = std::env::temp_dir().join(format!("ieql-streaming-{}.log", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let options = ScanOptions {
+            streaming: Some(StreamingOptions {
+                threshold: 64,
+                window: 64,
+                overlap: 32,
+            }),
+            ..ScanOptions::default()
+        };
+        assert!(options.validate().is_none());
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently_with(options);
+        let reference = DocumentReference::Unpopulated(String::from(path.to_str().unwrap()));
+        engine.process(DocumentReferenceBatch::from(vec![reference])).unwrap();
+        engine.shutdown();
+        let mut urls: Vec<String> = Vec::new();
+        while let Ok(batch) = engine.lock_for_outputs() {
+            for output in batch.outputs {
+                for item in output.items {
+                    if let OutputItem::Url(Some(url)) = item {
+                        urls.push(url);
+                    }
+                }
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(urls, vec![format!("{}#bytes=64-127", path.to_str().unwrap())]);
+        assert_eq!(engine.metrics().snapshot().documents_loaded, 6);
+
+        let invalid = ScanOptions {
+            streaming: Some(StreamingOptions {
+                threshold: 0,
+                window: 64,
+                overlap: 64,
+            }),
+            ..ScanOptions::default()
+        };
+        assert_eq!(invalid.validate().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_scan_concurrently_into_sinks() {
+        /// The URLs of the outputs that a sink received, and whether it
+        /// was closed.
+        type Record = Arc<Mutex<(Vec<String>, bool)>>;
+
+        struct RecordingSink(Record);
+
+        impl OutputSink for RecordingSink {
+            fn send(&mut self, batch: &OutputBatch) -> Result<(), Issue> {
+                let mut record = self.0.lock().unwrap();
+                assert!(!record.1, "outputs were sent to a closed sink");
+                for output in &batch.outputs {
+                    for item in &output.items {
+                        if let OutputItem::Url(Some(url)) = item {
+                            record.0.push(url.clone());
+                        }
+                    }
+                }
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<(), Issue> {
+                Ok(())
+            }
+
+            fn close(&mut self) -> Result<(), Issue> {
+                self.0.lock().unwrap().1 = true;
+                Err(Issue::Warning(String::from("closed")))
+            }
+        }
+
+        let records: Vec<Record> = (0..2).map(|_| Record::default()).collect();
+        let sinks: Vec<Box<dyn OutputSink + Send>> = records
+            .iter()
+            .map(|record| Box::new(RecordingSink(record.clone())) as Box<dyn OutputSink + Send>)
+            .collect();
+        let options = ScanOptions {
+            threads: 4,
+            ..ScanOptions::default()
+        };
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently_into(options, sinks);
+        let documents: Vec<DocumentReference> = (0..8)
+            .map(|index| {
+                DocumentReference::Populated(Document::from_text(
+                    &format!("https://example.com/{}", index),
+                    "hello everyone",
+                ))
+            })
+            .collect();
+        engine.process(DocumentReferenceBatch::from(documents)).unwrap();
+        engine.shutdown();
+
+        // outputs go to the sinks, which are closed once the engine is done
+        assert!(engine.lock_for_outputs().is_err());
+        for record in &records {
+            let (urls, closed) = &*record.lock().unwrap();
+            assert_eq!(urls.len(), 8);
+            assert!(closed);
+        }
+        assert_eq!(engine.issues().len(), 2);
+    }
+
+    #[test]
+    fn test_scan_concurrently_cached() {
+        let document = |url: &str, data: &str| {
+            DocumentReference::Populated(Document::from_text(url, data))
+        };
+        struct FailingSink;
+
+        impl OutputSink for FailingSink {
+            fn send(&mut self, _batch: &OutputBatch) -> Result<(), Issue> {
+                Err(Issue::Error(String::from("unavailable")))
+            }
+
+            fn flush(&mut self) -> Result<(), Issue> {
+                Ok(())
+            }
+        }
+
+        let scan_into = |group: &CompiledQueryGroup,
+                         cache: &Arc<ScanCache>,
+                         documents: Vec<DocumentReference>,
+                         sinks: Vec<Box<dyn OutputSink + Send>>| {
+            let options = ScanOptions {
+                threads: 2,
+                cache: Some(cache.clone()),
+                ..ScanOptions::default()
+            };
+            let mut engine = group.scan_concurrently_into(options, sinks);
+            engine.process(DocumentReferenceBatch::from(documents)).unwrap();
+            engine.shutdown();
+            let mut outputs = OutputBatch::new();
+            while let Ok(batch) = engine.lock_for_outputs() {
+                outputs.merge_with(batch);
+            }
+            let snapshot = engine.metrics().snapshot();
+            (outputs.outputs.len(), snapshot.documents_scanned, snapshot.documents_unchanged)
+        };
+        let scan = |group: &CompiledQueryGroup, cache: &Arc<ScanCache>, documents: Vec<DocumentReference>| {
+            scan_into(group, cache, documents, Vec::new())
+        };
+
+        let group = CompiledQueryGroup::from(get_basic_query().compile().unwrap());
+        assert_eq!(group.fingerprint(), CompiledQueryGroup::from(get_basic_query().compile().unwrap()).fingerprint());
+        let cache = Arc::new(ScanCache::in_memory());
+        let first = || {
+            vec![
+                document("https://example.com/a", "hello everyone"),
+                document("https://example.com/b", "hello everyone"),
+                document("https://example.com/c", "nothing to see"),
+            ]
+        };
+
+        // documents whose outputs are not delivered are not recorded
+        let failing: Vec<Box<dyn OutputSink + Send>> = vec![Box::new(FailingSink)];
+        assert_eq!(scan_into(&group, &cache, first(), failing), (0, 3, 0));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(scan(&group, &cache, first()), (2, 2, 1));
+        assert_eq!(cache.len(), 3);
+
+        // unchanged documents are skipped, whether or not they matched
+        let second = || {
+            vec![
+                document("https://example.com/a", "hello everyone"),
+                document("https://example.com/b", "hello everyone!"),
+                document("https://example.com/c", "nothing to see"),
+            ]
+        };
+        assert_eq!(scan(&group, &cache, second()), (1, 1, 2));
+
+        // but a changed query scans every document again
+        let mut query = get_basic_query();
+        query.id = Some(String::from("Changed"));
+        let changed = CompiledQueryGroup::from(query.compile().unwrap());
+        assert_ne!(changed.fingerprint(), group.fingerprint());
+        assert_eq!(scan(&changed, &cache, second()), (2, 3, 0));
+
+        // as does a change in the options that affect what is matched
+        let defaults = ScanOptions::default().cache_fingerprint(&group);
+        let threads = ScanOptions { threads: 1, ..ScanOptions::default() };
+        assert_eq!(threads.cache_fingerprint(&group), defaults);
+        let naive = ScanOptions {
+            text_extraction: TextExtraction::Naive,
+            ..ScanOptions::default()
+        };
+        assert_ne!(naive.cache_fingerprint(&group), defaults);
+        let streaming = ScanOptions {
+            streaming: Some(StreamingOptions::default()),
+            ..ScanOptions::default()
+        };
+        assert_ne!(streaming.cache_fingerprint(&group), defaults);
+
+        // or in the terms of a dictionary file that the query loads
+        let path = std::env::temp_dir().join(format!("ieql-cached-{}.txt", std::process::id()));
+        let mut query = get_basic_query();
+        query.triggers[0].pattern = Pattern {
+            content: format!("file:{}", path.display()),
+            kind: PatternKind::Dictionary,
+            normalization: None,
+        };
+        fs::write(&path, "hello").unwrap();
+        let before = CompiledQueryGroup::from(query.compile().unwrap()).fingerprint();
+        fs::write(&path, "hello\neveryone").unwrap();
+        let after = CompiledQueryGroup::from(query.compile().unwrap()).fingerprint();
+        fs::remove_file(&path).unwrap();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn test_scan_provenance() {
+        let document = |url: &str| {
+            DocumentReference::Populated(Document::from_text(url, "hello everyone"))
+        };
+        let compiled_query = get_basic_query().compile().unwrap();
+        let mut engine = compiled_query.scan_concurrently(1);
+        let batch = DocumentReferenceBatch::from(vec![document("https://example.com/a")]);
+        engine.process(batch.with_id("crawl-1")).unwrap();
+        engine.process(DocumentReferenceBatch::from(vec![document("https://example.com/b")])).unwrap();
+        engine.shutdown();
+        let mut batches: Vec<OutputBatch> = Vec::new();
+        while let Ok(batch) = engine.lock_for_outputs() {
+            batches.push(batch);
+        }
+        assert_eq!(batches.len(), 2);
+        batches.sort_by(|a, b| b.batch.cmp(&a.batch));
+        assert_eq!(batches[0].batch, Some(String::from("crawl-1")));
+        assert_eq!(
+            batches[0].outputs[0].provenance,
+            Some(Provenance {
+                batch: Some(String::from("crawl-1")),
+                reference: Some(String::from("https://example.com/a")),
+            })
+        );
+        assert_eq!(batches[1].batch, None);
+
+        let mut merged = OutputBatch::new();
+        merged.merge_with(batches.remove(0));
+        assert_eq!(merged.batch, Some(String::from("crawl-1")));
+        merged.merge_with(batches.remove(0));
+        assert_eq!(merged.batch, None); // the outputs came from different batches
+        assert!(merged.outputs.iter().all(|output| output.provenance.is_some()));
+    }
+
+    #[test]
+    fn test_scan_output_limits() {
+        let mut second = get_basic_query();
+        second.id = Some(String::from("Test Trigger #2"));
+        let compiled_group = QueryGroup {
+            queries: vec![get_basic_query(), second],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+        let documents: Vec<Document> = ["a", "b", "c"]
+            .iter()
+            .map(|name| Document::from_text(&format!("https://example.com/{}", name), "hello everyone"))
+            .collect();
+        let batch = DocumentBatch::from(documents.clone()).compile().unwrap();
+        let scan = |limits: OutputLimits| {
+            let (outputs, issues) = compiled_group.scan_batch_limited(&batch, limits);
+            let query_ids: Vec<String> =
+                outputs.outputs.into_iter().filter_map(|output| output.query_id).collect();
+            (query_ids, issues.len())
+        };
+
+        let (query_ids, issues) = scan(OutputLimits::default());
+        assert_eq!((query_ids.len(), issues), (6, 0));
+        assert_eq!(compiled_group.scan_batch(&batch).outputs.len(), 6);
+        let (query_ids, issues) = scan(OutputLimits {
+            per_document: Some(1),
+            per_query: None,
+        });
+        assert_eq!(query_ids, vec![String::from("Test Trigger #1"); 3]); // earlier queries are kept
+        assert_eq!(issues, 3);
+        let (query_ids, issues) = scan(OutputLimits {
+            per_document: None,
+            per_query: Some(2),
+        });
+        assert_eq!((query_ids.len(), issues), (4, 2));
+
+        let options = ScanOptions {
+            threads: 2,
+            output_limits: OutputLimits {
+                per_document: None,
+                per_query: Some(1),
+            },
+            ..ScanOptions::default()
+        };
+        assert_eq!(options.validate(), None);
+        let mut engine = compiled_group.scan_concurrently_with(options);
+        let references: Vec<DocumentReference> =
+            documents.into_iter().map(DocumentReference::Populated).collect();
+        engine.process(DocumentReferenceBatch::from(references)).unwrap();
+        engine.shutdown();
+        let mut outputs = OutputBatch::new();
+        while let Ok(batch) = engine.lock_for_outputs() {
+            outputs.merge_with(batch);
+        }
+        assert_eq!(outputs.outputs.len(), 2);
+        assert_eq!(engine.issues().len(), 2);
+    }
+
+    #[test]
+    fn test_group_scan_negated_trigger() {
+        // matches `hello` but not `goodbye`
+        let mut query = get_basic_query();
+        query.triggers[1].pattern.content = String::from("goodbye");
+        query.triggers[1].negate = true;
+        query.threshold = Threshold {
+            considers: vec![
+                ThresholdConsideration::Trigger(String::from("A")),
+                ThresholdConsideration::Trigger(String::from("B")),
+            ],
+            inverse: false,
+            requires: 2,
+            content: None,
+        };
+        // matches whenever `around` is absent
+        let mut absent_query = get_basic_query();
+        absent_query.triggers[2].negate = true;
+        absent_query.threshold = Threshold {
+            considers: vec![ThresholdConsideration::Trigger(String::from("C"))],
+            inverse: false,
+            requires: 1,
+            content: None,
+        };
+        let group = QueryGroup {
+            queries: vec![query, absent_query],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+        assert_eq!(group.queries.len(), 1);
+        assert_eq!(group.always_run_queries.len(), 1);
+
+        let get_document = |data: &[u8]| {
+            Document {
+                data: data.to_vec(),
+                ..Document::from_text("https://example.com", "")
+            }
+            .compile()
+            .unwrap()
+        };
+        assert_eq!(group.scan_single(&get_document(b"hello everyone")).outputs.len(), 2);
+        assert_eq!(group.scan_single(&get_document(b"hello goodbye")).outputs.len(), 1);
+        assert_eq!(group.scan_single(&get_document(b"hello around")).outputs.len(), 1);
+    }
+
+    #[test]
+    fn test_deterministic_output_ids() {
+        let compiled_query = get_basic_query().compile().unwrap();
+        let get_document = |url: &str| {
+            Document::from_text(url, "hello everyone").compile().unwrap()
+        };
+        let first = compiled_query.scan_single(&get_document("https://example.com/a")).outputs;
+        let again = compiled_query.scan_single(&get_document("https://example.com/a")).outputs;
+        let other = compiled_query.scan_single(&get_document("https://example.com/b")).outputs;
+        assert!(first[0].id.is_some());
+        assert_eq!(first[0].id, again[0].id);
+        assert_ne!(first[0].id, other[0].id);
+
+        let mut query = get_basic_query();
+        query.response.output_ids = Some(OutputIdPolicy::Random);
+        let compiled_query = query.compile().unwrap();
+        let first = compiled_query.scan_single(&get_document("https://example.com/a")).outputs;
+        let again = compiled_query.scan_single(&get_document("https://example.com/a")).outputs;
+        let id = first[0].id.clone().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(id.as_bytes()[14], b'4');
+        assert_ne!(first[0].id, again[0].id);
+
+        query.response.output_ids = Some(OutputIdPolicy::Omitted);
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(&get_document("https://example.com/a")).outputs;
+        assert_eq!(outputs[0].id, None);
+    }
+
+    #[test]
+    fn test_scan_bytes_scope() {
+        let mut query = get_basic_query();
+        query.scope.content = ScopeContent::Bytes;
+        query.triggers[0].pattern = Pattern {
+            content: String::from("ff d8 ff"), // JPEG signature
+            kind: PatternKind::Bytes,
+            normalization: None,
+        };
+        let group = QueryGroup {
+            queries: vec![query],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+        assert_eq!(group.always_run_queries.len(), 1);
+
+        let get_document = |data: &[u8]| {
+            Document {
+                data: data.to_vec(),
+                ..Document::from_text("https://example.com/image.jpg", "")
+            }
+            .compile()
+            .unwrap()
+        };
+        let document = get_document(b"\xff\xd8\xff\xe0 everyone");
+        assert_eq!(document.bytes(), b"\xff\xd8\xff\xe0 everyone");
+        assert_eq!(group.scan_single(&document).outputs.len(), 1);
+        assert_eq!(group.scan_single(&get_document(b"\xff\xd9\xff everyone")).outputs.len(), 0);
+    }
+
+    #[test]
+    fn test_scan_domain_scope() {
+        let mut query = get_basic_query();
+        query.scope.pattern.content = String::from(r"^(www\.)?example\.com$");
+        query.scope.target = ScopeTarget::Domain;
+        let serialized = ron::ser::to_string(&query.scope).unwrap();
+        assert!(serialized.contains("target:Domain"));
+        let compiled_query = query.compile().unwrap();
+
+        let get_document = |url: &str| {
+            Document::from_text(url, "hello everyone").compile().unwrap()
+        };
+        let matches = |url: &str| compiled_query.scan_single(&get_document(url)).outputs.len();
+        assert_eq!(matches("https://www.example.com/news"), 1);
+        // the domain appears in the URL, but is not the document's domain
+        assert_eq!(matches("https://evil.net/example.com?next=example.com"), 0);
+        assert_eq!(matches("https://example.com.evil.net/"), 0);
+    }
+
+    #[test]
+    fn test_scan_scope_language() {
+        let mut query = get_basic_query();
+        query.triggers[1].pattern.content = String::from("everyone|tout");
+        query.scope.language = vec![String::from("EN")];
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |text: &str| {
+            let document = Document::from_text("https://example.com", text);
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(
+            matches("hello everyone, and welcome to the evening news; here is what happened today"),
+            1
+        );
+        assert_eq!(
+            matches("hello tout le monde, et bienvenue au journal du soir; voici ce qui s'est passé aujourd'hui"),
+            0
+        );
+
+        query.scope.language = vec![String::from("xx")];
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_scope_sample() {
+        let mut query = get_basic_query();
+        query.scope.sample = Some(0.25);
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |url: &str| {
+            let document = Document::from_text(url, "hello everyone");
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        let sampled: Vec<usize> = (0..400)
+            .filter(|index| matches(&format!("https://example.com/{}", index)) == 1)
+            .collect();
+        assert!(sampled.len() > 60 && sampled.len() < 140);
+        // sampling is deterministic
+        for index in &sampled {
+            assert_eq!(matches(&format!("https://example.com/{}", index)), 1);
+        }
+
+        query.scope.sample = Some(1.5);
+        assert!(query.compile().is_err());
+        query.scope.sample = Some(f32::NAN);
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_scope_window() {
+        let mut query = get_basic_query();
+        query.scope.window = Some(TimeWindow {
+            after: Some(String::from("2019-01-01")),
+            before: Some(String::from("2019-07-01T00:00:00Z")),
+        });
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |timestamp: Option<i64>| {
+            let document = Document {
+                timestamp,
+                ..Document::from_text("https://example.com", "hello everyone")
+            };
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(matches(Some(1_546_300_800)), 1); // 2019-01-01
+        assert_eq!(matches(Some(1_556_713_800)), 1); // 2019-05-01
+        assert_eq!(matches(Some(1_561_939_200)), 0); // 2019-07-01
+        assert_eq!(matches(Some(1_546_300_799)), 0);
+        assert_eq!(matches(None), 0);
+
+        query.scope.window = Some(TimeWindow {
+            after: Some(String::from("last year")),
+            before: None,
+        });
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_group_scope_cache() {
+        let mut queries: Vec<Query> = Vec::new();
+        for index in 0..3 {
+            let mut query = get_basic_query();
+            query.id = Some(format!("query {}", index));
+            queries.push(query);
+        }
+        queries[1].scope.content = ScopeContent::Text; // same documents, different content
+        queries[2].scope.pattern.content = String::from("^https://example\\.org");
+        let group = QueryGroup {
+            queries,
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+        let mut keys: Vec<&String> = group
+            .queries
+            .iter()
+            .chain(group.always_run_queries.iter())
+            .map(|query| &query.scope.key)
+            .collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 2);
+
+        let document = Document::from_text("https://example.com", "hello everyone")
+            .compile()
+            .unwrap();
+        let outputs = group.scan_single(&document).outputs;
+        let mut ids: Vec<&str> = outputs
+            .iter()
+            .map(|output| output.query_id.as_ref().unwrap().as_str())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["query 0", "query 1"]);
+    }
+
+    #[test]
+    fn test_scan_scope_no_url() {
+        let mut query = get_basic_query(); // `.+` does not match an empty URL
+        let document = Document {
+            url: None,
+            ..Document::from_text("", "hello everyone")
+        }
+        .compile()
+        .unwrap();
+        let mut matches = |policy: Option<NoUrlPolicy>| {
+            query.scope.match_when_no_url = policy;
+            query.compile().unwrap().scan_single(&document).outputs.len()
+        };
+        assert_eq!(matches(None), 0);
+        assert_eq!(matches(Some(NoUrlPolicy::Include)), 1);
+        assert_eq!(matches(Some(NoUrlPolicy::Exclude)), 0);
+    }
+
+    #[test]
+    fn test_scan_scope_transforms() {
+        let mut query = get_basic_query();
+        query.triggers[0].pattern.content = String::from("hello everyone");
+        query.scope.transforms = vec![
+            ContentTransform::Lowercase,
+            ContentTransform::StripPunctuation,
+            ContentTransform::CollapseWhitespace,
+        ];
+        let group = group_of(&query);
+        assert_eq!(group.always_run_queries.len(), 1);
+
+        let document = Document::from_text("https://example.com", "Hello,\n  EVERYONE!")
+            .compile()
+            .unwrap();
+        let outputs = group.scan_single(&document).outputs;
+        assert_eq!(outputs.len(), 1);
+    }
+
+    #[test]
+    fn test_scan_trigger_transforms() {
+        let mut query = get_basic_query();
+        query.triggers[0].pattern.content = String::from("good morning");
+        query.triggers[0].transforms = vec![
+            ContentTransform::Lowercase,
+            ContentTransform::StripPunctuation,
+            ContentTransform::CollapseWhitespace,
+        ];
+        assert_eq!(group_of(&query).always_run_queries.len(), 1);
+
+        let documents = vec![
+            Document::from_text("https://example.com", "Good,\n  MORNING, everyone!"),
+            // only `A` is transformed
+            Document::from_text("https://example.com", "Good morning, EVERYONE!"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![1, 0]);
+
+        // the trigger's transforms follow those of the scope
+        query.scope.transforms = vec![ContentTransform::Lowercase];
+        query.triggers[0].transforms = vec![ContentTransform::StripPunctuation];
+        let compiled_query = query.compile().unwrap();
+        assert_eq!(
+            compiled_query.triggers[0].transforms,
+            Some(vec![ContentTransform::Lowercase, ContentTransform::StripPunctuation])
+        );
+        assert_eq!(compiled_query.triggers[1].transforms, None);
+    }
+
+    #[test]
+    fn test_scan_scope_source() {
+        let mut query = get_basic_query();
+        query.scope.source = Some(Pattern {
+            content: String::from("rss:*"),
+            kind: PatternKind::Glob,
+            normalization: None,
+        });
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |source: Option<&str>| {
+            let document = Document {
+                source: source.map(String::from),
+                ..Document::from_text("https://example.com", "hello everyone")
+            };
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(matches(Some("rss:nytimes")), 1);
+        assert_eq!(matches(Some("commoncrawl")), 0);
+        assert_eq!(matches(None), 0);
+    }
+
+    #[test]
+    fn test_scan_trigger_count() {
+        let mut query = get_basic_query();
+        query.threshold = Threshold {
+            considers: vec![ThresholdConsideration::TriggerCount(String::from("A"), 3)],
+            requires: 1,
+            inverse: false,
+            content: None,
+        };
+        let compiled_query = query.compile().unwrap();
+        let group = CompiledQueryGroup::from(compiled_query.clone());
+
+        let matches = |text: &str| {
+            let document = Document::from_text("https://example.com", text);
+            let document = document.compile().unwrap();
+            let outputs = compiled_query.scan_single(&document).outputs.len();
+            assert_eq!(group.scan_single(&document).outputs.len(), outputs);
+            outputs
+        };
+        assert_eq!(matches("hello hello"), 0);
+        assert_eq!(matches("hello hello, hello everyone"), 1);
+    }
+
+    #[test]
+    fn test_scan_scored_query() {
+        let mut query = get_basic_query();
+        query.threshold = Threshold {
+            considers: vec![
+                ThresholdConsideration::Weighted(2.0, Box::new(ThresholdConsideration::Trigger(String::from("A")))),
+                ThresholdConsideration::Trigger(String::from("B")),
+                ThresholdConsideration::Trigger(String::from("C")),
+            ],
+            requires: 3,
+            inverse: false,
+            content: None,
+        };
+        query.cutoff = Some(0.5);
+        let compiled_query = query.compile().unwrap();
+        let group = QueryGroup {
+            queries: vec![query.clone()],
+            optimized_content: ScopeContent::Raw,
+            thresholds: BTreeMap::new(),
+            triggers: vec![],
+        }
+        .compile()
+        .unwrap();
+        assert!(group.always_run_queries.is_empty());
+
+        let scores = |text: &str| {
+            let document = Document::from_text("https://example.com", text);
+            let document = document.compile().unwrap();
+            let outputs = compiled_query.scan_single(&document).outputs;
+            assert_eq!(group.scan_single(&document).outputs, outputs);
+            outputs.iter().map(|output| output.score.unwrap()).collect::<Vec<f32>>()
+        };
+        assert_eq!(scores("hello everyone around"), vec![1.0]);
+        assert_eq!(scores("hello"), vec![0.5]);
+        assert_eq!(scores("everyone around"), vec![0.5]);
+        assert_eq!(scores("everyone"), Vec::<f32>::new());
+
+        query.cutoff = Some(1.5);
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_lazy_triggers() {
+        let mut query = get_basic_query();
+        let excerpts = query.compile().unwrap();
+        assert!(excerpts.needs_all_triggers);
+        query.response.include = vec![ResponseItem::Url];
+        let urls = query.compile().unwrap();
+        assert!(!urls.needs_all_triggers);
+
+        for text in &["hello everyone around", "hello around", "everyone around", "hello"] {
+            let document = Document::from_text("https://example.com", text).compile().unwrap();
+            let expected = query.threshold.evaluate(&{
+                let mut counts: HashMap<&String, usize> = HashMap::new();
+                for trigger in &excerpts.triggers {
+                    counts.insert(&trigger.id, trigger.evaluate(&document.raw) as usize);
+                }
+                counts
+            });
+            let expected = expected.unwrap() as usize;
+            assert_eq!(excerpts.scan_single(&document).outputs.len(), expected);
+            assert_eq!(urls.scan_single(&document).outputs.len(), expected);
+        }
+        let outputs = excerpts.scan_single(
+            &Document::from_text("https://example.com", "hello everyone around").compile().unwrap(),
+        );
+        match &outputs.outputs[0].items[0] {
+            OutputItem::Excerpt(matches) => assert_eq!(matches.len(), 3), // every trigger ran
+            item => panic!("unexpected output item {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_scan_trigger_matches() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::TriggerMatches];
+        let compiled_query = query.compile().unwrap();
+        assert!(compiled_query.needs_all_triggers);
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com", "hello around, hello").compile().unwrap(),
+        );
+        match &outputs.outputs[0].items[0] {
+            OutputItem::TriggerMatches(matches) => {
+                let ids: Vec<&str> = matches.iter().map(|(id, _)| id.as_str()).collect();
+                assert_eq!(ids, vec!["A", "A", "C"]);
+                assert_eq!(matches[2].1.excerpt, "hello around, hello");
+            }
+            item => panic!("unexpected output item {:?}", item),
+        }
+
+        query.response.kind = ResponseKind::Partial;
+        assert!(query.response.validate().is_some());
+    }
+
+    #[test]
+    fn test_scan_match_offsets() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::TriggerMatches];
+        query.triggers[0].position = Some(TriggerPosition::Last(22));
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com", "héllo\nsay hello around\nhello")
+                .compile()
+                .unwrap(),
+        );
+        match &outputs.outputs[0].items[0] {
+            OutputItem::TriggerMatches(matches) => {
+                let found: Vec<_> = matches
+                    .iter()
+                    .filter(|(id, _)| id == "A")
+                    .map(|(_, found)| (found.content, found.offsets, found.location))
+                    .collect();
+                assert_eq!(
+                    found,
+                    vec![
+                        (Some(ScopeContent::Raw), Some((11, 16)), Some((2, 5))),
+                        (Some(ScopeContent::Raw), Some((24, 29)), Some((3, 1))),
+                    ]
+                );
+            }
+            item => panic!("unexpected output item {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_scan_partial_reduction() {
+        let mut query = get_basic_query();
+        query.response.kind = ResponseKind::Partial;
+        query.response.include = vec![ResponseItem::Count, ResponseItem::DomainHistogram];
+        assert_eq!(query.response.validate(), None);
+        let compiled_query = query.compile().unwrap();
+        let documents: Vec<Document> = ["a.com", "b.com", "a.com"]
+            .iter()
+            .map(|domain| Document::from_text(&format!("https://{}/", domain), "hello everyone"))
+            .collect();
+        let outputs = compiled_query.scan_batch(&DocumentBatch::from(documents).compile().unwrap());
+        let reduced = outputs.reduce_by_query().unwrap();
+        assert_eq!(reduced.outputs.len(), 1);
+        let histogram: BTreeMap<String, usize> =
+            vec![(String::from("a.com"), 2), (String::from("b.com"), 1)].into_iter().collect();
+        assert_eq!(
+            reduced.outputs[0].items,
+            vec![OutputItem::Count(3), OutputItem::DomainHistogram(histogram)]
+        );
+
+        query.response.include = vec![ResponseItem::Count, ResponseItem::Domain];
+        assert!(query.response.validate().is_some());
+        query.response.include = vec![ResponseItem::Count];
+        query.response.template = Some(String::from("{query_id}"));
+        assert!(query.response.validate().is_some());
+    }
+
+    #[test]
+    fn test_scan_trigger_stats() {
+        let mut query = get_basic_query();
+        query.response.kind = ResponseKind::Partial;
+        query.response.include = vec![ResponseItem::TriggerStats];
+        assert_eq!(query.response.validate(), None); // counts can be reduced
+        let compiled_query = query.compile().unwrap();
+        assert!(compiled_query.needs_all_triggers);
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com", "hello around, hello").compile().unwrap(),
+        );
+        let expected: BTreeMap<String, usize> = [("A", 2), ("B", 0), ("C", 1)]
+            .iter()
+            .map(|(id, count)| (String::from(*id), *count))
+            .collect();
+        assert_eq!(outputs.outputs[0].items, vec![OutputItem::TriggerStats(expected)]);
+    }
+
+    #[test]
+    fn test_scan_timestamp() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::Timestamp, ResponseItem::Engine];
+        let before = timestamp::now();
+        let outputs = query.compile().unwrap().scan_single(
+            &Document::from_text("https://example.com", "hello everyone").compile().unwrap(),
+        );
+        let items = &outputs.outputs[0].items;
+        match items[0] {
+            OutputItem::Timestamp(scanned) => assert!(scanned >= before && scanned <= timestamp::now()),
+            _ => panic!("expected a timestamp, found {:?}", items[0]),
+        }
+        assert_eq!(items[1], OutputItem::Engine(String::from(ENGINE)));
+        assert!(ENGINE.starts_with("ieql "));
+    }
+
+    #[test]
+    fn test_scan_template() {
+        let mut query = get_basic_query();
+        query.response.include = vec![];
+        query.response.template = Some(String::from(
+            "{query_id} matched {domain} ({count} matches): {match} in `{excerpt}`{mime}",
+        ));
+        assert_eq!(query.response.validate(), None);
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com/a", "  hello around, hello ")
+                .compile()
+                .unwrap(),
+        );
+        assert_eq!(
+            outputs.outputs[0].items,
+            vec![OutputItem::Message(String::from(
+                "Test Trigger #1 matched example.com (3 matches): hello in `hello around, hello`"
+            ))]
+        );
+
+        // values from the document are never rendered themselves
+        query.response.template = Some(String::from("{{{query_id}}} {excerpt}"));
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com/a", "hello {domain} {metadata.secret} {{around}}")
+                .compile()
+                .unwrap(),
+        );
+        assert_eq!(
+            outputs.outputs[0].items,
+            vec![OutputItem::Message(String::from(
+                "{Test Trigger #1} hello {domain} {metadata.secret} {{around}}"
+            ))]
+        );
+
+        query.response.template = Some(String::from("{query_id}: {matches}"));
+        match query.response.validate() {
+            Some(issues) => assert!(matches!(issues[..], [Issue::Error(_)])), // unknown placeholder
+            None => panic!("`matches` is not a placeholder"),
+        }
+    }
+
+    #[test]
+    fn test_scan_sentence_excerpts() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::Excerpt];
+        query.response.excerpt_mode = Some(ExcerptMode::Sentence);
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com", "Welcome. Say hello to everyone! Goodbye.")
+                .compile()
+                .unwrap(),
+        );
+        match &outputs.outputs[0].items[0] {
+            OutputItem::Excerpt(matches) => {
+                assert_eq!(matches[0].excerpt, "Say hello to everyone!");
+                assert_eq!(matches[0].relevant, (4, 9));
+            }
+            item => panic!("unexpected output item {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_scan_title() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::Title];
+        query.response.template = Some(String::from("{query_id}: {title}"));
+        assert_eq!(query.response.validate(), None);
+        let compiled_query = query.compile().unwrap();
+        let items = |url: &str, data: &str| {
+            let document = Document::from_text(url, data);
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.remove(0).items
+        };
+        assert_eq!(
+            items(
+                "https://example.com/a.html",
+                "<title>Evening  news</title><p>hello everyone</p>"
+            ),
+            vec![
+                OutputItem::Title(Some(String::from("Evening news"))),
+                OutputItem::Message(String::from("Test Trigger #1: Evening news")),
+            ]
+        );
+        assert_eq!(
+            items("https://example.com/a.txt", "hello everyone"),
+            vec![OutputItem::Title(None), OutputItem::Message(String::from("Test Trigger #1: "))]
+        );
+    }
+
+    #[test]
+    fn test_scan_metadata() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::Metadata];
+        query.response.template = Some(String::from("{metadata.crawl} ({metadata.missing}) {metadata.source"));
+        assert_eq!(query.response.validate(), None);
+        let compiled_query = query.compile().unwrap();
+        let mut metadata: HashMap<String, String> = HashMap::new();
+        metadata.insert(String::from("crawl"), String::from("CC-MAIN-2020-05 {metadata.crawl}"));
+        metadata.insert(String::from("content-type"), String::from("text/plain"));
+        let document = Document {
+            metadata,
+            ..Document::from_text("https://example.com/a.txt", "hello everyone")
+        };
+        let items = compiled_query.scan_single(&document.compile().unwrap()).outputs.remove(0).items;
+        let mut expected: BTreeMap<String, String> = BTreeMap::new();
+        expected.insert(String::from("content-type"), String::from("text/plain"));
+        expected.insert(String::from("crawl"), String::from("CC-MAIN-2020-05 {metadata.crawl}"));
+        assert_eq!(
+            items,
+            vec![
+                OutputItem::Metadata(expected),
+                OutputItem::Message(String::from("CC-MAIN-2020-05 {metadata.crawl} () {metadata.source")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_full_content_redaction() {
+        let mut query = get_basic_query();
+        query.response.include = vec![ResponseItem::FullContent];
+        query.response.redactions = vec![String::from(r"\b\d{3}-\d{2}-\d{4}\b")];
+        query.response.content_limit = Some(36);
+        let compiled_query = query.compile().unwrap();
+        let outputs = compiled_query.scan_single(
+            &Document::from_text("https://example.com", "hello everyone, 123-45-6789 is mine… or not")
+                .compile()
+                .unwrap(),
+        );
+        assert_eq!(
+            outputs.outputs[0].items,
+            vec![OutputItem::FullContent(Some(String::from(
+                "hello everyone, [REDACTED] is mine" // `…` would be cut in half
+            )))]
+        );
+
+        query.response.redactions.push(String::from("(unclosed"));
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_query_labels() {
+        let mut query = get_basic_query();
+        query.labels.insert(String::from("priority"), String::from("high"));
+        let serialized = ron::ser::to_string(&query).unwrap();
+        assert_eq!(serialized.parse::<Query>().unwrap(), query);
+
+        let outputs = query.compile().unwrap().scan_single(
+            &Document::from_text("https://example.com", "hello everyone").compile().unwrap(),
+        );
+        assert_eq!(outputs.outputs[0].labels, query.labels);
+        assert!(outputs.outputs[0].to_string().contains("{\"priority\": \"high\"}"));
+    }
+
+    #[test]
+    fn test_scan_scope_respect_robots() {
+        let mut query = get_basic_query();
+        query.scope.respect_robots = true;
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |robots: &str| {
+            let html = format!("<meta name=\"ROBOTS\" content=\"{}\"><p>hello everyone</p>", robots);
+            let document = Document::from_text(URL, &html);
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(matches("index, follow"), 1);
+        assert_eq!(matches("nofollow, noindex"), 0);
+        assert_eq!(matches("noarchive"), 0);
+        assert_eq!(matches("none"), 0);
+    }
+
+    #[test]
+    fn test_scan_scope_include_exclude() {
+        let glob = |content: &str| Pattern {
+            content: String::from(content),
+            kind: PatternKind::Glob,
+            normalization: None,
+        };
+        let mut query = get_basic_query();
+        query.scope.pattern = glob("https://example.com/**");
+        query.scope.include = vec![glob("https://example.org/**")];
+        query.scope.exclude = vec![glob("**/private/**"), glob("**.pdf")];
+        let compiled_query = query.compile().unwrap();
+
+        let matches = |url: &str| {
+            let document = Document::from_text(url, "hello everyone");
+            compiled_query.scan_single(&document.compile().unwrap()).outputs.len()
+        };
+        assert_eq!(matches("https://example.com/news"), 1);
+        assert_eq!(matches("https://example.org/news"), 1);
+        assert_eq!(matches("https://example.net/news"), 0);
+        assert_eq!(matches("https://example.com/private/news"), 0);
+        assert_eq!(matches("https://example.org/report.pdf"), 0);
+
+        query.scope.exclude.push(Pattern {
+            content: String::from("("),
+            kind: PatternKind::RegEx,
+            normalization: None,
+        });
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_threshold_content() {
+        let mut query = get_basic_query();
+        query.scope.content = ScopeContent::Text;
+        query.threshold = Threshold::from(ThresholdExpression::All(vec![
+            ThresholdExpression::Within(
+                ScopeContent::Title,
+                Box::new(ThresholdExpression::Trigger(String::from("A"))),
+            ),
+            ThresholdExpression::Trigger(String::from("B")),
+        ]));
+
+        let documents = vec![
+            Document::from_html(URL, "<title>hello there</title><p>everyone</p>"),
+            Document::from_html(URL, "<title>greetings</title><p>hello everyone</p>"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![1, 0]);
+    }
+
+    #[test]
+    fn test_scan_trigger_content() {
+        let mut query = get_basic_query();
+        query.scope.content = ScopeContent::Text;
+        query.triggers[0].content = Some(ScopeContent::Raw);
+        query.triggers[0].pattern.content = String::from("href=\"https://example\\.org");
+        query.threshold = Threshold::from(ThresholdExpression::All(vec![
+            ThresholdExpression::Trigger(String::from("A")),
+            ThresholdExpression::Trigger(String::from("B")),
+        ]));
+        let serialized = ron::ser::to_string(&query).unwrap();
+        assert_eq!(ron::de::from_str::<Query>(&serialized).unwrap(), query);
+        assert_eq!(group_of(&query).always_run_queries.len(), 1);
+
+        let documents = vec![
+            Document::from_html(URL, "<p>hello <a href=\"https://example.org\">everyone</a></p>"),
+            // not an attribute
+            Document::from_html(URL, "<p>href=&quot;https://example.org&quot; everyone</p>"),
+            Document::from_html(URL, "<p><a href=\"https://example.com\">everyone</a></p>"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![1, 0, 0]);
+    }
+
+    #[test]
+    fn test_scan_tagged_triggers() {
+        let mut query = get_basic_query();
+        query.triggers[1].tags = vec![String::from("greeting")];
+        query.triggers[2].tags = vec![String::from("greeting")];
+        query.threshold = Threshold::from(ThresholdExpression::All(vec![
+            ThresholdExpression::Trigger(String::from("A")),
+            ThresholdExpression::AtLeastTagged(2, String::from("greeting")),
+        ]));
+        assert_eq!(query.threshold.describe(), "all of (`A`, at least 2 triggers tagged `greeting`)");
+        assert_eq!(query.validate(), None);
+        let compiled_query = query.compile().unwrap();
+        assert_eq!(compiled_query.threshold.describe(), "all of (`A`, all of (`B`, `C`))");
+
+        let documents = vec![
+            Document::from_text("https://example.com", "hello everyone around"),
+            Document::from_text("https://example.com", "hello everyone"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![1, 0]);
+
+        query.threshold = Threshold::from(ThresholdExpression::Tag(String::from("farewell")));
+        assert!(query.compile().is_err());
+    }
+
+    #[test]
+    fn test_scan_lead_paragraph() {
+        let mut query = get_basic_query();
+        query.triggers[0].content = Some(ScopeContent::Paragraphs);
+        query.triggers[0].position = Some(TriggerPosition::Paragraph(1));
+
+        let documents = vec![
+            Document::from_html(URL, "<h1>hello</h1><p>Good evening, everyone.</p><p>hello</p>"),
+            Document::from_html(URL, "<h1>News</h1><p>Well, hello everyone.</p><p>Goodbye</p>"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_scan_main_content() {
+        let mut query = get_basic_query();
+        query.triggers[0].content = Some(ScopeContent::MainContent);
+
+        let article = "<article><p>The council met on Tuesday, and everyone voted to repave the road.</p></article>";
+        let elected = "<article><p>Residents said hello to the new mayor, who was elected in May.</p></article>";
+        let documents = vec![
+            Document::from_html(URL, &format!("<nav>hello</nav>{}<footer>hello</footer>", article)),
+            Document::from_html(URL, &format!("{}{}", article, elected)),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_scan_selector_trigger() {
+        let mut query = get_basic_query();
+        query.triggers[0].pattern = Pattern {
+            content: String::from("h1\nhello"),
+            kind: PatternKind::Selector,
+            normalization: None,
+        };
+        assert_eq!(group_of(&query).always_run_queries.len(), 1);
+
+        let documents = vec![
+            Document::from_html(URL, "<h1>News</h1><p>hello everyone</p>"),
+            Document::from_html(URL, "<h1>Well, hello</h1><p>everyone</p>"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_scan_link_target_trigger() {
+        let mut query = get_basic_query();
+        query.triggers[0].content = Some(ScopeContent::LinkTargets);
+        query.triggers[0].pattern = Pattern {
+            content: String::from("https://**.bad.example/**"),
+            kind: PatternKind::Glob,
+            normalization: None,
+        };
+
+        let url = "https://www.bad.example/news/a.html";
+        let documents = vec![
+            Document::from_html(url, "<p>everyone</p><a href=\"https://good.example/\">Good</a>"),
+            Document::from_html(url, "<p>everyone</p><a href=\"../offers\">Offers</a>"),
+        ];
+        assert_eq!(assert_group_agrees(&query, documents), vec![0, 1]);
     }
 }